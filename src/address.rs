@@ -1,10 +1,20 @@
 
 // Define a custom type for address for clarity
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AddressError {
     InvalidLength,
 }
 
+impl std::fmt::Display for AddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressError::InvalidLength => write!(f, "address must be 42 characters long"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
 // A typed string that guarantees a length of 42.
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct Address(String);
@@ -29,6 +39,6 @@ impl Address {
     }
 
 	pub fn to_normalized(&self) -> Address {
-		return Address(self.0.to_lowercase());
+		Address(self.0.to_lowercase())
 	}
 }
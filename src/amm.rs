@@ -0,0 +1,139 @@
+// amm.rs - Constant product liquidity pools users swap against
+
+use crate::address::Address;
+use crate::amount::{Amount, AmountError, Price, Rounding};
+use crate::exchange::{SpotAction, default_pool_address};
+use crate::exchange_rate_provider::AssetPair;
+use crate::user::User;
+use std::collections::HashMap;
+
+/// Shares the first deposit locks away for good, so the pool can never be drained back to empty
+/// and no later provider can be priced out by someone inflating a near-empty pool's share value
+pub const MINIMUM_SHARES: Amount = Amount::from_units(1_000_000);
+
+/// Base and quote pooled by liquidity providers, who own it pro rata to their shares.
+/// Swaps never shrink `base_reserve * quote_reserve`, the fee they leave behind grows every share.
+#[derive(Clone)]
+pub struct LiquidityPool {
+    pub pair: AssetPair,
+    pub base_reserve: Amount, // held in `balances`
+    pub quote_reserve: Amount,
+    pub total_shares: Amount, // including the locked `MINIMUM_SHARES`
+    pub shares: HashMap<Address, Amount>, // map from provider to LP shares held
+    pub balances: User,       // what the pool holds, see `Account::Pool`
+}
+
+impl LiquidityPool {
+    pub fn new(pair: AssetPair) -> Self {
+        LiquidityPool {
+            pair,
+            base_reserve: Amount::ZERO,
+            quote_reserve: Amount::ZERO,
+            total_shares: Amount::ZERO,
+            shares: HashMap::new(),
+            balances: User::new(default_pool_address()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_shares.is_zero()
+    }
+
+    /// Quote per unit of base implied by the reserves, None until someone deposits
+    pub fn get_price(&self) -> Option<Price> {
+        if self.base_reserve.is_zero() {
+            return None;
+        }
+        self.quote_reserve
+            .try_div(self.base_reserve, Rounding::HalfEven)
+            .ok()
+    }
+
+    pub fn get_shares(&self, provider_address: &Address) -> Amount {
+        self.shares
+            .get(provider_address)
+            .copied()
+            .unwrap_or(Amount::ZERO)
+    }
+
+    /// (base taken, quote taken, shares minted) for a deposit of up to `base_amount` and
+    /// `quote_amount`. The first deposit sets the price and mints the geometric mean of both
+    /// sides less `MINIMUM_SHARES`, later ones are taken at the pool's ratio as far as the
+    /// scarcer side allows.
+    pub fn quote_deposit(
+        &self,
+        base_amount: Amount,
+        quote_amount: Amount,
+    ) -> Result<(Amount, Amount, Amount), AmountError> {
+        if self.is_empty() {
+            let product = base_amount
+                .units()
+                .checked_mul(quote_amount.units())
+                .ok_or(AmountError::Overflow)?;
+            let minted = Amount::from_units(product.isqrt())
+                .try_sub(MINIMUM_SHARES)?
+                .max(Amount::ZERO);
+            return Ok((base_amount, quote_amount, minted));
+        }
+
+        let minted = base_amount
+            .try_mul(self.total_shares, Rounding::Down)?
+            .try_div(self.base_reserve, Rounding::Down)?
+            .min(
+                quote_amount
+                    .try_mul(self.total_shares, Rounding::Down)?
+                    .try_div(self.quote_reserve, Rounding::Down)?,
+            );
+        // the provider pays for the shares, so what it hands over rounds up, but never past its offer
+        let (base_taken, quote_taken) = self.share_of_reserves(minted, Rounding::Up)?;
+        Ok((
+            base_taken.min(base_amount),
+            quote_taken.min(quote_amount),
+            minted,
+        ))
+    }
+
+    /// (base, quote) paid out for burning `shares`
+    pub fn quote_withdrawal(&self, shares: Amount) -> Result<(Amount, Amount), AmountError> {
+        self.share_of_reserves(shares, Rounding::Down)
+    }
+
+    /// What a swap of `amount_in` pays out, quote going in when buying base and base when selling.
+    /// `fee_bps` of the input stays in the pool without moving the price.
+    pub fn quote_swap(
+        &self,
+        side: SpotAction,
+        amount_in: Amount,
+        fee_bps: u16,
+    ) -> Result<Amount, AmountError> {
+        let (reserve_in, reserve_out, asset_out) = match side {
+            SpotAction::BUY => (self.quote_reserve, self.base_reserve, &self.pair.base),
+            SpotAction::SELL => (self.base_reserve, self.quote_reserve, &self.pair.quote),
+        };
+        let amount_in_after_fee = amount_in.try_mul_bps(10_000 - fee_bps, Rounding::Down)?;
+
+        // (reserve_in + in) * (reserve_out - out) = reserve_in * reserve_out
+        Ok(reserve_out
+            .try_mul(amount_in_after_fee, Rounding::Down)?
+            .try_div(reserve_in.try_add(amount_in_after_fee)?, Rounding::Down)?
+            .round_to_asset(asset_out, Rounding::Down))
+    }
+
+    fn share_of_reserves(
+        &self,
+        shares: Amount,
+        rounding: Rounding,
+    ) -> Result<(Amount, Amount), AmountError> {
+        let base = self
+            .base_reserve
+            .try_mul(shares, rounding)?
+            .try_div(self.total_shares, rounding)?
+            .round_to_asset(&self.pair.base, rounding);
+        let quote = self
+            .quote_reserve
+            .try_mul(shares, rounding)?
+            .try_div(self.total_shares, rounding)?
+            .round_to_asset(&self.pair.quote, rounding);
+        Ok((base, quote))
+    }
+}
@@ -0,0 +1,269 @@
+// amount.rs - Fixed-point amounts and prices
+//
+// Every balance, price and fee is stored as an integer number of 10^-12 units so that
+// escrow totals reconcile to the last unit. Each asset only uses the first
+// `Asset::decimals()` of those places, and every computation that could produce more
+// precision than that takes an explicit `Rounding`:
+// - amounts collected by the exchange (premiums, fees, collateral, strike payments) round up
+// - amounts paid out by the exchange round down
+
+use crate::asset::Asset;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Decimal places every `Amount` is stored with, no asset can use more than this
+pub const AMOUNT_DECIMALS: u32 = 12;
+
+const AMOUNT_SCALE: i128 = 1_000_000_000_000;
+const BPS_SCALE: i128 = 10_000;
+
+/// How to get rid of the digits that don't fit the target precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    Down,     // toward negative infinity
+    Up,       // toward positive infinity
+    HalfEven, // to the nearest value, ties to even
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmountError {
+    Overflow,
+    DivisionByZero,
+    Invalid(String), // input that can't be turned into an amount
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmountError::Overflow => write!(f, "Arithmetic overflow"),
+            AmountError::DivisionByZero => write!(f, "Division by zero"),
+            AmountError::Invalid(input) => write!(f, "Invalid amount: {}", input),
+        }
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+/// Signed fixed-point number with `AMOUNT_DECIMALS` decimal places
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i128);
+
+/// Prices are amounts of quote asset per unit of base asset
+pub type Price = Amount;
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Build an amount from its raw number of 10^-12 units
+    pub const fn from_units(units: i128) -> Amount {
+        Amount(units)
+    }
+
+    pub const fn from_int(value: i64) -> Amount {
+        Amount(value as i128 * AMOUNT_SCALE)
+    }
+
+    /// Convert from a float through its shortest decimal representation, so `0.1` becomes
+    /// exactly 0.1. Panics on NaN, infinite or out of range values, see `try_from_f64`.
+    pub fn from_f64(value: f64) -> Amount {
+        Amount::try_from_f64(value).expect("Panic: f64 value can't be represented as an amount")
+    }
+
+    pub fn try_from_f64(value: f64) -> Result<Amount, AmountError> {
+        if !value.is_finite() {
+            return Err(AmountError::Invalid(value.to_string()));
+        }
+        value.to_string().parse()
+    }
+
+    /// Lossy conversion for display and floating point models (pricing, simulation)
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / AMOUNT_SCALE as f64
+    }
+
+    pub fn units(&self) -> i128 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn try_add(self, rhs: Amount) -> Result<Amount, AmountError> {
+        self.0
+            .checked_add(rhs.0)
+            .map(Amount)
+            .ok_or(AmountError::Overflow)
+    }
+
+    pub fn try_sub(self, rhs: Amount) -> Result<Amount, AmountError> {
+        self.0
+            .checked_sub(rhs.0)
+            .map(Amount)
+            .ok_or(AmountError::Overflow)
+    }
+
+    pub fn try_mul(self, rhs: Amount, rounding: Rounding) -> Result<Amount, AmountError> {
+        let product = self.0.checked_mul(rhs.0).ok_or(AmountError::Overflow)?;
+        Ok(Amount(div_rounded(product, AMOUNT_SCALE, rounding)))
+    }
+
+    pub fn try_div(self, rhs: Amount, rounding: Rounding) -> Result<Amount, AmountError> {
+        if rhs.0 == 0 {
+            return Err(AmountError::DivisionByZero);
+        }
+        let numerator = self
+            .0
+            .checked_mul(AMOUNT_SCALE)
+            .ok_or(AmountError::Overflow)?;
+        // keep the denominator positive so rounding directions hold
+        let (numerator, denominator) = if rhs.0 < 0 {
+            (
+                numerator.checked_neg().ok_or(AmountError::Overflow)?,
+                rhs.0.checked_neg().ok_or(AmountError::Overflow)?,
+            )
+        } else {
+            (numerator, rhs.0)
+        };
+        Ok(Amount(div_rounded(numerator, denominator, rounding)))
+    }
+
+    /// Multiply by a whole number, e.g. a contract multiplier
+    pub fn try_mul_int(self, factor: i64) -> Result<Amount, AmountError> {
+        self.0
+            .checked_mul(factor as i128)
+            .map(Amount)
+            .ok_or(AmountError::Overflow)
+    }
+
+    /// Take `bps` basis points (10_000 for 100%) of this amount
+    pub fn try_mul_bps(self, bps: u16, rounding: Rounding) -> Result<Amount, AmountError> {
+        let product = self
+            .0
+            .checked_mul(bps as i128)
+            .ok_or(AmountError::Overflow)?;
+        Ok(Amount(div_rounded(product, BPS_SCALE, rounding)))
+    }
+
+    /// Drop every decimal place after `decimals`
+    pub fn round_dp(self, decimals: u32, rounding: Rounding) -> Amount {
+        if decimals >= AMOUNT_DECIMALS {
+            return self;
+        }
+        let step = 10_i128.pow(AMOUNT_DECIMALS - decimals);
+        Amount(div_rounded(self.0, step, rounding).saturating_mul(step))
+    }
+
+    /// Round to the precision balances of `asset` are kept with
+    pub fn round_to_asset(self, asset: &Asset, rounding: Rounding) -> Amount {
+        self.round_dp(asset.decimals(), rounding)
+    }
+
+    /// Whether this amount can be held in `asset` without rounding
+    pub fn fits_asset(&self, asset: &Asset) -> bool {
+        self.round_to_asset(asset, Rounding::Down) == *self
+    }
+}
+
+// `numerator / denominator` for a positive denominator, rounded as requested
+fn div_rounded(numerator: i128, denominator: i128, rounding: Rounding) -> i128 {
+    let quotient = numerator.div_euclid(denominator); // floor, since denominator > 0
+    let remainder = numerator.rem_euclid(denominator);
+    if remainder == 0 {
+        return quotient;
+    }
+
+    match rounding {
+        Rounding::Down => quotient,
+        Rounding::Up => quotient + 1,
+        Rounding::HalfEven => match remainder.cmp(&(denominator - remainder)) {
+            Ordering::Less => quotient,
+            Ordering::Greater => quotient + 1,
+            Ordering::Equal => quotient + quotient.rem_euclid(2),
+        },
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    /// Parse a plain decimal like `-1234.5678`, extra decimal places are rounded half-even
+    fn from_str(s: &str) -> Result<Amount, AmountError> {
+        let invalid = || AmountError::Invalid(s.to_string());
+
+        let (is_negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (integer_part, fraction_part) = match digits.split_once('.') {
+            Some((integer_part, fraction_part)) => (integer_part, fraction_part),
+            None => (digits, ""),
+        };
+        if integer_part.is_empty()
+            || !integer_part.chars().all(|c| c.is_ascii_digit())
+            || !fraction_part.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let mut mantissa: i128 = 0;
+        for c in integer_part.chars().chain(fraction_part.chars()) {
+            mantissa = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(c.to_digit(10).unwrap_or(0) as i128))
+                .ok_or(AmountError::Overflow)?;
+        }
+
+        let fraction_len = fraction_part.len() as u32;
+        let units = if fraction_len <= AMOUNT_DECIMALS {
+            mantissa
+                .checked_mul(10_i128.pow(AMOUNT_DECIMALS - fraction_len))
+                .ok_or(AmountError::Overflow)?
+        } else {
+            let excess = 10_i128
+                .checked_pow(fraction_len - AMOUNT_DECIMALS)
+                .ok_or_else(invalid)?;
+            div_rounded(mantissa, excess, Rounding::HalfEven)
+        };
+
+        Ok(Amount(if is_negative { -units } else { units }))
+    }
+}
+
+impl fmt::Display for Amount {
+    /// Prints the shortest exact decimal, or exactly `{:.N}` places (rounded half-even)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (amount, decimals) = match f.precision() {
+            Some(precision) => {
+                let decimals = (precision as u32).min(AMOUNT_DECIMALS);
+                (self.round_dp(decimals, Rounding::HalfEven), Some(decimals))
+            }
+            None => (*self, None),
+        };
+
+        let sign = if amount.0 < 0 { "-" } else { "" };
+        let units = amount.0.unsigned_abs();
+        let integer_part = units / AMOUNT_SCALE as u128;
+        let fraction = format!(
+            "{:0width$}",
+            units % AMOUNT_SCALE as u128,
+            width = AMOUNT_DECIMALS as usize
+        );
+
+        let fraction = match decimals {
+            Some(decimals) => &fraction[..decimals as usize],
+            None => fraction.trim_end_matches('0'),
+        };
+
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, integer_part)
+        } else {
+            write!(f, "{}{}.{}", sign, integer_part, fraction)
+        }
+    }
+}
@@ -29,4 +29,18 @@ impl std::fmt::Display for Asset {
             Asset::OTHER(s) => write!(f, "{}", s), // handle OTHER variant
         }
     }
-}
\ No newline at end of file
+}
+impl Asset {
+    /// Number of decimal places balances of this asset are kept with
+    pub fn decimals(&self) -> u32 {
+        match self {
+            Asset::BTC => 8,
+            Asset::ETH => 12, // 18 on chain, capped at what `Amount` can hold
+            Asset::SOL => 9,
+            Asset::APPLE => 4,
+            Asset::OTHER(_) => 8,
+            Asset::USDT | Asset::USDC => 6,
+            Asset::VNDT | Asset::VNDC => 0,
+        }
+    }
+}
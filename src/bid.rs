@@ -0,0 +1,28 @@
+// bid.rs - Standing buy orders for an option series
+
+use crate::address::Address;
+use crate::amount::{Amount, AmountError, Price};
+use crate::listing_option::{ListingOption, OptionSeries};
+
+/// Order to buy contracts of any listing in `series` at up to `bid_price`.
+/// The premium and buyer fee of every wanted contract stay locked in escrow until filled or cancelled.
+#[derive(Debug, Clone)]
+pub struct OptionBid {
+    pub bid_id: u32,
+    pub series: OptionSeries,
+    pub bidder_address: Address,
+    pub bid_price: Price, // per contract, based on quote asset like `ask_price`
+    pub contract_count: u32, // contracts still wanted
+    pub premium_per_contract: Amount, // bid_price * 100, rounded to the quote asset
+    pub fee_per_contract: Amount, // buyer fee charged when the bid was placed
+}
+
+impl OptionBid {
+    /// Quote amount locked in escrow for `contracts` wanted contracts
+    pub fn get_locked_amount(&self, contracts: u32) -> Result<Amount, AmountError> {
+        ListingOption::for_contracts(
+            self.premium_per_contract.try_add(self.fee_per_contract)?,
+            contracts,
+        )
+    }
+}
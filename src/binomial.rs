@@ -0,0 +1,320 @@
+// binomial.rs - Cox-Ross-Rubinstein trees for options that can be exercised before expiry
+//
+// Black-Scholes ignores the holder's right to exercise early and underprices American puts. The
+// tree checks at every node the listing's exercise style allows whether exercising beats holding
+// on, which prices American, Bermudan and European listings alike.
+
+use crate::error::ExchangeError;
+use crate::listing_option::{ListingOption, OptionSeries};
+use crate::pricing::{self, OptionValuation, PricingParams};
+use crate::types::ListingType;
+use chrono::{DateTime, Duration, Utc};
+
+/// Steps that price listed options to well within a cent of the converged value
+pub const DEFAULT_BINOMIAL_STEPS: usize = 200;
+
+/// Fewest steps the tree is built with, its Greeks read the nodes two steps in
+const MIN_BINOMIAL_STEPS: usize = 2;
+
+/// Volatility and rate bump the tree is repriced with for vega and rho
+const GREEK_BUMP: f64 = 0.01;
+
+const MILLISECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0 * 1000.0;
+
+/// Binomial valuation of an option on one unit of the underlying
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinomialValuation {
+    pub valuation: OptionValuation, // delta, gamma and theta read off the tree, vega and rho from bumped trees
+    pub exercise_now: bool,         // exercising today is allowed and worth more than holding on
+    pub step_years: f64,            // time between two steps of the tree
+    // spot at or beyond which exercising is optimal at every step from now (index 0) to expiry,
+    // below it for a put and above it for a call. None where holding on always wins or the
+    // exercise style doesn't allow it.
+    pub exercise_boundary: Vec<Option<f64>>,
+}
+
+impl BinomialValuation {
+    /// Valuation of `factor` units of the underlying, the boundary stays a spot
+    pub fn scale(&self, factor: f64) -> BinomialValuation {
+        BinomialValuation {
+            valuation: self.valuation.scale(factor),
+            ..self.clone()
+        }
+    }
+
+    /// Exercise boundary as (years from now, spot) for the steps that have one
+    pub fn boundary_points(&self) -> Vec<(f64, f64)> {
+        self.exercise_boundary
+            .iter()
+            .enumerate()
+            .filter_map(|(step, spot)| spot.map(|spot| (step as f64 * self.step_years, spot)))
+            .collect()
+    }
+}
+
+/// Value an American option with a Cox-Ross-Rubinstein tree of `steps` steps, at least two.
+/// Without time or volatility left the option is worth exercising it on the spot.
+pub fn binomial_american(
+    listing_type: &ListingType,
+    spot: f64,
+    strike: f64,
+    years: f64,
+    params: &PricingParams,
+    steps: usize,
+) -> BinomialValuation {
+    binomial_tree(listing_type, spot, strike, years, params, steps, |_| true)
+}
+
+/// Value an option that may only be exercised early at the steps where `can_exercise_at`
+/// holds for the years from now, it is always exercised at expiry if in the money
+pub fn binomial_tree(
+    listing_type: &ListingType,
+    spot: f64,
+    strike: f64,
+    years: f64,
+    params: &PricingParams,
+    steps: usize,
+    can_exercise_at: impl Fn(f64) -> bool,
+) -> BinomialValuation {
+    let years = years.max(0.0);
+    if years == 0.0 || params.volatility <= 0.0 {
+        let price = intrinsic_value(listing_type, spot, strike);
+        let delta = match listing_type {
+            ListingType::CALL if price > 0.0 => 1.0,
+            ListingType::PUT if price > 0.0 => -1.0,
+            _ => 0.0,
+        };
+        return BinomialValuation {
+            valuation: OptionValuation {
+                price,
+                delta,
+                gamma: 0.0,
+                vega: 0.0,
+                theta: 0.0,
+                rho: 0.0,
+            },
+            exercise_now: price > 0.0 && can_exercise_at(0.0),
+            step_years: 0.0,
+            exercise_boundary: Vec::new(),
+        };
+    }
+
+    let steps = steps.max(MIN_BINOMIAL_STEPS);
+    let tree = roll_back(
+        listing_type,
+        spot,
+        strike,
+        years,
+        params,
+        steps,
+        &can_exercise_at,
+    );
+    let step_years = years / steps as f64;
+    let up = (params.volatility * step_years.sqrt()).exp();
+    let down = 1.0 / up;
+
+    // Greeks from the nodes one and two steps in, which share the root's spot in the middle
+    let (step_1, step_2) = (tree.step_1, tree.step_2);
+    let delta = (step_1[1] - step_1[0]) / (spot * up - spot * down);
+    let delta_up = (step_2[2] - step_2[1]) / (spot * up * up - spot);
+    let delta_down = (step_2[1] - step_2[0]) / (spot - spot * down * down);
+    let gamma = (delta_up - delta_down) / ((spot * up * up - spot * down * down) / 2.0);
+    let theta = (step_2[1] - tree.price) / (2.0 * step_years);
+
+    let price_with = |volatility: f64, risk_free_rate: f64| {
+        let params = PricingParams::new(volatility, risk_free_rate);
+        roll_back(
+            listing_type,
+            spot,
+            strike,
+            years,
+            &params,
+            steps,
+            &can_exercise_at,
+        )
+        .price
+    };
+    let vega = (price_with(params.volatility + GREEK_BUMP, params.risk_free_rate)
+        - price_with(
+            (params.volatility - GREEK_BUMP).max(f64::EPSILON),
+            params.risk_free_rate,
+        ))
+        / 2.0;
+    let rho = (price_with(params.volatility, params.risk_free_rate + GREEK_BUMP)
+        - price_with(params.volatility, params.risk_free_rate - GREEK_BUMP))
+        / 2.0;
+
+    BinomialValuation {
+        valuation: OptionValuation {
+            price: tree.price,
+            delta,
+            gamma,
+            vega,
+            theta: theta / 365.0,
+            rho,
+        },
+        exercise_now: tree.exercise_boundary[0].is_some(),
+        step_years,
+        exercise_boundary: tree.exercise_boundary,
+    }
+}
+
+/// Value one unit of a series' underlying at the rate provider's current spot, as if American
+pub fn value_series_american(
+    series: &OptionSeries,
+    params: &PricingParams,
+    steps: usize,
+    now: DateTime<Utc>,
+) -> Result<BinomialValuation, ExchangeError> {
+    Ok(binomial_american(
+        &series.listing_type,
+        pricing::spot_rate(&series.base_asset, &series.quote_asset)?,
+        series.strike_price.to_f64(),
+        pricing::years_until(now, series.expiration_time),
+        params,
+        steps,
+    ))
+}
+
+/// Value one contract of a listing, which covers `exercise_amount` of the underlying
+pub fn value_listing_american(
+    option: &ListingOption,
+    params: &PricingParams,
+    steps: usize,
+    now: DateTime<Utc>,
+) -> Result<BinomialValuation, ExchangeError> {
+    Ok(value_series_american(&option.series(), params, steps, now)?
+        .scale(option.exercise_amount.to_f64()))
+}
+
+/// Value one unit of a series' underlying at the rate provider's current spot, exercising early
+/// only where its exercise style allows it
+pub fn value_series_by_style(
+    series: &OptionSeries,
+    exercise_window: Duration,
+    params: &PricingParams,
+    steps: usize,
+    now: DateTime<Utc>,
+) -> Result<BinomialValuation, ExchangeError> {
+    let years = pricing::years_until(now, series.expiration_time);
+    let half_step_years = years / steps.max(MIN_BINOMIAL_STEPS) as f64 / 2.0;
+    Ok(binomial_tree(
+        &series.listing_type,
+        pricing::spot_rate(&series.base_asset, &series.quote_asset)?,
+        series.strike_price.to_f64(),
+        years,
+        params,
+        steps,
+        |years| {
+            // a step stands for half a step either side of it, so short windows aren't stepped over
+            let time_after = |years: f64| {
+                now + Duration::milliseconds((years.max(0.0) * MILLISECONDS_PER_YEAR) as i64)
+            };
+            series.exercise_style.allows_exercise_between(
+                time_after(years - half_step_years),
+                time_after(years + half_step_years),
+                series.expiration_time,
+                exercise_window,
+            )
+        },
+    ))
+}
+
+/// Value one contract of a listing under its own exercise style
+pub fn value_listing_by_style(
+    option: &ListingOption,
+    exercise_window: Duration,
+    params: &PricingParams,
+    steps: usize,
+    now: DateTime<Utc>,
+) -> Result<BinomialValuation, ExchangeError> {
+    Ok(
+        value_series_by_style(&option.series(), exercise_window, params, steps, now)?
+            .scale(option.exercise_amount.to_f64()),
+    )
+}
+
+// Price, the option values one and two steps in and the exercise boundary of a tree
+struct RolledBackTree {
+    price: f64,
+    step_1: [f64; 2],
+    step_2: [f64; 3],
+    exercise_boundary: Vec<Option<f64>>,
+}
+
+fn roll_back(
+    listing_type: &ListingType,
+    spot: f64,
+    strike: f64,
+    years: f64,
+    params: &PricingParams,
+    steps: usize,
+    can_exercise_at: &dyn Fn(f64) -> bool,
+) -> RolledBackTree {
+    let step_years = years / steps as f64;
+    let up = (params.volatility * step_years.sqrt()).exp();
+    let down = 1.0 / up;
+    let discount = (-params.risk_free_rate * step_years).exp();
+    // risk neutral odds of an up move, clamped when the rate outgrows a very low volatility
+    let up_odds =
+        (((params.risk_free_rate * step_years).exp() - down) / (up - down)).clamp(0.0, 1.0);
+    // spot after `ups` up moves out of `step`
+    let node_spot = |step: usize, ups: usize| spot * up.powi(2 * ups as i32 - step as i32);
+
+    let mut exercise_boundary = vec![None; steps + 1];
+    let mut values: Vec<f64> = (0..=steps)
+        .map(|ups| intrinsic_value(listing_type, node_spot(steps, ups), strike))
+        .collect();
+    exercise_boundary[steps] = critical_spot(
+        listing_type,
+        (0..=steps)
+            .filter(|ups| values[*ups] > 0.0)
+            .map(|ups| node_spot(steps, ups)),
+    );
+
+    let mut step_1 = [0.0; 2];
+    let mut step_2 = [0.0; 3];
+    for step in (0..steps).rev() {
+        let mut exercised = Vec::new();
+        let can_exercise = can_exercise_at(step as f64 * step_years);
+        for ups in 0..=step {
+            let holding = discount * (up_odds * values[ups + 1] + (1.0 - up_odds) * values[ups]);
+            let exercising = intrinsic_value(listing_type, node_spot(step, ups), strike);
+            if can_exercise && exercising > holding {
+                exercised.push(node_spot(step, ups));
+                values[ups] = exercising;
+            } else {
+                values[ups] = holding;
+            }
+        }
+        exercise_boundary[step] = critical_spot(listing_type, exercised.into_iter());
+
+        match step {
+            2 => step_2.copy_from_slice(&values[..3]),
+            1 => step_1.copy_from_slice(&values[..2]),
+            _ => {}
+        }
+    }
+
+    RolledBackTree {
+        price: values[0],
+        step_1,
+        step_2,
+        exercise_boundary,
+    }
+}
+
+fn intrinsic_value(listing_type: &ListingType, spot: f64, strike: f64) -> f64 {
+    match listing_type {
+        ListingType::CALL => (spot - strike).max(0.0),
+        ListingType::PUT => (strike - spot).max(0.0),
+    }
+}
+
+// Exercised spot closest to the money: the highest for a put, the lowest for a call
+fn critical_spot(listing_type: &ListingType, exercised: impl Iterator<Item = f64>) -> Option<f64> {
+    match listing_type {
+        ListingType::CALL => exercised.reduce(f64::min),
+        ListingType::PUT => exercised.reduce(f64::max),
+    }
+}
@@ -0,0 +1,55 @@
+use chrono::{DateTime, Duration, Utc};
+use std::sync::RwLock;
+
+/// Source of the current time for every time-dependent check (expiry, settlement, rate staleness)
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Wall clock, used by default
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that only moves when told to, for tests and simulations
+pub struct SimulatedClock {
+    current_time: RwLock<DateTime<Utc>>,
+}
+
+impl SimulatedClock {
+    pub fn new(start_time: DateTime<Utc>) -> SimulatedClock {
+        SimulatedClock {
+            current_time: RwLock::new(start_time),
+        }
+    }
+
+    /// Move the clock forward (or backward for a negative duration)
+    pub fn advance(&self, duration: Duration) {
+        let mut current_time = self
+            .current_time
+            .write()
+            .expect("Panic: simulated clock lock poisoned");
+        *current_time += duration;
+    }
+
+    pub fn set_time(&self, new_time: DateTime<Utc>) {
+        let mut current_time = self
+            .current_time
+            .write()
+            .expect("Panic: simulated clock lock poisoned");
+        *current_time = new_time;
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self
+            .current_time
+            .read()
+            .expect("Panic: simulated clock lock poisoned")
+    }
+}
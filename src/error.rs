@@ -0,0 +1,215 @@
+// error.rs - Typed errors returned by the exchange and the modules it drives
+
+use crate::address::{Address, AddressError};
+use crate::amount::{Amount, AmountError, Price};
+use crate::asset::Asset;
+use crate::exchange_rate_provider::AssetPair;
+use crate::listing_option::OptionState;
+use crate::rbac::UnauthorizedError;
+use chrono::{DateTime, Utc};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExchangeError {
+    // Lookups
+    UserNotFound(Address),
+    ListingNotFound(u32),
+    ResaleNotFound(u32),
+    BidNotFound(u32),
+    OrderNotFound(u32),
+    SpotOrderNotFound(u32),
+    PoolNotFound(AssetPair),
+    MarginAccountNotFound(Address),
+
+    // Access control
+    Unauthorized(String), // caller isn't allowed to perform the described action
+    Role(UnauthorizedError),
+
+    // Funds
+    InsufficientBalance {
+        asset: Asset,
+        required: Amount,
+        available: Amount,
+    },
+    InsufficientMargin {
+        owner: Address,
+        asset: Asset,
+        required: Amount,  // initial requirement the account would have to meet
+        available: Amount, // collateral the account holds
+    },
+    WriterShortfall {
+        listing_id: u32,
+        writer: Address,
+        asset: Asset,
+        shortfall: Amount, // payout beyond what the writer's margin account holds
+        covered: Amount,   // what the insurance fund has left to cover it
+    },
+
+    // Listing lifecycle
+    InvalidTransition {
+        listing_id: u32,
+        from: OptionState,
+        to: OptionState,
+    },
+    InvalidState {
+        listing_id: u32,
+        state: OptionState, // the listing's current state doesn't allow the operation
+    },
+    Expired(u32),
+    OutsideExerciseWindow {
+        listing_id: u32,
+        opens_at: DateTime<Utc>, // when the listing's exercise style next allows it
+    },
+
+    // Rates
+    RateMissing {
+        base: Asset,
+        quote: Asset,
+    },
+    RateStale {
+        base: Asset,
+        quote: Asset,
+    },
+
+    // Spot trading
+    SlippageExceeded {
+        limit_price: Price,
+        unfilled: Amount, // base left over once the book is walked down to the limit
+    },
+    SwapOutputTooLow {
+        amount_out: Amount,
+        min_amount_out: Amount,
+    },
+
+    // Malformed requests
+    InvalidInput(String),
+    Amount(AmountError),
+    Address(AddressError),
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::UserNotFound(address) => write!(f, "User {} not found", address),
+            ExchangeError::ListingNotFound(listing_id) => {
+                write!(f, "Listing #{} not found", listing_id)
+            }
+            ExchangeError::ResaleNotFound(listing_id) => {
+                write!(f, "Resale listing #{} not found", listing_id)
+            }
+            ExchangeError::BidNotFound(bid_id) => write!(f, "Bid #{} not found", bid_id),
+            ExchangeError::OrderNotFound(order_id) => write!(f, "Order #{} not found", order_id),
+            ExchangeError::SpotOrderNotFound(order_id) => {
+                write!(f, "Spot order #{} not found", order_id)
+            }
+            ExchangeError::PoolNotFound(pair) => write!(f, "No liquidity pool for {}", pair),
+            ExchangeError::MarginAccountNotFound(owner) => {
+                write!(f, "No margin account for {}", owner)
+            }
+            ExchangeError::Unauthorized(reason) => write!(f, "Unauthorized: {}", reason),
+            ExchangeError::Role(error) => write!(f, "Unauthorized: {}", error),
+            ExchangeError::InsufficientBalance {
+                asset,
+                required,
+                available,
+            } => write!(
+                f,
+                "Insufficient {} balance: {} required, {} available",
+                asset, required, available
+            ),
+            ExchangeError::InsufficientMargin {
+                owner,
+                asset,
+                required,
+                available,
+            } => write!(
+                f,
+                "Insufficient {} margin for {}: {} required, {} available",
+                asset, owner, required, available
+            ),
+            ExchangeError::WriterShortfall {
+                listing_id,
+                writer,
+                asset,
+                shortfall,
+                covered,
+            } => write!(
+                f,
+                "Writer {} of option #{} is {} {} short, the insurance fund covers {}",
+                writer, listing_id, shortfall, asset, covered
+            ),
+            ExchangeError::InvalidTransition {
+                listing_id,
+                from,
+                to,
+            } => write!(f, "Option #{} can't go from {} to {}", listing_id, from, to),
+            ExchangeError::InvalidState { listing_id, state } => {
+                write!(f, "Option #{} is {}", listing_id, state)
+            }
+            ExchangeError::Expired(listing_id) => write!(f, "Option #{} has expired", listing_id),
+            ExchangeError::OutsideExerciseWindow {
+                listing_id,
+                opens_at,
+            } => write!(
+                f,
+                "Option #{} can't be exercised until {}",
+                listing_id,
+                opens_at.format("%Y-%m-%d %H:%M")
+            ),
+            ExchangeError::RateMissing { base, quote } => {
+                write!(f, "Exchange rate for pair {}/{} not found", base, quote)
+            }
+            ExchangeError::RateStale { base, quote } => {
+                write!(f, "Exchange rate for pair {}/{} is stale", base, quote)
+            }
+            ExchangeError::SlippageExceeded {
+                limit_price,
+                unfilled,
+            } => write!(
+                f,
+                "Slippage limit exceeded: {} left unfilled at a limit price of {}",
+                unfilled, limit_price
+            ),
+            ExchangeError::SwapOutputTooLow {
+                amount_out,
+                min_amount_out,
+            } => write!(
+                f,
+                "Swap would pay out {}, below the minimum of {}",
+                amount_out, min_amount_out
+            ),
+            ExchangeError::InvalidInput(reason) => write!(f, "{}", reason),
+            ExchangeError::Amount(error) => write!(f, "{}", error),
+            ExchangeError::Address(error) => write!(f, "Invalid address: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ExchangeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExchangeError::Role(error) => Some(error),
+            ExchangeError::Amount(error) => Some(error),
+            ExchangeError::Address(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<UnauthorizedError> for ExchangeError {
+    fn from(error: UnauthorizedError) -> ExchangeError {
+        ExchangeError::Role(error)
+    }
+}
+
+impl From<AmountError> for ExchangeError {
+    fn from(error: AmountError) -> ExchangeError {
+        ExchangeError::Amount(error)
+    }
+}
+
+impl From<AddressError> for ExchangeError {
+    fn from(error: AddressError) -> ExchangeError {
+        ExchangeError::Address(error)
+    }
+}
@@ -1,19 +1,56 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 
 use crate::Asset;
 use crate::address::Address;
-use crate::exchange_rate_provider::get_readonly_rate_provider;
-use crate::listing_option::ListingOption;
-use crate::rbac::RoleAuthorizer;
+use crate::amm::{LiquidityPool, MINIMUM_SHARES};
+use crate::amount::{Amount, AmountError, Price, Rounding};
+use crate::bid::OptionBid;
+use crate::clock::{Clock, SystemClock};
+use crate::error::ExchangeError;
+use crate::exchange_rate_provider::{
+    AssetPair, default_exchange_rate_provider_admin_address, get_rate_provider,
+    get_readonly_rate_provider,
+};
+use crate::listing_option::{
+    ExerciseStyle, ListingOption, OptionSeries, OptionState, SettlementType,
+};
+use crate::margin::{Liquidation, MarginAccount, MarginHealth, MarginParams};
+use crate::order_book::{BookEntry, Fill, Order, OrderBook, OrderSide, OrderStatus, OrderType};
+use crate::portfolio_margin::{Portfolio, PortfolioLeg, PortfolioMarginParams};
+use crate::position::Position;
+use crate::rbac::{NamedRole, RoleAuthorizer};
+use crate::spot_book::{SpotOrder, SpotOrderBook, SpotTrade};
+use crate::strategy::{
+    self, DefinedRiskGroup, StrategyFill, StrategyLeg, StrategyOrder, StrategyQuote,
+};
+use crate::transaction::{Account, BalanceTransaction, Direction};
+use crate::types::ListingType;
 use crate::user::User;
 use crate::utils::are_addresses_equal;
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 pub fn default_escrow_address() -> Address {
     Address::from("0x0000000000000000000000000000000000000000")
         .expect("Invalid default escrow address literal")
 }
 
+pub fn default_insurance_fund_address() -> Address {
+    Address::from("0x0000000000000000000000000000000000000001")
+        .expect("Invalid default insurance fund address literal")
+}
+
+pub fn default_treasury_address() -> Address {
+    Address::from("0x0000000000000000000000000000000000000002")
+        .expect("Invalid default treasury address literal")
+}
+
+pub fn default_pool_address() -> Address {
+    Address::from("0x0000000000000000000000000000000000000003")
+        .expect("Invalid default pool address literal")
+}
+
 pub fn default_exchange_admin_address() -> Address {
     Address::from("0xb73B0A92544a5D2523F00F868d795d50DbDfcCf4")
         .expect("Invalid exchange admin address literal")
@@ -21,40 +58,177 @@ pub fn default_exchange_admin_address() -> Address {
 
 const MAX_FEE_BPS: u16 = 10_000;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SpotAction {
     BUY,
     SELL,
 }
 
+/// An ask posted by the current beneficiary to sell their rights on a purchased option
+#[derive(Debug, Clone)]
+pub struct ResaleListing {
+    pub listing_id: u32,
+    pub seller_address: Address,
+    pub contract_count: u32, // the seller's open contracts when the ask was posted
+    pub ask_price: Price,    // per contract, based on quote asset
+}
+
+impl ResaleListing {
+    /// Unrounded premium for all contracts, the exchange rounds it to the listing's quote asset
+    pub fn get_premium_price(&self) -> Result<Amount, AmountError> {
+        ListingOption::for_contracts(self.ask_price.try_mul_int(100)?, self.contract_count)
+    }
+}
+
+/// A listing whose collateral was released back to its grantor after expiring
+#[derive(Debug, Clone)]
+pub struct SettledListing {
+    pub listing_id: u32,
+    pub grantor_address: Address,
+    pub released_asset: Asset,
+    pub released_amount: Amount,
+    pub was_purchased: bool,
+}
+
+/// Contracts the exchange exercised on their holder's behalf at expiry
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoExercise {
+    pub listing_id: u32,
+    pub holder_address: Address,
+    pub contracts: u32,
+    pub spot: Price, // provider rate the listing was found in the money at
+    pub time: DateTime<Utc>,
+}
+
+/// Treasury and insurance fund balances at a point in time, assets with nothing in them left out
+#[derive(Debug, Clone, PartialEq)]
+pub struct FundReport {
+    pub time: DateTime<Utc>,
+    pub treasury: HashMap<Asset, Amount>,
+    pub insurance_fund: HashMap<Asset, Amount>,
+}
+
+/// Outcome of a settlement pass over expired listings
+#[derive(Debug, Clone, Default)]
+pub struct SettlementReport {
+    pub auto_exercised: Vec<AutoExercise>, // exercised in the money before the rest expired
+    pub settled: Vec<SettledListing>,
+    pub total_released: HashMap<Asset, Amount>, // map from asset to the amount returned to grantors
+    pub expired_bids: Vec<u32>, // bids on expired series whose locked premium went back to the bidder
+    pub unsettled: Vec<UnsettledListing>, // in the money but couldn't be exercised, lapsed with the rest
+}
+
+/// An expired listing in the money whose auto-exercise couldn't settle and lapsed instead, see
+/// `settle_expired`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsettledListing {
+    pub listing_id: u32,
+    pub error: ExchangeError, // why its exercises couldn't settle
+}
+
+/// A strategy order checked and priced, with the balance changes of every leg staged in order
+struct StagedStrategy {
+    quote: StrategyQuote,
+    legs: Vec<PortfolioLeg>, // option legs, the written ones negative
+    transaction: BalanceTransaction,
+}
+
 pub struct Exchange {
     pub users: HashMap<Address, User>,
     pub escrow_user: User,
+    pub treasury: User, // the exchange's share of trading fees, see `withdraw_treasury`
+    pub insurance_fund: User, // pays what liquidated margin accounts can't, funded by their penalties and fees
     pub listings: HashMap<u32, ListingOption>,
     pub next_listing_id: u32,
+    pub positions: HashMap<u32, HashMap<Address, Position>>, // map from listing id to each holder's position
+    pub resale_listings: HashMap<u32, ResaleListing>, // map from listing id to the beneficiary's ask
+    pub bids: HashMap<u32, OptionBid>,
+    pub next_bid_id: u32,
+    pub orders: HashMap<u32, Order>,
+    pub next_order_id: u32,
+    pub fills: Vec<Fill>, // every purchase of listed contracts, oldest first
+    pub auto_exercises: Vec<AutoExercise>, // every exercise the exchange made at expiry, oldest first
+    pub liquidations: Vec<Liquidation>,    // every margin account closed out, oldest first
+    pub fund_reports: Vec<FundReport>, // treasury and insurance fund balances as recorded, oldest first
+    pub strategy_fills: Vec<StrategyFill>, // every strategy order executed, oldest first
+    pub next_strategy_id: u32,
+    pub spot_books: HashMap<AssetPair, SpotOrderBook>,
+    pub next_spot_order_id: u32,
+    pub pools: HashMap<AssetPair, LiquidityPool>,
+    pub margin_accounts: HashMap<Address, MarginAccount>, // map from writer to the collateral they write on margin with
 
     pub beneficiary_fee_bps: u16, // basis points (10_000 for 100%)
     pub grantor_fee_bps: u16,
+    pub insurance_fee_share_bps: u16, // of every fee, paid into the insurance fund instead of the treasury
+    pub pool_fee_bps: u16, // taken from every swap input and left in the pool for its providers
+    pub max_rate_age: Option<Duration>, // reject spot trades on rates older than this, None to disable
+    pub exercise_window: Duration, // how long European and Bermudan exercise windows stay open before their date
+    pub auto_exercise_threshold_bps: Option<u16>, // exercise at expiry when this far in the money (bps of strike), None to disable
+    pub spot_rate_feed: bool, // push the last spot trade price of a pair into the rate provider
+    pub margin_params: MarginParams,
+    pub portfolio_margin_params: PortfolioMarginParams, // shocks accounts on portfolio margin are revalued under
+    pub liquidation_penalty_bps: u16, // of a liquidated account's maintenance requirement
 
     pub market_admin_address: Address,
     pub role_authorizer: RoleAuthorizer,
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for Exchange {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Exchange {
     pub fn new() -> Exchange {
+        Exchange::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Create an exchange whose time-dependent checks all go through `clock`
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Exchange {
         let exchange_admin_addr = default_exchange_admin_address();
 
         let mut exchange = Exchange {
             users: HashMap::new(),
             escrow_user: User::new(default_escrow_address()),
+            treasury: User::new(default_treasury_address()),
+            insurance_fund: User::new(default_insurance_fund_address()),
             listings: HashMap::new(),
             next_listing_id: 1,
-            beneficiary_fee_bps: 10, // default to 0.1%
-            grantor_fee_bps: 10,     // default to 0.1%
+            positions: HashMap::new(),
+            resale_listings: HashMap::new(),
+            bids: HashMap::new(),
+            next_bid_id: 1,
+            orders: HashMap::new(),
+            next_order_id: 1,
+            fills: Vec::new(),
+            auto_exercises: Vec::new(),
+            liquidations: Vec::new(),
+            fund_reports: Vec::new(),
+            strategy_fills: Vec::new(),
+            next_strategy_id: 1,
+            spot_books: HashMap::new(),
+            next_spot_order_id: 1,
+            pools: HashMap::new(),
+            margin_accounts: HashMap::new(),
+            beneficiary_fee_bps: 10,        // default to 0.1%
+            grantor_fee_bps: 10,            // default to 0.1%
+            insurance_fee_share_bps: 1_000, // default to 10%
+            pool_fee_bps: 30,               // default to 0.3%
+            max_rate_age: None,
+            exercise_window: Duration::hours(24),
+            auto_exercise_threshold_bps: Some(0), // anything in the money
+            spot_rate_feed: false,
+            margin_params: MarginParams::default(),
+            portfolio_margin_params: PortfolioMarginParams::default(),
+            liquidation_penalty_bps: 500, // default to 5%
             market_admin_address: Address::from("0x953674f672475ec0A1aBE55156400c6F0086E90a")
                 .expect("failed to initialize market admin address"),
 
             // Init RBAC authorizer (TODO: refactor to make a dedicated service handle auth in v2)
-            role_authorizer: RoleAuthorizer::new(exchange_admin_addr),
+            role_authorizer: RoleAuthorizer::new(exchange_admin_addr.clone()),
+            clock,
         };
 
         let escrow = std::mem::replace(
@@ -63,57 +237,153 @@ impl Exchange {
         );
         exchange.users.insert(escrow.address.clone(), escrow);
 
-        return exchange;
+        // The exchange admin withdraws from the treasury until the role manager hands it on
+        let treasurer_role = NamedRole("Treasurer".to_string());
+        exchange
+            .role_authorizer
+            .make_role_known(treasurer_role.clone(), exchange_admin_addr.clone())
+            .expect("Panic: role manager of exchange should be able to add treasurer role, unless sth's wrong with the setup");
+        exchange
+            .role_authorizer
+            .assign_role(treasurer_role, exchange_admin_addr.clone(), exchange_admin_addr)
+            .expect("Panic: role manager of exchange should be able to assign treasurer role, unless sth's wrong with the setup");
+
+        exchange
+    }
+
+    /* Getter funcs */
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Whether the listing's exercise style lets holders exercise it right now
+    pub fn can_exercise(&self, option: &ListingOption) -> bool {
+        option.can_exercise_at(self.now(), self.exercise_window)
     }
 
-    /** Getter funcs */
-    pub fn get_user_or_error(&mut self, user_address: &Address) -> Result<&mut User, String> {
+    pub fn get_user_or_error(
+        &mut self,
+        user_address: &Address,
+    ) -> Result<&mut User, ExchangeError> {
         self.users
-            .get_mut(&user_address)
-            .ok_or_else(|| String::from("User not found"))
+            .get_mut(user_address)
+            .ok_or_else(|| ExchangeError::UserNotFound(user_address.clone()))
     }
 
-    pub fn get_user_or_error_immutable(&self, user_address: &Address) -> Result<&User, String> {
+    pub fn get_user_or_error_immutable(
+        &self,
+        user_address: &Address,
+    ) -> Result<&User, ExchangeError> {
         self.users
-            .get(&user_address)
-            .ok_or_else(|| String::from("User not found"))
+            .get(user_address)
+            .ok_or_else(|| ExchangeError::UserNotFound(user_address.clone()))
     }
 
-    pub fn get_listing_or_error(&mut self, listing_id: u32) -> Result<&mut ListingOption, String> {
+    pub fn get_listing_or_error(
+        &mut self,
+        listing_id: u32,
+    ) -> Result<&mut ListingOption, ExchangeError> {
         self.listings
             .get_mut(&listing_id)
-            .ok_or_else(|| String::from("Listing not found"))
+            .ok_or(ExchangeError::ListingNotFound(listing_id))
     }
 
     pub fn get_listing_or_error_immutable(
         &self,
         listing_id: u32,
-    ) -> Result<&ListingOption, String> {
+    ) -> Result<&ListingOption, ExchangeError> {
         self.listings
             .get(&listing_id)
-            .ok_or_else(|| String::from("Listing not found"))
+            .ok_or(ExchangeError::ListingNotFound(listing_id))
+    }
+
+    pub fn get_position(&self, listing_id: u32, holder_address: &Address) -> Option<&Position> {
+        self.positions
+            .get(&listing_id)
+            .and_then(|holders| holders.get(holder_address))
+    }
+
+    /// Contracts of a listing that were sold and not yet exercised
+    pub fn get_open_contracts(&self, listing_id: u32) -> u32 {
+        self.positions
+            .get(&listing_id)
+            .map_or(0, |holders| holders.values().map(|p| p.contracts).sum())
+    }
+
+    pub fn get_pool_or_error(&self, pair: &AssetPair) -> Result<&LiquidityPool, ExchangeError> {
+        self.pools
+            .get(pair)
+            .ok_or_else(|| ExchangeError::PoolNotFound(pair.clone()))
+    }
+
+    pub fn get_margin_account_or_error(
+        &self,
+        owner: &Address,
+    ) -> Result<&MarginAccount, ExchangeError> {
+        self.margin_accounts
+            .get(owner)
+            .ok_or_else(|| ExchangeError::MarginAccountNotFound(owner.clone()))
     }
 
-    pub fn get_beneficiary_fee(&self, premium_price: f64) -> f64 {
-        premium_price * (self.beneficiary_fee_bps as f64) / (MAX_FEE_BPS as f64)
+    /// Price implied by a pool's reserves, None without a funded pool
+    pub fn get_pool_price(&self, pair: &AssetPair) -> Option<Price> {
+        self.pools.get(pair).and_then(|pool| pool.get_price())
     }
 
-    pub fn get_grantor_fee(&self, premium_price: f64) -> f64 {
-        premium_price * (self.grantor_fee_bps as f64) / (MAX_FEE_BPS as f64)
+    /// What swapping `amount_in` through a pool would pay out right now, after the pool fee
+    pub fn get_swap_output(
+        &self,
+        pair: &AssetPair,
+        side: SpotAction,
+        amount_in: Amount,
+    ) -> Result<Amount, ExchangeError> {
+        Ok(self
+            .get_pool_or_error(pair)?
+            .quote_swap(side, amount_in, self.pool_fee_bps)?)
+    }
+
+    /// Fees are collected by the exchange, so they round up
+    pub fn get_beneficiary_fee(&self, premium_price: Amount) -> Result<Amount, AmountError> {
+        premium_price.try_mul_bps(self.beneficiary_fee_bps, Rounding::Up)
+    }
+
+    pub fn get_grantor_fee(&self, premium_price: Amount) -> Result<Amount, AmountError> {
+        premium_price.try_mul_bps(self.grantor_fee_bps, Rounding::Up)
+    }
+
+    /// Credit a fee debited elsewhere in `transaction`: `insurance_fee_share_bps` of it to the
+    /// insurance fund, rounded down, and the rest to the treasury
+    fn collect_fee(
+        &self,
+        transaction: &mut BalanceTransaction,
+        asset: &Asset,
+        fee: Amount,
+    ) -> Result<(), ExchangeError> {
+        let insurance_share = fee
+            .try_mul_bps(self.insurance_fee_share_bps, Rounding::Down)?
+            .round_to_asset(asset, Rounding::Down);
+        transaction
+            .credit(Account::InsuranceFund, asset, insurance_share)
+            .credit(Account::Treasury, asset, fee.try_sub(insurance_share)?);
+        Ok(())
     }
-    /** */
+    /* */
 
-    /** Setters */
+    /* Setters */
     pub fn set_beneficiary_fee_bps(
         &mut self,
         new_bps: u16,
         caller_address: Address,
-    ) -> Result<(), String> {
+    ) -> Result<(), ExchangeError> {
         if !are_addresses_equal(&caller_address, &self.market_admin_address) {
-            return Err("Only market admin only".into());
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
         }
-        if new_bps > 10_000 {
-            return Err("Invalid bps, must be between 0 - 10.000".into());
+        if new_bps > MAX_FEE_BPS {
+            return Err(ExchangeError::InvalidInput(
+                "Invalid bps, must be between 0 - 10.000".into(),
+            ));
         }
         self.beneficiary_fee_bps = new_bps;
 
@@ -124,299 +394,3562 @@ impl Exchange {
         &mut self,
         new_bps: u16,
         caller_address: Address,
-    ) -> Result<(), String> {
+    ) -> Result<(), ExchangeError> {
         if !are_addresses_equal(&caller_address, &self.market_admin_address) {
-            return Err("Only market admin only".into());
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
         }
-        if new_bps > 10_000 {
-            return Err("Invalid bps, must be between 0 - 10.000".into());
+        if new_bps > MAX_FEE_BPS {
+            return Err(ExchangeError::InvalidInput(
+                "Invalid bps, must be between 0 - 10.000".into(),
+            ));
         }
         self.grantor_fee_bps = new_bps;
 
         Ok(())
     }
 
-    pub fn list_option(
+    pub fn set_pool_fee_bps(
         &mut self,
+        new_bps: u16,
         caller_address: Address,
-        option: ListingOption,
-    ) -> Result<u32, String> {
-        // Get a mutable reference to the seller (we already checked it exists)
-        let grantor = self.get_user_or_error(&caller_address)?;
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(&caller_address, &self.market_admin_address) {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
+        }
+        if new_bps > MAX_FEE_BPS {
+            return Err(ExchangeError::InvalidInput(
+                "Invalid bps, must be between 0 - 10.000".into(),
+            ));
+        }
+        self.pool_fee_bps = new_bps;
 
-        let (sell_amount, sell_asset) =
-            { (option.get_sell_amount(true), option.get_sell_asset(true)) };
+        Ok(())
+    }
 
-        let sell_asset_balance = grantor.get_balance(&sell_asset);
-        if sell_asset_balance < sell_amount {
-            return Err(format!(
-                "Insufficient {} asset balance to cover {} option",
-                sell_asset, option.listing_type
+    /// Let the last traded price of each spot pair update the rate provider, off by default
+    pub fn set_spot_rate_feed(
+        &mut self,
+        enabled: bool,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(&caller_address, &self.market_admin_address) {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
             ));
         }
+        self.spot_rate_feed = enabled;
 
-        grantor.deduct_asset(sell_asset, sell_amount)?;
-        self.escrow_user.add_asset(sell_asset, sell_amount)?;
+        Ok(())
+    }
 
-        // store into listings
-        let listing_id = self.next_listing_id;
-        self.next_listing_id += 1;
-        let mut option_with_id = option;
-        option_with_id.listing_id = listing_id;
-        self.listings.insert(listing_id, option_with_id);
+    pub fn set_max_rate_age(
+        &mut self,
+        max_rate_age: Option<Duration>,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(&caller_address, &self.market_admin_address) {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
+        }
+        self.max_rate_age = max_rate_age;
 
-        Ok(listing_id)
+        Ok(())
     }
 
-    /// Unlist a previously listed option
-    pub fn unlist_option(
+    pub fn set_auto_exercise_threshold_bps(
         &mut self,
-        listing_id: u32,
+        threshold_bps: Option<u16>,
         caller_address: Address,
-    ) -> Result<(), String> {
-        let listing_immut = self
-            .listings
-            .get(&listing_id)
-            .ok_or_else(|| String::from("Listing not found"))?;
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(&caller_address, &self.market_admin_address) {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
+        }
+        if threshold_bps.is_some_and(|bps| bps > MAX_FEE_BPS) {
+            return Err(ExchangeError::InvalidInput(
+                "Invalid bps, must be between 0 - 10.000".into(),
+            ));
+        }
+        self.auto_exercise_threshold_bps = threshold_bps;
+
+        Ok(())
+    }
 
-        if !are_addresses_equal(&caller_address, &listing_immut.grantor_address) {
-            return Err("Only the seller can unlist this option".into());
+    /// Margin rates apply to every margin account at once, including contracts already written
+    pub fn set_margin_params(
+        &mut self,
+        margin_params: MarginParams,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(&caller_address, &self.market_admin_address) {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
+        }
+        if margin_params.initial_margin_bps > MAX_FEE_BPS {
+            return Err(ExchangeError::InvalidInput(
+                "Invalid bps, must be between 0 - 10.000".into(),
+            ));
+        }
+        if margin_params.maintenance_margin_bps > margin_params.initial_margin_bps
+            || margin_params.minimum_margin_bps > margin_params.maintenance_margin_bps
+        {
+            return Err(ExchangeError::InvalidInput(
+                "Margin rates must go from initial down to maintenance down to minimum".into(),
+            ));
         }
+        self.margin_params = margin_params;
 
-        match listing_immut.beneficiary_address.as_ref() {
-            Some(address) => {
-                println!("Beneficiary address exists: {:?}, cannot unlist", address);
-                return Err("Option has been acquired, cannot unlist".into());
-            }
-            None => println!("Option is not acquired, can unlist"),
+        Ok(())
+    }
+
+    /// Shocks apply to every account on portfolio margin at once, including contracts already
+    /// written
+    pub fn set_portfolio_margin_params(
+        &mut self,
+        params: PortfolioMarginParams,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(&caller_address, &self.market_admin_address) {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
         }
-        // immutable borrow of listing ends here
+        let pricing_params = params.pricing_params;
+        if !pricing_params.volatility.is_finite()
+            || pricing_params.volatility <= 0.0
+            || !pricing_params.risk_free_rate.is_finite()
+        {
+            return Err(ExchangeError::InvalidInput(
+                "Volatility must be positive and the risk-free rate finite".into(),
+            ));
+        }
+        let is_valid_shock = |shock: &f64| shock.is_finite() && *shock > -1.0;
+        if params.vol_shocks.is_empty() || !params.vol_shocks.iter().all(is_valid_shock) {
+            return Err(ExchangeError::InvalidInput(
+                "Volatility shocks must be given and stay above -100%".into(),
+            ));
+        }
+        self.portfolio_margin_params = params;
 
-        // Remove the listing option to take ownership (no longer have to borrow afterwards)
-        let option = self
-            .listings
-            .remove(&listing_id)
-            .ok_or_else(|| String::from("Listing not found"))?;
+        Ok(())
+    }
+
+    pub fn set_insurance_fee_share_bps(
+        &mut self,
+        share_bps: u16,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(&caller_address, &self.market_admin_address) {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
+        }
+        if share_bps > MAX_FEE_BPS {
+            return Err(ExchangeError::InvalidInput(
+                "Invalid bps, must be between 0 - 10.000".into(),
+            ));
+        }
+        self.insurance_fee_share_bps = share_bps;
 
-        let sell_amount = option.get_sell_amount(true);
-        let sell_asset = option.get_sell_asset(true);
+        Ok(())
+    }
 
-        self.escrow_user.deduct_asset(sell_asset, sell_amount)?;
-        let grantor = self.get_user_or_error(&caller_address)?;
-        grantor.add_asset(sell_asset, sell_amount)?;
+    pub fn set_liquidation_penalty_bps(
+        &mut self,
+        penalty_bps: u16,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(&caller_address, &self.market_admin_address) {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
+        }
+        if penalty_bps > MAX_FEE_BPS {
+            return Err(ExchangeError::InvalidInput(
+                "Invalid bps, must be between 0 - 10.000".into(),
+            ));
+        }
+        self.liquidation_penalty_bps = penalty_bps;
 
         Ok(())
     }
 
-    pub fn purchase_option(
+    pub fn set_exercise_window(
         &mut self,
-        listing_id: u32,
-        beneficiary_address: Address,
-    ) -> Result<(), String> {
-        // Borrow listing immutably to compute prices and fees.
-        let (premium_price, quote_asset, grantor_address, beneficiary_fee, grantor_fee) = {
-            let option = self.get_listing_or_error_immutable(listing_id)?;
+        exercise_window: Duration,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(&caller_address, &self.market_admin_address) {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not the market admin".into(),
+            ));
+        }
+        if exercise_window <= Duration::zero() {
+            return Err(ExchangeError::InvalidInput(
+                "Exercise window must be longer than zero".into(),
+            ));
+        }
+        self.exercise_window = exercise_window;
 
-            // Exhaustive state transition check
-            match (option.is_purchased, option.is_unlisted, option.is_exercised) {
-                // Valid case first
-                (false, false, false) => {}
-                (true, _, _) => return Err("Option already purchased!".into()),
-                (_, true, _) => return Err("Option has been unlisted!".into()),
-                (_, _, true) => return Err("Option has already been exercised!".into()),
-            }
+        Ok(())
+    }
 
-            let premium_price = option.get_premium_price();
-            let beneficiary_fee = self.get_beneficiary_fee(premium_price);
-            let grantor_fee = self.get_grantor_fee(premium_price);
-            // clone fields needed later while we still have the immutable borrow
-            (
-                premium_price,
-                option.quote_asset.clone(),
-                option.grantor_address.clone(),
-                beneficiary_fee,
-                grantor_fee,
-            )
-        };
+    pub fn list_option(
+        &mut self,
+        caller_address: Address,
+        option: ListingOption,
+    ) -> Result<u32, ExchangeError> {
+        self.check_listing(&caller_address, &option)?;
 
-        // Compute amounts
-        let amt_from_beneficiary = premium_price + beneficiary_fee;
-        let amt_to_grantor = premium_price - grantor_fee;
+        if self.margin_accounts.contains_key(&caller_address) {
+            // The margin account must cover every contract offered on top of what it already wrote
+            let pending = [PortfolioLeg {
+                contracts: -i64::from(option.contract_count),
+                option: option.clone(),
+            }];
+            self.check_margin(&caller_address, &option.quote_asset, &pending, false)?;
+        } else {
+            // Collateral covers every contract offered
+            let mut transaction = BalanceTransaction::new();
+            self.stage_listing_collateral(&mut transaction, &caller_address, &option)?;
+            self.apply(transaction)?;
+        }
 
-        // Deduct from beneficiary and collect fee
-        {
-            let beneficiary = self.get_user_or_error(&beneficiary_address)?;
-            if beneficiary.get_balance(&quote_asset) < amt_from_beneficiary {
-                return Err(format!(
-                    "Buyer doesn't have enough {} balance to purchase option",
-                    quote_asset
+        let listing_id = self.store_listing(&caller_address, option);
+
+        // Buyers already bidding on the series take the new contracts first
+        self.match_if_live(listing_id)?;
+
+        Ok(listing_id)
+    }
+
+    /// Reject an option the caller can't list, before anything is escrowed
+    fn check_listing(
+        &self,
+        caller_address: &Address,
+        option: &ListingOption,
+    ) -> Result<(), ExchangeError> {
+        if !are_addresses_equal(caller_address, &option.grantor_address) {
+            return Err(ExchangeError::Unauthorized(
+                "Only the grantor can list an option".into(),
+            ));
+        }
+
+        if option.state != OptionState::Listed {
+            return Err(ExchangeError::InvalidState {
+                listing_id: option.listing_id,
+                state: option.state,
+            });
+        }
+
+        if option.expiration_time <= self.now() {
+            return Err(ExchangeError::InvalidInput(
+                "Expiration time has already passed".into(),
+            ));
+        }
+
+        if option.contract_count == 0 {
+            return Err(ExchangeError::InvalidInput(
+                "Contract count must be at least 1".into(),
+            ));
+        }
+
+        if !option.exercise_amount.fits_asset(&option.base_asset) {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Exercise amount has more than {} decimals for {}",
+                option.base_asset.decimals(),
+                option.base_asset
+            )));
+        }
+
+        if let ExerciseStyle::Bermudan(dates) = &option.exercise_style {
+            if dates.is_empty() {
+                return Err(ExchangeError::InvalidInput(
+                    "Bermudan options need at least one exercise date".into(),
+                ));
+            }
+            if dates.iter().any(|date| *date > option.expiration_time) {
+                return Err(ExchangeError::InvalidInput(
+                    "Bermudan exercise dates can't fall after expiry".into(),
                 ));
             }
-            beneficiary.deduct_asset(&quote_asset, amt_from_beneficiary)?;
         }
-        self.escrow_user.add_asset(&quote_asset, beneficiary_fee)?;
 
-        // Dispatch money to grantor and collect fee
+        if self.margin_accounts.contains_key(caller_address)
+            && option.settlement_type != SettlementType::Cash
         {
-            let grantor = self.get_user_or_error(&grantor_address)?;
-            grantor.add_asset(&quote_asset, amt_to_grantor)?;
+            return Err(ExchangeError::InvalidInput(
+                "Options written on margin must be cash-settled".into(),
+            ));
         }
-        self.escrow_user.add_asset(&quote_asset, grantor_fee)?;
 
-        // Mutate the listing (no other borrows active)
-        let option = self.get_listing_or_error(listing_id)?;
-        option.beneficiary_address = Some(beneficiary_address);
-        option.is_purchased = true;
+        Ok(())
+    }
 
+    /// Stage the collateral a listing not written on margin escrows for every contract offered
+    fn stage_listing_collateral(
+        &self,
+        transaction: &mut BalanceTransaction,
+        caller_address: &Address,
+        option: &ListingOption,
+    ) -> Result<(), ExchangeError> {
+        let sell_amount =
+            ListingOption::for_contracts(option.get_sell_amount(true)?, option.contract_count)?;
+        transaction.transfer(
+            Account::User(caller_address.clone()),
+            Account::Escrow,
+            option.get_sell_asset(true),
+            sell_amount,
+        );
         Ok(())
     }
 
-    pub fn exercise_option(
+    /// Store a listing whose collateral is in place under the next listing id
+    fn store_listing(&mut self, caller_address: &Address, option: ListingOption) -> u32 {
+        let listing_id = self.next_listing_id;
+        self.next_listing_id += 1;
+        let mut option_with_id = option;
+        option_with_id.listing_id = listing_id;
+        if let ExerciseStyle::Bermudan(dates) = &mut option_with_id.exercise_style {
+            dates.sort();
+            dates.dedup();
+        }
+        self.listings.insert(listing_id, option_with_id);
+        if let Some(account) = self.margin_accounts.get_mut(caller_address) {
+            account.listing_ids.push(listing_id);
+        }
+        listing_id
+    }
+
+    /// Match resting bids against a new listing unless it already expired
+    fn match_if_live(&mut self, listing_id: u32) -> Result<(), ExchangeError> {
+        let expiration_time = self
+            .get_listing_or_error_immutable(listing_id)?
+            .expiration_time;
+        if self.now() <= expiration_time {
+            self.match_resting_bids(listing_id)?;
+        }
+        Ok(())
+    }
+
+    /// Unlist the unsold contracts of a listing, refunding their share of the collateral.
+    /// A listing nobody bought from is removed, sold contracts stay with their holders.
+    /// Listings written on margin have nothing to refund, their requirement drops instead.
+    pub fn unlist_option(
         &mut self,
         listing_id: u32,
         caller_address: Address,
-    ) -> Result<(), String> {
-        // Immutable borrow
-        let (buy_amount, buy_asset, sell_amount, sell_asset, grantor_address) = {
-            let option_immut = self.get_listing_or_error_immutable(listing_id)?;
+    ) -> Result<(), ExchangeError> {
+        let (refund_amount, refund_asset, state) = {
+            let listing_immut = self.get_listing_or_error_immutable(listing_id)?;
 
-            let is_beneficiary = are_addresses_equal(
-                &caller_address,
-                option_immut
-                    .beneficiary_address
-                    .as_ref()
-                    .expect("panic: listed option doesn't have beneficiary address"),
-            );
+            if !are_addresses_equal(&caller_address, &listing_immut.grantor_address) {
+                return Err(ExchangeError::Unauthorized(
+                    "only the seller can unlist this option".into(),
+                ));
+            }
 
-            let now: DateTime<Utc> = Utc::now();
-            let is_expired = now > option_immut.expiration_time;
-
-            // Exhaustive state validity check
-            match (
-                option_immut.is_purchased,
-                option_immut.is_unlisted,
-                option_immut.is_exercised,
-                is_beneficiary,
-                is_expired,
-            ) {
-                // Valid case first
-                (true, false, false, true, false) => {}
-                (false, _, _, true, _) => {
-                    panic!("panic: option has beneficiary but isn't purchased!")
-                }
-                (_, _, _, _, true) => return Err("Option has expired!".into()),
-                (_, _, _, false, _) => return Err("Caller is not beneficiary of option!".into()),
-                (_, true, _, _, _) => return Err("Option has been unlisted!".into()),
-                (_, _, true, _, _) => return Err("Option has already been exercised!".into()),
+            // Withdrawing the unsold remainder of a partly sold listing doesn't change its state
+            let is_partial =
+                listing_immut.state == OptionState::Purchased && listing_immut.contract_count > 0;
+            if !is_partial {
+                listing_immut.check_transition(OptionState::Unlisted)?;
             }
 
             (
-                option_immut.get_buy_amount(false),
-                option_immut.get_buy_asset(false).clone(),
-                option_immut.get_sell_amount(false),
-                option_immut.get_sell_asset(false).clone(),
-                // clone grantor and beneficiary addresses so we don't return references into `self`
-                option_immut.grantor_address.clone(),
+                self.get_escrowed_collateral(listing_immut, listing_immut.contract_count)?,
+                listing_immut.get_sell_asset(true).clone(),
+                listing_immut.state,
             )
         };
+        // immutable borrow of listing ends here
 
-        // Transfer buy asset from escrow to beneficiary (base if CALL, quote if PUT)
-        {
-            self.escrow_user.deduct_asset(&buy_asset, buy_amount)?;
-        }
-        {
-            self.get_user_or_error(&caller_address)?
-                .add_asset(&buy_asset, buy_amount)?;
-        }
+        let mut transaction = BalanceTransaction::new();
+        transaction.transfer(
+            Account::Escrow,
+            Account::User(caller_address.clone()),
+            &refund_asset,
+            refund_amount,
+        );
+        self.apply(transaction)?;
 
-        // Transfer sell asset from beneficiary to grantor (quote if CALL, base if PUT)
-        {
-            self.get_user_or_error(&caller_address)?
-                .deduct_asset(&sell_asset, sell_amount)?;
-        }
-        {
-            let grantor = self.get_user_or_error(&grantor_address)?;
-            grantor.add_asset(&sell_asset, sell_amount)?;
+        if state == OptionState::Listed {
+            self.listings.remove(&listing_id);
+            // nothing of it is left for the margin account to cover
+            if let Some(account) = self.margin_accounts.get_mut(&caller_address) {
+                account.listing_ids.retain(|id| *id != listing_id);
+                for group in &mut account.defined_risk {
+                    group.written.retain(|id| *id != listing_id);
+                }
+            }
+        } else {
+            self.get_listing_or_error(listing_id)?.contract_count = 0;
+            self.finish_if_exhausted(listing_id)?;
         }
 
-        self.get_listing_or_error(listing_id)?.is_exercised = true;
-
         Ok(())
     }
-    // TODO: allow re-selling of acquired options contract
 
-    pub fn spot_trade_current_price(
+    /// Buy a single contract of a listing
+    pub fn purchase_option(
         &mut self,
-        base_asset: &Asset,
-        quote_asset: &Asset,
-        base_amount: f64,
-        action: &SpotAction,
-        caller_address: Address,
-    ) -> Result<(), String> {
-        let (buyer_addr, seller_addr) = match action {
-            SpotAction::BUY => (caller_address, self.escrow_user.address.clone()),
-            SpotAction::SELL => (self.escrow_user.address.clone(), caller_address),
-        };
-
-        self._spot_trade_current_price(
-            base_asset,
-            quote_asset,
-            base_amount,
-            &buyer_addr,
-            &seller_addr,
-        )
+        listing_id: u32,
+        beneficiary_address: Address,
+    ) -> Result<(), ExchangeError> {
+        self.purchase_contracts(listing_id, 1, beneficiary_address)
     }
 
-    fn _spot_trade_current_price(
+    /// Buy `contracts` contracts of a listing, the rest stays listed for other buyers
+    pub fn purchase_contracts(
         &mut self,
-        base_asset: &Asset,
-        quote_asset: &Asset,
-        base_amount: f64,
-        buyer_addr: &Address,
-        seller_addr: &Address,
-    ) -> Result<(), String> {
-        let rate_provider = get_readonly_rate_provider();
-        let exchange_rate = if let Some(rate) = rate_provider.get_rate(base_asset, quote_asset) {
-            rate
+        listing_id: u32,
+        contracts: u32,
+        beneficiary_address: Address,
+    ) -> Result<(), ExchangeError> {
+        self.buy_listing(listing_id, contracts, beneficiary_address, None)
+    }
+
+    /// Buy contracts of a listing at its ask, `bid_id` tags the fill when a buy order took the ask
+    fn buy_listing(
+        &mut self,
+        listing_id: u32,
+        contracts: u32,
+        beneficiary_address: Address,
+        bid_id: Option<u32>,
+    ) -> Result<(), ExchangeError> {
+        let mut transaction = BalanceTransaction::new();
+        let ask_price = self.stage_purchase(
+            &mut transaction,
+            listing_id,
+            contracts,
+            &beneficiary_address,
+        )?;
+        self.apply(transaction)?;
+
+        self.record_purchase(
+            listing_id,
+            contracts,
+            beneficiary_address,
+            ask_price,
+            bid_id,
+        )
+    }
+
+    /// Stage the premium and fees of buying contracts of a listing at its ask, which is returned
+    fn stage_purchase(
+        &self,
+        transaction: &mut BalanceTransaction,
+        listing_id: u32,
+        contracts: u32,
+        beneficiary_address: &Address,
+    ) -> Result<Price, ExchangeError> {
+        if contracts == 0 {
+            return Err(ExchangeError::InvalidInput(
+                "Contract count must be at least 1".into(),
+            ));
+        }
+
+        // Borrow listing immutably to compute prices and fees.
+        let (premium_price, ask_price, quote_asset, grantor_address, beneficiary_fee, grantor_fee) = {
+            let option = self.get_listing_or_error_immutable(listing_id)?;
+
+            // Partly sold listings keep selling their remainder
+            if option.state != OptionState::Purchased {
+                option.check_transition(OptionState::Purchased)?;
+            }
+            if option.contract_count == 0 {
+                return Err(ExchangeError::InvalidState {
+                    listing_id,
+                    state: option.state,
+                });
+            }
+            if contracts > option.contract_count {
+                return Err(ExchangeError::InvalidInput(format!(
+                    "Only {} contracts of listing #{} are left",
+                    option.contract_count, listing_id
+                )));
+            }
+
+            let premium_price =
+                ListingOption::for_contracts(option.get_premium_price()?, contracts)?;
+            let beneficiary_fee = self
+                .get_beneficiary_fee(premium_price)?
+                .round_to_asset(&option.quote_asset, Rounding::Up);
+            let grantor_fee = self
+                .get_grantor_fee(premium_price)?
+                .round_to_asset(&option.quote_asset, Rounding::Up);
+            // clone fields needed later while we still have the immutable borrow
+            (
+                premium_price,
+                option.ask_price,
+                option.quote_asset.clone(),
+                option.grantor_address.clone(),
+                beneficiary_fee,
+                grantor_fee,
+            )
+        };
+
+        // Compute amounts
+        let amt_from_beneficiary = premium_price.try_add(beneficiary_fee)?;
+        let amt_to_grantor = premium_price.try_sub(grantor_fee)?;
+
+        // Deduct from beneficiary and collect fee
+        transaction.debit(
+            Account::User(beneficiary_address.clone()),
+            &quote_asset,
+            amt_from_beneficiary,
+        );
+        self.collect_fee(transaction, &quote_asset, beneficiary_fee)?;
+        // Dispatch money to grantor and collect fee
+        transaction.credit(Account::User(grantor_address), &quote_asset, amt_to_grantor);
+        self.collect_fee(transaction, &quote_asset, grantor_fee)?;
+
+        Ok(ask_price)
+    }
+
+    /// Premium plus buyer fee charged for `contracts` contracts of a listing at its ask
+    pub fn get_purchase_cost(
+        &self,
+        listing_id: u32,
+        contracts: u32,
+    ) -> Result<Amount, ExchangeError> {
+        let option = self.get_listing_or_error_immutable(listing_id)?;
+        let premium_price = ListingOption::for_contracts(option.get_premium_price()?, contracts)?;
+        let beneficiary_fee = self
+            .get_beneficiary_fee(premium_price)?
+            .round_to_asset(&option.quote_asset, Rounding::Up);
+        Ok(premium_price.try_add(beneficiary_fee)?)
+    }
+
+    /// Move `contracts` sold contracts of a listing into the holder's position once they are paid for,
+    /// and record the trade as a fill at `price` per contract
+    fn record_purchase(
+        &mut self,
+        listing_id: u32,
+        contracts: u32,
+        holder_address: Address,
+        price: Price,
+        bid_id: Option<u32>,
+    ) -> Result<(), ExchangeError> {
+        let time = self.now();
+
+        // Mutate the listing (no other borrows active)
+        let option = self.get_listing_or_error(listing_id)?;
+        if option.state != OptionState::Purchased {
+            option.transition_to(OptionState::Purchased)?;
+        }
+        option.contract_count -= contracts;
+        let seller_address = option.grantor_address.clone();
+
+        self.positions
+            .entry(listing_id)
+            .or_default()
+            .entry(holder_address.clone())
+            .or_insert_with(|| Position::new(listing_id, holder_address.clone()))
+            .contracts += contracts;
+
+        self.fills.push(Fill {
+            listing_id,
+            bid_id,
+            buyer_address: holder_address,
+            seller_address,
+            contracts,
+            price,
+            time,
+        });
+
+        Ok(())
+    }
+
+    /// Exercise every open contract the caller holds on a listing
+    pub fn exercise_option(
+        &mut self,
+        listing_id: u32,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let contracts = self
+            .get_position(listing_id, &caller_address)
+            .map_or(0, |position| position.contracts);
+        self.exercise_contracts(listing_id, contracts, caller_address)
+    }
+
+    /// Exercise part of the caller's position. Escrow and grantor transfers are pro-rata
+    /// to `contracts`, the rest of the position stays exercisable until expiry. Cash-settled
+    /// listings pay their intrinsic value out of their collateral at the provider's current
+    /// rate, in quote as far as the spot desk can buy a CALL's base, see
+    /// `get_cash_settlement_amount`.
+    pub fn exercise_contracts(
+        &mut self,
+        listing_id: u32,
+        contracts: u32,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        // Immutable borrow
+        let transaction = {
+            let option_immut = self.get_listing_or_error_immutable(listing_id)?;
+
+            let now: DateTime<Utc> = self.now();
+            if option_immut.state == OptionState::Expired || now > option_immut.expiration_time {
+                return Err(ExchangeError::Expired(listing_id));
+            }
+            if !option_immut.can_exercise_at(now, self.exercise_window) {
+                return Err(ExchangeError::OutsideExerciseWindow {
+                    listing_id,
+                    opens_at: option_immut
+                        .next_exercise_time(now, self.exercise_window)
+                        .unwrap_or(option_immut.expiration_time),
+                });
+            }
+
+            let position = self
+                .get_position(listing_id, &caller_address)
+                .ok_or_else(|| {
+                    ExchangeError::Unauthorized("caller is not beneficiary of option".into())
+                })?;
+
+            option_immut.check_transition(OptionState::Exercised)?;
+            if position.contracts == 0 {
+                return Err(ExchangeError::InvalidInput(format!(
+                    "No open contracts left to exercise on listing #{}",
+                    listing_id
+                )));
+            }
+            if contracts == 0 {
+                return Err(ExchangeError::InvalidInput(
+                    "Contract count must be at least 1".into(),
+                ));
+            }
+            if contracts > position.contracts {
+                return Err(ExchangeError::InvalidInput(format!(
+                    "Only {} open contracts left to exercise on listing #{}",
+                    position.contracts, listing_id
+                )));
+            }
+            self.check_position_release(&caller_address, listing_id, contracts)?;
+
+            let mut transaction = BalanceTransaction::new();
+            self.stage_exercise(&mut transaction, option_immut, contracts, &caller_address)?;
+            transaction
+        };
+        self.apply(transaction)?;
+
+        self.record_exercise(listing_id, &caller_address, contracts);
+        self.finish_if_exhausted(listing_id)?;
+
+        Ok(())
+    }
+
+    /// Stage the transfers that exercise `contracts` of the holder's contracts under the
+    /// listing's settlement type, without checking whether the holder may exercise them
+    fn stage_exercise(
+        &self,
+        transaction: &mut BalanceTransaction,
+        option: &ListingOption,
+        contracts: u32,
+        holder_address: &Address,
+    ) -> Result<(), ExchangeError> {
+        let grantor_address = option.grantor_address.clone();
+        if self.is_margined(option) {
+            // Written on margin: the intrinsic value comes out of the writer's margin collateral,
+            // the insurance fund covers what it can't, the same way liquidation does
+            let spot = self.settlement_rate(option)?;
+            let payout =
+                ListingOption::for_contracts(option.get_intrinsic_value(spot)?, contracts)?;
+            let margin = Account::Margin(grantor_address.clone());
+            let collateral = self.staged_balance(transaction, &margin, &option.quote_asset)?;
+            let shortfall = payout.try_sub(collateral)?.max(Amount::ZERO);
+            if !shortfall.is_zero() {
+                let fund =
+                    self.staged_balance(transaction, &Account::InsuranceFund, &option.quote_asset)?;
+                if fund < shortfall {
+                    return Err(ExchangeError::WriterShortfall {
+                        listing_id: option.listing_id,
+                        writer: grantor_address,
+                        asset: option.quote_asset.clone(),
+                        shortfall,
+                        covered: fund,
+                    });
+                }
+                transaction.transfer(
+                    Account::InsuranceFund,
+                    margin.clone(),
+                    &option.quote_asset,
+                    shortfall,
+                );
+            }
+            transaction.transfer(
+                margin,
+                Account::User(holder_address.clone()),
+                &option.quote_asset,
+                payout,
+            );
+            return Ok(());
+        }
+        match option.settlement_type {
+            SettlementType::Physical => {
+                // Transfer buy asset from escrow to beneficiary (base if CALL, quote if PUT)
+                transaction.transfer(
+                    Account::Escrow,
+                    Account::User(holder_address.clone()),
+                    option.get_buy_asset(false),
+                    ListingOption::for_contracts(option.get_buy_amount(false)?, contracts)?,
+                );
+                // Transfer sell asset from beneficiary to grantor (quote if CALL, base if PUT)
+                transaction.transfer(
+                    Account::User(holder_address.clone()),
+                    Account::User(grantor_address),
+                    option.get_sell_asset(false),
+                    ListingOption::for_contracts(option.get_sell_amount(false)?, contracts)?,
+                );
+            }
+            SettlementType::Cash => {
+                let spot = self.settlement_rate(option)?;
+                let collateral_asset = option.get_sell_asset(true);
+                let collateral =
+                    ListingOption::for_contracts(option.get_sell_amount(true)?, contracts)?;
+                let payout =
+                    ListingOption::for_contracts(option.get_intrinsic_value(spot)?, contracts)?;
+                let collateral_used = ListingOption::for_contracts(
+                    option.get_cash_settlement_amount(spot)?,
+                    contracts,
+                )?;
+                // The beneficiary takes the intrinsic value in quote, the grantor gets the rest back
+                match option.listing_type {
+                    ListingType::CALL => {
+                        // Sell the base collateral it takes to the spot desk for the payout, or
+                        // hand the holder that base itself when the desk is short of quote
+                        let desk = Account::User(self.escrow_user.address.clone());
+                        if self.staged_balance(transaction, &desk, &option.quote_asset)? >= payout {
+                            transaction.transfer(
+                                Account::Escrow,
+                                desk.clone(),
+                                collateral_asset,
+                                collateral_used,
+                            );
+                            transaction.transfer(
+                                desk,
+                                Account::User(holder_address.clone()),
+                                &option.quote_asset,
+                                payout,
+                            );
+                        } else {
+                            transaction.transfer(
+                                Account::Escrow,
+                                Account::User(holder_address.clone()),
+                                collateral_asset,
+                                collateral_used,
+                            );
+                        }
+                    }
+                    ListingType::PUT => {
+                        transaction.transfer(
+                            Account::Escrow,
+                            Account::User(holder_address.clone()),
+                            collateral_asset,
+                            payout,
+                        );
+                    }
+                }
+                transaction.transfer(
+                    Account::Escrow,
+                    Account::User(grantor_address),
+                    collateral_asset,
+                    collateral.try_sub(collateral_used)?,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Move exercised contracts out of the holder's open position
+    fn record_exercise(&mut self, listing_id: u32, holder_address: &Address, contracts: u32) {
+        if let Some(position) = self
+            .positions
+            .get_mut(&listing_id)
+            .and_then(|holders| holders.get_mut(holder_address))
+        {
+            position.contracts -= contracts;
+            position.exercised_contracts += contracts;
+        }
+
+        // exercised rights can no longer be resold
+        if self
+            .resale_listings
+            .get(&listing_id)
+            .is_some_and(|resale| are_addresses_equal(&resale.seller_address, holder_address))
+        {
+            self.resale_listings.remove(&listing_id);
+        }
+    }
+
+    /// Provider rate a listing is settled at, a pair nobody has quoted yet sits at zero
+    fn settlement_rate(&self, option: &ListingOption) -> Result<Price, ExchangeError> {
+        let spot = self.current_rate(&option.base_asset, &option.quote_asset)?;
+        if spot.is_zero() {
+            return Err(ExchangeError::RateMissing {
+                base: option.base_asset.clone(),
+                quote: option.quote_asset.clone(),
+            });
+        }
+        Ok(spot)
+    }
+
+    /// Opt a position in or out of being exercised by the exchange at expiry, in by default
+    pub fn set_auto_exercise(
+        &mut self,
+        listing_id: u32,
+        enabled: bool,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        self.get_listing_or_error_immutable(listing_id)?;
+        let position = self
+            .positions
+            .get_mut(&listing_id)
+            .and_then(|holders| holders.get_mut(&caller_address))
+            .ok_or_else(|| {
+                ExchangeError::Unauthorized("caller is not beneficiary of option".into())
+            })?;
+        position.auto_exercise = enabled;
+
+        Ok(())
+    }
+
+    /// Mark a listing exercised once nothing is left unsold and every sold contract was exercised
+    fn finish_if_exhausted(&mut self, listing_id: u32) -> Result<(), ExchangeError> {
+        let open_contracts = self.get_open_contracts(listing_id);
+        let option = self.get_listing_or_error(listing_id)?;
+        if option.state == OptionState::Purchased
+            && option.contract_count == 0
+            && open_contracts == 0
+        {
+            option.transition_to(OptionState::Exercised)?;
+        }
+        Ok(())
+    }
+
+    /// Post an ask for all open contracts the caller holds on a purchased option.
+    /// A listing has at most one resale ask open at a time.
+    pub fn relist_purchased_option(
+        &mut self,
+        listing_id: u32,
+        ask_price: Price,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let contract_count = self.check_resellable(listing_id, &caller_address)?;
+        if ask_price.is_negative() {
+            return Err(ExchangeError::InvalidInput(
+                "Ask price must not be negative".into(),
+            ));
+        }
+        self.check_position_release(&caller_address, listing_id, contract_count)?;
+        if let Some(resale) = self.resale_listings.get(&listing_id)
+            && !are_addresses_equal(&resale.seller_address, &caller_address)
+        {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Listing #{} already has an open resale",
+                listing_id
+            )));
+        }
+
+        self.resale_listings.insert(
+            listing_id,
+            ResaleListing {
+                listing_id,
+                seller_address: caller_address,
+                contract_count,
+                ask_price,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Withdraw a previously posted resale ask
+    pub fn cancel_resale(
+        &mut self,
+        listing_id: u32,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let resale = self
+            .resale_listings
+            .get(&listing_id)
+            .ok_or(ExchangeError::ResaleNotFound(listing_id))?;
+
+        if !are_addresses_equal(&caller_address, &resale.seller_address) {
+            return Err(ExchangeError::Unauthorized(
+                "only the reseller can cancel this resale".into(),
+            ));
+        }
+
+        self.resale_listings.remove(&listing_id);
+        Ok(())
+    }
+
+    /// Buy the contracts offered by a resale ask from their current holder.
+    /// The grantor's collateral stays in escrow, only the holder changes.
+    pub fn purchase_resale(
+        &mut self,
+        listing_id: u32,
+        buyer_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let (premium_price, seller_address, contracts) = {
+            let resale = self
+                .resale_listings
+                .get(&listing_id)
+                .ok_or(ExchangeError::ResaleNotFound(listing_id))?;
+            (
+                resale.get_premium_price()?,
+                resale.seller_address.clone(),
+                resale.contract_count,
+            )
+        };
+
+        if are_addresses_equal(&buyer_address, &seller_address) {
+            return Err(ExchangeError::InvalidInput(
+                "Cannot purchase your own resale".into(),
+            ));
+        }
+
+        // The seller must still hold the offered contracts when the resale is filled
+        let held_contracts = self.check_resellable(listing_id, &seller_address)?;
+        if held_contracts < contracts {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Reseller only holds {} of the {} offered contracts",
+                held_contracts, contracts
+            )));
+        }
+        self.check_position_release(&seller_address, listing_id, contracts)?;
+
+        let quote_asset = self
+            .get_listing_or_error_immutable(listing_id)?
+            .quote_asset
+            .clone();
+        let premium_price = premium_price.round_to_asset(&quote_asset, Rounding::Up);
+        let buyer_fee = self
+            .get_beneficiary_fee(premium_price)?
+            .round_to_asset(&quote_asset, Rounding::Up);
+        let seller_fee = self
+            .get_grantor_fee(premium_price)?
+            .round_to_asset(&quote_asset, Rounding::Up);
+
+        // Compute amounts
+        let amt_from_buyer = premium_price.try_add(buyer_fee)?;
+        let amt_to_seller = premium_price.try_sub(seller_fee)?;
+
+        let mut transaction = BalanceTransaction::new();
+        // Deduct from buyer and collect fee
+        transaction.debit(
+            Account::User(buyer_address.clone()),
+            &quote_asset,
+            amt_from_buyer,
+        );
+        self.collect_fee(&mut transaction, &quote_asset, buyer_fee)?;
+        // Dispatch money to seller and collect fee
+        transaction.credit(
+            Account::User(seller_address.clone()),
+            &quote_asset,
+            amt_to_seller,
+        );
+        self.collect_fee(&mut transaction, &quote_asset, seller_fee)?;
+        self.apply(transaction)?;
+
+        // Hand the contracts over to the buyer
+        let holders = self.positions.entry(listing_id).or_default();
+        if let Some(seller_position) = holders.get_mut(&seller_address) {
+            seller_position.contracts -= contracts;
+            if seller_position.is_empty() {
+                holders.remove(&seller_address);
+            }
+        }
+        holders
+            .entry(buyer_address.clone())
+            .or_insert_with(|| Position::new(listing_id, buyer_address))
+            .contracts += contracts;
+        self.resale_listings.remove(&listing_id);
+
+        Ok(())
+    }
+
+    /// Returns the open contracts `seller_address` holds and could resell
+    fn check_resellable(
+        &self,
+        listing_id: u32,
+        seller_address: &Address,
+    ) -> Result<u32, ExchangeError> {
+        let option = self.get_listing_or_error_immutable(listing_id)?;
+
+        // only held rights can change hands
+        match option.state {
+            OptionState::Purchased => {}
+            OptionState::Expired => return Err(ExchangeError::Expired(listing_id)),
+            state => return Err(ExchangeError::InvalidState { listing_id, state }),
+        }
+
+        let held_contracts = self
+            .get_position(listing_id, seller_address)
+            .map_or(0, |position| position.contracts);
+        if held_contracts == 0 {
+            return Err(ExchangeError::Unauthorized(
+                "caller is not beneficiary of option".into(),
+            ));
+        }
+
+        if self.now() > option.expiration_time {
+            return Err(ExchangeError::Expired(listing_id));
+        }
+
+        Ok(held_contracts)
+    }
+
+    /// Bid for up to `contracts` contracts of `series` at `bid_price` per contract.
+    /// Listings already asking no more than the bid are bought right away at their ask, the rest
+    /// rests with its premium and buyer fee locked in escrow until a new listing crosses it.
+    /// Returns the bid id, which only stays in `bids` while contracts are still wanted.
+    pub fn place_bid(
+        &mut self,
+        series: OptionSeries,
+        bid_price: Price,
+        contracts: u32,
+        caller_address: Address,
+    ) -> Result<u32, ExchangeError> {
+        if contracts == 0 {
+            return Err(ExchangeError::InvalidInput(
+                "Contract count must be at least 1".into(),
+            ));
+        }
+        if bid_price.is_negative() {
+            return Err(ExchangeError::InvalidInput(
+                "Bid price must not be negative".into(),
+            ));
+        }
+        if self.now() > series.expiration_time {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Series {} has already expired",
+                series
+            )));
+        }
+
+        let premium_per_contract = bid_price
+            .try_mul_int(100)?
+            .round_to_asset(&series.quote_asset, Rounding::Up);
+        let fee_per_contract = self
+            .get_beneficiary_fee(premium_per_contract)?
+            .round_to_asset(&series.quote_asset, Rounding::Up);
+        let bid_id = self.next_bid_id;
+        let mut bid = OptionBid {
+            bid_id,
+            series,
+            bidder_address: caller_address.clone(),
+            bid_price,
+            contract_count: contracts,
+            premium_per_contract,
+            fee_per_contract,
+        };
+
+        // Buying at asks at or below the bid never costs more than locking the whole bid,
+        // so checking for the full lock up front keeps the immediate fills from stopping halfway
+        let required = bid.get_locked_amount(contracts)?;
+        let available = self
+            .get_user_or_error_immutable(&caller_address)?
+            .get_balance(&bid.series.quote_asset);
+        if available < required {
+            return Err(ExchangeError::InsufficientBalance {
+                asset: bid.series.quote_asset.clone(),
+                required,
+                available,
+            });
+        }
+        self.next_bid_id += 1;
+
+        // Take the asks this bid crosses in price-time priority
+        let crossing: Vec<BookEntry> = self
+            .order_book(&bid.series)
+            .asks
+            .into_iter()
+            .take_while(|ask| ask.price <= bid_price)
+            .filter(|ask| !are_addresses_equal(&ask.owner_address, &caller_address))
+            .collect();
+
+        for ask in crossing {
+            if bid.contract_count == 0 {
+                break;
+            }
+            let filled = bid.contract_count.min(ask.contracts);
+            self.buy_listing(ask.id, filled, caller_address.clone(), Some(bid_id))?;
+            bid.contract_count -= filled;
+        }
+
+        if bid.contract_count > 0 {
+            let mut transaction = BalanceTransaction::new();
+            transaction.transfer(
+                Account::User(caller_address),
+                Account::Escrow,
+                &bid.series.quote_asset,
+                bid.get_locked_amount(bid.contract_count)?,
+            );
+            self.apply(transaction)?;
+            self.bids.insert(bid_id, bid);
+        }
+
+        Ok(bid_id)
+    }
+
+    /// Withdraw a resting bid, refunding what is still locked for it
+    pub fn cancel_bid(
+        &mut self,
+        bid_id: u32,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let (refund_amount, refund_asset) = {
+            let bid = self
+                .bids
+                .get(&bid_id)
+                .ok_or(ExchangeError::BidNotFound(bid_id))?;
+
+            if !are_addresses_equal(&caller_address, &bid.bidder_address) {
+                return Err(ExchangeError::Unauthorized(
+                    "only the bidder can cancel this bid".into(),
+                ));
+            }
+
+            (
+                bid.get_locked_amount(bid.contract_count)?,
+                bid.series.quote_asset.clone(),
+            )
+        };
+
+        let mut transaction = BalanceTransaction::new();
+        transaction.transfer(
+            Account::Escrow,
+            Account::User(caller_address),
+            &refund_asset,
+            refund_amount,
+        );
+        self.apply(transaction)?;
+
+        self.bids.remove(&bid_id);
+        Ok(())
+    }
+
+    /// Fill the resting bids a new listing crosses, best price first and oldest first among equal prices
+    fn match_resting_bids(&mut self, listing_id: u32) -> Result<(), ExchangeError> {
+        let (series, ask_price, grantor_address) = {
+            let option = self.get_listing_or_error_immutable(listing_id)?;
+            (
+                option.series(),
+                option.ask_price,
+                option.grantor_address.clone(),
+            )
+        };
+
+        let crossing: Vec<u32> = self
+            .order_book(&series)
+            .bids
+            .into_iter()
+            .take_while(|bid| bid.price >= ask_price)
+            .filter(|bid| !are_addresses_equal(&bid.owner_address, &grantor_address))
+            .map(|bid| bid.id)
+            .collect();
+
+        for bid_id in crossing {
+            if self
+                .get_listing_or_error_immutable(listing_id)?
+                .contract_count
+                == 0
+            {
+                break;
+            }
+            self.fill_bid(bid_id, listing_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sell as many contracts of a listing as a resting bid still wants, at the bid's price.
+    /// The bidder paid into escrow when bidding, so the grantor's share and both fees are
+    /// paid out of escrow.
+    fn fill_bid(&mut self, bid_id: u32, listing_id: u32) -> Result<(), ExchangeError> {
+        let (
+            contracts,
+            bid_price,
+            premium_price,
+            buyer_fee,
+            quote_asset,
+            bidder_address,
+            grantor_address,
+        ) = {
+            let bid = self
+                .bids
+                .get(&bid_id)
+                .ok_or(ExchangeError::BidNotFound(bid_id))?;
+            let option = self.get_listing_or_error_immutable(listing_id)?;
+            if option.state != OptionState::Purchased {
+                option.check_transition(OptionState::Purchased)?;
+            }
+
+            let contracts = bid.contract_count.min(option.contract_count);
+            (
+                contracts,
+                bid.bid_price,
+                ListingOption::for_contracts(bid.premium_per_contract, contracts)?,
+                ListingOption::for_contracts(bid.fee_per_contract, contracts)?,
+                bid.series.quote_asset.clone(),
+                bid.bidder_address.clone(),
+                option.grantor_address.clone(),
+            )
+        };
+        let grantor_fee = self
+            .get_grantor_fee(premium_price)?
+            .round_to_asset(&quote_asset, Rounding::Up);
+
+        let mut transaction = BalanceTransaction::new();
+        transaction.transfer(
+            Account::Escrow,
+            Account::User(grantor_address),
+            &quote_asset,
+            premium_price.try_sub(grantor_fee)?,
+        );
+        // The buyer fee was locked with the bid
+        let fees = buyer_fee.try_add(grantor_fee)?;
+        transaction.debit(Account::Escrow, &quote_asset, fees);
+        self.collect_fee(&mut transaction, &quote_asset, fees)?;
+        self.apply(transaction)?;
+
+        self.record_purchase(
+            listing_id,
+            contracts,
+            bidder_address,
+            bid_price,
+            Some(bid_id),
+        )?;
+
+        let bid = self
+            .bids
+            .get_mut(&bid_id)
+            .ok_or(ExchangeError::BidNotFound(bid_id))?;
+        bid.contract_count -= contracts;
+        if bid.contract_count == 0 {
+            self.bids.remove(&bid_id);
+        }
+
+        Ok(())
+    }
+
+    /// Resting asks and bids of a series in price-time priority. Listings and bids of a series
+    /// that is past its expiry are left out since they can no longer trade.
+    pub fn order_book(&self, series: &OptionSeries) -> OrderBook {
+        if self.now() > series.expiration_time {
+            return OrderBook::new(series.clone(), Vec::new(), Vec::new());
+        }
+
+        let asks = self
+            .listings
+            .values()
+            .filter(|option| option.has_unsold_contracts() && option.series() == *series)
+            .map(|option| BookEntry {
+                id: option.listing_id,
+                owner_address: option.grantor_address.clone(),
+                price: option.ask_price,
+                contracts: option.contract_count,
+            })
+            .collect();
+        let bids = self
+            .bids
+            .values()
+            .filter(|bid| bid.series == *series)
+            .map(|bid| BookEntry {
+                id: bid.bid_id,
+                owner_address: bid.bidder_address.clone(),
+                price: bid.bid_price,
+                contracts: bid.contract_count,
+            })
+            .collect();
+
+        OrderBook::new(series.clone(), bids, asks)
+    }
+
+    /// Books of every series with resting liquidity, nearest expiry and lowest strike first
+    pub fn order_books(&self) -> Vec<OrderBook> {
+        let mut series: Vec<OptionSeries> = self
+            .listings
+            .values()
+            .map(|option| option.series())
+            .chain(self.bids.values().map(|bid| bid.series.clone()))
+            .collect();
+        series.sort_by_key(|series| {
+            (
+                series.expiration_time,
+                series.strike_price,
+                series.to_string(),
+            )
+        });
+        series.dedup();
+
+        series
+            .iter()
+            .map(|series| self.order_book(series))
+            .filter(|book| !book.is_empty())
+            .collect()
+    }
+
+    /// Submit a buy order for `contracts` contracts of `series`, asks are taken best price first.
+    /// A limit order rests what it couldn't fill as a bid, a market order drops it.
+    pub fn submit_buy_order(
+        &mut self,
+        series: OptionSeries,
+        order_type: OrderType,
+        contracts: u32,
+        caller_address: Address,
+    ) -> Result<u32, ExchangeError> {
+        let bid_id = match order_type {
+            OrderType::Limit(limit_price) => self.place_bid(
+                series.clone(),
+                limit_price,
+                contracts,
+                caller_address.clone(),
+            )?,
+            OrderType::Market => self.take_asks(&series, contracts, caller_address.clone())?,
+        };
+
+        Ok(self.register_order(
+            series,
+            OrderSide::Buy,
+            order_type,
+            caller_address,
+            contracts,
+            bid_id,
+        ))
+    }
+
+    /// Submit a sell order for every contract of `option`, resting bids are filled best price first.
+    /// A limit order lists what it couldn't fill at the limit price, a market order unlists it.
+    pub fn submit_sell_order(
+        &mut self,
+        option: ListingOption,
+        order_type: OrderType,
+        caller_address: Address,
+    ) -> Result<u32, ExchangeError> {
+        let mut option = option;
+        option.ask_price = match order_type {
+            OrderType::Limit(limit_price) => limit_price,
+            OrderType::Market => {
+                let has_bids = self
+                    .order_book(&option.series())
+                    .bids
+                    .iter()
+                    .any(|bid| !are_addresses_equal(&bid.owner_address, &caller_address));
+                if !has_bids {
+                    return Err(ExchangeError::InvalidInput(format!(
+                        "No bids to fill a market order on {}",
+                        option.series()
+                    )));
+                }
+                // crosses every bid, fills happen at the bid's price
+                Amount::ZERO
+            }
+        };
+        let series = option.series();
+        let contracts = option.contract_count;
+
+        let listing_id = self.list_option(caller_address.clone(), option)?;
+        if order_type == OrderType::Market
+            && self
+                .get_listing_or_error_immutable(listing_id)?
+                .contract_count
+                > 0
+        {
+            self.unlist_option(listing_id, caller_address.clone())?;
+        }
+
+        Ok(self.register_order(
+            series,
+            OrderSide::Sell,
+            order_type,
+            caller_address,
+            contracts,
+            listing_id,
+        ))
+    }
+
+    fn register_order(
+        &mut self,
+        series: OptionSeries,
+        side: OrderSide,
+        order_type: OrderType,
+        owner_address: Address,
+        contract_count: u32,
+        book_id: u32,
+    ) -> u32 {
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        self.orders.insert(
+            order_id,
+            Order {
+                order_id,
+                series,
+                side,
+                order_type,
+                owner_address,
+                contract_count,
+                book_id,
+            },
+        );
+        order_id
+    }
+
+    /// Buy up to `contracts` contracts from the best asks of a series, returns the bid id
+    /// the fills are tagged with. Nothing is bought unless the whole sweep is affordable.
+    fn take_asks(
+        &mut self,
+        series: &OptionSeries,
+        contracts: u32,
+        caller_address: Address,
+    ) -> Result<u32, ExchangeError> {
+        if contracts == 0 {
+            return Err(ExchangeError::InvalidInput(
+                "Contract count must be at least 1".into(),
+            ));
+        }
+
+        let mut remaining = contracts;
+        let mut sweep: Vec<(u32, u32)> = Vec::new(); // (listing id, contracts)
+        let mut required = Amount::ZERO;
+        for ask in self.order_book(series).asks {
+            if remaining == 0 {
+                break;
+            }
+            if are_addresses_equal(&ask.owner_address, &caller_address) {
+                continue;
+            }
+            let taken = remaining.min(ask.contracts);
+            required = required.try_add(self.get_purchase_cost(ask.id, taken)?)?;
+            sweep.push((ask.id, taken));
+            remaining -= taken;
+        }
+        if sweep.is_empty() {
+            return Err(ExchangeError::InvalidInput(format!(
+                "No asks to fill a market order on {}",
+                series
+            )));
+        }
+
+        let available = self
+            .get_user_or_error_immutable(&caller_address)?
+            .get_balance(&series.quote_asset);
+        if available < required {
+            return Err(ExchangeError::InsufficientBalance {
+                asset: series.quote_asset.clone(),
+                required,
+                available,
+            });
+        }
+
+        let bid_id = self.next_bid_id;
+        self.next_bid_id += 1;
+        for (listing_id, taken) in sweep {
+            self.buy_listing(listing_id, taken, caller_address.clone(), Some(bid_id))?;
+        }
+
+        Ok(bid_id)
+    }
+
+    /// Withdraw the resting part of an order, filled contracts stay with their holders
+    pub fn cancel_order(
+        &mut self,
+        order_id: u32,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let status = self.get_order_status(order_id)?;
+        let (side, book_id) = {
+            let order = self.get_order_or_error(order_id)?;
+            if !are_addresses_equal(&caller_address, &order.owner_address) {
+                return Err(ExchangeError::Unauthorized(
+                    "only the owner can cancel this order".into(),
+                ));
+            }
+            (order.side, order.book_id)
+        };
+
+        if !matches!(status, OrderStatus::Open | OrderStatus::PartiallyFilled) {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Order #{} is already {}",
+                order_id, status
+            )));
+        }
+
+        match side {
+            OrderSide::Buy => self.cancel_bid(book_id, caller_address),
+            OrderSide::Sell => self.unlist_option(book_id, caller_address),
+        }
+    }
+
+    pub fn get_order_or_error(&self, order_id: u32) -> Result<&Order, ExchangeError> {
+        self.orders
+            .get(&order_id)
+            .ok_or(ExchangeError::OrderNotFound(order_id))
+    }
+
+    /// Fills of an order in the order they happened
+    pub fn get_order_fills(&self, order_id: u32) -> Result<Vec<&Fill>, ExchangeError> {
+        let order = self.get_order_or_error(order_id)?;
+        Ok(self
+            .fills
+            .iter()
+            .filter(|fill| match order.side {
+                OrderSide::Buy => fill.bid_id == Some(order.book_id),
+                OrderSide::Sell => fill.listing_id == order.book_id,
+            })
+            .collect())
+    }
+
+    pub fn get_filled_contracts(&self, order_id: u32) -> Result<u32, ExchangeError> {
+        Ok(self
+            .get_order_fills(order_id)?
+            .iter()
+            .map(|fill| fill.contracts)
+            .sum())
+    }
+
+    /// Where an order stands, derived from its fills and whether its listing or bid still rests
+    pub fn get_order_status(&self, order_id: u32) -> Result<OrderStatus, ExchangeError> {
+        let order = self.get_order_or_error(order_id)?;
+        let filled = self.get_filled_contracts(order_id)?;
+        if filled >= order.contract_count {
+            return Ok(OrderStatus::Filled);
+        }
+
+        let is_resting = match order.side {
+            OrderSide::Buy => self.bids.contains_key(&order.book_id),
+            OrderSide::Sell => self
+                .listings
+                .get(&order.book_id)
+                .is_some_and(|option| option.has_unsold_contracts()),
+        };
+        let status = if order.order_type == OrderType::Market {
+            OrderStatus::Cancelled
+        } else if self.now() > order.series.expiration_time {
+            OrderStatus::Expired
+        } else if !is_resting {
+            OrderStatus::Cancelled
+        } else if filled == 0 {
+            OrderStatus::Open
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+
+        Ok(status)
+    }
+
+    /// Release the escrowed collateral of every listing that has expired unexercised
+    /// by the exchange clock, covering both unsold and unexercised contracts,
+    /// and mark those listings as expired. Contracts in the money are auto-exercised first,
+    /// see `stage_auto_exercises`, and listings that can't be are reported before they lapse. The whole pass applies as one transaction, so it either
+    /// settles everything it reports or nothing at all.
+    pub fn settle_expired(&mut self) -> Result<SettlementReport, ExchangeError> {
+        let now = self.now();
+        let mut transaction = BalanceTransaction::new();
+        let (auto_exercised, unsettled) = self.stage_auto_exercises(now, &mut transaction)?;
+        let mut exercised: HashMap<u32, u32> = HashMap::new();
+        for auto_exercise in &auto_exercised {
+            *exercised.entry(auto_exercise.listing_id).or_insert(0) += auto_exercise.contracts;
+        }
+
+        let mut exhausted_ids = Vec::new();
+        let mut expired_ids = Vec::new();
+        for option in self.listings.values() {
+            if now <= option.expiration_time {
+                continue;
+            }
+            let open_contracts = self.get_open_contracts(option.listing_id)
+                - exercised.get(&option.listing_id).copied().unwrap_or(0);
+            if exercised.contains_key(&option.listing_id)
+                && option.contract_count == 0
+                && open_contracts == 0
+            {
+                exhausted_ids.push(option.listing_id);
+            } else if option.check_transition(OptionState::Expired).is_ok() {
+                expired_ids.push((option.listing_id, open_contracts));
+            }
+        }
+        // settle in listing order so reports are deterministic
+        expired_ids.sort();
+
+        let mut report = SettlementReport {
+            auto_exercised,
+            unsettled,
+            ..SettlementReport::default()
+        };
+        for &(listing_id, open_contracts) in &expired_ids {
+            let (sell_amount, sell_asset, grantor_address, was_purchased) = {
+                let option = self.get_listing_or_error_immutable(listing_id)?;
+                (
+                    self.get_escrowed_collateral(option, option.contract_count + open_contracts)?,
+                    option.get_sell_asset(true).clone(),
+                    option.grantor_address.clone(),
+                    option.state == OptionState::Purchased,
+                )
+            };
+
+            // Return collateral from escrow to grantor
+            transaction.transfer(
+                Account::Escrow,
+                Account::User(grantor_address.clone()),
+                &sell_asset,
+                sell_amount,
+            );
+
+            let total_released = report
+                .total_released
+                .entry(sell_asset.clone())
+                .or_insert(Amount::ZERO);
+            *total_released = total_released.try_add(sell_amount)?;
+            report.settled.push(SettledListing {
+                listing_id,
+                grantor_address,
+                released_asset: sell_asset,
+                released_amount: sell_amount,
+                was_purchased,
+            });
+        }
+
+        // Bids on an expired series can't be filled anymore, hand back what they locked
+        let mut expired_bids: Vec<u32> = self
+            .bids
+            .values()
+            .filter(|bid| now > bid.series.expiration_time)
+            .map(|bid| bid.bid_id)
+            .collect();
+        expired_bids.sort();
+        for &bid_id in &expired_bids {
+            let bid = self
+                .bids
+                .get(&bid_id)
+                .ok_or(ExchangeError::BidNotFound(bid_id))?;
+            transaction.transfer(
+                Account::Escrow,
+                Account::User(bid.bidder_address.clone()),
+                &bid.series.quote_asset,
+                bid.get_locked_amount(bid.contract_count)?,
+            );
+        }
+
+        // the whole pass settles or none of it does
+        self.apply(transaction)?;
+
+        for auto_exercise in &report.auto_exercised {
+            self.record_exercise(
+                auto_exercise.listing_id,
+                &auto_exercise.holder_address,
+                auto_exercise.contracts,
+            );
+            self.auto_exercises.push(auto_exercise.clone());
+        }
+        for listing_id in exhausted_ids {
+            self.get_listing_or_error(listing_id)?
+                .transition_to(OptionState::Settled)?;
+        }
+
+        for &bid_id in &expired_bids {
+            self.bids.remove(&bid_id);
+        }
+        report.expired_bids = expired_bids;
+
+        for (listing_id, _) in expired_ids {
+            self.get_listing_or_error(listing_id)?
+                .transition_to(OptionState::Expired)?;
+            // expired rights can no longer be resold
+            self.resale_listings.remove(&listing_id);
+        }
+
+        Ok(report)
+    }
+
+    /// Stage the exercise of the open contracts of every purchased listing expired by `now` that
+    /// is at least `auto_exercise_threshold_bps` of its strike in the money, on behalf of holders
+    /// who haven't opted out. Listings without a fresh rate and holders who can't pay a physical
+    /// exercise are left to expire. A listing whose exercises can't settle for any other reason
+    /// stages nothing and is returned as unsettled, it lapses like one out of the money.
+    fn stage_auto_exercises(
+        &self,
+        now: DateTime<Utc>,
+        transaction: &mut BalanceTransaction,
+    ) -> Result<(Vec<AutoExercise>, Vec<UnsettledListing>), ExchangeError> {
+        let Some(threshold_bps) = self.auto_exercise_threshold_bps else {
+            return Ok((Vec::new(), Vec::new()));
+        };
+        let mut expired_ids: Vec<u32> = self
+            .listings
+            .values()
+            .filter(|option| now > option.expiration_time && option.state == OptionState::Purchased)
+            .map(|option| option.listing_id)
+            .collect();
+        // exercise in listing order so events are deterministic
+        expired_ids.sort();
+
+        let mut auto_exercised = Vec::new();
+        let mut unsettled = Vec::new();
+        for listing_id in expired_ids {
+            let option = self.get_listing_or_error_immutable(listing_id)?;
+            let Ok(spot) = self.settlement_rate(option) else {
+                continue;
+            };
+            if !option.is_in_the_money(spot, threshold_bps)? {
+                continue;
+            }
+            let mut holders: Vec<(Address, u32)> = self
+                .positions
+                .get(&listing_id)
+                .map(|holders| {
+                    holders
+                        .values()
+                        .filter(|position| position.contracts > 0 && position.auto_exercise)
+                        .map(|position| (position.holder_address.clone(), position.contracts))
+                        .collect()
+                })
+                .unwrap_or_default();
+            holders.sort_by_key(|(holder_address, _)| holder_address.to_string());
+
+            let mut listing_transaction = transaction.clone();
+            let mut listing_exercises = Vec::new();
+            let mut staged = Ok(());
+            for (holder_address, contracts) in holders {
+                // the holder can't pay the strike, their contracts lapse with the rest
+                if option.settlement_type == SettlementType::Physical && !self.is_margined(option) {
+                    let payment =
+                        ListingOption::for_contracts(option.get_sell_amount(false)?, contracts)?;
+                    let balance = self.staged_balance(
+                        &listing_transaction,
+                        &Account::User(holder_address.clone()),
+                        option.get_sell_asset(false),
+                    )?;
+                    if balance < payment {
+                        continue;
+                    }
+                }
+                staged = self.stage_exercise(
+                    &mut listing_transaction,
+                    option,
+                    contracts,
+                    &holder_address,
+                );
+                if staged.is_err() {
+                    break;
+                }
+                listing_exercises.push(AutoExercise {
+                    listing_id,
+                    holder_address,
+                    contracts,
+                    spot,
+                    time: now,
+                });
+            }
+
+            // Anything short other than a holder keeps the listing open instead of lapsing it
+            match staged.and_then(|()| self.check_transaction(&listing_transaction)) {
+                Ok(()) => {
+                    *transaction = listing_transaction;
+                    auto_exercised.extend(listing_exercises);
+                }
+                Err(error) => unsettled.push(UnsettledListing { listing_id, error }),
+            }
+        }
+
+        Ok((auto_exercised, unsettled))
+    }
+
+    /// Whether a listing was written from its grantor's margin account instead of escrowing
+    /// its own collateral
+    pub fn is_margined(&self, option: &ListingOption) -> bool {
+        self.margin_accounts
+            .get(&option.grantor_address)
+            .is_some_and(|account| account.listing_ids.contains(&option.listing_id))
+    }
+
+    /// Collateral the listing escrowed for `contracts` of its contracts, none if written on margin
+    fn get_escrowed_collateral(
+        &self,
+        option: &ListingOption,
+        contracts: u32,
+    ) -> Result<Amount, ExchangeError> {
+        if self.is_margined(option) {
+            return Ok(Amount::ZERO);
+        }
+        Ok(ListingOption::for_contracts(
+            option.get_sell_amount(true)?,
+            contracts,
+        )?)
+    }
+
+    /// Open a margin account for the caller. Every option they list from then on is written
+    /// on margin and has to be cash-settled.
+    pub fn open_margin_account(&mut self, caller_address: Address) -> Result<(), ExchangeError> {
+        self.get_user_or_error_immutable(&caller_address)?;
+        if self.margin_accounts.contains_key(&caller_address) {
+            return Err(ExchangeError::InvalidInput(
+                "Margin account is already open".into(),
+            ));
+        }
+        self.margin_accounts
+            .insert(caller_address.clone(), MarginAccount::new(caller_address));
+
+        Ok(())
+    }
+
+    /// Move funds from the caller's balance into their margin account
+    pub fn deposit_margin(
+        &mut self,
+        asset: &Asset,
+        amount: Amount,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if amount <= Amount::ZERO {
+            return Err(ExchangeError::InvalidInput(
+                "Margin deposits must be positive".into(),
+            ));
+        }
+        let mut transaction = BalanceTransaction::new();
+        transaction.transfer(
+            Account::User(caller_address.clone()),
+            Account::Margin(caller_address),
+            asset,
+            amount,
+        );
+        self.apply(transaction)
+    }
+
+    /// Move funds from the caller's margin account back to their balance, as far as the
+    /// account still meets its initial requirement afterwards
+    pub fn withdraw_margin(
+        &mut self,
+        asset: &Asset,
+        amount: Amount,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if amount <= Amount::ZERO {
+            return Err(ExchangeError::InvalidInput(
+                "Margin withdrawals must be positive".into(),
+            ));
+        }
+        let health = self.margin_health(&caller_address, asset)?;
+        if health.free_collateral()? < amount {
+            return Err(ExchangeError::InsufficientMargin {
+                owner: caller_address,
+                asset: asset.clone(),
+                required: health.initial_requirement.try_add(amount)?,
+                available: health.collateral,
+            });
+        }
+        // On portfolio margin the asset may also hedge options quoted in other assets
+        let account = self.get_margin_account_or_error(&caller_address)?;
+        if account.portfolio_margin {
+            let mut after = account.clone();
+            after.collateral.deduct_asset(asset, amount)?;
+            self.check_margin_change(account, &after, &[])?;
+        }
+
+        let mut transaction = BalanceTransaction::new();
+        transaction.transfer(
+            Account::Margin(caller_address.clone()),
+            Account::User(caller_address),
+            asset,
+            amount,
+        );
+        self.apply(transaction)
+    }
+
+    /// Collateral of a margin account in `asset` against the requirements of the open short
+    /// contracts it wrote in that quote asset, at the provider's current rates
+    pub fn margin_health(
+        &self,
+        owner: &Address,
+        asset: &Asset,
+    ) -> Result<MarginHealth, ExchangeError> {
+        self.account_health(
+            self.get_margin_account_or_error(owner)?,
+            asset,
+            &[],
+            false,
+            &[],
+        )
+    }
+
+    /// Fails with `InsufficientMargin` unless the owner's margin account covers its initial
+    /// requirement in `asset` once it takes on the `pending` legs, see `account_health`
+    fn check_margin(
+        &self,
+        owner: &Address,
+        asset: &Asset,
+        pending: &[PortfolioLeg],
+        defined_risk: bool,
+    ) -> Result<(), ExchangeError> {
+        let account = self.get_margin_account_or_error(owner)?;
+        let health = self.account_health(account, asset, pending, defined_risk, &[])?;
+        if health.free_collateral()? < Amount::ZERO {
+            return Err(ExchangeError::InsufficientMargin {
+                owner: owner.clone(),
+                asset: asset.clone(),
+                required: health.initial_requirement,
+                available: health.collateral,
+            });
+        }
+        Ok(())
+    }
+
+    /// Health of `account` in `asset`, as if it also took on the `pending` legs, with the ones it
+    /// writes negative, and no longer held the `released` contracts, as (listing id, contracts).
+    /// Listings are margined one by one with `margin_params`, or a defined-risk strategy's
+    /// together at its maximum loss, or everything together with the account's other holdings on
+    /// portfolio margin.
+    fn account_health(
+        &self,
+        account: &MarginAccount,
+        asset: &Asset,
+        pending: &[PortfolioLeg],
+        defined_risk: bool,
+        released: &[(u32, u32)],
+    ) -> Result<MarginHealth, ExchangeError> {
+        let pending: Vec<PortfolioLeg> = pending
+            .iter()
+            .filter(|leg| leg.option.quote_asset == *asset)
+            .cloned()
+            .collect();
+        let mut initial_requirement = Amount::ZERO;
+        let mut maintenance_requirement = Amount::ZERO;
+        if account.portfolio_margin {
+            // spot shocks reach as far as the margin rates would
+            let (portfolio, spots) = self.portfolio(account, asset, pending, released)?;
+            let params = &self.portfolio_margin_params;
+            initial_requirement = portfolio.requirement(
+                params,
+                self.margin_params.initial_margin_bps,
+                &spots,
+                self.now(),
+            )?;
+            maintenance_requirement = portfolio.requirement(
+                params,
+                self.margin_params.maintenance_margin_bps,
+                &spots,
+                self.now(),
+            )?;
+        } else {
+            // listings of a defined-risk strategy are margined with the rest of it
+            let grouped: Vec<u32> = account
+                .defined_risk
+                .iter()
+                .flat_map(|group| group.written.iter().copied())
+                .collect();
+            let mut requirements = Vec::new();
+            for (option, contracts) in self.written_contracts(account, asset)? {
+                if !grouped.contains(&option.listing_id) {
+                    requirements.push(self.listing_requirements(option, contracts)?);
+                }
+            }
+            for group in &account.defined_risk {
+                let legs = self.defined_risk_legs(account, group, asset, released)?;
+                requirements.push(self.defined_risk_requirements(&legs)?);
+            }
+            if defined_risk {
+                requirements.push(self.defined_risk_requirements(&pending)?);
+            } else {
+                for leg in pending.iter().filter(|leg| leg.contracts < 0) {
+                    requirements
+                        .push(self.listing_requirements(&leg.option, leg.written_contracts())?);
+                }
+            }
+
+            for (initial, maintenance) in requirements {
+                initial_requirement = initial_requirement.try_add(initial)?;
+                maintenance_requirement = maintenance_requirement.try_add(maintenance)?;
+            }
+        }
+
+        Ok(MarginHealth {
+            owner: account.owner.clone(),
+            asset: asset.clone(),
+            collateral: account.collateral.get_balance(asset),
+            initial_requirement,
+            maintenance_requirement,
+        })
+    }
+
+    /// Open listings `account` wrote in `asset` with their contracts still short
+    fn written_contracts<'a>(
+        &'a self,
+        account: &MarginAccount,
+        asset: &Asset,
+    ) -> Result<Vec<(&'a ListingOption, u32)>, ExchangeError> {
+        let mut written = Vec::new();
+        for listing_id in &account.listing_ids {
+            let option = self.get_listing_or_error_immutable(*listing_id)?;
+            // unsold contracts count too, anyone can buy them without the writer's say
+            let contracts = option.contract_count + self.get_open_contracts(*listing_id);
+            if option.quote_asset != *asset || option.state.is_final() || contracts == 0 {
+                continue;
+            }
+            written.push((option, contracts));
+        }
+        Ok(written)
+    }
+
+    /// Initial and maintenance requirements of `contracts` short contracts of a listing on its own
+    fn listing_requirements(
+        &self,
+        option: &ListingOption,
+        contracts: u32,
+    ) -> Result<(Amount, Amount), ExchangeError> {
+        let spot = self.settlement_rate(option)?;
+        Ok((
+            ListingOption::for_contracts(
+                self.margin_params.initial_requirement(option, spot)?,
+                contracts,
+            )?,
+            ListingOption::for_contracts(
+                self.margin_params.maintenance_requirement(option, spot)?,
+                contracts,
+            )?,
+        ))
+    }
+
+    /// Legs of a defined-risk strategy still open in `asset`. Its written listings count every
+    /// contract still short, its bought ones only as many contracts as the owner still holds
+    /// less the `released` ones.
+    fn defined_risk_legs(
+        &self,
+        account: &MarginAccount,
+        group: &DefinedRiskGroup,
+        asset: &Asset,
+        released: &[(u32, u32)],
+    ) -> Result<Vec<PortfolioLeg>, ExchangeError> {
+        let mut legs = Vec::new();
+        for listing_id in &group.written {
+            let option = self.get_listing_or_error_immutable(*listing_id)?;
+            let contracts = option.contract_count + self.get_open_contracts(*listing_id);
+            if option.quote_asset != *asset || option.state.is_final() || contracts == 0 {
+                continue;
+            }
+            legs.push(PortfolioLeg {
+                option: option.clone(),
+                contracts: -i64::from(contracts),
+            });
+        }
+        for (listing_id, bought) in &group.bought {
+            let option = self.get_listing_or_error_immutable(*listing_id)?;
+            let held = self
+                .get_position(*listing_id, &account.owner)
+                .map_or(0, |position| {
+                    held_after_release(*listing_id, position.contracts, released).min(*bought)
+                });
+            if option.quote_asset != *asset || option.state.is_final() || held == 0 {
+                continue;
+            }
+            legs.push(PortfolioLeg {
+                option: option.clone(),
+                contracts: i64::from(held),
+            });
+        }
+        Ok(legs)
+    }
+
+    /// Requirements of a strategy's written legs. As long as the bought legs cap the loss, both
+    /// are the most the strategy can lose at expiry, but never less than what the written legs
+    /// would pay if exercised today: that comes out of the account while the bought legs pay their
+    /// holder. Written legs are margined on their own once the loss isn't capped anymore.
+    fn defined_risk_requirements(
+        &self,
+        legs: &[PortfolioLeg],
+    ) -> Result<(Amount, Amount), ExchangeError> {
+        let written: Vec<&PortfolioLeg> = legs.iter().filter(|leg| leg.contracts < 0).collect();
+        let Some(first) = written.first() else {
+            return Ok((Amount::ZERO, Amount::ZERO));
+        };
+
+        let Some(max_loss) = strategy::max_expiry_loss(legs)? else {
+            let mut initial_requirement = Amount::ZERO;
+            let mut maintenance_requirement = Amount::ZERO;
+            for leg in written {
+                let (initial, maintenance) =
+                    self.listing_requirements(&leg.option, leg.written_contracts())?;
+                initial_requirement = initial_requirement.try_add(initial)?;
+                maintenance_requirement = maintenance_requirement.try_add(maintenance)?;
+            }
+            return Ok((initial_requirement, maintenance_requirement));
+        };
+
+        let mut owed = Amount::ZERO;
+        for leg in &written {
+            let spot = self.settlement_rate(&leg.option)?;
+            owed = owed.try_add(ListingOption::for_contracts(
+                leg.option.get_intrinsic_value(spot)?,
+                leg.written_contracts(),
+            )?)?;
+        }
+        let requirement = max_loss
+            .max(owed)
+            .round_to_asset(&first.option.quote_asset, Rounding::Up);
+        Ok((requirement, requirement))
+    }
+
+    /// Everything `account` has on options quoted in `asset`, with the current spot of each
+    /// underlying. Listings it wrote go in short, contracts its owner holds long, and collateral
+    /// in an underlying as spot.
+    fn portfolio(
+        &self,
+        account: &MarginAccount,
+        asset: &Asset,
+        pending: Vec<PortfolioLeg>,
+        released: &[(u32, u32)],
+    ) -> Result<(Portfolio, HashMap<Asset, Price>), ExchangeError> {
+        let mut portfolio = Portfolio::new(asset.clone());
+        for (option, contracts) in self.written_contracts(account, asset)? {
+            portfolio.legs.push(PortfolioLeg {
+                option: option.clone(),
+                contracts: -i64::from(contracts),
+            });
+        }
+        portfolio.legs.extend(pending);
+        for (listing_id, holders) in &self.positions {
+            let Some(position) = holders.get(&account.owner) else {
+                continue;
+            };
+            let option = self.get_listing_or_error_immutable(*listing_id)?;
+            let contracts = held_after_release(*listing_id, position.contracts, released);
+            if option.quote_asset != *asset || option.state.is_final() || contracts == 0 {
+                continue;
+            }
+            portfolio.legs.push(PortfolioLeg {
+                option: option.clone(),
+                contracts: i64::from(contracts),
+            });
+        }
+        // positions come out of a map, keep the valuation deterministic
+        portfolio.legs.sort_by_key(|leg| leg.option.listing_id);
+
+        let mut spots = HashMap::new();
+        for base_asset in portfolio.underlyings() {
+            let balance = account.collateral.get_balance(&base_asset);
+            if !balance.is_zero() {
+                portfolio.spot_balances.insert(base_asset.clone(), balance);
+            }
+            let spot = self.current_rate(&base_asset, asset)?;
+            if spot.is_zero() {
+                return Err(ExchangeError::RateMissing {
+                    base: base_asset,
+                    quote: asset.clone(),
+                });
+            }
+            spots.insert(base_asset, spot);
+        }
+
+        Ok((portfolio, spots))
+    }
+
+    /// Switch the caller's margin account between margining each listing on its own and
+    /// portfolio margin, which revalues everything the caller holds on an underlying together
+    /// under `portfolio_margin_params`. Rejected if the account wouldn't cover the initial
+    /// requirement of the new mode in an asset where that needs more.
+    pub fn set_portfolio_margin(
+        &mut self,
+        enabled: bool,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let account = self.get_margin_account_or_error(&caller_address)?;
+        let mut switched = account.clone();
+        switched.portfolio_margin = enabled;
+        self.check_margin_change(account, &switched, &[])?;
+
+        if let Some(account) = self.margin_accounts.get_mut(&caller_address) {
+            account.portfolio_margin = enabled;
+        }
+        Ok(())
+    }
+
+    /// Fails with `InsufficientMargin` if `after` falls short of its initial requirement in an
+    /// asset where it is worse off than `account`, e.g. once spot collateral hedging a portfolio
+    /// is gone, or once its owner no longer holds the `released` contracts, see `account_health`
+    fn check_margin_change(
+        &self,
+        account: &MarginAccount,
+        after: &MarginAccount,
+        released: &[(u32, u32)],
+    ) -> Result<(), ExchangeError> {
+        for asset in self.margin_assets(after) {
+            self.check_asset_margin_change(account, after, released, &asset)?;
+        }
+        Ok(())
+    }
+
+    /// `check_margin_change` in a single asset
+    fn check_asset_margin_change(
+        &self,
+        account: &MarginAccount,
+        after: &MarginAccount,
+        released: &[(u32, u32)],
+        asset: &Asset,
+    ) -> Result<(), ExchangeError> {
+        let before = self.account_health(account, asset, &[], false, &[])?;
+        let health = self.account_health(after, asset, &[], false, released)?;
+        let free_collateral = health.free_collateral()?;
+        if free_collateral < Amount::ZERO && free_collateral < before.free_collateral()? {
+            return Err(ExchangeError::InsufficientMargin {
+                owner: after.owner.clone(),
+                asset: asset.clone(),
+                required: health.initial_requirement,
+                available: health.collateral,
+            });
+        }
+        Ok(())
+    }
+
+    /// Fails with `InsufficientMargin` if the holder's margin account counts `contracts` of a
+    /// listing they hold against what it wrote and can't do without them, before they're resold
+    /// or exercised
+    fn check_position_release(
+        &self,
+        holder_address: &Address,
+        listing_id: u32,
+        contracts: u32,
+    ) -> Result<(), ExchangeError> {
+        match self.margin_accounts.get(holder_address) {
+            Some(account) => self.check_margin_change(account, account, &[(listing_id, contracts)]),
+            None => Ok(()),
+        }
+    }
+
+    /// Health of every margin account in every quote asset it holds or wrote options in,
+    /// ordered by owner and asset
+    pub fn margin_healths(&self) -> Result<Vec<MarginHealth>, ExchangeError> {
+        let mut healths = Vec::new();
+        for (owner, asset) in self.margin_account_assets() {
+            healths.push(self.margin_health(&owner, &asset)?);
+        }
+        Ok(healths)
+    }
+
+    /// Every margin account with each asset it holds or wrote open options in, ordered by
+    /// owner and asset
+    fn margin_account_assets(&self) -> Vec<(Address, Asset)> {
+        let mut accounts: Vec<&MarginAccount> = self.margin_accounts.values().collect();
+        accounts.sort_by_key(|account| account.owner.to_string());
+
+        let mut account_assets = Vec::new();
+        for account in accounts {
+            account_assets.extend(
+                self.margin_assets(account)
+                    .into_iter()
+                    .map(|asset| (account.owner.clone(), asset)),
+            );
+        }
+        account_assets
+    }
+
+    /// Assets a margin account holds or wrote open options in, in asset order
+    fn margin_assets(&self, account: &MarginAccount) -> Vec<Asset> {
+        let mut assets: Vec<Asset> = account
+            .collateral
+            .balances
+            .iter()
+            .filter(|(_, balance)| !balance.is_zero())
+            .map(|(asset, _)| asset.clone())
+            .chain(account.listing_ids.iter().filter_map(|listing_id| {
+                self.listings
+                    .get(listing_id)
+                    .filter(|option| !option.state.is_final())
+                    .map(|option| option.quote_asset.clone())
+            }))
+            .collect();
+        assets.sort_by_key(|asset| asset.to_string());
+        assets.dedup();
+        assets
+    }
+
+    /// Pay into the insurance fund from the caller's balance
+    pub fn fund_insurance(
+        &mut self,
+        asset: &Asset,
+        amount: Amount,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        if amount <= Amount::ZERO {
+            return Err(ExchangeError::InvalidInput(
+                "Insurance fund deposits must be positive".into(),
+            ));
+        }
+        let mut transaction = BalanceTransaction::new();
+        transaction.transfer(
+            Account::User(caller_address),
+            Account::InsuranceFund,
+            asset,
+            amount,
+        );
+        self.apply(transaction)
+    }
+
+    /// Pay treasury funds out to `recipient_address`, only the treasurer may
+    pub fn withdraw_treasury(
+        &mut self,
+        asset: &Asset,
+        amount: Amount,
+        recipient_address: Address,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let treasurer_role = NamedRole("Treasurer".to_string());
+        self.role_authorizer
+            .only_authorized_role(&[treasurer_role], caller_address)?;
+        if amount <= Amount::ZERO {
+            return Err(ExchangeError::InvalidInput(
+                "Treasury withdrawals must be positive".into(),
+            ));
+        }
+
+        let mut transaction = BalanceTransaction::new();
+        transaction.transfer(
+            Account::Treasury,
+            Account::User(recipient_address),
+            asset,
+            amount,
+        );
+        self.apply(transaction)
+    }
+
+    /// Current treasury and insurance fund balances
+    pub fn fund_report(&self) -> FundReport {
+        let non_zero = |user: &User| -> HashMap<Asset, Amount> {
+            user.balances
+                .iter()
+                .filter(|(_, balance)| !balance.is_zero())
+                .map(|(asset, balance)| (asset.clone(), *balance))
+                .collect()
+        };
+        FundReport {
+            time: self.now(),
+            treasury: non_zero(&self.treasury),
+            insurance_fund: non_zero(&self.insurance_fund),
+        }
+    }
+
+    /// Add the current fund balances to `fund_reports`, e.g. once per settlement pass
+    pub fn record_fund_report(&mut self) -> FundReport {
+        let report = self.fund_report();
+        self.fund_reports.push(report.clone());
+        report
+    }
+
+    /// Close out the short contracts a margin account wrote in `asset` once its collateral is
+    /// under the maintenance requirement. Just enough of the account's other collateral to pay for
+    /// it is sold to the spot desk at the provider's rate, as far as the desk can pay for it and
+    /// the account's requirements in other assets don't need it. Unsold contracts are withdrawn
+    /// and open ones bought back from their holders at intrinsic value, what exercising would pay
+    /// them right now. The insurance fund covers what the collateral can't pay, and
+    /// `liquidation_penalty_bps` of the maintenance requirement goes into the fund out of what is
+    /// left. Nothing changes if the fund can't cover the shortfall.
+    pub fn liquidate_margin_account(
+        &mut self,
+        owner: &Address,
+        asset: &Asset,
+    ) -> Result<Liquidation, ExchangeError> {
+        let health = self.margin_health(owner, asset)?;
+        if health.is_healthy() {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Margin account of {} covers its {} maintenance requirement",
+                owner, asset
+            )));
+        }
+        let account = self
+            .margin_accounts
+            .get(owner)
+            .ok_or_else(|| ExchangeError::MarginAccountNotFound(owner.clone()))?;
+        let mut transaction = BalanceTransaction::new();
+
+        // Buy back every open contract of the listings written in `asset`
+        let mut listing_ids = Vec::new();
+        let mut buybacks: Vec<(u32, Address, u32, Amount)> = Vec::new();
+        let mut buyback_cost = Amount::ZERO;
+        for &listing_id in &account.listing_ids {
+            let option = self.get_listing_or_error_immutable(listing_id)?;
+            if option.quote_asset != *asset || option.state.is_final() {
+                continue;
+            }
+            listing_ids.push(listing_id);
+            let mut holders: Vec<&Position> = self
+                .positions
+                .get(&listing_id)
+                .map(|holders| {
+                    holders
+                        .values()
+                        .filter(|position| position.contracts > 0)
+                        .collect()
+                })
+                .unwrap_or_default();
+            if holders.is_empty() {
+                continue;
+            }
+            holders.sort_by_key(|position| position.holder_address.to_string());
+            let intrinsic_value = option.get_intrinsic_value(self.settlement_rate(option)?)?;
+            for position in holders {
+                let payout = ListingOption::for_contracts(intrinsic_value, position.contracts)?;
+                buyback_cost = buyback_cost.try_add(payout)?;
+                buybacks.push((
+                    listing_id,
+                    position.holder_address.clone(),
+                    position.contracts,
+                    payout,
+                ));
+            }
+        }
+
+        // Sell other collateral for what the buyback and penalty need beyond the collateral in
+        // `asset`, in asset order so events are deterministic. What the account's requirements in
+        // other assets lean on stays put.
+        let full_penalty = health
+            .maintenance_requirement
+            .try_mul_bps(self.liquidation_penalty_bps, Rounding::Up)?
+            .round_to_asset(asset, Rounding::Up);
+        let mut needed = buyback_cost
+            .try_add(full_penalty)?
+            .try_sub(health.collateral)?
+            .max(Amount::ZERO);
+        let desk_address = self.escrow_user.address.clone();
+        let mut desk_balance = self
+            .get_user_or_error_immutable(&desk_address)?
+            .get_balance(asset);
+        let mut collateral: Vec<(&Asset, &Amount)> = account
+            .collateral
+            .balances
+            .iter()
+            .filter(|(other, balance)| *other != asset && !balance.is_zero())
+            .collect();
+        collateral.sort_by_key(|(other, _)| other.to_string());
+        let mut after_sales = account.clone();
+        let mut collateral_sold = Vec::new();
+        let mut sale_proceeds = Amount::ZERO;
+        for (other, &balance) in collateral {
+            if needed.is_zero() {
+                break;
+            }
+            let Ok(rate) = self.current_rate(other, asset) else {
+                continue;
+            };
+            if rate.is_zero() {
+                continue;
+            }
+            let sold = needed
+                .try_div(rate, Rounding::Up)?
+                .round_to_asset(other, Rounding::Up)
+                .min(balance);
+            let proceeds = rate
+                .try_mul(sold, Rounding::Down)?
+                .round_to_asset(asset, Rounding::Down);
+            if proceeds > desk_balance {
+                continue;
+            }
+            let mut after_sale = after_sales.clone();
+            after_sale.collateral.deduct_asset(other, sold)?;
+            let weakens_other_assets = self
+                .margin_assets(&after_sale)
+                .iter()
+                .filter(|margin_asset| *margin_asset != asset)
+                .any(|margin_asset| {
+                    self.check_asset_margin_change(account, &after_sale, &[], margin_asset)
+                        .is_err()
+                });
+            if weakens_other_assets {
+                continue;
+            }
+            after_sales = after_sale;
+            transaction.transfer(
+                Account::Margin(owner.clone()),
+                Account::User(desk_address.clone()),
+                other,
+                sold,
+            );
+            transaction.transfer(
+                Account::User(desk_address.clone()),
+                Account::Margin(owner.clone()),
+                asset,
+                proceeds,
+            );
+            desk_balance = desk_balance.try_sub(proceeds)?;
+            needed = needed.try_sub(proceeds)?.max(Amount::ZERO);
+            sale_proceeds = sale_proceeds.try_add(proceeds)?;
+            collateral_sold.push((other.clone(), sold));
+        }
+
+        let available = health.collateral.try_add(sale_proceeds)?;
+        let shortfall = buyback_cost.try_sub(available)?.max(Amount::ZERO);
+        let penalty = full_penalty.min(available.try_add(shortfall)?.try_sub(buyback_cost)?);
+        transaction.transfer(
+            Account::InsuranceFund,
+            Account::Margin(owner.clone()),
+            asset,
+            shortfall,
+        );
+        for (_, holder_address, _, payout) in &buybacks {
+            transaction.transfer(
+                Account::Margin(owner.clone()),
+                Account::User(holder_address.clone()),
+                asset,
+                *payout,
+            );
+        }
+        transaction.transfer(
+            Account::Margin(owner.clone()),
+            Account::InsuranceFund,
+            asset,
+            penalty,
+        );
+        self.apply(transaction)?;
+
+        let mut contracts = 0;
+        for (listing_id, holder_address, holder_contracts, _) in buybacks {
+            if let Some(position) = self
+                .positions
+                .get_mut(&listing_id)
+                .and_then(|holders| holders.get_mut(&holder_address))
+            {
+                position.contracts -= holder_contracts;
+            }
+            contracts += holder_contracts;
+        }
+        for &listing_id in &listing_ids {
+            // bought back rights can no longer be resold
+            self.resale_listings.remove(&listing_id);
+            let option = self.get_listing_or_error(listing_id)?;
+            option.contract_count = 0;
+            if option.state == OptionState::Listed {
+                option.transition_to(OptionState::Unlisted)?;
+            } else {
+                option.transition_to(OptionState::Settled)?;
+            }
+        }
+
+        let liquidation = Liquidation {
+            owner: owner.clone(),
+            asset: asset.clone(),
+            listing_ids,
+            contracts,
+            buyback_cost,
+            collateral_sold,
+            sale_proceeds,
+            penalty,
+            shortfall,
+            time: self.now(),
+        };
+        self.liquidations.push(liquidation.clone());
+
+        Ok(liquidation)
+    }
+
+    /// Liquidate every margin account under its maintenance requirement in some asset, see
+    /// `liquidate_margin_account`. Accounts that can't be valued for lack of a fresh rate, or
+    /// whose shortfall the insurance fund can't cover, are left for a later pass.
+    pub fn liquidate_unhealthy(&mut self) -> Result<Vec<Liquidation>, ExchangeError> {
+        let mut liquidations = Vec::new();
+        for (owner, asset) in self.margin_account_assets() {
+            match self.margin_health(&owner, &asset) {
+                Ok(health) if health.is_healthy() => continue,
+                Ok(_) => {}
+                Err(ExchangeError::RateMissing { .. } | ExchangeError::RateStale { .. }) => {
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+            match self.liquidate_margin_account(&owner, &asset) {
+                Ok(liquidation) => liquidations.push(liquidation),
+                Err(
+                    ExchangeError::InsufficientBalance { .. }
+                    | ExchangeError::RateMissing { .. }
+                    | ExchangeError::RateStale { .. },
+                ) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(liquidations)
+    }
+
+    /// Price a strategy order for the caller without executing it, see `execute_strategy`
+    pub fn quote_strategy(
+        &self,
+        order: &StrategyOrder,
+        caller_address: &Address,
+    ) -> Result<StrategyQuote, ExchangeError> {
+        Ok(self.stage_strategy(order, caller_address)?.quote)
+    }
+
+    /// Execute every leg of a strategy order, or none of them if any leg fails. The legs' balance
+    /// changes apply as one transaction in leg order, so an earlier leg can pay for a later one,
+    /// like spot bought to cover a written call. Written legs go on margin when the caller has a
+    /// margin account, where a defined-risk strategy only needs its maximum loss. Without one they
+    /// escrow their full collateral, defined risk or not. Resting bids then take the written
+    /// contracts first, as with `list_option`.
+    pub fn execute_strategy(
+        &mut self,
+        order: StrategyOrder,
+        caller_address: Address,
+    ) -> Result<StrategyFill, ExchangeError> {
+        let staged = self.stage_strategy(&order, &caller_address)?;
+        let is_margined = self.margin_accounts.contains_key(&caller_address);
+        if is_margined && staged.legs.iter().any(|leg| leg.contracts < 0) {
+            self.check_margin(
+                &caller_address,
+                &order.quote_asset,
+                &staged.legs,
+                staged.quote.defined_risk,
+            )?;
+        }
+        self.apply(staged.transaction)?;
+
+        // Every leg is paid for, only the bookkeeping is left
+        let strategy_id = self.next_strategy_id;
+        self.next_strategy_id += 1;
+        let mut listing_ids = Vec::new();
+        let mut bought = Vec::new();
+        for leg in order.legs {
+            match leg {
+                StrategyLeg::Write(option) => {
+                    listing_ids.push(self.store_listing(&caller_address, option));
+                }
+                StrategyLeg::Buy {
+                    listing_id,
+                    contracts,
+                } => {
+                    let ask_price = self.get_listing_or_error_immutable(listing_id)?.ask_price;
+                    self.record_purchase(
+                        listing_id,
+                        contracts,
+                        caller_address.clone(),
+                        ask_price,
+                        None,
+                    )?;
+                    bought.push((listing_id, contracts));
+                }
+                StrategyLeg::Spot { .. } => {}
+            }
+        }
+        if staged.quote.defined_risk
+            && let Some(account) = self.margin_accounts.get_mut(&caller_address)
+        {
+            account.defined_risk.push(DefinedRiskGroup {
+                strategy_id,
+                written: listing_ids.clone(),
+                bought,
+            });
+        }
+
+        let fill = StrategyFill {
+            strategy_id,
+            owner: caller_address,
+            listing_ids,
+            quote: staged.quote,
+            time: self.now(),
+        };
+        self.strategy_fills.push(fill.clone());
+
+        // Buyers already bidding on the series take the written contracts first
+        for listing_id in &fill.listing_ids {
+            self.match_if_live(*listing_id)?;
+        }
+
+        Ok(fill)
+    }
+
+    /// Check every leg of a strategy order and stage its balance changes in leg order. Written
+    /// legs are priced as if sold at their ask, and need their margin requirement instead of
+    /// escrow when the caller has a margin account.
+    fn stage_strategy(
+        &self,
+        order: &StrategyOrder,
+        caller_address: &Address,
+    ) -> Result<StagedStrategy, ExchangeError> {
+        self.get_user_or_error_immutable(caller_address)?;
+        if order.legs.is_empty() {
+            return Err(ExchangeError::InvalidInput(
+                "Strategy orders need at least one leg".into(),
+            ));
+        }
+        let quote_asset = &order.quote_asset;
+        let check_asset = |option: &ListingOption| {
+            if option.base_asset != order.base_asset || option.quote_asset != *quote_asset {
+                return Err(ExchangeError::InvalidInput(format!(
+                    "Strategy legs must all trade {}/{}",
+                    order.base_asset, quote_asset
+                )));
+            }
+            Ok(())
+        };
+        let is_margined = self.margin_accounts.contains_key(caller_address);
+
+        let mut transaction = BalanceTransaction::new();
+        let mut legs = Vec::new();
+        let mut net_premium = Amount::ZERO;
+        let mut spot_cost = Amount::ZERO;
+        let mut base_held = Amount::ZERO;
+        let mut collateral: HashMap<Asset, Amount> = HashMap::new();
+        let mut bought_ids = HashSet::new();
+        for leg in &order.legs {
+            match leg {
+                StrategyLeg::Write(option) => {
+                    check_asset(option)?;
+                    self.check_listing(caller_address, option)?;
+                    // Escrow isn't capped at the max loss even for a defined-risk order. Each
+                    // escrowed listing pays its holders out of its own collateral, physical ones
+                    // deliver it, and the bought legs can be resold or exercised on their own,
+                    // so nothing would be left to make up for the reduced escrow.
+                    if !is_margined {
+                        self.stage_listing_collateral(&mut transaction, caller_address, option)?;
+                        let escrowed = collateral
+                            .entry(option.get_sell_asset(true).clone())
+                            .or_insert(Amount::ZERO);
+                        *escrowed = escrowed.try_add(ListingOption::for_contracts(
+                            option.get_sell_amount(true)?,
+                            option.contract_count,
+                        )?)?;
+                    }
+                    let premium = ListingOption::for_contracts(
+                        option.get_premium_price()?,
+                        option.contract_count,
+                    )?;
+                    let grantor_fee = self
+                        .get_grantor_fee(premium)?
+                        .round_to_asset(quote_asset, Rounding::Up);
+                    net_premium = net_premium.try_sub(premium.try_sub(grantor_fee)?)?;
+                    legs.push(PortfolioLeg {
+                        option: option.clone(),
+                        contracts: -i64::from(option.contract_count),
+                    });
+                }
+                StrategyLeg::Buy {
+                    listing_id,
+                    contracts,
+                } => {
+                    let option = self.get_listing_or_error_immutable(*listing_id)?;
+                    check_asset(option)?;
+                    if !bought_ids.insert(*listing_id) {
+                        return Err(ExchangeError::InvalidInput(format!(
+                            "Listing #{} is bought by more than one leg",
+                            listing_id
+                        )));
+                    }
+                    self.stage_purchase(&mut transaction, *listing_id, *contracts, caller_address)?;
+                    net_premium =
+                        net_premium.try_add(self.get_purchase_cost(*listing_id, *contracts)?)?;
+                    legs.push(PortfolioLeg {
+                        option: option.clone(),
+                        contracts: i64::from(*contracts),
+                    });
+                }
+                StrategyLeg::Spot {
+                    action,
+                    base_amount,
+                } => {
+                    let pair = AssetPair::from(order.base_asset.clone(), quote_asset.clone());
+                    self.check_spot_order(&pair, *base_amount)?;
+                    let quote_amount = self.stage_desk_trade(
+                        &mut transaction,
+                        &order.base_asset,
+                        quote_asset,
+                        *base_amount,
+                        action,
+                        caller_address.clone(),
+                    )?;
+                    (spot_cost, base_held) = match action {
+                        SpotAction::BUY => (
+                            spot_cost.try_add(quote_amount)?,
+                            base_held.try_add(*base_amount)?,
+                        ),
+                        SpotAction::SELL => (
+                            spot_cost.try_sub(quote_amount)?,
+                            base_held.try_sub(*base_amount)?,
+                        ),
+                    };
+                }
+            }
+        }
+
+        // Margin only holds the option legs, spot bought for a collar stays with the caller
+        let is_writing = legs.iter().any(|leg| leg.contracts < 0);
+        let defined_risk = is_writing && strategy::max_expiry_loss(&legs)?.is_some();
+        let max_loss = match strategy::lowest_expiry_value(&legs, base_held)? {
+            Some(lowest) => Some(
+                net_premium
+                    .try_add(spot_cost)?
+                    .try_sub(lowest)?
+                    .max(Amount::ZERO),
+            ),
+            None => None,
+        };
+        if is_margined && is_writing {
+            let account = self.get_margin_account_or_error(caller_address)?;
+            let before = self.account_health(account, quote_asset, &[], false, &[])?;
+            let after = self.account_health(account, quote_asset, &legs, defined_risk, &[])?;
+            let requirement = after
+                .initial_requirement
+                .try_sub(before.initial_requirement)?
+                .max(Amount::ZERO);
+            collateral.insert(quote_asset.clone(), requirement);
+        }
+
+        Ok(StagedStrategy {
+            quote: StrategyQuote {
+                net_premium,
+                spot_cost,
+                collateral,
+                max_loss,
+                defined_risk,
+            },
+            legs,
+            transaction,
+        })
+    }
+
+    /// Post a limit order on a spot pair. It trades right away at the resting orders' prices as far
+    /// as it crosses them, and what is left rests in the book with its funds locked in escrow.
+    /// Returns the order id, which only stays in the book while something is left to trade.
+    pub fn place_spot_limit_order(
+        &mut self,
+        pair: AssetPair,
+        side: SpotAction,
+        price: Price,
+        base_amount: Amount,
+        caller_address: Address,
+    ) -> Result<u32, ExchangeError> {
+        self.check_spot_order(&pair, base_amount)?;
+        if price <= Amount::ZERO {
+            return Err(ExchangeError::InvalidInput(
+                "Limit price must be positive".into(),
+            ));
+        }
+
+        let mut transaction = BalanceTransaction::new();
+        let fills = self.plan_spot_fills(
+            &pair,
+            side,
+            price,
+            base_amount,
+            &caller_address,
+            &mut transaction,
+        )?;
+        let mut remaining = base_amount;
+        for (_, traded, _) in &fills {
+            remaining = remaining.try_sub(*traded)?;
+        }
+
+        let order_id = self.next_spot_order_id;
+        let resting = if remaining.is_zero() {
+            None
         } else {
-            return Err(format!(
-                "Exchange rate for pair {}/{} not found",
-                base_asset, quote_asset
+            // a resting buy locks its quote at the limit price, a resting sell its base
+            let (locked_asset, locked_amount) = match side {
+                SpotAction::BUY => (
+                    &pair.quote,
+                    price
+                        .try_mul(remaining, Rounding::Up)?
+                        .round_to_asset(&pair.quote, Rounding::Up),
+                ),
+                SpotAction::SELL => (&pair.base, remaining),
+            };
+            transaction.transfer(
+                Account::User(caller_address.clone()),
+                Account::Escrow,
+                locked_asset,
+                locked_amount,
+            );
+            Some(SpotOrder {
+                order_id,
+                pair: pair.clone(),
+                side,
+                owner_address: caller_address.clone(),
+                price,
+                base_amount: remaining,
+                locked_amount,
+            })
+        };
+        self.apply(transaction)?;
+        self.next_spot_order_id += 1;
+
+        self.record_spot_fills(&pair, side, &caller_address, fills)?;
+        if let Some(order) = resting {
+            self.spot_books
+                .entry(pair.clone())
+                .or_insert_with(|| SpotOrderBook::new(pair))
+                .insert(order);
+        }
+
+        Ok(order_id)
+    }
+
+    /// Trade `base_amount` right away against the best resting orders of a pair. Nothing trades
+    /// unless the whole amount fills within `max_slippage_bps` of the best price on offer.
+    pub fn place_spot_market_order(
+        &mut self,
+        pair: AssetPair,
+        side: SpotAction,
+        base_amount: Amount,
+        max_slippage_bps: u16,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        self.check_spot_order(&pair, base_amount)?;
+        if max_slippage_bps > MAX_FEE_BPS {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Slippage must not exceed {} bps",
+                MAX_FEE_BPS
+            )));
+        }
+
+        let best_price = self
+            .spot_books
+            .get(&pair)
+            .and_then(|book| {
+                book.opposite(side)
+                    .iter()
+                    .find(|order| !are_addresses_equal(&order.owner_address, &caller_address))
+            })
+            .map(|order| order.price)
+            .ok_or_else(|| {
+                ExchangeError::InvalidInput(format!(
+                    "No resting orders to trade against on {}",
+                    pair
+                ))
+            })?;
+        // buyers accept paying up to the slippage above the best ask, sellers as much below the best bid
+        let limit_price = match side {
+            SpotAction::BUY => {
+                best_price.try_mul_bps(MAX_FEE_BPS + max_slippage_bps, Rounding::Down)?
+            }
+            SpotAction::SELL => {
+                best_price.try_mul_bps(MAX_FEE_BPS - max_slippage_bps, Rounding::Up)?
+            }
+        };
+
+        let mut transaction = BalanceTransaction::new();
+        let fills = self.plan_spot_fills(
+            &pair,
+            side,
+            limit_price,
+            base_amount,
+            &caller_address,
+            &mut transaction,
+        )?;
+        let mut unfilled = base_amount;
+        for (_, traded, _) in &fills {
+            unfilled = unfilled.try_sub(*traded)?;
+        }
+        if !unfilled.is_zero() {
+            return Err(ExchangeError::SlippageExceeded {
+                limit_price,
+                unfilled,
+            });
+        }
+        self.apply(transaction)?;
+
+        self.record_spot_fills(&pair, side, &caller_address, fills)
+    }
+
+    /// Withdraw a resting spot order, refunding what it still has locked in escrow
+    pub fn cancel_spot_order(
+        &mut self,
+        order_id: u32,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let (pair, refund_asset, refund_amount) = {
+            let order = self
+                .spot_books
+                .values()
+                .find_map(|book| book.get(order_id))
+                .ok_or(ExchangeError::SpotOrderNotFound(order_id))?;
+
+            if !are_addresses_equal(&caller_address, &order.owner_address) {
+                return Err(ExchangeError::Unauthorized(
+                    "only the owner can cancel this spot order".into(),
+                ));
+            }
+
+            let refund_asset = match order.side {
+                SpotAction::BUY => order.pair.quote.clone(),
+                SpotAction::SELL => order.pair.base.clone(),
+            };
+            (order.pair.clone(), refund_asset, order.locked_amount)
+        };
+
+        let mut transaction = BalanceTransaction::new();
+        transaction.transfer(
+            Account::Escrow,
+            Account::User(caller_address),
+            &refund_asset,
+            refund_amount,
+        );
+        self.apply(transaction)?;
+
+        if let Some(book) = self.spot_books.get_mut(&pair) {
+            book.remove(order_id);
+        }
+        Ok(())
+    }
+
+    fn check_spot_order(&self, pair: &AssetPair, base_amount: Amount) -> Result<(), ExchangeError> {
+        if pair.base == pair.quote {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Can't trade {} against itself",
+                pair.base
+            )));
+        }
+        if base_amount <= Amount::ZERO {
+            return Err(ExchangeError::InvalidInput(
+                "Base amount must be positive".into(),
             ));
+        }
+        if !base_amount.fits_asset(&pair.base) {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Base amount has more than {} decimals for {}",
+                pair.base.decimals(),
+                pair.base
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_pool_amount(&self, asset: &Asset, amount: Amount) -> Result<(), ExchangeError> {
+        if amount <= Amount::ZERO {
+            return Err(ExchangeError::InvalidInput(format!(
+                "{} amount must be positive",
+                asset
+            )));
+        }
+        if !amount.fits_asset(asset) {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Amount has more than {} decimals for {}",
+                asset.decimals(),
+                asset
+            )));
+        }
+        Ok(())
+    }
+
+    /// Stage the trades an order on `side` makes against the resting orders it crosses up to
+    /// `limit_price`, best price first. Each fill trades at the resting order's price and
+    /// is returned as (resting order id, base traded, quote traded).
+    fn plan_spot_fills(
+        &self,
+        pair: &AssetPair,
+        side: SpotAction,
+        limit_price: Price,
+        base_amount: Amount,
+        caller_address: &Address,
+        transaction: &mut BalanceTransaction,
+    ) -> Result<Vec<(u32, Amount, Amount)>, ExchangeError> {
+        let mut fills = Vec::new();
+        let Some(book) = self.spot_books.get(pair) else {
+            return Ok(fills);
         };
 
-        let quote_amount = exchange_rate * base_amount;
+        let mut remaining = base_amount;
+        for resting in book.opposite(side) {
+            let crosses = match side {
+                SpotAction::BUY => resting.price <= limit_price,
+                SpotAction::SELL => resting.price >= limit_price,
+            };
+            if remaining.is_zero() || !crosses {
+                break;
+            }
+            if are_addresses_equal(&resting.owner_address, caller_address) {
+                continue;
+            }
 
-        // buyer pays quote to seller
-        self._transfer_between_addresses(&buyer_addr, &seller_addr, quote_asset, quote_amount)?;
-        // seller pays base to buyer
-        self._transfer_between_addresses(&seller_addr, &buyer_addr, base_asset, base_amount)?;
+            let traded = remaining.min(resting.base_amount);
+            // paid out of the buyer's balance or lock, so it rounds in the buyer's favour
+            let quote_amount = resting
+                .price
+                .try_mul(traded, Rounding::Down)?
+                .round_to_asset(&pair.quote, Rounding::Down);
+            let taker = Account::User(caller_address.clone());
+            let maker = Account::User(resting.owner_address.clone());
+            match side {
+                SpotAction::BUY => {
+                    transaction
+                        .transfer(taker.clone(), maker, &pair.quote, quote_amount)
+                        .transfer(Account::Escrow, taker, &pair.base, traded);
+                }
+                SpotAction::SELL => {
+                    transaction
+                        .transfer(taker.clone(), maker.clone(), &pair.base, traded)
+                        .transfer(Account::Escrow, taker, &pair.quote, quote_amount);
+                    // a filled bid gets back what it locked above the quote it actually paid
+                    let leftover = resting.locked_amount.try_sub(quote_amount)?;
+                    if traded == resting.base_amount && !leftover.is_zero() {
+                        transaction.transfer(Account::Escrow, maker, &pair.quote, leftover);
+                    }
+                }
+            }
+
+            fills.push((resting.order_id, traded, quote_amount));
+            remaining = remaining.try_sub(traded)?;
+        }
+
+        Ok(fills)
+    }
+
+    /// Update the resting orders behind fills that were just paid for and record the trades,
+    /// feeding the last price into the rate provider when enabled
+    fn record_spot_fills(
+        &mut self,
+        pair: &AssetPair,
+        side: SpotAction,
+        caller_address: &Address,
+        fills: Vec<(u32, Amount, Amount)>,
+    ) -> Result<(), ExchangeError> {
+        if fills.is_empty() {
+            return Ok(());
+        }
+        let time = self.now();
+        let book = self
+            .spot_books
+            .get_mut(pair)
+            .ok_or_else(|| ExchangeError::InvalidInput(format!("No spot book for {}", pair)))?;
+
+        for (order_id, traded, quote_amount) in fills {
+            let resting_orders = match side {
+                SpotAction::BUY => &mut book.asks,
+                SpotAction::SELL => &mut book.bids,
+            };
+            let position = resting_orders
+                .iter()
+                .position(|order| order.order_id == order_id)
+                .ok_or(ExchangeError::SpotOrderNotFound(order_id))?;
+            let resting = &mut resting_orders[position];
+            resting.base_amount = resting.base_amount.try_sub(traded)?;
+            resting.locked_amount = match side {
+                SpotAction::BUY => resting.locked_amount.try_sub(traded)?,
+                SpotAction::SELL => resting.locked_amount.try_sub(quote_amount)?,
+            };
+
+            let (buyer_address, seller_address) = match side {
+                SpotAction::BUY => (caller_address.clone(), resting.owner_address.clone()),
+                SpotAction::SELL => (resting.owner_address.clone(), caller_address.clone()),
+            };
+            let price = resting.price;
+            if resting.base_amount.is_zero() {
+                resting_orders.remove(position);
+            }
+
+            book.trades.push(SpotTrade {
+                buyer_address,
+                seller_address,
+                price,
+                base_amount: traded,
+                quote_amount,
+                time,
+            });
+        }
+
+        if self.spot_rate_feed
+            && let Some(last_price) = book.last_price()
+        {
+            get_rate_provider().set_rate(
+                pair.base.clone(),
+                pair.quote.clone(),
+                last_price,
+                default_exchange_rate_provider_admin_address(),
+            )?;
+        }
 
         Ok(())
     }
 
-    fn _transfer_between_addresses(
+    /// Deposit up to `base_amount` and `quote_amount` into the pool of a pair, creating it if needed,
+    /// in exchange for LP shares. The first deposit sets the pool price, later ones only take
+    /// as much of each side as the pool's ratio calls for. Returns the shares minted.
+    pub fn add_liquidity(
         &mut self,
-        sender_addr: &Address,
-        recipient_addr: &Address,
-        asset: &Asset,
-        amount: f64,
-    ) -> Result<(), String> {
-        // Deduct from sender
-        let sender = self.get_user_or_error(&sender_addr)?;
-        let sender_balance = sender.get_balance(asset);
-        if sender_balance < amount {
-            return Err(format!(
-                "Transfer failed: sender doesn't have enough {} of {}",
-                amount, asset
+        pair: AssetPair,
+        base_amount: Amount,
+        quote_amount: Amount,
+        caller_address: Address,
+    ) -> Result<Amount, ExchangeError> {
+        self.check_spot_order(&pair, base_amount)?;
+        self.check_pool_amount(&pair.quote, quote_amount)?;
+
+        let is_new = !self.pools.contains_key(&pair);
+        let (base_taken, quote_taken, minted) = match self.pools.get(&pair) {
+            Some(pool) => pool.quote_deposit(base_amount, quote_amount)?,
+            None => LiquidityPool::new(pair.clone()).quote_deposit(base_amount, quote_amount)?,
+        };
+        if minted.is_zero() {
+            return Err(ExchangeError::InvalidInput(
+                "Deposit is too small to mint any shares".into(),
+            ));
+        }
+
+        let mut transaction = BalanceTransaction::new();
+        transaction
+            .transfer(
+                Account::User(caller_address.clone()),
+                Account::Pool(pair.clone()),
+                &pair.base,
+                base_taken,
+            )
+            .transfer(
+                Account::User(caller_address.clone()),
+                Account::Pool(pair.clone()),
+                &pair.quote,
+                quote_taken,
+            );
+        if is_new {
+            self.pools
+                .insert(pair.clone(), LiquidityPool::new(pair.clone()));
+        }
+        if let Err(err) = self.apply(transaction) {
+            // the pool only exists once someone has deposited into it
+            if is_new {
+                self.pools.remove(&pair);
+            }
+            return Err(err);
+        }
+
+        let pool = self
+            .pools
+            .get_mut(&pair)
+            .ok_or_else(|| ExchangeError::PoolNotFound(pair.clone()))?;
+        pool.base_reserve = pool.base_reserve.try_add(base_taken)?;
+        pool.quote_reserve = pool.quote_reserve.try_add(quote_taken)?;
+        // the first deposit also mints the shares that stay locked in the pool for good
+        let locked = if is_new { MINIMUM_SHARES } else { Amount::ZERO };
+        pool.total_shares = pool.total_shares.try_add(minted)?.try_add(locked)?;
+        let held = pool.get_shares(&caller_address).try_add(minted)?;
+        pool.shares.insert(caller_address, held);
+
+        Ok(minted)
+    }
+
+    /// Burn LP shares for their part of both reserves, returned as (base, quote)
+    pub fn remove_liquidity(
+        &mut self,
+        pair: AssetPair,
+        shares: Amount,
+        caller_address: Address,
+    ) -> Result<(Amount, Amount), ExchangeError> {
+        let pool = self.get_pool_or_error(&pair)?;
+        if shares <= Amount::ZERO {
+            return Err(ExchangeError::InvalidInput(
+                "Shares must be positive".into(),
             ));
         }
-        sender.deduct_asset(asset, amount)?;
+        let held = pool.get_shares(&caller_address);
+        if shares > held {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Only {} shares held in the {} pool",
+                held, pair
+            )));
+        }
+        let (base_amount, quote_amount) = pool.quote_withdrawal(shares)?;
+
+        let mut transaction = BalanceTransaction::new();
+        transaction
+            .transfer(
+                Account::Pool(pair.clone()),
+                Account::User(caller_address.clone()),
+                &pair.base,
+                base_amount,
+            )
+            .transfer(
+                Account::Pool(pair.clone()),
+                Account::User(caller_address.clone()),
+                &pair.quote,
+                quote_amount,
+            );
+        self.apply(transaction)?;
+
+        let pool = self
+            .pools
+            .get_mut(&pair)
+            .ok_or_else(|| ExchangeError::PoolNotFound(pair.clone()))?;
+        pool.base_reserve = pool.base_reserve.try_sub(base_amount)?;
+        pool.quote_reserve = pool.quote_reserve.try_sub(quote_amount)?;
+        pool.total_shares = pool.total_shares.try_sub(shares)?;
+        let remaining = held.try_sub(shares)?;
+        if remaining.is_zero() {
+            pool.shares.remove(&caller_address);
+        } else {
+            pool.shares.insert(caller_address, remaining);
+        }
+
+        Ok((base_amount, quote_amount))
+    }
+
+    /// Swap against the pool of a pair: buying pays `amount_in` of quote for base, selling pays
+    /// `amount_in` of base for quote. Nothing trades if the output would fall below `min_amount_out`.
+    /// Returns the amount received.
+    pub fn swap(
+        &mut self,
+        pair: AssetPair,
+        side: SpotAction,
+        amount_in: Amount,
+        min_amount_out: Amount,
+        caller_address: Address,
+    ) -> Result<Amount, ExchangeError> {
+        let pool = self.get_pool_or_error(&pair)?;
+        if pool.is_empty() {
+            return Err(ExchangeError::InvalidInput(format!(
+                "The {} pool has no liquidity",
+                pair
+            )));
+        }
+        let (asset_in, asset_out) = match side {
+            SpotAction::BUY => (&pair.quote, &pair.base),
+            SpotAction::SELL => (&pair.base, &pair.quote),
+        };
+        self.check_pool_amount(asset_in, amount_in)?;
+
+        let amount_out = pool.quote_swap(side, amount_in, self.pool_fee_bps)?;
+        if amount_out.is_zero() {
+            return Err(ExchangeError::InvalidInput(format!(
+                "Swap is too small to pay out any {}",
+                asset_out
+            )));
+        }
+        if amount_out < min_amount_out {
+            return Err(ExchangeError::SwapOutputTooLow {
+                amount_out,
+                min_amount_out,
+            });
+        }
+
+        let mut transaction = BalanceTransaction::new();
+        transaction
+            .transfer(
+                Account::User(caller_address.clone()),
+                Account::Pool(pair.clone()),
+                asset_in,
+                amount_in,
+            )
+            .transfer(
+                Account::Pool(pair.clone()),
+                Account::User(caller_address),
+                asset_out,
+                amount_out,
+            );
+        self.apply(transaction)?;
+
+        let pool = self
+            .pools
+            .get_mut(&pair)
+            .ok_or_else(|| ExchangeError::PoolNotFound(pair.clone()))?;
+        match side {
+            SpotAction::BUY => {
+                pool.quote_reserve = pool.quote_reserve.try_add(amount_in)?;
+                pool.base_reserve = pool.base_reserve.try_sub(amount_out)?;
+            }
+            SpotAction::SELL => {
+                pool.base_reserve = pool.base_reserve.try_add(amount_in)?;
+                pool.quote_reserve = pool.quote_reserve.try_sub(amount_out)?;
+            }
+        }
+
+        Ok(amount_out)
+    }
+
+    /// Trade against the spot desk at the rate provider's current rate. The desk only
+    /// has what earlier trades left it, see `place_spot_limit_order` to trade between users.
+    pub fn spot_trade_current_price(
+        &mut self,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        base_amount: Amount,
+        action: &SpotAction,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let mut transaction = BalanceTransaction::new();
+        self.stage_desk_trade(
+            &mut transaction,
+            base_asset,
+            quote_asset,
+            base_amount,
+            action,
+            caller_address,
+        )?;
+        self.apply(transaction)
+    }
+
+    /// Stage a trade between the caller and the spot desk at the current rate, returning the
+    /// quote amount that changes hands
+    fn stage_desk_trade(
+        &self,
+        transaction: &mut BalanceTransaction,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+        base_amount: Amount,
+        action: &SpotAction,
+        caller_address: Address,
+    ) -> Result<Amount, ExchangeError> {
+        // The quote leg rounds in favour of the exchange: up when the caller pays, down when paid
+        let (buyer_addr, seller_addr, rounding) = match action {
+            SpotAction::BUY => (
+                caller_address,
+                self.escrow_user.address.clone(),
+                Rounding::Up,
+            ),
+            SpotAction::SELL => (
+                self.escrow_user.address.clone(),
+                caller_address,
+                Rounding::Down,
+            ),
+        };
+
+        let exchange_rate = self.current_rate(base_asset, quote_asset)?;
+        let quote_amount = exchange_rate
+            .try_mul(base_amount, rounding)?
+            .round_to_asset(quote_asset, rounding);
+
+        // buyer pays quote to seller
+        transaction.transfer(
+            Account::User(buyer_addr.clone()),
+            Account::User(seller_addr.clone()),
+            quote_asset,
+            quote_amount,
+        );
+        // seller pays base to buyer
+        transaction.transfer(
+            Account::User(seller_addr),
+            Account::User(buyer_addr),
+            base_asset,
+            base_amount,
+        );
+        Ok(quote_amount)
+    }
+
+    /// Provider rate for the pair, rejected once older than `max_rate_age`
+    fn current_rate(
+        &self,
+        base_asset: &Asset,
+        quote_asset: &Asset,
+    ) -> Result<Price, ExchangeError> {
+        let rate_provider = get_readonly_rate_provider();
+        let exchange_rate = if let Some(rate) = rate_provider.get_rate(base_asset, quote_asset) {
+            rate
+        } else {
+            return Err(ExchangeError::RateMissing {
+                base: base_asset.clone(),
+                quote: quote_asset.clone(),
+            });
+        };
+
+        if let Some(max_rate_age) = self.max_rate_age {
+            let is_stale = match rate_provider.get_rate_updated_at(base_asset, quote_asset) {
+                Some(updated_at) => self.now() - updated_at > max_rate_age,
+                None => true,
+            };
+            if is_stale {
+                return Err(ExchangeError::RateStale {
+                    base: base_asset.clone(),
+                    quote: quote_asset.clone(),
+                });
+            }
+        }
+
+        Ok(exchange_rate)
+    }
 
-        // Credit to recipient
-        let recipient = self.get_user_or_error(&recipient_addr)?;
-        recipient.add_asset(asset, amount)?;
+    /// Apply every change of `transaction` or none of them. Changes run in order against
+    /// staged copies of the accounts involved, which replace the live ones only once all succeed.
+    pub fn apply(&mut self, transaction: BalanceTransaction) -> Result<(), ExchangeError> {
+        for (account, user) in self.stage_accounts(&transaction)? {
+            match account {
+                Account::Escrow => self.escrow_user = user,
+                Account::User(address) => {
+                    self.users.insert(address, user);
+                }
+                Account::Margin(address) => {
+                    if let Some(margin_account) = self.margin_accounts.get_mut(&address) {
+                        margin_account.collateral = user;
+                    }
+                }
+                Account::Treasury => self.treasury = user,
+                Account::InsuranceFund => self.insurance_fund = user,
+                Account::Pool(pair) => {
+                    if let Some(pool) = self.pools.get_mut(&pair) {
+                        pool.balances = user;
+                    }
+                }
+            }
+        }
 
         Ok(())
     }
+
+    /// Fail the way `apply` would without changing anything
+    pub fn check_transaction(&self, transaction: &BalanceTransaction) -> Result<(), ExchangeError> {
+        self.stage_accounts(transaction).map(|_| ())
+    }
+
+    /// Copies of the accounts `transaction` touches with its changes run against them in order
+    fn stage_accounts(
+        &self,
+        transaction: &BalanceTransaction,
+    ) -> Result<HashMap<Account, User>, ExchangeError> {
+        let mut staged: HashMap<Account, User> = HashMap::new();
+        for change in &transaction.changes {
+            let account = match staged.entry(change.account.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let live = self.get_account_or_error(&change.account)?.clone();
+                    entry.insert(live)
+                }
+            };
+            match change.direction {
+                Direction::Debit => account.deduct_asset(&change.asset, change.amount)?,
+                Direction::Credit => account.add_asset(&change.asset, change.amount)?,
+            }
+        }
+        Ok(staged)
+    }
+
+    /// Balance `account` holds in `asset` once every change of `transaction` is made
+    fn staged_balance(
+        &self,
+        transaction: &BalanceTransaction,
+        account: &Account,
+        asset: &Asset,
+    ) -> Result<Amount, ExchangeError> {
+        let mut balance = self.get_account_or_error(account)?.get_balance(asset);
+        for change in &transaction.changes {
+            if change.account != *account || change.asset != *asset {
+                continue;
+            }
+            balance = match change.direction {
+                Direction::Debit => balance.try_sub(change.amount)?,
+                Direction::Credit => balance.try_add(change.amount)?,
+            };
+        }
+        Ok(balance)
+    }
+
+    fn get_account_or_error(&self, account: &Account) -> Result<&User, ExchangeError> {
+        match account {
+            Account::Escrow => Ok(&self.escrow_user),
+            Account::User(address) => self.get_user_or_error_immutable(address),
+            Account::Margin(address) => self
+                .margin_accounts
+                .get(address)
+                .map(|margin_account| &margin_account.collateral)
+                .ok_or_else(|| ExchangeError::MarginAccountNotFound(address.clone())),
+            Account::Treasury => Ok(&self.treasury),
+            Account::InsuranceFund => Ok(&self.insurance_fund),
+            Account::Pool(pair) => self.get_pool_or_error(pair).map(|pool| &pool.balances),
+        }
+    }
+}
+
+// Contracts of a position left once the `released` ones, as (listing id, contracts), are gone
+fn held_after_release(listing_id: u32, contracts: u32, released: &[(u32, u32)]) -> u32 {
+    released
+        .iter()
+        .filter(|(released_id, _)| *released_id == listing_id)
+        .fold(contracts, |held, (_, released)| {
+            held.saturating_sub(*released)
+        })
 }
@@ -1,15 +1,20 @@
 use crate::Address;
+use crate::amount::{Amount, Price};
 use crate::asset::Asset;
+use crate::clock::{Clock, SystemClock};
+use crate::error::ExchangeError;
 use crate::rbac::{NamedRole, RoleAuthorizer};
+use chrono::{DateTime, Utc};
 use once_cell::sync::OnceCell;
 use std::collections::HashMap;
-use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use strum::IntoEnumIterator; // add this so Asset::iter() is in scope // added to allow mutable access to the singleton
 
+/// Market of `base` priced in `quote`
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct AssetPair {
-    base: Asset,
-    quote: Asset,
+pub struct AssetPair {
+    pub base: Asset,
+    pub quote: Asset,
 }
 
 impl AssetPair {
@@ -18,6 +23,12 @@ impl AssetPair {
     }
 }
 
+impl std::fmt::Display for AssetPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.base, self.quote)
+    }
+}
+
 pub fn default_exchange_rate_provider_admin_address() -> Address {
     Address::from("0xb73B0A92544a5D2523F00F868d795d50DbDfcCf4")
         .expect("Invalid exchange rate provider admin address literal")
@@ -25,8 +36,16 @@ pub fn default_exchange_rate_provider_admin_address() -> Address {
 
 // should be used as a singleton
 pub struct ExchangeRateProvider {
-    exchange_rates: HashMap<AssetPair, f64>, // map pair to quote_amount
+    exchange_rates: HashMap<AssetPair, Price>, // map pair to quote_amount
+    rate_updated_at: HashMap<AssetPair, DateTime<Utc>>, // map pair to the time its rate was last set
     authorizer: RoleAuthorizer,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for ExchangeRateProvider {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ExchangeRateProvider {
@@ -35,7 +54,9 @@ impl ExchangeRateProvider {
 
         let mut provider = ExchangeRateProvider {
             exchange_rates: HashMap::new(),
+            rate_updated_at: HashMap::new(),
             authorizer: RoleAuthorizer::new(role_manager_addr.clone()),
+            clock: Arc::new(SystemClock),
         };
 
         // Set admin for module
@@ -57,7 +78,7 @@ impl ExchangeRateProvider {
                     continue;
                 }
                 let pair = AssetPair::from(base_asset.clone(), quote_asset.clone());
-                provider.exchange_rates.insert(pair, 0.0);
+                provider.exchange_rates.insert(pair, Amount::ZERO);
             }
         }
 
@@ -66,37 +87,64 @@ impl ExchangeRateProvider {
             base: Asset::BTC,
             quote: Asset::USDT,
         };
-        provider.exchange_rates.insert(btc_usdt_pair, 100_000.0);
+        provider
+            .exchange_rates
+            .insert(btc_usdt_pair, Amount::from_int(100_000));
+
+        let now = provider.clock.now();
+        for pair in provider.exchange_rates.keys() {
+            provider.rate_updated_at.insert(pair.clone(), now);
+        }
 
-        return provider;
+        provider
     }
 
-    pub fn get_rate(&self, base: &Asset, quote: &Asset) -> Option<f64> {
+    pub fn get_rate(&self, base: &Asset, quote: &Asset) -> Option<Price> {
         let pair = AssetPair::from(base.clone(), quote.clone());
         self.exchange_rates.get(&pair).copied()
     }
 
+    /// When the rate of a pair was last set, according to the provider's clock
+    pub fn get_rate_updated_at(&self, base: &Asset, quote: &Asset) -> Option<DateTime<Utc>> {
+        let pair = AssetPair::from(base.clone(), quote.clone());
+        self.rate_updated_at.get(&pair).copied()
+    }
+
     pub fn set_rate(
         &mut self,
         base: Asset,
         quote: Asset,
-        rate: f64,
+        rate: Price,
         caller_address: Address,
-    ) -> Result<(), String> {
+    ) -> Result<(), ExchangeError> {
         let admin_role = NamedRole("Admin".to_string());
-        match self
-            .authorizer
-            .only_authorized_role(&[admin_role], caller_address)
-        {
-            Ok(()) => {}
-            Err(e) => {
-                return Err("caller not authorized to update exchange rate".into());
-            }
+        self.authorizer
+            .only_authorized_role(&[admin_role], caller_address)?;
+
+        if rate.is_negative() {
+            return Err(ExchangeError::InvalidInput(
+                "Exchange rate must not be negative".into(),
+            ));
         }
 
         let pair: AssetPair = AssetPair::from(base, quote);
-        self.exchange_rates.insert(pair, rate);
+        self.exchange_rates.insert(pair.clone(), rate);
+        self.rate_updated_at.insert(pair, self.clock.now());
+
+        Ok(())
+    }
+
+    /// Replace the clock used to timestamp rate updates, e.g. with the exchange's simulated clock
+    pub fn set_clock(
+        &mut self,
+        clock: Arc<dyn Clock>,
+        caller_address: Address,
+    ) -> Result<(), ExchangeError> {
+        let admin_role = NamedRole("Admin".to_string());
+        self.authorizer
+            .only_authorized_role(&[admin_role], caller_address)?;
 
+        self.clock = clock;
         Ok(())
     }
 }
@@ -109,16 +157,14 @@ pub fn get_readonly_rate_provider() -> RwLockReadGuard<'static, ExchangeRateProv
     let lock: &'static RwLock<ExchangeRateProvider> =
         EXCHANGE_RATE_PROVIDER.get_or_init(|| RwLock::new(ExchangeRateProvider::new()));
 
-    return lock
-        .read()
-        .expect("Panic: could not get or init exchange rate provider");
+    lock.read()
+        .expect("Panic: could not get or init exchange rate provider")
 }
 
 // Return a write guard. Propagate poisoning via the returned Result so callers can handle it.
 pub fn get_rate_provider() -> RwLockWriteGuard<'static, ExchangeRateProvider> {
     let lock: &'static RwLock<ExchangeRateProvider> =
         EXCHANGE_RATE_PROVIDER.get_or_init(|| RwLock::new(ExchangeRateProvider::new()));
-    return lock
-        .write()
-        .expect("Panic: could not get or init exchange rate provider");
+    lock.write()
+        .expect("Panic: could not get or init exchange rate provider")
 }
@@ -8,12 +8,43 @@ pub mod asset;
 pub mod rbac;
 pub mod exchange_rate_provider;
 pub mod address;
+pub mod clock;
+pub mod amount;
+pub mod error;
+pub mod position;
+pub mod transaction;
+pub mod bid;
+pub mod order_book;
+pub mod spot_book;
+pub mod amm;
+pub mod pricing;
+pub mod vol_surface;
+pub mod binomial;
+pub mod margin;
+pub mod portfolio_margin;
+pub mod strategy;
 
 // Re-export for convenience
 pub use types::{ListingType};
 pub use user::User;
-pub use listing_option::ListingOption;
+pub use listing_option::{ExerciseStyle, ListingOption, OptionSeries, OptionState, SettlementType};
 pub use exchange::Exchange;
 pub use utils::are_addresses_equal;
 pub use asset::Asset;
-pub use address::{Address, AddressError};
\ No newline at end of file
+pub use address::{Address, AddressError};
+pub use clock::{Clock, SimulatedClock, SystemClock};
+pub use amount::{Amount, AmountError, Price, Rounding};
+pub use error::ExchangeError;
+pub use position::Position;
+pub use transaction::{Account, BalanceTransaction};
+pub use bid::OptionBid;
+pub use order_book::{Fill, Order, OrderBook, OrderSide, OrderStatus, OrderType};
+pub use exchange_rate_provider::AssetPair;
+pub use spot_book::{SpotOrder, SpotOrderBook, SpotTrade};
+pub use amm::LiquidityPool;
+pub use pricing::{AssetImpliedVolatility, ImpliedVolatility, OptionValuation, PricingParams};
+pub use vol_surface::{SurfacePoint, VolatilitySurface};
+pub use binomial::BinomialValuation;
+pub use margin::{Liquidation, MarginAccount, MarginHealth, MarginParams};
+pub use portfolio_margin::{Portfolio, PortfolioLeg, PortfolioMarginParams};
+pub use strategy::{DefinedRiskGroup, StrategyFill, StrategyLeg, StrategyOrder, StrategyQuote};
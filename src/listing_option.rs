@@ -1,7 +1,198 @@
 use crate::Address;
+use crate::amount::{Amount, AmountError, Price, Rounding};
 use crate::asset::Asset;
+use crate::error::ExchangeError;
 use crate::types::ListingType;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+
+/// Lifecycle of a listing, see `OptionState::can_transition_to` for the allowed moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptionState {
+    Listed,    // collateral escrowed, open for purchase
+    Purchased, // a beneficiary holds the rights
+    Exercised, // the beneficiary used the rights
+    Unlisted,  // withdrawn by the grantor before anyone bought it
+    Expired,   // lapsed unexercised, collateral released back to the grantor
+    Settled,   // settled by the exchange at expiry or liquidation on the beneficiary's behalf
+}
+
+impl OptionState {
+    pub fn can_transition_to(&self, next: OptionState) -> bool {
+        matches!(
+            (self, next),
+            (OptionState::Listed, OptionState::Purchased)
+                | (OptionState::Listed, OptionState::Unlisted)
+                | (OptionState::Listed, OptionState::Expired)
+                | (OptionState::Purchased, OptionState::Exercised)
+                | (OptionState::Purchased, OptionState::Expired)
+                | (OptionState::Purchased, OptionState::Settled)
+        )
+    }
+
+    /// No further transitions are possible and the collateral has left escrow
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            OptionState::Exercised
+                | OptionState::Unlisted
+                | OptionState::Expired
+                | OptionState::Settled
+        )
+    }
+}
+
+impl std::fmt::Display for OptionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptionState::Listed => write!(f, "listed"),
+            OptionState::Purchased => write!(f, "purchased"),
+            OptionState::Exercised => write!(f, "exercised"),
+            OptionState::Unlisted => write!(f, "unlisted"),
+            OptionState::Expired => write!(f, "expired"),
+            OptionState::Settled => write!(f, "settled"),
+        }
+    }
+}
+
+/// When the holder of a listing may exercise it. Exercise windows close at their date and open
+/// the exchange's `exercise_window` before it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ExerciseStyle {
+    American,                     // any time until expiry
+    European,                     // only in the window closing at expiry
+    Bermudan(Vec<DateTime<Utc>>), // in the windows closing at each date, sorted, and at expiry
+}
+
+impl ExerciseStyle {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExerciseStyle::American => "American",
+            ExerciseStyle::European => "European",
+            ExerciseStyle::Bermudan(_) => "Bermudan",
+        }
+    }
+
+    /// Whether an option expiring at `expiration_time` may be exercised at `time`, given how long
+    /// before each of the style's dates its `exercise_window` opens
+    pub fn allows_exercise_at(
+        &self,
+        time: DateTime<Utc>,
+        expiration_time: DateTime<Utc>,
+        exercise_window: Duration,
+    ) -> bool {
+        self.allows_exercise_between(time, time, expiration_time, exercise_window)
+    }
+
+    /// Whether any exercise window overlaps the span from `from` to `to`
+    pub fn allows_exercise_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        expiration_time: DateTime<Utc>,
+        exercise_window: Duration,
+    ) -> bool {
+        if from > expiration_time {
+            return false;
+        }
+        match self {
+            ExerciseStyle::American => true,
+            _ => self
+                .window_closes(expiration_time)
+                .any(|date| date - exercise_window <= to && from <= date),
+        }
+    }
+
+    /// Earliest time from `time` on at which the style allows exercise, None once expired
+    pub fn next_exercise_time(
+        &self,
+        time: DateTime<Utc>,
+        expiration_time: DateTime<Utc>,
+        exercise_window: Duration,
+    ) -> Option<DateTime<Utc>> {
+        if self.allows_exercise_at(time, expiration_time, exercise_window) {
+            return Some(time);
+        }
+        self.window_closes(expiration_time)
+            .map(|date| date - exercise_window)
+            .filter(|opens_at| *opens_at > time)
+            .min()
+    }
+
+    // Dates the exercise windows close at, expiry last
+    fn window_closes(
+        &self,
+        expiration_time: DateTime<Utc>,
+    ) -> impl Iterator<Item = DateTime<Utc>> + '_ {
+        let dates: &[DateTime<Utc>] = match self {
+            ExerciseStyle::Bermudan(dates) => dates,
+            _ => &[],
+        };
+        dates
+            .iter()
+            .copied()
+            .chain(std::iter::once(expiration_time))
+    }
+}
+
+impl std::fmt::Display for ExerciseStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExerciseStyle::Bermudan(dates) => write!(f, "Bermudan ({} dates)", dates.len()),
+            style => write!(f, "{}", style.name()),
+        }
+    }
+}
+
+/// What changes hands when a listing is exercised
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SettlementType {
+    Physical, // the holder pays the strike and receives the escrowed collateral
+    Cash,     // the holder receives the intrinsic value out of the escrowed collateral
+}
+
+impl SettlementType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SettlementType::Physical => "physical",
+            SettlementType::Cash => "cash",
+        }
+    }
+}
+
+impl std::fmt::Display for SettlementType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Terms shared by every listing that is interchangeable for a buyer
+/// (listings written on margin settle the same as ones escrowing their own collateral)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OptionSeries {
+    pub base_asset: Asset,
+    pub quote_asset: Asset,
+    pub listing_type: ListingType,
+    pub strike_price: Price,
+    pub expiration_time: DateTime<Utc>,
+    pub exercise_style: ExerciseStyle,
+    pub settlement_type: SettlementType,
+}
+
+impl std::fmt::Display for OptionSeries {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} {} {} {} {} {}",
+            self.base_asset,
+            self.quote_asset,
+            self.listing_type,
+            self.strike_price,
+            self.expiration_time.format("%Y-%m-%d %H:%M"),
+            self.exercise_style,
+            self.settlement_type
+        )
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ListingOption {
@@ -9,37 +200,35 @@ pub struct ListingOption {
     pub base_asset: Asset,
     pub quote_asset: Asset,
     pub listing_type: ListingType,
-    pub strike_price: f64, // based on base asset
-    pub ask_price: f64,    // based on quote asset
-    pub bid_price: f64,    // based on quote asset
+    pub strike_price: Price, // based on base asset
+    pub ask_price: Price,    // based on quote asset
+    pub bid_price: Price,    // based on quote asset
     pub expiration_time: DateTime<Utc>,
     pub grantor_address: Address,
-    pub beneficiary_address: Option<Address>, // the one who has the right to buy/sell, defaults to None
-    pub exercise_amount: f64,                 // based on quote asset
-
-    // State transitions
-    pub is_purchased: bool,
-    pub is_unlisted: bool,
-    pub is_exercised: bool, //  whether the option contract has been exercised by the beneficiary or not
+    pub contract_count: u32, // contracts still offered for sale, holders are tracked by the exchange
+    pub exercise_amount: Amount, // per contract, based on quote asset
+    pub exercise_style: ExerciseStyle,
+    pub settlement_type: SettlementType,
+    pub state: OptionState,
 }
 
 impl ListingOption {
-    // TODO: add new() function here
+    /// An American, physically settled listing, see `with_exercise_style` and
+    /// `with_settlement_type` for the others
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         listing_id: u32,
         base_asset: Asset,
         quote_asset: Asset,
         listing_type: ListingType,
-        strike_price: f64,
-        ask_price: f64,
-        bid_price: f64,
+        strike_price: Price,
+        ask_price: Price,
+        bid_price: Price,
         expiration_time: DateTime<Utc>,
         grantor_address: Address,
-        beneficiary_address: Option<Address>,
-        exercise_amount: f64,
-        is_purchased: bool,
-        is_unlisted: bool,
-        is_exercised: bool,
+        contract_count: u32,
+        exercise_amount: Amount,
+        state: OptionState,
     ) -> Self {
         ListingOption {
             listing_id,
@@ -51,46 +240,176 @@ impl ListingOption {
             bid_price,
             expiration_time,
             grantor_address,
-            beneficiary_address,
+            contract_count,
             exercise_amount,
-            is_purchased,
-            is_unlisted,
-            is_exercised,
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state,
+        }
+    }
+
+    pub fn with_exercise_style(mut self, exercise_style: ExerciseStyle) -> Self {
+        self.exercise_style = exercise_style;
+        self
+    }
+
+    pub fn with_settlement_type(mut self, settlement_type: SettlementType) -> Self {
+        self.settlement_type = settlement_type;
+        self
+    }
+
+    /// Whether the holder may exercise at `time` under the listing's exercise style
+    pub fn can_exercise_at(&self, time: DateTime<Utc>, exercise_window: Duration) -> bool {
+        self.exercise_style
+            .allows_exercise_at(time, self.expiration_time, exercise_window)
+    }
+
+    /// Earliest time from `time` on at which the holder may exercise, None once expired
+    pub fn next_exercise_time(
+        &self,
+        time: DateTime<Utc>,
+        exercise_window: Duration,
+    ) -> Option<DateTime<Utc>> {
+        self.exercise_style
+            .next_exercise_time(time, self.expiration_time, exercise_window)
+    }
+
+    /// Whether the listing still quotes its ask, open or partly sold with contracts left
+    pub fn has_unsold_contracts(&self) -> bool {
+        matches!(self.state, OptionState::Listed | OptionState::Purchased)
+            && self.contract_count > 0
+    }
+
+    /// The series this listing belongs to, resting bids are matched against it
+    pub fn series(&self) -> OptionSeries {
+        OptionSeries {
+            base_asset: self.base_asset.clone(),
+            quote_asset: self.quote_asset.clone(),
+            listing_type: self.listing_type.clone(),
+            strike_price: self.strike_price,
+            expiration_time: self.expiration_time,
+            exercise_style: self.exercise_style.clone(),
+            settlement_type: self.settlement_type,
+        }
+    }
+
+    /// Check that the listing may move to `next` without moving it,
+    /// so callers can validate before any funds change hands
+    pub fn check_transition(&self, next: OptionState) -> Result<(), ExchangeError> {
+        if self.state == OptionState::Expired {
+            return Err(ExchangeError::Expired(self.listing_id));
+        }
+        if !self.state.can_transition_to(next) {
+            return Err(ExchangeError::InvalidTransition {
+                listing_id: self.listing_id,
+                from: self.state,
+                to: next,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn transition_to(&mut self, next: OptionState) -> Result<(), ExchangeError> {
+        self.check_transition(next)?;
+        self.state = next;
+        Ok(())
+    }
+
+    /// Premium of a single contract in quote asset, rounded up to the quote asset's precision since the buyer pays it
+    pub fn get_premium_price(&self) -> Result<Amount, AmountError> {
+        Ok(self
+            .ask_price
+            .try_mul_int(100)?
+            .round_to_asset(&self.quote_asset, Rounding::Up))
+    }
+
+    /// Quote amount exchanged for one contract's `exercise_amount` at the strike, rounded up since it's
+    /// collected (PUT collateral, CALL exercise payment) before it is paid out
+    pub fn get_strike_value(&self) -> Result<Amount, AmountError> {
+        Ok(self
+            .exercise_amount
+            .try_mul(self.strike_price, Rounding::Up)?
+            .round_to_asset(&self.quote_asset, Rounding::Up))
+    }
+
+    /// Whether `spot` is beyond the strike in the holder's favour by at least `threshold_bps` of it
+    pub fn is_in_the_money(&self, spot: Price, threshold_bps: u16) -> Result<bool, AmountError> {
+        let threshold = self.strike_price.try_mul_bps(threshold_bps, Rounding::Up)?;
+        let intrinsic_value = match self.listing_type {
+            ListingType::CALL => spot.try_sub(self.strike_price)?,
+            ListingType::PUT => self.strike_price.try_sub(spot)?,
+        };
+        Ok(intrinsic_value > Amount::ZERO && intrinsic_value >= threshold)
+    }
+
+    /// Quote value one contract is in the money by at `spot`, rounded down since it's paid out
+    pub fn get_intrinsic_value(&self, spot: Price) -> Result<Amount, AmountError> {
+        Ok(self
+            .intrinsic_value(spot)?
+            .round_to_asset(&self.quote_asset, Rounding::Down))
+    }
+
+    /// Collateral one contract cash-settled at `spot` gives up to pay the holder its intrinsic value
+    /// in quote. A PUT pays it straight out of its quote collateral. A CALL escrows base, so that
+    /// much base is sold to the spot desk at `spot` for the payout, rounded up so the desk is
+    /// never short. When the desk can't buy it, the holder takes that base instead.
+    pub fn get_cash_settlement_amount(&self, spot: Price) -> Result<Amount, AmountError> {
+        match self.listing_type {
+            ListingType::CALL if spot > Amount::ZERO => Ok(self
+                .get_intrinsic_value(spot)?
+                .try_div(spot, Rounding::Up)?
+                .round_to_asset(&self.base_asset, Rounding::Up)),
+            ListingType::CALL => Ok(Amount::ZERO),
+            ListingType::PUT => self.get_intrinsic_value(spot),
+        }
+    }
+
+    // Unrounded quote value of `exercise_amount` in the money, zero out of the money
+    fn intrinsic_value(&self, spot: Price) -> Result<Amount, AmountError> {
+        let intrinsic_value = match self.listing_type {
+            ListingType::CALL => spot.try_sub(self.strike_price)?,
+            ListingType::PUT => self.strike_price.try_sub(spot)?,
+        };
+        if intrinsic_value <= Amount::ZERO {
+            return Ok(Amount::ZERO);
         }
+        self.exercise_amount
+            .try_mul(intrinsic_value, Rounding::Down)
     }
 
-    pub fn get_premium_price(&self) -> f64 {
-        self.ask_price * 100.0
+    /// Scale a per-contract amount from the getters below to `contracts` contracts
+    pub fn for_contracts(amount: Amount, contracts: u32) -> Result<Amount, AmountError> {
+        amount.try_mul_int(i64::from(contracts))
     }
 
-    pub fn get_buy_amount(&self, is_for_grantor: bool) -> f64 {
+    pub fn get_buy_amount(&self, is_for_grantor: bool) -> Result<Amount, AmountError> {
         match (self.listing_type.clone(), is_for_grantor) {
             // `get_buy_amount(true)` is used by tests to inspect the price-scaled
             // value (premium-equivalent) of the strike. It returns strike * 100.
-            (ListingType::CALL, true) => self.strike_price * 100.0,
-            (ListingType::PUT, true) => self.strike_price * 100.0,
+            (ListingType::CALL, true) => self.strike_price.try_mul_int(100),
+            (ListingType::PUT, true) => self.strike_price.try_mul_int(100),
 
             // From the beneficiary viewpoint, the buy amount is the quantity they
             // will receive when exercising: for CALL it's base units, for PUT it's
             // the quote amount equal to strike * exercise_amount.
-            (ListingType::CALL, false) => self.exercise_amount,
-            (ListingType::PUT, false) => self.exercise_amount * self.strike_price,
+            (ListingType::CALL, false) => Ok(self.exercise_amount),
+            (ListingType::PUT, false) => self.get_strike_value(),
         }
     }
 
-    pub fn get_sell_amount(&self, is_for_grantor: bool) -> f64 {
+    pub fn get_sell_amount(&self, is_for_grantor: bool) -> Result<Amount, AmountError> {
         match (self.listing_type.clone(), is_for_grantor) {
             // What the grantor must escrow (sell amount from their POV):
             // - CALL: grantor escrows base asset units (exercise_amount)
             // - PUT: grantor escrows quote asset equal to strike * exercise_amount
-            (ListingType::CALL, true) => self.exercise_amount,
-            (ListingType::PUT, true) => self.exercise_amount * self.strike_price,
+            (ListingType::CALL, true) => Ok(self.exercise_amount),
+            (ListingType::PUT, true) => self.get_strike_value(),
 
             // From the beneficiary POV, the sell amount is the amount they must
             // deliver to exercise: for CALL they pay quote (strike * exercise_amount),
             // for PUT they deliver base (exercise_amount).
-            (ListingType::CALL, false) => self.exercise_amount * self.strike_price,
-            (ListingType::PUT, false) => self.exercise_amount,
+            (ListingType::CALL, false) => self.get_strike_value(),
+            (ListingType::PUT, false) => Ok(self.exercise_amount),
         }
     }
 
@@ -0,0 +1,153 @@
+// margin.rs - Margin accounts that let writers post part of an option's collateral
+//
+// A listing written from a margin account escrows nothing of its own. Its writer keeps quote
+// collateral in the account instead, enough to cover a risk based requirement on every short
+// contract, and pays the intrinsic value out of it when the holder exercises. An account whose
+// collateral falls under its maintenance requirement gets liquidated, see `Liquidation`.
+
+use chrono::{DateTime, Utc};
+
+use crate::address::Address;
+use crate::amount::{Amount, AmountError, Price, Rounding};
+use crate::asset::Asset;
+use crate::listing_option::ListingOption;
+use crate::strategy::DefinedRiskGroup;
+use crate::types::ListingType;
+use crate::user::User;
+
+/// Margin rates in basis points, see `MarginParams::requirement` for the formula
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarginParams {
+    pub initial_margin_bps: u16, // of the underlying's value, to write new contracts or withdraw
+    pub maintenance_margin_bps: u16, // of the underlying's value, below which an account is unhealthy
+    pub minimum_margin_bps: u16, // floor, of the underlying for calls and of the strike for puts
+}
+
+impl Default for MarginParams {
+    fn default() -> Self {
+        MarginParams {
+            initial_margin_bps: 2_000,
+            maintenance_margin_bps: 1_500,
+            minimum_margin_bps: 1_000,
+        }
+    }
+}
+
+impl MarginParams {
+    /// Quote collateral one short contract needs to be written at `spot`
+    pub fn initial_requirement(
+        &self,
+        option: &ListingOption,
+        spot: Price,
+    ) -> Result<Amount, AmountError> {
+        self.requirement(option, spot, self.initial_margin_bps)
+    }
+
+    /// Quote collateral one short contract needs to stay healthy at `spot`
+    pub fn maintenance_requirement(
+        &self,
+        option: &ListingOption,
+        spot: Price,
+    ) -> Result<Amount, AmountError> {
+        self.requirement(option, spot, self.maintenance_margin_bps)
+    }
+
+    /// The intrinsic value owed today, plus `margin_bps` of the underlying's value less how far
+    /// out of the money the contract is, but never less than `minimum_margin_bps` of the
+    /// underlying (calls) or strike (puts). A put never needs more than its strike value, the
+    /// most its writer can owe. Rounded up since it's collateral.
+    pub fn requirement(
+        &self,
+        option: &ListingOption,
+        spot: Price,
+        margin_bps: u16,
+    ) -> Result<Amount, AmountError> {
+        let underlying_value = option.exercise_amount.try_mul(spot, Rounding::Up)?;
+        let strike_value = option.get_strike_value()?;
+        let (out_of_the_money, floor_value) = match option.listing_type {
+            ListingType::CALL => (
+                option
+                    .exercise_amount
+                    .try_mul(option.strike_price.try_sub(spot)?, Rounding::Down)?,
+                underlying_value,
+            ),
+            ListingType::PUT => (
+                option
+                    .exercise_amount
+                    .try_mul(spot.try_sub(option.strike_price)?, Rounding::Down)?,
+                strike_value,
+            ),
+        };
+
+        let risk = underlying_value
+            .try_mul_bps(margin_bps, Rounding::Up)?
+            .try_sub(out_of_the_money.max(Amount::ZERO))?
+            .max(floor_value.try_mul_bps(self.minimum_margin_bps, Rounding::Up)?);
+        let mut requirement = option.get_intrinsic_value(spot)?.try_add(risk)?;
+        if option.listing_type == ListingType::PUT {
+            requirement = requirement.min(strike_value);
+        }
+        Ok(requirement.round_to_asset(&option.quote_asset, Rounding::Up))
+    }
+}
+
+/// Collateral a writer posted for the listings they wrote on margin
+#[derive(Clone)]
+pub struct MarginAccount {
+    pub owner: Address,
+    pub collateral: User, // balances held by the exchange, see `Account::Margin`
+    pub listing_ids: Vec<u32>, // listings written from the account, oldest first, less unlisted ones
+    pub portfolio_margin: bool, // margined on the whole book instead of per listing, see `Portfolio`
+    pub defined_risk: Vec<DefinedRiskGroup>, // strategies margined at their maximum loss instead of per listing
+}
+
+impl MarginAccount {
+    pub fn new(owner: Address) -> Self {
+        MarginAccount {
+            collateral: User::new(owner.clone()),
+            owner,
+            listing_ids: Vec::new(),
+            portfolio_margin: false,
+            defined_risk: Vec::new(),
+        }
+    }
+}
+
+/// Collateral of a margin account in one quote asset against what its open short contracts need
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarginHealth {
+    pub owner: Address,
+    pub asset: Asset,
+    pub collateral: Amount,
+    pub initial_requirement: Amount,
+    pub maintenance_requirement: Amount,
+}
+
+impl MarginHealth {
+    /// The collateral still covers the maintenance requirement
+    pub fn is_healthy(&self) -> bool {
+        self.collateral >= self.maintenance_requirement
+    }
+
+    /// Collateral beyond the initial requirement, free to withdraw or write against.
+    /// Negative once the account can't write anything new.
+    pub fn free_collateral(&self) -> Result<Amount, AmountError> {
+        self.collateral.try_sub(self.initial_requirement)
+    }
+}
+
+/// A margin account closed out in one quote asset once its collateral fell under maintenance.
+/// Every amount is in `asset`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Liquidation {
+    pub owner: Address,
+    pub asset: Asset,
+    pub listing_ids: Vec<u32>, // listings closed out, oldest first
+    pub contracts: u32,        // open contracts bought back from their holders
+    pub buyback_cost: Amount,  // intrinsic value paid to the holders
+    pub collateral_sold: Vec<(Asset, Amount)>, // other collateral sold to the spot desk
+    pub sale_proceeds: Amount,
+    pub penalty: Amount, // taken from what was left and paid into the insurance fund
+    pub shortfall: Amount, // part of the buyback the collateral couldn't pay, covered by the insurance fund
+    pub time: DateTime<Utc>,
+}
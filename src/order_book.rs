@@ -0,0 +1,104 @@
+// order_book.rs - Orders, fills and the price-time ranked book of an option series
+
+use crate::address::Address;
+use crate::amount::Price;
+use crate::listing_option::OptionSeries;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderSide {
+    Buy,  // rests as an `OptionBid`
+    Sell, // rests as a `ListingOption`
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderType {
+    Limit(Price), // per contract, based on quote asset, the remainder rests in the book
+    Market,       // takes whatever the book offers, the remainder is dropped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderStatus {
+    Open,            // resting, nothing filled yet
+    PartiallyFilled, // resting with some contracts filled
+    Filled,          // every contract filled
+    Cancelled,       // withdrawn, or a market order that ran out of liquidity
+    Expired,         // the series expired before the order filled
+}
+
+impl std::fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderStatus::Open => write!(f, "open"),
+            OrderStatus::PartiallyFilled => write!(f, "partially filled"),
+            OrderStatus::Filled => write!(f, "filled"),
+            OrderStatus::Cancelled => write!(f, "cancelled"),
+            OrderStatus::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+/// Order submitted through the matching engine. Its resting part lives in the exchange
+/// as a listing (sell) or a bid (buy), `book_id` points at it.
+#[derive(Debug, Clone)]
+pub struct Order {
+    pub order_id: u32,
+    pub series: OptionSeries,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub owner_address: Address,
+    pub contract_count: u32, // contracts requested
+    pub book_id: u32,        // listing id for sell orders, bid id for buy orders
+}
+
+/// Contracts of a listing changing hands. Every purchase is recorded as a fill,
+/// whether it came from an order, a direct purchase or a resting bid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub listing_id: u32,
+    pub bid_id: Option<u32>, // set when the buyer's side was a bid
+    pub buyer_address: Address,
+    pub seller_address: Address,
+    pub contracts: u32,
+    pub price: Price, // per contract, based on quote asset
+    pub time: DateTime<Utc>,
+}
+
+/// One resting listing or bid in a book
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookEntry {
+    pub id: u32, // listing id for asks, bid id for bids
+    pub owner_address: Address,
+    pub price: Price,
+    pub contracts: u32,
+}
+
+/// Snapshot of a series' resting liquidity, best price first. Ids grow with time,
+/// so ties at the same price go to the older entry.
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    pub series: OptionSeries,
+    pub bids: Vec<BookEntry>, // highest price first
+    pub asks: Vec<BookEntry>, // lowest price first
+}
+
+impl OrderBook {
+    /// Sort both sides into price-time priority
+    pub fn new(series: OptionSeries, mut bids: Vec<BookEntry>, mut asks: Vec<BookEntry>) -> Self {
+        bids.sort_by(|a, b| b.price.cmp(&a.price).then(a.id.cmp(&b.id)));
+        asks.sort_by(|a, b| a.price.cmp(&b.price).then(a.id.cmp(&b.id)));
+        OrderBook { series, bids, asks }
+    }
+
+    pub fn best_bid(&self) -> Option<&BookEntry> {
+        self.bids.first()
+    }
+
+    pub fn best_ask(&self) -> Option<&BookEntry> {
+        self.asks.first()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+}
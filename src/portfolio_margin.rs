@@ -0,0 +1,199 @@
+// portfolio_margin.rs - Margin on a whole book of spot and option positions instead of per listing
+//
+// A margin account in portfolio mode is revalued under a grid of spot and volatility shocks,
+// one underlying at a time, so a long call offsets a short call and spot BTC covers a short BTC
+// call. The requirement is the worst loss against today's value the grid turns up. Like the pricing models it works in
+// floating point, only the resulting requirement is rounded to the quote asset.
+
+use crate::amount::{Amount, Price, Rounding};
+use crate::asset::Asset;
+use crate::error::ExchangeError;
+use crate::listing_option::ListingOption;
+use crate::pricing::{self, PricingParams};
+use crate::types::ListingType;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// Shocks a portfolio is revalued under. Spot moves reach as far either side of the current rate
+/// as the margin rate the requirement is for, see `MarginParams`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortfolioMarginParams {
+    pub pricing_params: PricingParams, // volatility and rate options are revalued at before shocks
+    pub spot_steps: usize,             // grid points either side of the current spot
+    pub vol_shocks: Vec<f64>,          // relative volatility moves, -0.3 for 30% lower
+}
+
+impl Default for PortfolioMarginParams {
+    fn default() -> Self {
+        PortfolioMarginParams {
+            pricing_params: PricingParams::new(0.8, 0.04),
+            spot_steps: 3,
+            vol_shocks: vec![-0.3, 0.0, 0.3],
+        }
+    }
+}
+
+impl PortfolioMarginParams {
+    /// Every (spot shock, volatility shock) pair of the grid, spot shocks spread evenly up to
+    /// `max_spot_shock_bps` either side and including no move at all
+    pub fn shock_grid(&self, max_spot_shock_bps: u16) -> Vec<(f64, f64)> {
+        let max_spot_shock = f64::from(max_spot_shock_bps) / 10_000.0;
+        let steps = self.spot_steps as i64;
+        let mut grid = Vec::new();
+        for step in -steps..=steps {
+            let spot_shock = if steps == 0 {
+                0.0
+            } else {
+                max_spot_shock * step as f64 / steps as f64
+            };
+            for &vol_shock in &self.vol_shocks {
+                grid.push((spot_shock, vol_shock));
+            }
+        }
+        grid
+    }
+}
+
+/// Contracts of one listing an account holds, or has written when negative
+#[derive(Debug, Clone)]
+pub struct PortfolioLeg {
+    pub option: ListingOption,
+    pub contracts: i64,
+}
+
+impl PortfolioLeg {
+    /// Contracts of a written leg as a count, zero for a bought one
+    pub fn written_contracts(&self) -> u32 {
+        u32::try_from(self.contracts.min(0).unsigned_abs()).unwrap_or(u32::MAX)
+    }
+
+    // Intrinsic value of one contract at `spot`, zero out of the money
+    fn intrinsic_value(&self, spot: f64) -> f64 {
+        let strike = self.option.strike_price.to_f64();
+        let per_unit = match self.option.listing_type {
+            ListingType::CALL => spot - strike,
+            ListingType::PUT => strike - spot,
+        };
+        self.option.exercise_amount.to_f64() * per_unit.max(0.0)
+    }
+}
+
+/// Spot balances and option legs of an account in one quote asset
+#[derive(Debug, Clone)]
+pub struct Portfolio {
+    pub quote_asset: Asset,
+    pub spot_balances: HashMap<Asset, Amount>, // base assets held, revalued alongside their options
+    pub legs: Vec<PortfolioLeg>,
+}
+
+impl Portfolio {
+    pub fn new(quote_asset: Asset) -> Self {
+        Portfolio {
+            quote_asset,
+            spot_balances: HashMap::new(),
+            legs: Vec::new(),
+        }
+    }
+
+    /// Base assets the portfolio holds or has options on, in asset order
+    pub fn underlyings(&self) -> Vec<Asset> {
+        let mut underlyings: Vec<Asset> = self
+            .spot_balances
+            .keys()
+            .cloned()
+            .chain(self.legs.iter().map(|leg| leg.option.base_asset.clone()))
+            .collect();
+        underlyings.sort_by_key(|asset| asset.to_string());
+        underlyings.dedup();
+        underlyings
+    }
+
+    /// Worst loss over the shock grid against the value at today's `spots`, summed over the
+    /// underlyings with no offsets between them. An underlying never needs less than the
+    /// intrinsic value its short legs owe at today's `spots`, which settles in quote however well
+    /// hedged it is, and needs nothing without short legs. Every underlying needs a spot in
+    /// `spots`, rounded up since it's collateral.
+    pub fn requirement(
+        &self,
+        params: &PortfolioMarginParams,
+        max_spot_shock_bps: u16,
+        spots: &HashMap<Asset, Price>,
+        now: DateTime<Utc>,
+    ) -> Result<Amount, ExchangeError> {
+        let grid = params.shock_grid(max_spot_shock_bps);
+        let mut requirement = 0.0;
+        for base_asset in self.underlyings() {
+            let spot = spots
+                .get(&base_asset)
+                .ok_or_else(|| ExchangeError::RateMissing {
+                    base: base_asset.clone(),
+                    quote: self.quote_asset.clone(),
+                })?
+                .to_f64();
+            if !self
+                .legs
+                .iter()
+                .any(|leg| leg.option.base_asset == base_asset && leg.contracts < 0)
+            {
+                continue;
+            }
+
+            let current_value = self.value(&base_asset, spot, &params.pricing_params, now);
+            let mut worst_loss = self.owed(&base_asset, spot);
+            for &(spot_shock, vol_shock) in &grid {
+                let shocked_params = PricingParams {
+                    volatility: params.pricing_params.volatility * (1.0 + vol_shock),
+                    ..params.pricing_params
+                };
+                let value =
+                    self.value(&base_asset, spot * (1.0 + spot_shock), &shocked_params, now);
+                worst_loss = worst_loss.max(current_value - value);
+            }
+            requirement += worst_loss;
+        }
+
+        Ok(Amount::try_from_f64(requirement)?.round_to_asset(&self.quote_asset, Rounding::Up))
+    }
+
+    /// Value of the spot balance and every leg on one underlying at `spot`, in quote. Options
+    /// are worth their model price but never less than what exercising or cash settling pays.
+    fn value(
+        &self,
+        base_asset: &Asset,
+        spot: f64,
+        pricing_params: &PricingParams,
+        now: DateTime<Utc>,
+    ) -> f64 {
+        let spot_value = self
+            .spot_balances
+            .get(base_asset)
+            .map_or(0.0, |balance| balance.to_f64() * spot);
+        let options_value: f64 = self
+            .legs
+            .iter()
+            .filter(|leg| leg.option.base_asset == *base_asset)
+            .map(|leg| {
+                let price = pricing::black_scholes(
+                    &leg.option.listing_type,
+                    spot,
+                    leg.option.strike_price.to_f64(),
+                    pricing::years_until(now, leg.option.expiration_time),
+                    pricing_params,
+                )
+                .price;
+                let unit_value = leg.option.exercise_amount.to_f64() * price;
+                leg.contracts as f64 * unit_value.max(leg.intrinsic_value(spot))
+            })
+            .sum();
+        spot_value + options_value
+    }
+
+    /// What the short legs on one underlying would pay out if exercised at `spot`, in quote
+    fn owed(&self, base_asset: &Asset, spot: f64) -> f64 {
+        self.legs
+            .iter()
+            .filter(|leg| leg.option.base_asset == *base_asset && leg.contracts < 0)
+            .map(|leg| -(leg.contracts as f64) * leg.intrinsic_value(spot))
+            .sum()
+    }
+}
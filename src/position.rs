@@ -0,0 +1,29 @@
+// position.rs - Contracts of a listing held by a single beneficiary
+
+use crate::address::Address;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub listing_id: u32,
+    pub holder_address: Address,
+    pub contracts: u32,           // open contracts, exercisable until expiry
+    pub exercised_contracts: u32, // contracts this holder already exercised
+    pub auto_exercise: bool,      // let the exchange exercise open contracts in the money at expiry
+}
+
+impl Position {
+    pub fn new(listing_id: u32, holder_address: Address) -> Self {
+        Position {
+            listing_id,
+            holder_address,
+            contracts: 0,
+            exercised_contracts: 0,
+            auto_exercise: true,
+        }
+    }
+
+    /// A position can be dropped once it holds nothing and has no exercise history
+    pub fn is_empty(&self) -> bool {
+        self.contracts == 0 && self.exercised_contracts == 0
+    }
+}
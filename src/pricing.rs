@@ -0,0 +1,388 @@
+// pricing.rs - Black-Scholes fair value, Greeks and implied volatility of listed options
+//
+// Valuation is a floating point model: amounts are converted with `Amount::to_f64` and the
+// results are meant for quoting and risk, never for moving balances.
+
+use crate::amount::{Amount, Price};
+use crate::asset::Asset;
+use crate::error::ExchangeError;
+use crate::exchange_rate_provider::get_readonly_rate_provider;
+use crate::listing_option::{ListingOption, OptionSeries};
+use crate::types::ListingType;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+
+/// Range of volatilities the implied volatility solver searches
+const MIN_IMPLIED_VOLATILITY: f64 = 0.0001;
+const MAX_IMPLIED_VOLATILITY: f64 = 10.0;
+
+/// The solver stops once the model price is this close to the market price, in quote asset
+const IMPLIED_VOLATILITY_TOLERANCE: f64 = 1e-9;
+const MAX_SOLVER_ITERATIONS: usize = 100;
+
+/// Model inputs that neither the listing nor the rate provider supply
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PricingParams {
+    pub volatility: f64,     // annualized, 0.8 for 80%
+    pub risk_free_rate: f64, // annualized and continuously compounded, 0.05 for 5%
+}
+
+impl PricingParams {
+    pub fn new(volatility: f64, risk_free_rate: f64) -> Self {
+        PricingParams {
+            volatility,
+            risk_free_rate,
+        }
+    }
+}
+
+/// Theoretical price of an option and its sensitivities, in quote asset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptionValuation {
+    pub price: f64,
+    pub delta: f64, // per unit move of the spot
+    pub gamma: f64, // change of delta per unit move of the spot
+    pub vega: f64,  // per percentage point of volatility
+    pub theta: f64, // per calendar day that passes
+    pub rho: f64,   // per percentage point of the risk-free rate
+}
+
+impl OptionValuation {
+    /// Valuation of `factor` units of the underlying, e.g. a contract's `exercise_amount`
+    pub fn scale(&self, factor: f64) -> OptionValuation {
+        OptionValuation {
+            price: self.price * factor,
+            delta: self.delta * factor,
+            gamma: self.gamma * factor,
+            vega: self.vega * factor,
+            theta: self.theta * factor,
+            rho: self.rho * factor,
+        }
+    }
+}
+
+/// Black-Scholes valuation of an option on one unit of the underlying. Without time or
+/// volatility left the option is only worth its intrinsic value against the discounted strike.
+pub fn black_scholes(
+    listing_type: &ListingType,
+    spot: f64,
+    strike: f64,
+    years: f64,
+    params: &PricingParams,
+) -> OptionValuation {
+    let rate = params.risk_free_rate;
+    let years = years.max(0.0);
+    let discounted_strike = strike * (-rate * years).exp();
+
+    if years == 0.0 || params.volatility <= 0.0 {
+        let (price, delta) = match listing_type {
+            ListingType::CALL if spot > discounted_strike => (spot - discounted_strike, 1.0),
+            ListingType::PUT if spot < discounted_strike => (discounted_strike - spot, -1.0),
+            _ => (0.0, 0.0),
+        };
+        return OptionValuation {
+            price,
+            delta,
+            gamma: 0.0,
+            vega: 0.0,
+            theta: 0.0,
+            rho: 0.0,
+        };
+    }
+
+    let vol_sqrt_t = params.volatility * years.sqrt();
+    let d1 = ((spot / strike).ln() + (rate + params.volatility.powi(2) / 2.0) * years) / vol_sqrt_t;
+    let d2 = d1 - vol_sqrt_t;
+    // time decay of the volatility part, the same for calls and puts
+    let decay = -spot * normal_pdf(d1) * params.volatility / (2.0 * years.sqrt());
+
+    let (price, delta, theta, rho) = match listing_type {
+        ListingType::CALL => (
+            spot * normal_cdf(d1) - discounted_strike * normal_cdf(d2),
+            normal_cdf(d1),
+            decay - rate * discounted_strike * normal_cdf(d2),
+            years * discounted_strike * normal_cdf(d2),
+        ),
+        ListingType::PUT => (
+            discounted_strike * normal_cdf(-d2) - spot * normal_cdf(-d1),
+            normal_cdf(d1) - 1.0,
+            decay + rate * discounted_strike * normal_cdf(-d2),
+            -years * discounted_strike * normal_cdf(-d2),
+        ),
+    };
+
+    OptionValuation {
+        price,
+        delta,
+        gamma: normal_pdf(d1) / (spot * vol_sqrt_t),
+        vega: spot * normal_pdf(d1) * years.sqrt() / 100.0,
+        theta: theta / 365.0,
+        rho: rho / 100.0,
+    }
+}
+
+/// Time from `now` to `expiration_time` in years, zero once it has passed
+pub fn years_until(now: DateTime<Utc>, expiration_time: DateTime<Utc>) -> f64 {
+    ((expiration_time - now).num_seconds() as f64 / SECONDS_PER_YEAR).max(0.0)
+}
+
+/// Value one unit of a series' underlying at the rate provider's current spot
+pub fn value_series(
+    series: &OptionSeries,
+    params: &PricingParams,
+    now: DateTime<Utc>,
+) -> Result<OptionValuation, ExchangeError> {
+    Ok(black_scholes(
+        &series.listing_type,
+        spot_rate(&series.base_asset, &series.quote_asset)?,
+        series.strike_price.to_f64(),
+        years_until(now, series.expiration_time),
+        params,
+    ))
+}
+
+/// Value one contract of a listing, which covers `exercise_amount` of the underlying
+pub fn value_listing(
+    option: &ListingOption,
+    params: &PricingParams,
+    now: DateTime<Utc>,
+) -> Result<OptionValuation, ExchangeError> {
+    Ok(value_series(&option.series(), params, now)?.scale(option.exercise_amount.to_f64()))
+}
+
+/// Ask price at which a listing's premium (ask price * 100 per contract) matches its value
+pub fn fair_ask_price(
+    option: &ListingOption,
+    params: &PricingParams,
+    now: DateTime<Utc>,
+) -> Result<Price, ExchangeError> {
+    let value = value_listing(option, params, now)?;
+    Ok(Amount::try_from_f64(value.price / 100.0)?)
+}
+
+/// Volatility at which the Black-Scholes price of one unit matches `price`. Newton steps on
+/// vega converge quickly near the money, a bisection step takes over whenever Newton would leave
+/// the bracket known to hold the answer. None if no volatility in the searched range explains the price.
+pub fn implied_volatility(
+    listing_type: &ListingType,
+    price: f64,
+    spot: f64,
+    strike: f64,
+    years: f64,
+    risk_free_rate: f64,
+) -> Option<f64> {
+    if price <= 0.0 || spot <= 0.0 || strike <= 0.0 || years <= 0.0 {
+        return None;
+    }
+    let value_at = |volatility: f64| {
+        black_scholes(
+            listing_type,
+            spot,
+            strike,
+            years,
+            &PricingParams::new(volatility, risk_free_rate),
+        )
+    };
+
+    // the price grows with volatility, so the bounds must bracket it
+    let (mut low, mut high) = (MIN_IMPLIED_VOLATILITY, MAX_IMPLIED_VOLATILITY);
+    if price < value_at(low).price || price > value_at(high).price {
+        return None;
+    }
+
+    // Brenner-Subrahmanyam approximation for an at the money option as the first guess
+    let mut volatility =
+        ((2.0 * std::f64::consts::PI / years).sqrt() * price / spot).clamp(low, high);
+    for _ in 0..MAX_SOLVER_ITERATIONS {
+        let valuation = value_at(volatility);
+        let error = valuation.price - price;
+        if error.abs() < IMPLIED_VOLATILITY_TOLERANCE {
+            break;
+        }
+        if error > 0.0 {
+            high = volatility;
+        } else {
+            low = volatility;
+        }
+
+        let vega = valuation.vega * 100.0; // per unit of volatility rather than per point
+        let newton = volatility - error / vega;
+        volatility = if vega > 0.0 && newton > low && newton < high {
+            newton
+        } else {
+            (low + high) / 2.0
+        };
+    }
+
+    Some(volatility)
+}
+
+/// Volatilities implied by the quotes of a listing, None for a side no volatility explains
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImpliedVolatility {
+    pub listing_id: u32,
+    pub base_asset: Asset,
+    pub bid: Option<f64>,
+    pub ask: Option<f64>,
+    pub vega: f64, // per contract at the mid volatility, weighs the listing in asset averages
+}
+
+impl ImpliedVolatility {
+    /// Midpoint of both sides, or whichever side could be solved
+    pub fn mid(&self) -> Option<f64> {
+        match (self.bid, self.ask) {
+            (Some(bid), Some(ask)) => Some((bid + ask) / 2.0),
+            (bid, ask) => bid.or(ask),
+        }
+    }
+}
+
+/// Back out the volatilities a listing's `bid_price` and `ask_price` imply at the rate provider's spot
+pub fn listing_implied_volatility(
+    option: &ListingOption,
+    risk_free_rate: f64,
+    now: DateTime<Utc>,
+) -> Result<ImpliedVolatility, ExchangeError> {
+    let spot = spot_rate(&option.base_asset, &option.quote_asset)?;
+    let strike = option.strike_price.to_f64();
+    let years = years_until(now, option.expiration_time);
+    let exercise_amount = option.exercise_amount.to_f64();
+    if exercise_amount <= 0.0 {
+        return Err(ExchangeError::InvalidInput(
+            "Exercise amount must be positive".into(),
+        ));
+    }
+
+    // premiums are quoted as price * 100 per contract, solve for one unit of the underlying
+    let solve = |quote: Price| {
+        implied_volatility(
+            &option.listing_type,
+            quote.to_f64() * 100.0 / exercise_amount,
+            spot,
+            strike,
+            years,
+            risk_free_rate,
+        )
+    };
+    let mut implied = ImpliedVolatility {
+        listing_id: option.listing_id,
+        base_asset: option.base_asset.clone(),
+        bid: solve(option.bid_price),
+        ask: solve(option.ask_price),
+        vega: 0.0,
+    };
+    if let Some(mid) = implied.mid() {
+        let params = PricingParams::new(mid, risk_free_rate);
+        implied.vega = black_scholes(&option.listing_type, spot, strike, years, &params)
+            .scale(exercise_amount)
+            .vega;
+    }
+
+    Ok(implied)
+}
+
+/// Implied volatility of an underlying across the listings quoting it
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetImpliedVolatility {
+    pub asset: Asset,
+    pub volatility: f64, // vega weighted mean of the listings' mid volatilities
+    pub listing_count: usize,
+}
+
+/// Aggregate the mid implied volatility of every unexpired listing with contracts left to sell
+/// per underlying asset. Listings without a spot rate or whose quotes no volatility explains are left out.
+pub fn implied_volatility_by_asset<'a>(
+    listings: impl IntoIterator<Item = &'a ListingOption>,
+    risk_free_rate: f64,
+    now: DateTime<Utc>,
+) -> HashMap<Asset, AssetImpliedVolatility> {
+    let mut totals: HashMap<Asset, (f64, f64, usize)> = HashMap::new(); // weighted sum, weights, count
+    for option in listings {
+        if !option.has_unsold_contracts() || option.expiration_time <= now {
+            continue;
+        }
+        let Ok(implied) = listing_implied_volatility(option, risk_free_rate, now) else {
+            continue;
+        };
+        let Some(mid) = implied.mid() else {
+            continue;
+        };
+        if implied.vega <= 0.0 {
+            continue;
+        }
+
+        let entry = totals.entry(implied.base_asset).or_insert((0.0, 0.0, 0));
+        entry.0 += mid * implied.vega;
+        entry.1 += implied.vega;
+        entry.2 += 1;
+    }
+
+    totals
+        .into_iter()
+        .map(|(asset, (weighted_sum, weights, listing_count))| {
+            let aggregate = AssetImpliedVolatility {
+                asset: asset.clone(),
+                volatility: weighted_sum / weights,
+                listing_count,
+            };
+            (asset, aggregate)
+        })
+        .collect()
+}
+
+/// Rate provider's current rate of a pair for the model, missing until someone quotes it
+pub fn spot_rate(base_asset: &Asset, quote_asset: &Asset) -> Result<f64, ExchangeError> {
+    get_readonly_rate_provider()
+        .get_rate(base_asset, quote_asset)
+        .filter(|rate| *rate > Amount::ZERO) // pairs nobody has quoted yet sit at zero
+        .map(|rate| rate.to_f64())
+        .ok_or_else(|| ExchangeError::RateMissing {
+            base: base_asset.clone(),
+            quote: quote_asset.clone(),
+        })
+}
+
+fn normal_pdf(x: f64) -> f64 {
+    (-x * x / 2.0).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+// Rational approximation of the normal tail for |x| below 5 * sqrt(2), highest power first
+const CDF_NUMERATOR: [f64; 7] = [
+    0.0352624965998911,
+    0.700383064443688,
+    6.37396220353165,
+    33.912866078383,
+    112.079291497871,
+    221.213596169931,
+    220.206867912376,
+];
+const CDF_DENOMINATOR: [f64; 8] = [
+    0.0883883476483184,
+    1.75566716318264,
+    16.064177579207,
+    86.7807322029461,
+    296.564248779674,
+    637.333633378831,
+    793.826512519948,
+    440.413735824752,
+];
+
+/// Standard normal CDF, Hart's double precision approximation as given by West (2005)
+fn normal_cdf(x: f64) -> f64 {
+    let polynomial =
+        |coefficients: &[f64], z: f64| coefficients.iter().fold(0.0, |acc, c| acc * z + c);
+
+    let z = x.abs();
+    let tail = if z > 37.0 {
+        0.0
+    } else if z < 7.07106781186547 {
+        (-z * z / 2.0).exp() * polynomial(&CDF_NUMERATOR, z) / polynomial(&CDF_DENOMINATOR, z)
+    } else {
+        let continued_fraction = z + 1.0 / (z + 2.0 / (z + 3.0 / (z + 4.0 / (z + 0.65))));
+        (-z * z / 2.0).exp() / continued_fraction / 2.506628274631
+    };
+
+    if x > 0.0 { 1.0 - tail } else { tail }
+}
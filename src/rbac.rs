@@ -5,11 +5,23 @@ use std::collections::HashMap;
 #[derive(Eq, PartialEq, Hash, Clone)]
 pub struct NamedRole(pub String);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum UnauthorizedError {
     AddressNotAuthorized,
 }
 
+impl std::fmt::Display for UnauthorizedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnauthorizedError::AddressNotAuthorized => {
+                write!(f, "address is not assigned an allowed role")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnauthorizedError {}
+
 pub struct RoleAuthorizer {
     pub role_assignees: HashMap<NamedRole, Address>,
     pub is_role_known: HashMap<NamedRole, bool>,
@@ -36,7 +48,7 @@ impl RoleAuthorizer {
         authorizer
             .role_assignees
             .insert(role.clone(), role_manager_addr);
-        return authorizer;
+        authorizer
     }
 
     pub fn change_role_manager_address(
@@ -45,8 +57,8 @@ impl RoleAuthorizer {
         caller_address: Address,
     ) -> Result<(), UnauthorizedError> {
         let role = NamedRole("RolesManager".to_string());
-        self.only_authorized_role(&[role.clone()], caller_address)?;
-        self.role_assignees.insert(role.clone(), new_address);
+        self.only_authorized_role(std::slice::from_ref(&role), caller_address)?;
+        self.role_assignees.insert(role, new_address);
         Ok(())
     }
 
@@ -57,15 +69,15 @@ impl RoleAuthorizer {
     ) -> Result<(), UnauthorizedError> {
         for role in allowed_roles {
             let role_assignee = self.role_assignees.get(role);
-            if (match role_assignee {
+            if match role_assignee {
                 Some(role_assignee) => are_addresses_equal(role_assignee, &caller_addres),
                 None => false,
-            }) {
+            } {
                 return Ok(());
             }
         }
 
-        return Err(UnauthorizedError::AddressNotAuthorized);
+        Err(UnauthorizedError::AddressNotAuthorized)
     }
 
     pub fn make_role_known(
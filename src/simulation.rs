@@ -1,22 +1,83 @@
 // simulation.rs - Enhanced Trading bot simulation with spot trading and dynamic rates
 
-use crate::exchange::SpotAction;
+use crate::binomial::{self, BinomialValuation, DEFAULT_BINOMIAL_STEPS};
+use crate::exchange::{FundReport, SpotAction};
 use crate::exchange_rate_provider::{
-    default_exchange_rate_provider_admin_address, get_rate_provider,
+    AssetPair, default_exchange_rate_provider_admin_address, get_rate_provider,
+    get_readonly_rate_provider,
 };
-use crate::{Address, Asset, Exchange, ListingOption, ListingType, User};
-use chrono::{Duration, Utc};
+use crate::order_book::BookEntry;
+use crate::pricing::{self, PricingParams};
+use crate::vol_surface::{self, EXPIRY_BUCKET_DAYS, MONEYNESS_BUCKETS};
+use crate::{
+    Address, Amount, Asset, Exchange, ExchangeError, ExerciseStyle, ListingOption, ListingType,
+    OptionSeries, OptionState, OrderType, Price, Rounding, SettlementType, SimulatedClock, User,
+};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
 use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Simulated time that passes between two rounds, so 25 rounds cover about a month of trading
+const SIMULATED_HOURS_PER_ROUND: i64 = 30;
+
+/// Lifetime of the options listed by the bots
+const OPTION_LIFETIME_DAYS: i64 = 30;
+
+/// Strikes are spaced this many steps per power of ten, so listings share series at any price level
+const STRIKE_STEPS_PER_DECADE: f64 = 20.0;
+
+/// Model the bots value options with
+const PRICING_PARAMS: PricingParams = PricingParams {
+    volatility: 0.8,
+    risk_free_rate: 0.04,
+};
+
+/// How far above fair value the market maker asks, and below it bids
+const MARKET_MAKER_EDGE: f64 = 0.05;
+
+/// Worst price a bot's spot market order accepts, relative to the best price on the book
+const SPOT_SLIPPAGE_BPS: u16 = 200;
+
+/// USDT value of each side the market maker quotes on the spot books, and the spread between them
+const SPOT_QUOTE_VALUE: f64 = 100000.0;
+const SPOT_QUOTE_SPREAD: f64 = 0.01;
+
+/// USDT the market maker posts to its margin account to write options on margin
+const MARKET_MAKER_MARGIN: f64 = 200000.0;
+
+/// USDT the exchange starts its insurance fund with to cover liquidation shortfalls
+const INSURANCE_FUND_SEED: f64 = 100000.0;
+
+/// USDT the spot desk starts with to buy the collateral cash-settled calls pay out with
+const SPOT_DESK_SEED: f64 = 100000.0;
+
+/// USDT value of each side the liquidity providers deposit into every pool
+const POOL_SEED_VALUE: f64 = 50000.0;
+
+/// How far a pool's price may drift from the rate before the arbitrageur trades it back
+const POOL_ARBITRAGE_THRESHOLD: f64 = 0.01;
+
+// Bots list between 1 and this many contracts at once
+const MAX_LISTED_CONTRACTS: u32 = 5;
+
+/// Bots size their trades with floats, round those down to what the asset can hold
+fn to_asset_amount(amount: f64, asset: &Asset) -> Amount {
+    Amount::from_f64(amount).round_to_asset(asset, Rounding::Down)
+}
 
 /// Actions that a trading bot can take
 #[derive(Debug, Clone)]
 pub enum TraderAction {
-    ListCall(Asset, f64, f64), // asset, strike_price, ask_price
-    ListPut(Asset, f64, f64),  // asset, strike_price, ask_price
-    BuyOption(u32),            // listing_id
-    ExerciseOption(u32),       // listing_id
-    SpotBuy(Asset, f64),       // asset, amount
-    SpotSell(Asset, f64),      // asset, amount
+    ListCall(Asset, f64, f64, u32, ExerciseStyle, SettlementType), // asset, strike_price, ask_price, contract_count, exercise_style, settlement_type
+    ListPut(Asset, f64, f64, u32, ExerciseStyle, SettlementType), // asset, strike_price, ask_price, contract_count, exercise_style, settlement_type
+    BuyOption(u32, u32),                                          // listing_id, contract_count
+    PlaceBid(OptionSeries, f64, u32), // series, bid_price, contract_count
+    BuySeries(OptionSeries, u32),     // series, contract_count at the best asks
+    ExerciseOption(u32),              // listing_id
+    SpotBuy(Asset, f64),              // asset, amount
+    SpotSell(Asset, f64),             // asset, amount
+    PoolSwap(Asset, SpotAction, f64), // asset, side, amount paid in (USDT when buying)
     DoNothing,
 }
 
@@ -85,6 +146,13 @@ pub struct MarketVolatility {
     pub base_eth_rate: f64,
     pub base_sol_rate: f64,
     pub base_apple_rate: f64,
+    pub rate_history: HashMap<Asset, Vec<f64>>, // USDT rate of each asset set every round, oldest first
+}
+
+impl Default for MarketVolatility {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MarketVolatility {
@@ -99,7 +167,30 @@ impl MarketVolatility {
             base_eth_rate: 3000.0,
             base_sol_rate: 100.0,
             base_apple_rate: 150.0,
+            rate_history: HashMap::new(),
+        }
+    }
+
+    pub fn record_rate(&mut self, asset: Asset, rate: f64) {
+        self.rate_history.entry(asset).or_default().push(rate);
+    }
+
+    /// Annualized standard deviation of the log returns between rounds, None before two returns
+    pub fn realized_volatility(&self, asset: &Asset) -> Option<f64> {
+        let rates = self.rate_history.get(asset)?;
+        let returns: Vec<f64> = rates
+            .windows(2)
+            .map(|pair| (pair[1] / pair[0]).ln())
+            .collect();
+        if returns.len() < 2 {
+            return None;
         }
+
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        let variance =
+            returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+        let rounds_per_year = 365.0 * 24.0 / SIMULATED_HOURS_PER_ROUND as f64;
+        Some((variance * rounds_per_year).sqrt())
     }
 
     /// Update market conditions with random price movements - BALANCED VERSION
@@ -135,27 +226,22 @@ impl MarketVolatility {
     }
 
     /// Balanced trend update with growth bias - more realistic market dynamics
-    fn update_trend_balanced(&self, current_trend: f64, rng: &mut impl Rng, growth_target: f64) -> f64 {
+    fn update_trend_balanced(
+        &self,
+        current_trend: f64,
+        rng: &mut impl Rng,
+        growth_target: f64,
+    ) -> f64 {
         // Mean reversion toward growth target instead of 1.0
         let mean_reversion = (growth_target - current_trend) * 0.05; // Slower reversion
-        
+
         // Random walk with slight upward bias
         let random_change = (rng.gen_range(0.0..1.0) - 0.45) * self.volatility_factor * 2.0; // 0.45 vs 0.5 = upward bias
-        
-        let new_trend = current_trend + mean_reversion + random_change;
-        
-        // More generous ranges allowing for bigger gains and more moderate losses
-        new_trend.max(0.6).min(3.5) // 40% max loss, 250% max gain
-    }
 
-    fn update_trend(&self, current_trend: f64, rng: &mut impl Rng) -> f64 {
-        // Mean reversion: trends tend to move back toward 1.0
-        let mean_reversion = (1.0 - current_trend) * 0.1;
-        let random_change = (rng.gen_range(0.0..1.0) - 0.5) * self.volatility_factor * 2.0;
+        let new_trend = current_trend + mean_reversion + random_change;
 
-        (current_trend + mean_reversion + random_change)
-            .max(0.3)
-            .min(3.0)
+        // More generous ranges allowing for bigger gains and more moderate losses
+        new_trend.clamp(0.6, 3.5) // 40% max loss, 250% max gain
     }
 
     /// Get updated price for an asset
@@ -199,11 +285,12 @@ impl TraderBot {
         let mut rng = rand::thread_rng();
 
         // Check if we recently exercised an option (within 2 rounds) and should consider spot trading
-        if let Some(last_exercise) = self.last_exercise_round {
-            if current_round - last_exercise <= 2 && rng.gen_bool(0.7) {
-                // 70% chance to make a spot trade after exercising to realize profits
-                return self.post_exercise_spot_trade(&mut rng, exchange);
-            }
+        if let Some(last_exercise) = self.last_exercise_round
+            && current_round - last_exercise <= 2
+            && rng.gen_bool(0.7)
+        {
+            // 70% chance to make a spot trade after exercising to realize profits
+            return self.post_exercise_spot_trade(&mut rng, exchange);
         }
 
         match self.strategy.as_str() {
@@ -232,9 +319,23 @@ impl TraderBot {
             let ask_price = rng.gen_range(10.0..500.0);
 
             if rng.gen_bool(0.6) {
-                TraderAction::ListCall(asset, strike_price, ask_price)
+                TraderAction::ListCall(
+                    asset,
+                    strike_price,
+                    ask_price,
+                    rng.gen_range(1..=MAX_LISTED_CONTRACTS),
+                    ExerciseStyle::American,
+                    SettlementType::Physical,
+                )
             } else {
-                TraderAction::ListPut(asset, strike_price, ask_price)
+                TraderAction::ListPut(
+                    asset,
+                    strike_price,
+                    ask_price,
+                    rng.gen_range(1..=MAX_LISTED_CONTRACTS),
+                    ExerciseStyle::American,
+                    SettlementType::Physical,
+                )
             }
         } else if action_type < 0.8 {
             // 20% spot selling with balance check
@@ -243,7 +344,7 @@ impl TraderBot {
 
             // Check user's balance and sell a reasonable percentage
             if let Some(user) = exchange.users.get(&self.address) {
-                let user_balance = user.get_balance(&asset);
+                let user_balance = user.get_balance(&asset).to_f64();
                 if user_balance > 0.1 {
                     let max_sellable = user_balance * 0.5; // Sell up to 50% of holdings
                     let amount = (rng.gen_range(0.1..1.0) * max_sellable).min(2.0);
@@ -263,11 +364,22 @@ impl TraderBot {
     fn aggressive_buyer_strategy(&self, rng: &mut impl Rng, exchange: &Exchange) -> TraderAction {
         let action_type = rng.gen_range(0.0..1.0);
 
-        if action_type < 0.5 && !exchange.listings.is_empty() {
-            // 50% option buying
-            let listing_ids: Vec<u32> = exchange.listings.keys().cloned().collect();
-            let random_listing = listing_ids[rng.gen_range(0..listing_ids.len())];
-            TraderAction::BuyOption(random_listing)
+        if action_type < 0.5 {
+            // 50% option buying, sweeping the best asks of a series someone else offers
+            let offered: Vec<OptionSeries> = exchange
+                .order_books()
+                .into_iter()
+                .filter(|book| {
+                    book.best_ask()
+                        .is_some_and(|ask| ask.owner_address != self.address)
+                })
+                .map(|book| book.series)
+                .collect();
+            if offered.is_empty() {
+                return TraderAction::DoNothing;
+            }
+            let series = offered[rng.gen_range(0..offered.len())].clone();
+            TraderAction::BuySeries(series, rng.gen_range(1..=3))
         } else if action_type < 0.8 {
             // 30% spot buying with balance check
             let assets = [Asset::BTC, Asset::ETH, Asset::SOL];
@@ -275,14 +387,14 @@ impl TraderBot {
 
             // Check USDT balance for purchasing
             if let Some(user) = exchange.users.get(&self.address) {
-                let usdt_balance = user.get_balance(&Asset::USDT);
+                let usdt_balance = user.get_balance(&Asset::USDT).to_f64();
                 if usdt_balance > 1000.0 {
                     // Need at least 1000 USDT to trade
                     let max_trade_value = (usdt_balance * 0.1).min(10000.0); // Use up to 10% of USDT, max 10k
                     // Get current price to calculate amount
                     let rate_provider = crate::exchange_rate_provider::get_readonly_rate_provider();
                     if let Some(price) = rate_provider.get_rate(&asset, &Asset::USDT) {
-                        let amount = (max_trade_value / price) * rng.gen_range(0.1..1.0);
+                        let amount = (max_trade_value / price.to_f64()) * rng.gen_range(0.1..1.0);
                         TraderAction::SpotBuy(asset, amount)
                     } else {
                         TraderAction::DoNothing
@@ -303,24 +415,25 @@ impl TraderBot {
         let action_type = rng.gen_range(0..6);
         match action_type {
             0 => {
-                // List option
-                let assets = [Asset::BTC, Asset::ETH, Asset::SOL, Asset::APPLE];
-                let asset = assets[rng.gen_range(0..assets.len())].clone();
-                let strike_price = rng.gen_range(1000.0..100000.0);
-                let ask_price = rng.gen_range(10.0..500.0);
-
-                if rng.gen_bool(0.5) {
-                    TraderAction::ListCall(asset, strike_price, ask_price)
-                } else {
-                    TraderAction::ListPut(asset, strike_price, ask_price)
-                }
+                // List option up to 15% over fair value
+                let markup = rng.gen_range(0.0..0.15);
+                self.list_at_fair_value(rng, exchange, markup)
             }
             1 => {
-                // Buy option
+                // Buy option, unless it asks well over fair value
                 if !exchange.listings.is_empty() {
                     let listing_ids: Vec<u32> = exchange.listings.keys().cloned().collect();
                     let random_listing = listing_ids[rng.gen_range(0..listing_ids.len())];
-                    TraderAction::BuyOption(random_listing)
+                    let listing = &exchange.listings[&random_listing];
+                    match listing_fair_ask_price(exchange, listing) {
+                        Some(fair_ask) if listing.ask_price.to_f64() <= fair_ask * 1.05 => {
+                            TraderAction::BuyOption(
+                                random_listing,
+                                contracts_to_buy(rng, exchange, random_listing),
+                            )
+                        }
+                        _ => TraderAction::DoNothing,
+                    }
                 } else {
                     TraderAction::DoNothing
                 }
@@ -332,9 +445,11 @@ impl TraderBot {
                         .listings
                         .iter()
                         .filter_map(|(id, listing)| {
-                            if listing.beneficiary_address.as_ref() == Some(&self.address)
-                                && listing.is_purchased
-                                && !listing.is_exercised
+                            if listing.state == OptionState::Purchased
+                                && exchange.can_exercise(listing)
+                                && exchange
+                                    .get_position(*id, &self.address)
+                                    .is_some_and(|p| p.contracts > 0)
                             {
                                 Some(*id)
                             } else {
@@ -360,13 +475,13 @@ impl TraderBot {
                 let asset = assets[rng.gen_range(0..assets.len())].clone();
 
                 if let Some(user) = exchange.users.get(&self.address) {
-                    let usdt_balance = user.get_balance(&Asset::USDT);
+                    let usdt_balance = user.get_balance(&Asset::USDT).to_f64();
                     if usdt_balance > 2000.0 {
                         let rate_provider =
                             crate::exchange_rate_provider::get_readonly_rate_provider();
                         if let Some(price) = rate_provider.get_rate(&asset, &Asset::USDT) {
                             let trade_value = usdt_balance * 0.15; // Use 15% of USDT
-                            let amount = (trade_value / price) * rng.gen_range(0.3..1.0);
+                            let amount = (trade_value / price.to_f64()) * rng.gen_range(0.3..1.0);
                             TraderAction::SpotBuy(asset, amount)
                         } else {
                             TraderAction::DoNothing
@@ -384,7 +499,7 @@ impl TraderBot {
                 let asset = assets[rng.gen_range(0..assets.len())].clone();
 
                 if let Some(user) = exchange.users.get(&self.address) {
-                    let asset_balance = user.get_balance(&asset);
+                    let asset_balance = user.get_balance(&asset).to_f64();
                     if asset_balance > 0.2 {
                         let amount = (asset_balance * 0.3).min(2.0);
                         TraderAction::SpotSell(asset, amount)
@@ -409,7 +524,7 @@ impl TraderBot {
         if rng.gen_bool(0.6) {
             // 60% chance to sell (realize profits)
             if let Some(user) = exchange.users.get(&self.address) {
-                let asset_balance = user.get_balance(&asset);
+                let asset_balance = user.get_balance(&asset).to_f64();
                 if asset_balance > 0.1 {
                     let amount = (asset_balance * 0.4).min(3.0); // Sell up to 40% after exercise
                     TraderAction::SpotSell(asset, amount)
@@ -422,12 +537,12 @@ impl TraderBot {
         } else {
             // 40% chance to buy (reinvest)
             if let Some(user) = exchange.users.get(&self.address) {
-                let usdt_balance = user.get_balance(&Asset::USDT);
+                let usdt_balance = user.get_balance(&Asset::USDT).to_f64();
                 if usdt_balance > 5000.0 {
                     let rate_provider = crate::exchange_rate_provider::get_readonly_rate_provider();
                     if let Some(price) = rate_provider.get_rate(&asset, &Asset::USDT) {
                         let reinvest_amount = usdt_balance * 0.2; // Reinvest 20% of USDT
-                        let amount = (reinvest_amount / price) * rng.gen_range(0.5..1.0);
+                        let amount = (reinvest_amount / price.to_f64()) * rng.gen_range(0.5..1.0);
                         TraderAction::SpotBuy(asset, amount)
                     } else {
                         TraderAction::DoNothing
@@ -446,38 +561,129 @@ impl TraderBot {
         // Market makers try to profit from bid-ask spreads
         // They typically list options at competitive prices
         if rng.gen_bool(0.9) {
-            let assets = [Asset::BTC, Asset::ETH, Asset::SOL, Asset::APPLE];
-            let asset = assets[rng.gen_range(0..assets.len())].clone();
-
-            // Use tighter spreads and more competitive pricing
-            let strike_price = rng.gen_range(30000.0..80000.0);
-            let ask_price = rng.gen_range(5.0..200.0); // Lower ask prices for market making
-
-            if rng.gen_bool(0.5) {
-                TraderAction::ListCall(asset, strike_price, ask_price)
-            } else {
-                TraderAction::ListPut(asset, strike_price, ask_price)
-            }
+            // Ask a fixed edge over fair value
+            self.list_at_fair_value(rng, exchange, MARKET_MAKER_EDGE)
         } else if !exchange.listings.is_empty() && rng.gen_bool(0.3) {
-            // Occasionally buy underpriced options
             let listing_ids: Vec<u32> = exchange.listings.keys().cloned().collect();
             let random_listing = listing_ids[rng.gen_range(0..listing_ids.len())];
-            TraderAction::BuyOption(random_listing)
+            let listing = &exchange.listings[&random_listing];
+            let Some(fair_ask) = listing_fair_ask_price(exchange, listing) else {
+                return TraderAction::DoNothing;
+            };
+            let bid_price = round_to_cents(fair_ask * (1.0 - MARKET_MAKER_EDGE));
+
+            if listing.ask_price.to_f64() <= bid_price {
+                // Buy options offered below the market maker's own bid
+                TraderAction::BuyOption(
+                    random_listing,
+                    contracts_to_buy(rng, exchange, random_listing),
+                )
+            } else if bid_price > 0.0 {
+                // Or quote the bid side of the series and wait for a seller to cross it
+                TraderAction::PlaceBid(
+                    listing.series(),
+                    bid_price,
+                    rng.gen_range(1..=MAX_LISTED_CONTRACTS),
+                )
+            } else {
+                TraderAction::DoNothing
+            }
         } else {
             TraderAction::DoNothing
         }
     }
 
-    /// Arbitrageur strategy - looks for price discrepancies and exercises profitable options
+    /// List a call or put struck near the spot of a random asset, asking `markup` over its fair value
+    fn list_at_fair_value(
+        &self,
+        rng: &mut impl Rng,
+        exchange: &Exchange,
+        markup: f64,
+    ) -> TraderAction {
+        let assets = [Asset::BTC, Asset::ETH, Asset::SOL, Asset::APPLE];
+        let asset = assets[rng.gen_range(0..assets.len())].clone();
+        let Some(spot) = get_readonly_rate_provider().get_rate(&asset, &Asset::USDT) else {
+            return TraderAction::DoNothing;
+        };
+        let listing_type = if rng.gen_bool(0.5) {
+            ListingType::CALL
+        } else {
+            ListingType::PUT
+        };
+        let (strike_price, expiration_time) =
+            series_terms(exchange, spot.to_f64() * rng.gen_range(0.85..1.15));
+        let exercise_style = match rng.gen_range(0..5) {
+            0 => ExerciseStyle::European,
+            1 => ExerciseStyle::Bermudan(bermudan_dates(expiration_time)),
+            _ => ExerciseStyle::American,
+        };
+        // a third of the listings pay out their intrinsic value instead of delivering,
+        // all of those written on margin do
+        let settlement_type =
+            if exchange.margin_accounts.contains_key(&self.address) || rng.gen_bool(1.0 / 3.0) {
+                SettlementType::Cash
+            } else {
+                SettlementType::Physical
+            };
+
+        let series = OptionSeries {
+            base_asset: asset.clone(),
+            quote_asset: Asset::USDT,
+            listing_type: listing_type.clone(),
+            strike_price,
+            expiration_time,
+            exercise_style: exercise_style.clone(),
+            settlement_type,
+        };
+        let ask_price = match binomial::value_series_by_style(
+            &series,
+            exchange.exercise_window,
+            &PRICING_PARAMS,
+            DEFAULT_BINOMIAL_STEPS,
+            exchange.now(),
+        ) {
+            // premiums are ask_price * 100 for a contract on one unit
+            Ok(value) => round_to_cents(value.valuation.price / 100.0 * (1.0 + markup)),
+            Err(_) => return TraderAction::DoNothing,
+        };
+        if ask_price <= 0.0 {
+            return TraderAction::DoNothing;
+        }
+
+        let contract_count = rng.gen_range(1..=MAX_LISTED_CONTRACTS);
+        let strike_price = strike_price.to_f64();
+        match listing_type {
+            ListingType::CALL => TraderAction::ListCall(
+                asset,
+                strike_price,
+                ask_price,
+                contract_count,
+                exercise_style,
+                settlement_type,
+            ),
+            ListingType::PUT => TraderAction::ListPut(
+                asset,
+                strike_price,
+                ask_price,
+                contract_count,
+                exercise_style,
+                settlement_type,
+            ),
+        }
+    }
+
+    /// Arbitrageur strategy - looks for price discrepancies and exercises options once holding them is worth less
     fn arbitrageur_strategy(&self, rng: &mut impl Rng, exchange: &Exchange) -> TraderAction {
-        // First priority: exercise profitable options
+        // First priority: exercise options the binomial tree says to exercise early
         let exercisable_options: Vec<u32> = exchange
             .listings
             .iter()
             .filter_map(|(id, listing)| {
-                if listing.beneficiary_address.as_ref() == Some(&self.address)
-                    && listing.is_purchased
-                    && !listing.is_exercised
+                if listing.state == OptionState::Purchased
+                    && exchange
+                        .get_position(*id, &self.address)
+                        .is_some_and(|p| p.contracts > 0)
+                    && should_exercise_early(exchange, listing)
                 {
                     Some(*id)
                 } else {
@@ -491,13 +697,18 @@ impl TraderBot {
             return TraderAction::ExerciseOption(random_option);
         }
 
-        // Second priority: spot arbitrage between assets
+        // Second priority: trade the pool furthest from the rate back towards it
+        if let Some(action) = self.pool_arbitrage(exchange) {
+            return action;
+        }
+
+        // Third priority: spot arbitrage between assets
         if rng.gen_bool(0.6) {
             let assets = [Asset::BTC, Asset::ETH, Asset::SOL];
             let asset = assets[rng.gen_range(0..assets.len())].clone();
 
             if let Some(user) = exchange.users.get(&self.address) {
-                let user_balance = user.get_balance(&asset);
+                let user_balance = user.get_balance(&asset).to_f64();
                 if user_balance > 0.1 {
                     let amount = (user_balance * 0.3).min(1.0);
                     TraderAction::SpotSell(asset, amount)
@@ -512,6 +723,49 @@ impl TraderBot {
         }
     }
 
+    /// Swap that moves the most mispriced pool back to the rate, sized from its reserves
+    /// so that the price lands on the rate before fees
+    fn pool_arbitrage(&self, exchange: &Exchange) -> Option<TraderAction> {
+        let user = exchange.users.get(&self.address)?;
+        let rate_provider = get_readonly_rate_provider();
+
+        let mut best: Option<(f64, TraderAction)> = None;
+        for asset in [Asset::BTC, Asset::ETH, Asset::SOL] {
+            let pair = AssetPair::from(asset.clone(), Asset::USDT);
+            let (Some(pool), Some(rate)) = (
+                exchange.pools.get(&pair),
+                rate_provider.get_rate(&asset, &Asset::USDT),
+            ) else {
+                continue;
+            };
+            let Some(pool_price) = pool.get_price() else {
+                continue;
+            };
+            let (rate, pool_price) = (rate.to_f64(), pool_price.to_f64());
+            let deviation = (pool_price / rate - 1.0).abs();
+            if deviation < POOL_ARBITRAGE_THRESHOLD
+                || best.as_ref().is_some_and(|(best, _)| deviation <= *best)
+            {
+                continue;
+            }
+
+            // reserves that keep x * y = k at the rate
+            let k = pool.base_reserve.to_f64() * pool.quote_reserve.to_f64();
+            let action = if pool_price > rate {
+                let amount = (k / rate).sqrt() - pool.base_reserve.to_f64();
+                let amount = amount.min(user.get_balance(&asset).to_f64() * 0.5);
+                TraderAction::PoolSwap(asset, SpotAction::SELL, amount)
+            } else {
+                let amount = (k * rate).sqrt() - pool.quote_reserve.to_f64();
+                let amount = amount.min(user.get_balance(&Asset::USDT).to_f64() * 0.5);
+                TraderAction::PoolSwap(asset, SpotAction::BUY, amount)
+            };
+            best = Some((deviation, action));
+        }
+
+        best.map(|(_, action)| action)
+    }
+
     /// Momentum trader - follows market trends
     fn momentum_trader_strategy(&self, rng: &mut impl Rng, exchange: &Exchange) -> TraderAction {
         let action_type = rng.gen_range(0.0..1.0);
@@ -520,19 +774,22 @@ impl TraderBot {
             // Buy options aggressively during momentum
             let listing_ids: Vec<u32> = exchange.listings.keys().cloned().collect();
             let random_listing = listing_ids[rng.gen_range(0..listing_ids.len())];
-            TraderAction::BuyOption(random_listing)
+            TraderAction::BuyOption(
+                random_listing,
+                contracts_to_buy(rng, exchange, random_listing),
+            )
         } else if action_type < 0.7 {
             // Spot trading following momentum
             let assets = [Asset::BTC, Asset::ETH, Asset::SOL];
             let asset = assets[rng.gen_range(0..assets.len())].clone();
 
             if let Some(user) = exchange.users.get(&self.address) {
-                let usdt_balance = user.get_balance(&Asset::USDT);
+                let usdt_balance = user.get_balance(&Asset::USDT).to_f64();
                 if usdt_balance > 5000.0 {
                     let rate_provider = crate::exchange_rate_provider::get_readonly_rate_provider();
                     if let Some(price) = rate_provider.get_rate(&asset, &Asset::USDT) {
                         let trade_value = usdt_balance * 0.2; // Use 20% of USDT
-                        let amount = (trade_value / price) * rng.gen_range(0.5..1.0);
+                        let amount = (trade_value / price.to_f64()) * rng.gen_range(0.5..1.0);
                         TraderAction::SpotBuy(asset, amount)
                     } else {
                         TraderAction::DoNothing
@@ -560,9 +817,23 @@ impl TraderBot {
             let ask_price = rng.gen_range(100.0..800.0);
 
             if rng.gen_bool(0.5) {
-                TraderAction::ListPut(asset, strike_price, ask_price) // Prefer puts during bullish times
+                TraderAction::ListPut(
+                    asset,
+                    strike_price,
+                    ask_price,
+                    rng.gen_range(1..=MAX_LISTED_CONTRACTS),
+                    ExerciseStyle::American,
+                    SettlementType::Physical,
+                ) // Prefer puts during bullish times
             } else {
-                TraderAction::ListCall(asset, strike_price, ask_price)
+                TraderAction::ListCall(
+                    asset,
+                    strike_price,
+                    ask_price,
+                    rng.gen_range(1..=MAX_LISTED_CONTRACTS),
+                    ExerciseStyle::American,
+                    SettlementType::Physical,
+                )
             }
         } else if action_type < 0.8 {
             // Contrarian spot trading
@@ -570,7 +841,7 @@ impl TraderBot {
             let asset = assets[rng.gen_range(0..assets.len())].clone();
 
             if let Some(user) = exchange.users.get(&self.address) {
-                let asset_balance = user.get_balance(&asset);
+                let asset_balance = user.get_balance(&asset).to_f64();
                 if asset_balance > 0.5 {
                     let amount = (asset_balance * 0.4).min(3.0);
                     TraderAction::SpotSell(asset, amount)
@@ -593,7 +864,10 @@ impl TraderBot {
             // Quick option trades
             let listing_ids: Vec<u32> = exchange.listings.keys().cloned().collect();
             let random_listing = listing_ids[rng.gen_range(0..listing_ids.len())];
-            TraderAction::BuyOption(random_listing)
+            TraderAction::BuyOption(
+                random_listing,
+                contracts_to_buy(rng, exchange, random_listing),
+            )
         } else if action_type < 0.8 {
             // Frequent small spot trades
             let assets = [Asset::BTC, Asset::ETH, Asset::SOL];
@@ -602,13 +876,13 @@ impl TraderBot {
             if rng.gen_bool(0.5) {
                 // Small buys
                 if let Some(user) = exchange.users.get(&self.address) {
-                    let usdt_balance = user.get_balance(&Asset::USDT);
+                    let usdt_balance = user.get_balance(&Asset::USDT).to_f64();
                     if usdt_balance > 2000.0 {
                         let rate_provider =
                             crate::exchange_rate_provider::get_readonly_rate_provider();
                         if let Some(price) = rate_provider.get_rate(&asset, &Asset::USDT) {
                             let small_trade = usdt_balance * 0.05; // Only 5% per trade
-                            let amount = (small_trade / price) * rng.gen_range(0.8..1.0);
+                            let amount = (small_trade / price.to_f64()) * rng.gen_range(0.8..1.0);
                             TraderAction::SpotBuy(asset, amount)
                         } else {
                             TraderAction::DoNothing
@@ -622,7 +896,7 @@ impl TraderBot {
             } else {
                 // Small sells
                 if let Some(user) = exchange.users.get(&self.address) {
-                    let asset_balance = user.get_balance(&asset);
+                    let asset_balance = user.get_balance(&asset).to_f64();
                     if asset_balance > 0.2 {
                         let amount = (asset_balance * 0.1).min(0.5); // Very small amounts
                         TraderAction::SpotSell(asset, amount)
@@ -650,34 +924,51 @@ impl TraderBot {
             let ask_price = rng.gen_range(500.0..2000.0); // High premiums
 
             if rng.gen_bool(0.5) {
-                TraderAction::ListCall(asset, strike_price, ask_price)
+                TraderAction::ListCall(
+                    asset,
+                    strike_price,
+                    ask_price,
+                    rng.gen_range(1..=MAX_LISTED_CONTRACTS),
+                    ExerciseStyle::American,
+                    SettlementType::Physical,
+                )
             } else {
-                TraderAction::ListPut(asset, strike_price, ask_price)
+                TraderAction::ListPut(
+                    asset,
+                    strike_price,
+                    ask_price,
+                    rng.gen_range(1..=MAX_LISTED_CONTRACTS),
+                    ExerciseStyle::American,
+                    SettlementType::Physical,
+                )
             }
         } else if action_type < 0.7 && !exchange.listings.is_empty() {
             // Buy multiple options
             let listing_ids: Vec<u32> = exchange.listings.keys().cloned().collect();
             let random_listing = listing_ids[rng.gen_range(0..listing_ids.len())];
-            TraderAction::BuyOption(random_listing)
+            TraderAction::BuyOption(
+                random_listing,
+                contracts_to_buy(rng, exchange, random_listing),
+            )
         } else if action_type < 0.9 {
-            // Large spot trades that move markets
+            // Large swaps through the pools that move their price, up to a tenth of the reserves
             let assets = [Asset::BTC, Asset::ETH, Asset::SOL];
             let asset = assets[rng.gen_range(0..assets.len())].clone();
+            let Some(pool) = exchange
+                .pools
+                .get(&AssetPair::from(asset.clone(), Asset::USDT))
+            else {
+                return TraderAction::DoNothing;
+            };
 
             if rng.gen_bool(0.5) {
                 // Large buys
                 if let Some(user) = exchange.users.get(&self.address) {
-                    let usdt_balance = user.get_balance(&Asset::USDT);
+                    let usdt_balance = user.get_balance(&Asset::USDT).to_f64();
                     if usdt_balance > 50000.0 {
-                        let rate_provider =
-                            crate::exchange_rate_provider::get_readonly_rate_provider();
-                        if let Some(price) = rate_provider.get_rate(&asset, &Asset::USDT) {
-                            let whale_trade = usdt_balance * 0.3; // 30% of USDT in one trade
-                            let amount = (whale_trade / price) * rng.gen_range(0.7..1.0);
-                            TraderAction::SpotBuy(asset, amount)
-                        } else {
-                            TraderAction::DoNothing
-                        }
+                        let amount = (usdt_balance * 0.3).min(pool.quote_reserve.to_f64() * 0.1)
+                            * rng.gen_range(0.7..1.0);
+                        TraderAction::PoolSwap(asset, SpotAction::BUY, amount)
                     } else {
                         TraderAction::DoNothing
                     }
@@ -687,10 +978,10 @@ impl TraderBot {
             } else {
                 // Large sells
                 if let Some(user) = exchange.users.get(&self.address) {
-                    let asset_balance = user.get_balance(&asset);
+                    let asset_balance = user.get_balance(&asset).to_f64();
                     if asset_balance > 5.0 {
-                        let amount = (asset_balance * 0.4).min(10.0); // Large amounts
-                        TraderAction::SpotSell(asset, amount)
+                        let amount = (asset_balance * 0.4).min(pool.base_reserve.to_f64() * 0.1);
+                        TraderAction::PoolSwap(asset, SpotAction::SELL, amount)
                     } else {
                         TraderAction::DoNothing
                     }
@@ -713,27 +1004,27 @@ fn auto_exercise_profitable_options(
     let mut exercised_count = 0;
     let rate_provider = crate::exchange_rate_provider::get_readonly_rate_provider();
 
-    // Get all exercisable options
-    let exercisable_options: Vec<(u32, Address)> = exchange
-        .listings
-        .iter()
-        .filter_map(|(id, listing)| {
-            if listing.is_purchased && !listing.is_exercised {
-                if let Some(beneficiary) = &listing.beneficiary_address {
-                    // Check if option is profitable before exercising
-                    if let Some(current_price) =
-                        rate_provider.get_rate(&listing.base_asset, &Asset::USDT)
-                    {
-                        let is_profitable = match listing.listing_type {
-                            ListingType::CALL => current_price > listing.strike_price,
-                            ListingType::PUT => current_price < listing.strike_price,
-                        };
-
-                        if is_profitable {
-                            Some((*id, beneficiary.clone()))
-                        } else {
-                            None
-                        }
+    // Get all exercisable positions
+    let exercisable_options: Vec<(u32, Address, u32)> = exchange
+        .positions
+        .values()
+        .flat_map(|holders| holders.values())
+        .filter_map(|position| {
+            let listing = exchange.listings.get(&position.listing_id)?;
+            if listing.state == OptionState::Purchased
+                && position.contracts > 0
+                && exchange.can_exercise(listing)
+            {
+                // Check if option is profitable before exercising
+                if let Some(current_price) =
+                    rate_provider.get_rate(&listing.base_asset, &Asset::USDT)
+                {
+                    if listing.is_in_the_money(current_price, 0).unwrap_or(false) {
+                        Some((
+                            position.listing_id,
+                            position.holder_address.clone(),
+                            position.contracts,
+                        ))
                     } else {
                         None
                     }
@@ -745,130 +1036,146 @@ fn auto_exercise_profitable_options(
             }
         })
         .collect();
+    // spot trades below may push their price into the provider
+    drop(rate_provider);
 
-    for (option_id, beneficiary_address) in exercisable_options {
+    for (option_id, beneficiary_address, contracts) in exercisable_options {
         // Find the bot that owns this option
         if let Some(bot) = bots.iter_mut().find(|b| b.address == beneficiary_address) {
             // Check if user has enough balance for option exercise
-            if let Some(user) = exchange.users.get(&beneficiary_address) {
-                if let Some(listing) = exchange.listings.get(&option_id) {
-                    let exercise_cost = listing.strike_price * listing.exercise_amount;
-                    let usdt_balance = user.get_balance(&Asset::USDT);
-
-                    // Only proceed if user has enough USDT for exercise
-                    if usdt_balance >= exercise_cost {
-                        // Try to exercise the option
-                        let result =
-                            exchange.exercise_option(option_id, beneficiary_address.clone());
-                        match result {
-                            Ok(_) => {
-                                exercised_count += 1;
-                                bot.last_exercise_round = Some(1000); // Mark as recently exercised
-
-                                if verbose {
-                                    println!("[EXERCISED] {} exercised option #{}", bot.strategy, option_id);
-                                }
+            if let Some(user) = exchange.users.get(&beneficiary_address)
+                && let Some(listing) = exchange.listings.get(&option_id)
+            {
+                let contract_cost =
+                    listing.strike_price.to_f64() * listing.exercise_amount.to_f64();
+                let usdt_balance = user.get_balance(&Asset::USDT).to_f64();
+
+                // Exercise as many contracts as the user can pay for, the rest stays open.
+                // Cash-settled contracts pay out their intrinsic value and cost nothing.
+                let affordable = if listing.settlement_type == SettlementType::Cash {
+                    contracts
+                } else if contract_cost > 0.0 {
+                    (usdt_balance / contract_cost)
+                        .floor()
+                        .min(f64::from(contracts)) as u32
+                } else {
+                    contracts
+                };
+                if affordable > 0 {
+                    // Try to exercise the option
+                    let result = exchange.exercise_contracts(
+                        option_id,
+                        affordable,
+                        beneficiary_address.clone(),
+                    );
+                    match result {
+                        Ok(_) => {
+                            exercised_count += 1;
+                            bot.last_exercise_round = Some(1000); // Mark as recently exercised
+
+                            if verbose {
+                                println!(
+                                    "[EXERCISED] {} exercised {} of {} contracts of option #{}",
+                                    bot.strategy, affordable, contracts, option_id
+                                );
+                            }
 
-                                // Post-exercise spot trading with balance validation
-                                let mut rng = rand::thread_rng();
-                                let post_action = bot.post_exercise_spot_trade(&mut rng, exchange);
-                                match post_action {
-                                    TraderAction::SpotBuy(asset, amount) => {
-                                        // Validate user has enough USDT for the buy
-                                        if let Some(updated_user) = exchange.users.get(&bot.address)
-                                        {
-                                            if let Some(price) =
-                                                rate_provider.get_rate(&asset, &Asset::USDT)
-                                            {
-                                                let trade_cost = amount * price;
-                                                let usdt_balance =
-                                                    updated_user.get_balance(&Asset::USDT);
-
-                                                if usdt_balance >= trade_cost {
-                                                    if let Err(e) = exchange
-                                                        .spot_trade_current_price(
-                                                            &asset,
-                                                            &Asset::USDT,
-                                                            amount,
-                                                            &SpotAction::BUY,
-                                                            bot.address.clone(),
-                                                        )
-                                                    {
-                                                        if verbose {
-                                                            println!(
-                                                                "[FAILED] {} failed post-exercise spot buy: {}",
-                                                                bot.strategy, e
-                                                            );
-                                                        }
-                                                    } else if verbose {
-                                                        println!(
-                                                            "[POST-EXERCISE] {} made post-exercise spot buy: {:.4} {}",
-                                                            bot.strategy, amount, asset
-                                                        );
-                                                    }
-                                                } else if verbose {
+                            // Post-exercise spot trading with balance validation
+                            let mut rng = rand::thread_rng();
+                            let post_action = bot.post_exercise_spot_trade(&mut rng, exchange);
+                            match post_action {
+                                TraderAction::SpotBuy(asset, amount) => {
+                                    // Validate user has enough USDT for the buy
+                                    let rate =
+                                        get_readonly_rate_provider().get_rate(&asset, &Asset::USDT);
+                                    if let Some(updated_user) = exchange.users.get(&bot.address)
+                                        && let Some(price) = rate
+                                    {
+                                        let trade_cost = amount * price.to_f64();
+                                        let usdt_balance =
+                                            updated_user.get_balance(&Asset::USDT).to_f64();
+
+                                        if usdt_balance >= trade_cost {
+                                            if let Err(e) = trade_spot(
+                                                exchange,
+                                                &asset,
+                                                amount,
+                                                SpotAction::BUY,
+                                                bot.address.clone(),
+                                            ) {
+                                                if verbose {
                                                     println!(
-                                                        "⚠️ {} skipped post-exercise buy: insufficient USDT balance",
-                                                        bot.strategy
+                                                        "[FAILED] {} failed post-exercise spot buy: {}",
+                                                        bot.strategy, e
                                                     );
                                                 }
+                                            } else if verbose {
+                                                println!(
+                                                    "[POST-EXERCISE] {} made post-exercise spot buy: {:.4} {}",
+                                                    bot.strategy, amount, asset
+                                                );
                                             }
+                                        } else if verbose {
+                                            println!(
+                                                "⚠️ {} skipped post-exercise buy: insufficient USDT balance",
+                                                bot.strategy
+                                            );
                                         }
                                     }
-                                    TraderAction::SpotSell(asset, amount) => {
-                                        // Validate user has enough of the asset to sell
-                                        if let Some(updated_user) = exchange.users.get(&bot.address)
-                                        {
-                                            let asset_balance = updated_user.get_balance(&asset);
-                                            let safe_amount = amount.min(asset_balance);
-
-                                            if safe_amount > 0.001 {
-                                                // Only sell if meaningful amount
-                                                if let Err(e) = exchange.spot_trade_current_price(
-                                                    &asset,
-                                                    &Asset::USDT,
-                                                    safe_amount,
-                                                    &SpotAction::SELL,
-                                                    bot.address.clone(),
-                                                ) {
-                                                    if verbose {
-                                                        println!(
-                                                            "[FAILED] {} failed post-exercise spot sell: {}",
-                                                            bot.strategy, e
-                                                        );
-                                                    }
-                                                } else if verbose {
+                                }
+                                TraderAction::SpotSell(asset, amount) => {
+                                    // Validate user has enough of the asset to sell
+                                    if let Some(updated_user) = exchange.users.get(&bot.address) {
+                                        let asset_balance =
+                                            updated_user.get_balance(&asset).to_f64();
+                                        let safe_amount = amount.min(asset_balance);
+
+                                        if safe_amount > 0.001 {
+                                            // Only sell if meaningful amount
+                                            if let Err(e) = trade_spot(
+                                                exchange,
+                                                &asset,
+                                                safe_amount,
+                                                SpotAction::SELL,
+                                                bot.address.clone(),
+                                            ) {
+                                                if verbose {
                                                     println!(
-                                                        "[PROFIT] {} realized profits via spot sell: {:.4} {}",
-                                                        bot.strategy, safe_amount, asset
+                                                        "[FAILED] {} failed post-exercise spot sell: {}",
+                                                        bot.strategy, e
                                                     );
                                                 }
                                             } else if verbose {
                                                 println!(
-                                                    "⚠️ {} skipped post-exercise sell: insufficient {} balance",
-                                                    bot.strategy, asset
+                                                    "[PROFIT] {} realized profits via spot sell: {:.4} {}",
+                                                    bot.strategy, safe_amount, asset
                                                 );
                                             }
+                                        } else if verbose {
+                                            println!(
+                                                "⚠️ {} skipped post-exercise sell: insufficient {} balance",
+                                                bot.strategy, asset
+                                            );
                                         }
                                     }
-                                    _ => {}
                                 }
+                                _ => {}
                             }
-                            Err(e) => {
-                                if verbose {
-                                    println!(
-                                        "[FAILED] {} failed to exercise option #{}: {}",
-                                        bot.strategy, option_id, e
-                                    );
-                                }
+                        }
+                        Err(e) => {
+                            if verbose {
+                                println!(
+                                    "[FAILED] {} failed to exercise option #{}: {}",
+                                    bot.strategy, option_id, e
+                                );
                             }
                         }
-                    } else if verbose {
-                        println!(
-                            "[FAILED] {} failed to exercise option #{}: Insufficient USDT balance",
-                            bot.strategy, option_id
-                        );
                     }
+                } else if verbose {
+                    println!(
+                        "[FAILED] {} failed to exercise option #{}: Insufficient USDT balance",
+                        bot.strategy, option_id
+                    );
                 }
             }
         }
@@ -889,8 +1196,41 @@ pub fn run_simulation(rounds: usize, verbose: bool) {
         "💹 Features: Dynamic market volatility, sophisticated trading bots, options exercising, and profit realization"
     );
 
-    // Initialize exchange
-    let mut exchange = Exchange::new();
+    // Initialize exchange on a simulated clock that advances with each round
+    let clock = Arc::new(SimulatedClock::new(Utc::now()));
+    let mut exchange = Exchange::with_clock(clock.clone());
+    if let Err(e) = get_rate_provider().set_clock(
+        clock.clone(),
+        default_exchange_rate_provider_admin_address(),
+    ) {
+        eprintln!("Warning: Failed to set rate provider clock: {}", e);
+    }
+
+    // Spot trades between bots move the rates the options are valued at
+    if let Err(e) = exchange.set_spot_rate_feed(true, exchange.market_admin_address.clone()) {
+        eprintln!("Warning: Failed to enable the spot rate feed: {}", e);
+    }
+
+    // Keep European and Bermudan windows open for two rounds, so no bot steps over one
+    if let Err(e) = exchange.set_exercise_window(
+        Duration::hours(2 * SIMULATED_HOURS_PER_ROUND),
+        exchange.market_admin_address.clone(),
+    ) {
+        eprintln!("Warning: Failed to set the exercise window: {}", e);
+    }
+
+    if let Err(e) = exchange.insurance_fund.add_asset(
+        &Asset::USDT,
+        to_asset_amount(INSURANCE_FUND_SEED, &Asset::USDT),
+    ) {
+        eprintln!("Warning: Failed to seed the insurance fund: {}", e);
+    }
+    let desk_address = exchange.escrow_user.address.clone();
+    if let Some(desk) = exchange.users.get_mut(&desk_address)
+        && let Err(e) = desk.add_asset(&Asset::USDT, to_asset_amount(SPOT_DESK_SEED, &Asset::USDT))
+    {
+        eprintln!("Warning: Failed to seed the spot desk: {}", e);
+    }
 
     // Initialize market volatility system
     let mut market_volatility = MarketVolatility::new();
@@ -933,13 +1273,20 @@ pub fn run_simulation(rounds: usize, verbose: bool) {
         let mut user = User::new(address.clone());
 
         // Give users initial assets for trading
-        user.add_asset(&Asset::USDT, 1000000.0).unwrap(); // 1M USDT
-        user.add_asset(&Asset::BTC, 10.0).unwrap(); // 10 BTC
-        user.add_asset(&Asset::ETH, 100.0).unwrap(); // 100 ETH
-        user.add_asset(&Asset::SOL, 1000.0).unwrap(); // 1000 SOL
-        user.add_asset(&Asset::APPLE, 50.0).unwrap(); // 50 APPLE shares
+        user.add_asset(&Asset::USDT, Amount::from_f64(1000000.0))
+            .unwrap(); // 1M USDT
+        user.add_asset(&Asset::BTC, Amount::from_f64(10.0)).unwrap(); // 10 BTC
+        user.add_asset(&Asset::ETH, Amount::from_f64(100.0))
+            .unwrap(); // 100 ETH
+        user.add_asset(&Asset::SOL, Amount::from_f64(1000.0))
+            .unwrap(); // 1000 SOL
+        user.add_asset(&Asset::APPLE, Amount::from_f64(50.0))
+            .unwrap(); // 50 APPLE shares
 
         exchange.users.insert(address.clone(), user);
+        if *strategy == "market_maker" {
+            open_margin_account(&mut exchange, &address, verbose);
+        }
         let mut bot = TraderBot::new(address, strategy.to_string());
 
         // Set proper trader name instead of address-based name
@@ -969,7 +1316,7 @@ pub fn run_simulation(rounds: usize, verbose: bool) {
     for round in 1..=rounds {
         // Update market conditions with dynamic exchange rates
         if let Err(e) =
-            update_exchange_rates(&exchange, round as u32, &mut market_volatility, verbose)
+            update_exchange_rates(&mut exchange, round as u32, &mut market_volatility, verbose)
         {
             eprintln!("Warning: Failed to update exchange rates: {}", e);
         }
@@ -978,6 +1325,16 @@ pub fn run_simulation(rounds: usize, verbose: bool) {
             println!("\n>> Round {} of {}", round, rounds);
         }
 
+        // Balanced traders fund the pools the others swap through
+        for bot in bots.iter().filter(|bot| bot.strategy == "balanced") {
+            seed_pools(&mut exchange, &bot.address, verbose);
+        }
+
+        // Market makers keep the spot books stocked before anyone trades
+        for bot in bots.iter().filter(|bot| bot.strategy == "market_maker") {
+            refresh_spot_quotes(&mut exchange, &bot.address, verbose);
+        }
+
         // Each bot takes an action
         for bot in &mut bots {
             let action = bot.decide_action(&exchange, round as u32);
@@ -987,6 +1344,10 @@ pub fn run_simulation(rounds: usize, verbose: bool) {
         // Display market state periodically
         if verbose && round % 5 == 0 {
             display_listings(&exchange);
+            display_order_books(&exchange);
+            display_pools(&exchange);
+            display_volatility(&exchange, &market_volatility);
+            display_vol_surfaces(&exchange);
             display_users(&exchange);
 
             let stats = get_market_stats(&exchange);
@@ -994,7 +1355,51 @@ pub fn run_simulation(rounds: usize, verbose: bool) {
                 "Market Stats: {} listings ({} calls, {} puts), Total value: ${:.2}",
                 stats.total_listings, stats.call_count, stats.put_count, stats.total_premium_value
             );
+            display_style_stats(&stats);
+            display_margin_health(&exchange);
+            display_funds(&exchange.fund_report());
+        }
+
+        // Move on to the next round and release the collateral of anything that expired meanwhile
+        clock.advance(Duration::hours(SIMULATED_HOURS_PER_ROUND));
+        match exchange.settle_expired() {
+            Ok(report) => {
+                if verbose {
+                    for auto_exercise in &report.auto_exercised {
+                        println!(
+                            "[AUTO-EXERCISED] {} contracts of option #{} exercised at expiry for {} at ${:.2}",
+                            auto_exercise.contracts,
+                            auto_exercise.listing_id,
+                            get_user_name(&auto_exercise.holder_address),
+                            auto_exercise.spot
+                        );
+                    }
+                    for settled in &report.settled {
+                        println!(
+                            "[EXPIRED] Option #{} expired unexercised, {:.2} {} returned to {}",
+                            settled.listing_id,
+                            settled.released_amount,
+                            settled.released_asset,
+                            get_user_name(&settled.grantor_address)
+                        );
+                    }
+                    for bid_id in &report.expired_bids {
+                        println!(
+                            "[EXPIRED] Bid #{} expired unfilled, locked premium returned",
+                            bid_id
+                        );
+                    }
+                    for unsettled in &report.unsettled {
+                        println!(
+                            "[UNSETTLED] Option #{} couldn't be exercised at expiry and lapsed: {}",
+                            unsettled.listing_id, unsettled.error
+                        );
+                    }
+                }
+            }
+            Err(e) => eprintln!("Warning: Failed to settle expired options: {}", e),
         }
+        exchange.record_fund_report();
 
         // Add delay for readability
         if verbose {
@@ -1004,6 +1409,48 @@ pub fn run_simulation(rounds: usize, verbose: bool) {
 
     println!("\n[SIMULATION COMPLETE]");
 
+    // Unfilled bids hand their locked funds back so they count towards each bot's portfolio
+    let mut open_bids: Vec<(u32, Address)> = exchange
+        .bids
+        .values()
+        .map(|bid| (bid.bid_id, bid.bidder_address.clone()))
+        .collect();
+    open_bids.sort_by_key(|(bid_id, _)| *bid_id);
+    for (bid_id, bidder_address) in open_bids {
+        if let Err(e) = exchange.cancel_bid(bid_id, bidder_address) {
+            eprintln!("Warning: Failed to cancel bid #{}: {}", bid_id, e);
+        }
+    }
+    // Same for resting spot orders
+    let open_spot_orders: Vec<(u32, Address)> = exchange
+        .spot_books
+        .values()
+        .flat_map(|book| book.bids.iter().chain(book.asks.iter()))
+        .map(|order| (order.order_id, order.owner_address.clone()))
+        .collect();
+    for (order_id, owner_address) in open_spot_orders {
+        if let Err(e) = exchange.cancel_spot_order(order_id, owner_address) {
+            eprintln!("Warning: Failed to cancel spot order #{}: {}", order_id, e);
+        }
+    }
+
+    // Liquidity providers take their share of the pools back
+    let mut provisions: Vec<(AssetPair, Address, Amount)> = exchange
+        .pools
+        .values()
+        .flat_map(|pool| {
+            pool.shares
+                .iter()
+                .map(|(provider, shares)| (pool.pair.clone(), provider.clone(), *shares))
+        })
+        .collect();
+    provisions.sort_by_key(|(pair, _, _)| pair.to_string());
+    for (pair, provider_address, shares) in provisions {
+        if let Err(e) = exchange.remove_liquidity(pair.clone(), shares, provider_address) {
+            eprintln!("Warning: Failed to withdraw from the {} pool: {}", pair, e);
+        }
+    }
+
     // Exercise profitable options automatically at the end
     if verbose {
         println!("\n[AUTO-EXERCISING PROFITABLE OPTIONS]...");
@@ -1018,6 +1465,9 @@ pub fn run_simulation(rounds: usize, verbose: bool) {
         "Final Market Stats: {} listings, Total value: ${:.2}",
         final_stats.total_listings, final_stats.total_premium_value
     );
+    display_style_stats(&final_stats);
+    display_margin_health(&exchange);
+    display_fund_history(&exchange);
 
     // Generate comprehensive PnL report
     generate_pnl_report(&bots, &exchange);
@@ -1029,6 +1479,15 @@ pub struct MarketStats {
     pub call_count: usize,
     pub put_count: usize,
     pub total_premium_value: f64,
+    pub by_style: Vec<StyleStats>, // American, European then Bermudan
+}
+
+/// Listings of one exercise style, premiums and fair values only over those still open for purchase
+pub struct StyleStats {
+    pub style: &'static str,
+    pub listing_count: usize,
+    pub open_premium_value: f64,
+    pub open_fair_value: f64,
 }
 
 /// Get market statistics
@@ -1037,13 +1496,40 @@ fn get_market_stats(exchange: &Exchange) -> MarketStats {
     let mut call_count = 0;
     let mut put_count = 0;
     let mut total_premium_value = 0.0;
+    let mut by_style: Vec<StyleStats> = ["American", "European", "Bermudan"]
+        .into_iter()
+        .map(|style| StyleStats {
+            style,
+            listing_count: 0,
+            open_premium_value: 0.0,
+            open_fair_value: 0.0,
+        })
+        .collect();
 
     for listing in exchange.listings.values() {
         match listing.listing_type {
             ListingType::CALL => call_count += 1,
             ListingType::PUT => put_count += 1,
         }
-        total_premium_value += listing.get_premium_price();
+        let premium_price = listing.get_premium_price().map(|premium| premium.to_f64());
+        if let Ok(premium_price) = premium_price {
+            total_premium_value += premium_price;
+        }
+
+        let Some(style_stats) = by_style
+            .iter_mut()
+            .find(|stats| stats.style == listing.exercise_style.name())
+        else {
+            continue;
+        };
+        style_stats.listing_count += 1;
+        if listing.state == OptionState::Listed
+            && let Ok(premium_price) = premium_price
+            && let Some(value) = value_listing(exchange, listing)
+        {
+            style_stats.open_premium_value += premium_price;
+            style_stats.open_fair_value += value.valuation.price;
+        }
     }
 
     MarketStats {
@@ -1051,6 +1537,108 @@ fn get_market_stats(exchange: &Exchange) -> MarketStats {
         call_count,
         put_count,
         total_premium_value,
+        by_style,
+    }
+}
+
+/// Display how the open listings of each exercise style ask against their fair value
+fn display_style_stats(stats: &MarketStats) {
+    for style_stats in &stats.by_style {
+        if style_stats.listing_count == 0 {
+            continue;
+        }
+        println!(
+            "  {}: {} listings, open premiums ${:.2} vs fair value ${:.2}",
+            style_stats.style,
+            style_stats.listing_count,
+            style_stats.open_premium_value,
+            style_stats.open_fair_value
+        );
+    }
+}
+
+/// Open a margin account on portfolio margin for a writer and fund it with USDT
+fn open_margin_account(exchange: &mut Exchange, address: &Address, verbose: bool) {
+    let result = exchange
+        .open_margin_account(address.clone())
+        .and_then(|_| {
+            exchange.deposit_margin(
+                &Asset::USDT,
+                to_asset_amount(MARKET_MAKER_MARGIN, &Asset::USDT),
+                address.clone(),
+            )
+        })
+        .and_then(|_| exchange.set_portfolio_margin(true, address.clone()));
+    match result {
+        Ok(()) if verbose => println!(
+            "[MARGIN] {} posted ${:.2} to a portfolio margin account",
+            get_user_name(address),
+            MARKET_MAKER_MARGIN
+        ),
+        Ok(()) => {}
+        Err(e) => eprintln!("Warning: Failed to open a margin account: {}", e),
+    }
+}
+
+/// Display the collateral of every margin account against its requirements
+fn display_margin_health(exchange: &Exchange) {
+    let healths = match exchange.margin_healths() {
+        Ok(healths) => healths,
+        Err(e) => {
+            println!("  Margin health unavailable: {}", e);
+            return;
+        }
+    };
+    for health in healths {
+        println!(
+            "  Margin {} {}: ${:.2} posted, ${:.2} initial, ${:.2} maintenance [{}]",
+            get_user_name(&health.owner),
+            health.asset,
+            health.collateral,
+            health.initial_requirement,
+            health.maintenance_requirement,
+            if health.is_healthy() {
+                "HEALTHY"
+            } else {
+                "UNDER MAINTENANCE"
+            }
+        );
+    }
+}
+
+/// Format fund balances as "1.00 USDT, 2.00 USDC", in asset order
+fn format_fund_balances(balances: &HashMap<Asset, Amount>) -> String {
+    if balances.is_empty() {
+        return "empty".to_string();
+    }
+    let mut balances: Vec<(&Asset, &Amount)> = balances.iter().collect();
+    balances.sort_by_key(|(asset, _)| asset.to_string());
+    balances
+        .iter()
+        .map(|(asset, balance)| format!("{:.2} {}", balance, asset))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Display the treasury and insurance fund balances of a fund report
+fn display_funds(report: &FundReport) {
+    println!("  Treasury: {}", format_fund_balances(&report.treasury));
+    println!(
+        "  Insurance fund: {}",
+        format_fund_balances(&report.insurance_fund)
+    );
+}
+
+/// Display the fund balances recorded after every round
+fn display_fund_history(exchange: &Exchange) {
+    println!("\nFUND BALANCES BY ROUND:");
+    for (round, report) in exchange.fund_reports.iter().enumerate() {
+        println!(
+            "  Round {}: treasury {} | insurance fund {}",
+            round + 1,
+            format_fund_balances(&report.treasury),
+            format_fund_balances(&report.insurance_fund)
+        );
     }
 }
 
@@ -1075,6 +1663,325 @@ fn get_user_name(address: &Address) -> &'static str {
     }
 }
 
+/// Pick how many contracts to buy, never more than the listing has left
+fn contracts_to_buy(rng: &mut impl Rng, exchange: &Exchange, listing_id: u32) -> u32 {
+    let available = exchange
+        .listings
+        .get(&listing_id)
+        .map_or(0, |listing| listing.contract_count);
+    rng.gen_range(1..=available.clamp(1, 3))
+}
+
+/// Value of a listing's contract under the bots' pricing model and its exercise style
+fn value_listing(exchange: &Exchange, listing: &ListingOption) -> Option<BinomialValuation> {
+    binomial::value_listing_by_style(
+        listing,
+        exchange.exercise_window,
+        &PRICING_PARAMS,
+        DEFAULT_BINOMIAL_STEPS,
+        exchange.now(),
+    )
+    .ok()
+}
+
+/// Fair `ask_price` of a listing under the bots' pricing model, None without a rate to price it at
+fn listing_fair_ask_price(exchange: &Exchange, listing: &ListingOption) -> Option<f64> {
+    // premiums are ask_price * 100 per contract
+    value_listing(exchange, listing).map(|value| value.valuation.price / 100.0)
+}
+
+/// Exercise dates of the Bermudan options the bots list, weekly up to expiry
+fn bermudan_dates(expiration_time: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+    (1..OPTION_LIFETIME_DAYS / 7)
+        .map(|weeks| expiration_time - Duration::weeks(weeks))
+        .collect()
+}
+
+/// Whether exercising a listing today is allowed and beats holding on to it, or it is in the
+/// money and would expire before the next round
+fn should_exercise_early(exchange: &Exchange, listing: &ListingOption) -> bool {
+    if !exchange.can_exercise(listing) {
+        return false;
+    }
+    let Some(valuation) = value_listing(exchange, listing) else {
+        return false;
+    };
+    let expires_before_next_round =
+        listing.expiration_time <= exchange.now() + Duration::hours(SIMULATED_HOURS_PER_ROUND);
+    let Ok(spot) = pricing::spot_rate(&listing.base_asset, &listing.quote_asset) else {
+        return false;
+    };
+    let in_the_money = match listing.listing_type {
+        ListingType::CALL => spot > listing.strike_price.to_f64(),
+        ListingType::PUT => spot < listing.strike_price.to_f64(),
+    };
+
+    valuation.exercise_now || (expires_before_next_round && in_the_money)
+}
+
+fn round_to_cents(price: f64) -> f64 {
+    (price * 100.0).round() / 100.0
+}
+
+/// Buy or sell against the spot book for USDT at whatever the book offers within `SPOT_SLIPPAGE_BPS`
+fn trade_spot(
+    exchange: &mut Exchange,
+    asset: &Asset,
+    amount: f64,
+    action: SpotAction,
+    address: Address,
+) -> Result<(), ExchangeError> {
+    exchange.place_spot_market_order(
+        AssetPair::from(asset.clone(), Asset::USDT),
+        action,
+        to_asset_amount(amount, asset),
+        SPOT_SLIPPAGE_BPS,
+        address,
+    )
+}
+
+/// Replace the market maker's spot quotes with a fresh bid and ask around the current rate,
+/// so the other bots always have someone to trade with
+fn refresh_spot_quotes(exchange: &mut Exchange, maker_address: &Address, verbose: bool) {
+    for asset in [Asset::BTC, Asset::ETH, Asset::SOL] {
+        let pair = AssetPair::from(asset.clone(), Asset::USDT);
+        let stale: Vec<u32> = exchange.spot_books.get(&pair).map_or(Vec::new(), |book| {
+            book.bids
+                .iter()
+                .chain(book.asks.iter())
+                .filter(|order| order.owner_address == *maker_address)
+                .map(|order| order.order_id)
+                .collect()
+        });
+        for order_id in stale {
+            if let Err(e) = exchange.cancel_spot_order(order_id, maker_address.clone()) {
+                eprintln!("Warning: Failed to cancel spot order #{}: {}", order_id, e);
+            }
+        }
+
+        let rate = match get_readonly_rate_provider().get_rate(&asset, &Asset::USDT) {
+            Some(rate) if rate > Amount::ZERO => rate.to_f64(),
+            _ => continue,
+        };
+        let size = to_asset_amount(SPOT_QUOTE_VALUE / rate, &asset);
+        let half_spread = SPOT_QUOTE_SPREAD / 2.0;
+        for (action, price) in [
+            (SpotAction::BUY, rate * (1.0 - half_spread)),
+            (SpotAction::SELL, rate * (1.0 + half_spread)),
+        ] {
+            if let Err(e) = exchange.place_spot_limit_order(
+                pair.clone(),
+                action,
+                Amount::from_f64(price),
+                size,
+                maker_address.clone(),
+            ) && verbose
+            {
+                println!("[FAILED] market maker failed to quote {}: {}", pair, e);
+            }
+        }
+    }
+}
+
+/// Fund a pool for every pair that doesn't have one yet at the current rate
+fn seed_pools(exchange: &mut Exchange, provider_address: &Address, verbose: bool) {
+    for asset in [Asset::BTC, Asset::ETH, Asset::SOL] {
+        let pair = AssetPair::from(asset.clone(), Asset::USDT);
+        if exchange.get_pool_price(&pair).is_some() {
+            continue;
+        }
+        let rate = match get_readonly_rate_provider().get_rate(&asset, &Asset::USDT) {
+            Some(rate) if rate > Amount::ZERO => rate.to_f64(),
+            _ => continue,
+        };
+
+        match exchange.add_liquidity(
+            pair.clone(),
+            to_asset_amount(POOL_SEED_VALUE / rate, &asset),
+            to_asset_amount(POOL_SEED_VALUE, &Asset::USDT),
+            provider_address.clone(),
+        ) {
+            Ok(shares) => {
+                if verbose {
+                    println!(
+                        "[POOL] {} seeded the {} pool for {:.4} shares",
+                        get_user_name(provider_address),
+                        pair,
+                        shares
+                    );
+                }
+            }
+            Err(e) => {
+                if verbose {
+                    println!("[FAILED] failed to seed the {} pool: {}", pair, e);
+                }
+            }
+        }
+    }
+}
+
+/// Snap a bot's strike to the grid and its expiry to the start of the day,
+/// so listings made a few rounds apart still trade in the same series
+fn series_terms(exchange: &Exchange, strike_price: f64) -> (Price, DateTime<Utc>) {
+    let grid = 10_f64.powf(strike_price.log10().floor() + 1.0) / STRIKE_STEPS_PER_DECADE;
+    let strike_price = (strike_price / grid).round().max(1.0) * grid;
+    let expiration = (exchange.now() + Duration::days(OPTION_LIFETIME_DAYS))
+        .date_naive()
+        .and_time(NaiveTime::MIN)
+        .and_utc();
+    (Amount::from_f64(strike_price), expiration)
+}
+
+/// Display the best bid and ask of every series with resting liquidity
+fn display_order_books(exchange: &Exchange) {
+    println!("\nOrder Books:");
+    let books = exchange.order_books();
+    if books.is_empty() {
+        println!("  No resting orders");
+        return;
+    }
+
+    for book in books {
+        let side = |entry: Option<&BookEntry>, depth: u32| match entry {
+            Some(entry) => format!("${:.2} ({} contracts)", entry.price, depth),
+            None => "-".to_string(),
+        };
+        let bid_depth = book.bids.iter().map(|entry| entry.contracts).sum();
+        let ask_depth = book.asks.iter().map(|entry| entry.contracts).sum();
+        println!(
+            "  {}: bid {} / ask {}",
+            book.series,
+            side(book.best_bid(), bid_depth),
+            side(book.best_ask(), ask_depth)
+        );
+    }
+}
+
+/// Display the reserves of every pool next to the rate it should trade at
+fn display_pools(exchange: &Exchange) {
+    println!("\nLiquidity Pools:");
+    let mut pools: Vec<_> = exchange.pools.values().collect();
+    if pools.is_empty() {
+        println!("  No pools");
+        return;
+    }
+    pools.sort_by_key(|pool| pool.pair.to_string());
+
+    let rate_provider = get_readonly_rate_provider();
+    for pool in pools {
+        let price = pool
+            .get_price()
+            .map_or("-".to_string(), |price| format!("${:.2}", price));
+        let rate = rate_provider
+            .get_rate(&pool.pair.base, &pool.pair.quote)
+            .map_or("-".to_string(), |rate| format!("${:.2}", rate));
+        println!(
+            "  {}: {:.4} {} / {:.2} {}, price {} (rate {})",
+            pool.pair,
+            pool.base_reserve,
+            pool.pair.base,
+            pool.quote_reserve,
+            pool.pair.quote,
+            price,
+            rate
+        );
+    }
+}
+
+/// Compare the volatility the listed premiums imply with what the rates actually did,
+/// telling whether the options of each asset trade rich or cheap
+fn display_volatility(exchange: &Exchange, volatility: &MarketVolatility) {
+    println!("\nImplied vs Realized Volatility:");
+    let implied = pricing::implied_volatility_by_asset(
+        exchange.listings.values(),
+        PRICING_PARAMS.risk_free_rate,
+        exchange.now(),
+    );
+    if implied.is_empty() {
+        println!("  No listings to imply a volatility from");
+        return;
+    }
+
+    for asset in [Asset::BTC, Asset::ETH, Asset::SOL, Asset::APPLE] {
+        let Some(implied) = implied.get(&asset) else {
+            continue;
+        };
+        match volatility.realized_volatility(&asset) {
+            Some(realized) => {
+                let verdict = if implied.volatility > realized * 1.1 {
+                    "rich"
+                } else if implied.volatility < realized * 0.9 {
+                    "cheap"
+                } else {
+                    "fair"
+                };
+                println!(
+                    "  {}: implied {:.1}% over {} listings, realized {:.1}% -> options {}",
+                    asset,
+                    implied.volatility * 100.0,
+                    implied.listing_count,
+                    realized * 100.0,
+                    verdict
+                );
+            }
+            None => println!(
+                "  {}: implied {:.1}% over {} listings, not enough rounds for a realized volatility",
+                asset,
+                implied.volatility * 100.0,
+                implied.listing_count
+            ),
+        }
+    }
+}
+
+/// Display the interpolated volatility surface of every asset with listings, moneyness across
+fn display_vol_surfaces(exchange: &Exchange) {
+    println!("\nVolatility Surfaces:");
+    let surfaces = vol_surface::build_surfaces(
+        exchange.listings.values(),
+        &Asset::USDT,
+        PRICING_PARAMS.risk_free_rate,
+        exchange.now(),
+    );
+    if surfaces.is_empty() {
+        println!("  No listings to build a surface from");
+        return;
+    }
+
+    for asset in [Asset::BTC, Asset::ETH, Asset::SOL, Asset::APPLE] {
+        let Some(surface) = surfaces.get(&asset) else {
+            continue;
+        };
+        let listing_count: usize = surface
+            .points
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|point| point.listing_count)
+            .sum();
+        println!(
+            "  {} at ${:.2}, {} listings:",
+            asset, surface.spot, listing_count
+        );
+        let header: String = MONEYNESS_BUCKETS
+            .iter()
+            .map(|moneyness| format!("{:>7.2}", moneyness))
+            .collect();
+        println!("    {:>5}{}", "days", header);
+        for (days, row) in EXPIRY_BUCKET_DAYS.iter().zip(surface.grid()) {
+            let cells: String = row
+                .iter()
+                .map(|volatility| {
+                    volatility.map_or(format!("{:>7}", "-"), |volatility| {
+                        format!("{:>6.1}%", volatility * 100.0)
+                    })
+                })
+                .collect();
+            println!("    {:>5}{}", days, cells);
+        }
+    }
+}
+
 /// Display current listings
 fn display_listings(exchange: &Exchange) {
     println!("\nCurrent Listings:");
@@ -1084,24 +1991,34 @@ fn display_listings(exchange: &Exchange) {
     }
 
     for (id, listing) in &exchange.listings {
-        let status = if listing.is_exercised {
-            "EXERCISED"
-        } else if listing.is_purchased {
-            "PURCHASED"
-        } else if listing.is_unlisted {
-            "UNLISTED"
+        let status = match listing.state {
+            OptionState::Listed => "ACTIVE",
+            OptionState::Purchased => "PURCHASED",
+            OptionState::Exercised => "EXERCISED",
+            OptionState::Unlisted => "UNLISTED",
+            OptionState::Expired => "EXPIRED",
+            OptionState::Settled => "SETTLED",
+        };
+
+        // what the style is worth per contract, quoted like the ask
+        let fair_ask = if listing.state.is_final() {
+            None
         } else {
-            "ACTIVE"
+            listing_fair_ask_price(exchange, listing)
         };
 
         println!(
-            "  #{}: {} {} {}/{} @ ${:.2} (strike: ${:.2}) [{}]",
+            "  #{}: {} {}-settled {} {} x {} {}/{} @ ${:.2}, fair {} (strike: ${:.2}) [{}]",
             id,
+            listing.exercise_style,
+            listing.settlement_type,
             listing.listing_type,
+            listing.contract_count,
             listing.exercise_amount,
             listing.base_asset,
             listing.quote_asset,
             listing.ask_price,
+            fair_ask.map_or("-".to_string(), |fair_ask| format!("${:.2}", fair_ask)),
             listing.strike_price,
             status
         );
@@ -1116,7 +2033,7 @@ fn display_users(exchange: &Exchange) {
         let addr_display = format_address(address);
         println!("  {} ({}): ", user_name, addr_display);
         for (asset, balance) in &user.balances {
-            if *balance > 0.0 {
+            if !balance.is_zero() {
                 println!("    {}: {:.2}", asset, balance);
             }
         }
@@ -1135,8 +2052,15 @@ fn execute_action(
     let addr_display = format_address(&bot.address);
 
     match action {
-        TraderAction::ListCall(base_asset, strike_price, ask_price) => {
-            let expiration = Utc::now() + Duration::days(30);
+        TraderAction::ListCall(
+            base_asset,
+            strike_price,
+            ask_price,
+            contract_count,
+            exercise_style,
+            settlement_type,
+        ) => {
+            let (strike_price, expiration) = series_terms(exchange, strike_price);
             let bid_price = ask_price * 0.95; // Set bid slightly lower than ask
 
             let option = ListingOption {
@@ -1145,24 +2069,27 @@ fn execute_action(
                 quote_asset: Asset::USDT, // Use USDT as quote asset
                 listing_type: ListingType::CALL,
                 strike_price,
-                ask_price,
-                bid_price,
+                ask_price: Amount::from_f64(ask_price),
+                bid_price: Amount::from_f64(bid_price),
                 expiration_time: expiration,
                 grantor_address: bot.address.clone(),
-                beneficiary_address: None,
-                exercise_amount: 1.0, // Default to 1 unit
-                is_purchased: false,
-                is_unlisted: false,
-                is_exercised: false,
+                contract_count,
+                exercise_amount: Amount::from_int(1), // 1 unit per contract
+                exercise_style: exercise_style.clone(),
+                settlement_type,
+                state: OptionState::Listed,
             };
 
             match exchange.list_option(bot.address.clone(), option) {
                 Ok(listing_id) => {
                     if verbose {
                         println!(
-                            "[LISTED] {} ({}) listed CALL option #{} for {}/{} @ ${:.2}",
+                            "[LISTED] {} ({}) listed {} {} {}-settled CALL contracts #{} for {}/{} @ ${:.2}",
                             user_name,
                             addr_display,
+                            contract_count,
+                            exercise_style.name(),
+                            settlement_type,
                             listing_id,
                             base_asset,
                             Asset::USDT,
@@ -1180,8 +2107,15 @@ fn execute_action(
                 }
             }
         }
-        TraderAction::ListPut(base_asset, strike_price, ask_price) => {
-            let expiration = Utc::now() + Duration::days(30);
+        TraderAction::ListPut(
+            base_asset,
+            strike_price,
+            ask_price,
+            contract_count,
+            exercise_style,
+            settlement_type,
+        ) => {
+            let (strike_price, expiration) = series_terms(exchange, strike_price);
             let bid_price = ask_price * 0.95; // Set bid slightly lower than ask
 
             let option = ListingOption {
@@ -1190,24 +2124,27 @@ fn execute_action(
                 quote_asset: Asset::USDT, // Use USDT as quote asset
                 listing_type: ListingType::PUT,
                 strike_price,
-                ask_price,
-                bid_price,
+                ask_price: Amount::from_f64(ask_price),
+                bid_price: Amount::from_f64(bid_price),
                 expiration_time: expiration,
                 grantor_address: bot.address.clone(),
-                beneficiary_address: None,
-                exercise_amount: 1.0, // Default to 1 unit
-                is_purchased: false,
-                is_unlisted: false,
-                is_exercised: false,
+                contract_count,
+                exercise_amount: Amount::from_int(1), // 1 unit per contract
+                exercise_style: exercise_style.clone(),
+                settlement_type,
+                state: OptionState::Listed,
             };
 
             match exchange.list_option(bot.address.clone(), option) {
                 Ok(listing_id) => {
                     if verbose {
                         println!(
-                            "[LISTED] {} ({}) listed PUT option #{} for {}/{} @ ${:.2}",
+                            "[LISTED] {} ({}) listed {} {} {}-settled PUT contracts #{} for {}/{} @ ${:.2}",
                             user_name,
                             addr_display,
+                            contract_count,
+                            exercise_style.name(),
+                            settlement_type,
                             listing_id,
                             base_asset,
                             Asset::USDT,
@@ -1225,13 +2162,13 @@ fn execute_action(
                 }
             }
         }
-        TraderAction::BuyOption(listing_id) => {
-            match exchange.purchase_option(listing_id, bot.address.clone()) {
+        TraderAction::BuyOption(listing_id, contract_count) => {
+            match exchange.purchase_contracts(listing_id, contract_count, bot.address.clone()) {
                 Ok(_) => {
                     if verbose {
                         println!(
-                            "[PURCHASED] {} ({}) purchased option #{}",
-                            user_name, addr_display, listing_id
+                            "[PURCHASED] {} ({}) purchased {} contracts of option #{}",
+                            user_name, addr_display, contract_count, listing_id
                         );
                     }
                 }
@@ -1245,6 +2182,72 @@ fn execute_action(
                 }
             }
         }
+        TraderAction::PlaceBid(series, bid_price, contract_count) => {
+            match exchange.submit_buy_order(
+                series.clone(),
+                OrderType::Limit(Amount::from_f64(bid_price)),
+                contract_count,
+                bot.address.clone(),
+            ) {
+                Ok(order_id) => {
+                    if verbose {
+                        println!(
+                            "[BID] {} ({}) bid for {} contracts of {} @ ${:.2}, {} filled right away",
+                            user_name,
+                            addr_display,
+                            contract_count,
+                            series,
+                            bid_price,
+                            exchange.get_filled_contracts(order_id).unwrap_or(0)
+                        );
+                    }
+                }
+                Err(e) => {
+                    if verbose {
+                        println!(
+                            "[FAILED] {} ({}) failed to bid on {}: {}",
+                            user_name, addr_display, series, e
+                        );
+                    }
+                }
+            }
+        }
+        TraderAction::BuySeries(series, contract_count) => {
+            match exchange.submit_buy_order(
+                series.clone(),
+                OrderType::Market,
+                contract_count,
+                bot.address.clone(),
+            ) {
+                Ok(order_id) => {
+                    if verbose {
+                        let fills = exchange.get_order_fills(order_id).unwrap_or_default();
+                        let filled: u32 = fills.iter().map(|fill| fill.contracts).sum();
+                        let listings: Vec<String> = fills
+                            .iter()
+                            .map(|fill| format!("#{}", fill.listing_id))
+                            .collect();
+                        println!(
+                            "[PURCHASED] {} ({}) bought {} of {} contracts of {} at the best asks ({})",
+                            user_name,
+                            addr_display,
+                            filled,
+                            contract_count,
+                            series,
+                            listings.join(", ")
+                        );
+                    }
+                }
+                Err(e) => {
+                    if verbose {
+                        println!(
+                            "[FAILED] {} ({}) failed to buy {}: {}",
+                            user_name, addr_display, series, e
+                        );
+                    }
+                }
+            }
+        }
         TraderAction::ExerciseOption(listing_id) => {
             match exchange.exercise_option(listing_id, bot.address.clone()) {
                 Ok(_) => {
@@ -1269,11 +2272,11 @@ fn execute_action(
             }
         }
         TraderAction::SpotBuy(asset, amount) => {
-            match exchange.spot_trade_current_price(
+            match trade_spot(
+                exchange,
                 &asset,
-                &Asset::USDT,
                 amount,
-                &SpotAction::BUY,
+                SpotAction::BUY,
                 bot.address.clone(),
             ) {
                 Ok(()) => {
@@ -1295,11 +2298,11 @@ fn execute_action(
             }
         }
         TraderAction::SpotSell(asset, amount) => {
-            match exchange.spot_trade_current_price(
+            match trade_spot(
+                exchange,
                 &asset,
-                &Asset::USDT,
                 amount,
-                &SpotAction::SELL,
+                SpotAction::SELL,
                 bot.address.clone(),
             ) {
                 Ok(()) => {
@@ -1320,6 +2323,49 @@ fn execute_action(
                 }
             }
         }
+        TraderAction::PoolSwap(asset, side, amount) => {
+            let pair = AssetPair::from(asset.clone(), Asset::USDT);
+            let (asset_in, asset_out) = match side {
+                SpotAction::BUY => (&Asset::USDT, &asset),
+                SpotAction::SELL => (&asset, &Asset::USDT),
+            };
+            let price_before = exchange.get_pool_price(&pair);
+            match exchange.swap(
+                pair.clone(),
+                side,
+                to_asset_amount(amount, asset_in),
+                Amount::ZERO,
+                bot.address.clone(),
+            ) {
+                Ok(amount_out) => {
+                    if verbose
+                        && let (Some(before), Some(after)) =
+                            (price_before, exchange.get_pool_price(&pair))
+                    {
+                        println!(
+                            "[SWAPPED] {} ({}) swapped {:.2} {} for {:.4} {} through the pool, price ${:.2} -> ${:.2} ({:+.2}%)",
+                            user_name,
+                            addr_display,
+                            amount,
+                            asset_in,
+                            amount_out,
+                            asset_out,
+                            before,
+                            after,
+                            (after.to_f64() / before.to_f64() - 1.0) * 100.0
+                        );
+                    }
+                }
+                Err(e) => {
+                    if verbose {
+                        println!(
+                            "❌ {} ({}) failed to swap through the {} pool: {}",
+                            user_name, addr_display, pair, e
+                        );
+                    }
+                }
+            }
+        }
         TraderAction::DoNothing => {
             if verbose {
                 println!(
@@ -1333,18 +2379,18 @@ fn execute_action(
 
 /// Updates exchange rates with dynamic market behavior
 fn update_exchange_rates(
-    _exchange: &Exchange,
+    exchange: &mut Exchange,
     round: u32,
     volatility: &mut MarketVolatility,
     verbose: bool,
-) -> Result<(), String> {
+) -> Result<(), ExchangeError> {
     // 10% chance of major market events with better balance
     let random_event = rand::random::<f64>();
     let market_event = if random_event < 0.06 {
         // 6% chance of major bullish event
         "🚀 MAJOR BULLISH BREAKOUT! Markets surge dramatically!"
     } else if random_event < 0.10 {
-        // 4% chance of major bearish event  
+        // 4% chance of major bearish event
         "[CRASH] Massive sell-off across all assets!"
     } else if random_event < 0.20 {
         // 10% chance of moderate bullish
@@ -1359,41 +2405,45 @@ fn update_exchange_rates(
     // Update volatility factors with BALANCED probability distribution
     if random_event < 0.06 {
         // Major bullish event - dramatic gains (6% chance)
-        volatility.btc_trend *= 1.15 + rand::random::<f64>() * 0.25;  // 15-40% gain
-        volatility.eth_trend *= 1.12 + rand::random::<f64>() * 0.20;  // 12-32% gain
-        volatility.sol_trend *= 1.18 + rand::random::<f64>() * 0.30;  // 18-48% gain
+        volatility.btc_trend *= 1.15 + rand::random::<f64>() * 0.25; // 15-40% gain
+        volatility.eth_trend *= 1.12 + rand::random::<f64>() * 0.20; // 12-32% gain
+        volatility.sol_trend *= 1.18 + rand::random::<f64>() * 0.30; // 18-48% gain
         volatility.apple_trend *= 1.08 + rand::random::<f64>() * 0.12; // 8-20% gain
     } else if random_event < 0.10 {
         // Major bearish event - but less severe than before (4% chance)
-        volatility.btc_trend *= 0.85 - rand::random::<f64>() * 0.10;  // 10-15% loss
-        volatility.eth_trend *= 0.88 - rand::random::<f64>() * 0.08;  // 4-12% loss  
-        volatility.sol_trend *= 0.80 - rand::random::<f64>() * 0.15;  // 5-20% loss
+        volatility.btc_trend *= 0.85 - rand::random::<f64>() * 0.10; // 10-15% loss
+        volatility.eth_trend *= 0.88 - rand::random::<f64>() * 0.08; // 4-12% loss  
+        volatility.sol_trend *= 0.80 - rand::random::<f64>() * 0.15; // 5-20% loss
         volatility.apple_trend *= 0.94 - rand::random::<f64>() * 0.06; // 0-6% loss
     } else if random_event < 0.20 {
         // Moderate bullish momentum (10% chance)
-        volatility.btc_trend *= 1.0 + rand::random::<f64>() * 0.08;   // 0-8% gain
-        volatility.eth_trend *= 1.0 + rand::random::<f64>() * 0.06;   // 0-6% gain
-        volatility.sol_trend *= 1.0 + rand::random::<f64>() * 0.10;   // 0-10% gain
+        volatility.btc_trend *= 1.0 + rand::random::<f64>() * 0.08; // 0-8% gain
+        volatility.eth_trend *= 1.0 + rand::random::<f64>() * 0.06; // 0-6% gain
+        volatility.sol_trend *= 1.0 + rand::random::<f64>() * 0.10; // 0-10% gain
         volatility.apple_trend *= 1.0 + rand::random::<f64>() * 0.04; // 0-4% gain
     } else if random_event < 0.25 {
-        // Moderate bearish correction (5% chance) 
-        volatility.btc_trend *= 1.0 - rand::random::<f64>() * 0.04;   // 0-4% loss
-        volatility.eth_trend *= 1.0 - rand::random::<f64>() * 0.03;   // 0-3% loss
-        volatility.sol_trend *= 1.0 - rand::random::<f64>() * 0.05;   // 0-5% loss
+        // Moderate bearish correction (5% chance)
+        volatility.btc_trend *= 1.0 - rand::random::<f64>() * 0.04; // 0-4% loss
+        volatility.eth_trend *= 1.0 - rand::random::<f64>() * 0.03; // 0-3% loss
+        volatility.sol_trend *= 1.0 - rand::random::<f64>() * 0.05; // 0-5% loss
         volatility.apple_trend *= 1.0 - rand::random::<f64>() * 0.02; // 0-2% loss
     } else {
         // Normal market movement with POSITIVE bias (75% of time)
-        volatility.btc_trend += (1.05 - volatility.btc_trend) * 0.02 + (rand::random::<f64>() - 0.4) * 0.02;
-        volatility.eth_trend += (1.04 - volatility.eth_trend) * 0.02 + (rand::random::<f64>() - 0.4) * 0.015;
-        volatility.sol_trend += (1.06 - volatility.sol_trend) * 0.03 + (rand::random::<f64>() - 0.4) * 0.025;
-        volatility.apple_trend += (1.02 - volatility.apple_trend) * 0.01 + (rand::random::<f64>() - 0.4) * 0.01;
+        volatility.btc_trend +=
+            (1.05 - volatility.btc_trend) * 0.02 + (rand::random::<f64>() - 0.4) * 0.02;
+        volatility.eth_trend +=
+            (1.04 - volatility.eth_trend) * 0.02 + (rand::random::<f64>() - 0.4) * 0.015;
+        volatility.sol_trend +=
+            (1.06 - volatility.sol_trend) * 0.03 + (rand::random::<f64>() - 0.4) * 0.025;
+        volatility.apple_trend +=
+            (1.02 - volatility.apple_trend) * 0.01 + (rand::random::<f64>() - 0.4) * 0.01;
     }
 
     // More generous clamping ranges - allow bigger gains, limit severe losses
-    volatility.btc_trend = volatility.btc_trend.max(0.65).min(3.0);   // Max 35% loss, 200% gain
-    volatility.eth_trend = volatility.eth_trend.max(0.70).min(2.8);   // Max 30% loss, 180% gain  
-    volatility.sol_trend = volatility.sol_trend.max(0.60).min(3.5);   // Max 40% loss, 250% gain
-    volatility.apple_trend = volatility.apple_trend.max(0.80).min(1.8); // Max 20% loss, 80% gain
+    volatility.btc_trend = volatility.btc_trend.clamp(0.65, 3.0); // Max 35% loss, 200% gain
+    volatility.eth_trend = volatility.eth_trend.clamp(0.70, 2.8); // Max 30% loss, 180% gain  
+    volatility.sol_trend = volatility.sol_trend.clamp(0.60, 3.5); // Max 40% loss, 250% gain
+    volatility.apple_trend = volatility.apple_trend.clamp(0.80, 1.8); // Max 20% loss, 80% gain
 
     // Calculate new rates
     let btc_rate = volatility.base_btc_rate * volatility.btc_trend;
@@ -1405,13 +2455,39 @@ fn update_exchange_rates(
     let admin_address = default_exchange_rate_provider_admin_address();
     let mut rate_provider = get_rate_provider();
 
-    rate_provider.set_rate(Asset::BTC, Asset::USDT, btc_rate, admin_address.clone())?;
-    rate_provider.set_rate(Asset::ETH, Asset::USDT, eth_rate, admin_address.clone())?;
-    rate_provider.set_rate(Asset::SOL, Asset::USDT, sol_rate, admin_address.clone())?;
-    rate_provider.set_rate(Asset::APPLE, Asset::USDT, apple_rate, admin_address.clone())?;
+    rate_provider.set_rate(
+        Asset::BTC,
+        Asset::USDT,
+        Amount::from_f64(btc_rate),
+        admin_address.clone(),
+    )?;
+    rate_provider.set_rate(
+        Asset::ETH,
+        Asset::USDT,
+        Amount::from_f64(eth_rate),
+        admin_address.clone(),
+    )?;
+    rate_provider.set_rate(
+        Asset::SOL,
+        Asset::USDT,
+        Amount::from_f64(sol_rate),
+        admin_address.clone(),
+    )?;
+    rate_provider.set_rate(
+        Asset::APPLE,
+        Asset::USDT,
+        Amount::from_f64(apple_rate),
+        admin_address.clone(),
+    )?;
+
+    volatility.record_rate(Asset::BTC, btc_rate);
+    volatility.record_rate(Asset::ETH, eth_rate);
+    volatility.record_rate(Asset::SOL, sol_rate);
+    volatility.record_rate(Asset::APPLE, apple_rate);
+    drop(rate_provider);
 
     // Display market updates
-    if verbose && (!market_event.is_empty() || round % 5 == 0) {
+    if verbose && (!market_event.is_empty() || round.is_multiple_of(5)) {
         if !market_event.is_empty() {
             println!("\n{}", market_event);
         }
@@ -1439,6 +2515,23 @@ fn update_exchange_rates(
         println!();
     }
 
+    // Crashes and breakouts alike can push margin writers under maintenance
+    for liquidation in exchange.liquidate_unhealthy()? {
+        if verbose {
+            println!(
+                "[LIQUIDATED] {}'s {} margin account: {} options closed, {} contracts bought back for ${:.2}, ${:.2} from selling collateral, ${:.2} penalty, ${:.2} covered by the insurance fund",
+                get_user_name(&liquidation.owner),
+                liquidation.asset,
+                liquidation.listing_ids.len(),
+                liquidation.contracts,
+                liquidation.buyback_cost,
+                liquidation.sale_proceeds,
+                liquidation.penalty,
+                liquidation.shortfall
+            );
+        }
+    }
+
     Ok(())
 }
 
@@ -1453,10 +2546,10 @@ fn calculate_portfolio_value(
 
     for (asset, balance) in &user.balances {
         match asset {
-            Asset::USDT => total_value += balance,
+            Asset::USDT => total_value += balance.to_f64(),
             _ => {
                 if let Some(rate) = exchange_rate_provider.get_rate(asset, &Asset::USDT) {
-                    total_value += balance * rate;
+                    total_value += balance.to_f64() * rate.to_f64();
                 }
             }
         }
@@ -1499,12 +2592,12 @@ fn generate_pnl_report(bots: &[TraderBot], exchange: &Exchange) {
             // Asset breakdown
             println!("   Asset Holdings:");
             for (asset, balance) in &user.balances {
-                if *balance > 0.0 {
+                if !balance.is_zero() {
                     let asset_value = match asset {
-                        Asset::USDT => *balance,
+                        Asset::USDT => balance.to_f64(),
                         _ => {
                             if let Some(rate) = rate_provider.get_rate(asset, &Asset::USDT) {
-                                balance * rate
+                                balance.to_f64() * rate.to_f64()
                             } else {
                                 0.0
                             }
@@ -1515,7 +2608,9 @@ fn generate_pnl_report(bots: &[TraderBot], exchange: &Exchange) {
             }
 
             if bot.last_exercise_round.is_some() {
-                println!("   [Note] Recently exercised options and engaged in post-exercise trading");
+                println!(
+                    "   [Note] Recently exercised options and engaged in post-exercise trading"
+                );
             }
         }
     }
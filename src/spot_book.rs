@@ -0,0 +1,103 @@
+// spot_book.rs - Limit orders users post against each other on a spot pair
+
+use crate::address::Address;
+use crate::amount::{Amount, Price};
+use crate::exchange::SpotAction;
+use crate::exchange_rate_provider::AssetPair;
+use chrono::{DateTime, Utc};
+
+/// Resting limit order. What it still needs to pay is held in escrow until it fills or is cancelled.
+#[derive(Debug, Clone)]
+pub struct SpotOrder {
+    pub order_id: u32,
+    pub pair: AssetPair,
+    pub side: SpotAction,
+    pub owner_address: Address,
+    pub price: Price,          // quote per unit of base
+    pub base_amount: Amount,   // base still to trade
+    pub locked_amount: Amount, // held in escrow, base for sells and quote for buys
+}
+
+/// Base changing hands between two users at the resting order's price
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpotTrade {
+    pub buyer_address: Address,
+    pub seller_address: Address,
+    pub price: Price,
+    pub base_amount: Amount,
+    pub quote_amount: Amount,
+    pub time: DateTime<Utc>,
+}
+
+/// Limit orders of a pair in price-time priority, plus the trades they made
+#[derive(Debug, Clone)]
+pub struct SpotOrderBook {
+    pub pair: AssetPair,
+    pub bids: Vec<SpotOrder>, // highest price first, oldest first at the same price
+    pub asks: Vec<SpotOrder>, // lowest price first, oldest first at the same price
+    pub trades: Vec<SpotTrade>,
+}
+
+impl SpotOrderBook {
+    pub fn new(pair: AssetPair) -> Self {
+        SpotOrderBook {
+            pair,
+            bids: Vec::new(),
+            asks: Vec::new(),
+            trades: Vec::new(),
+        }
+    }
+
+    /// Queue an order behind every order at a better or equal price
+    pub fn insert(&mut self, order: SpotOrder) {
+        let side = order.side;
+        let orders = match side {
+            SpotAction::BUY => &mut self.bids,
+            SpotAction::SELL => &mut self.asks,
+        };
+        let position = orders
+            .iter()
+            .position(|resting| match side {
+                SpotAction::BUY => resting.price < order.price,
+                SpotAction::SELL => resting.price > order.price,
+            })
+            .unwrap_or(orders.len());
+        orders.insert(position, order);
+    }
+
+    pub fn remove(&mut self, order_id: u32) -> Option<SpotOrder> {
+        for orders in [&mut self.bids, &mut self.asks] {
+            if let Some(position) = orders.iter().position(|order| order.order_id == order_id) {
+                return Some(orders.remove(position));
+            }
+        }
+        None
+    }
+
+    pub fn get(&self, order_id: u32) -> Option<&SpotOrder> {
+        self.bids
+            .iter()
+            .chain(self.asks.iter())
+            .find(|order| order.order_id == order_id)
+    }
+
+    /// Orders a taker on `side` trades against, best first
+    pub fn opposite(&self, side: SpotAction) -> &[SpotOrder] {
+        match side {
+            SpotAction::BUY => &self.asks,
+            SpotAction::SELL => &self.bids,
+        }
+    }
+
+    pub fn best_bid(&self) -> Option<&SpotOrder> {
+        self.bids.first()
+    }
+
+    pub fn best_ask(&self) -> Option<&SpotOrder> {
+        self.asks.first()
+    }
+
+    pub fn last_price(&self) -> Option<Price> {
+        self.trades.last().map(|trade| trade.price)
+    }
+}
@@ -0,0 +1,148 @@
+// strategy.rs - Multi-leg orders on one underlying executed all together or not at all
+//
+// Spreads, straddles, strangles and collars take several option and spot trades. A strategy
+// order bundles them so a trader can't end up holding some legs without the others, see
+// `Exchange::execute_strategy`. Writers with a margin account get defined-risk combos such as
+// vertical spreads or iron condors margined at the most the legs can lose at expiry. Written
+// legs without a margin account escrow their full collateral like any other listing.
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use crate::address::Address;
+use crate::amount::{Amount, AmountError, Price, Rounding};
+use crate::asset::Asset;
+use crate::exchange::SpotAction;
+use crate::listing_option::ListingOption;
+use crate::portfolio_margin::PortfolioLeg;
+use crate::types::ListingType;
+
+/// One trade of a strategy order
+#[derive(Debug, Clone)]
+pub enum StrategyLeg {
+    /// List a new option, as `Exchange::list_option`
+    Write(ListingOption),
+    /// Buy listed contracts at their ask
+    Buy { listing_id: u32, contracts: u32 },
+    /// Trade the underlying with the spot desk at the current rate
+    Spot {
+        action: SpotAction,
+        base_amount: Amount,
+    },
+}
+
+/// Legs on a single underlying and quote asset, executed in order
+#[derive(Debug, Clone)]
+pub struct StrategyOrder {
+    pub base_asset: Asset,
+    pub quote_asset: Asset,
+    pub legs: Vec<StrategyLeg>,
+}
+
+impl StrategyOrder {
+    pub fn new(base_asset: Asset, quote_asset: Asset) -> Self {
+        StrategyOrder {
+            base_asset,
+            quote_asset,
+            legs: Vec::new(),
+        }
+    }
+
+    pub fn with_leg(mut self, leg: StrategyLeg) -> Self {
+        self.legs.push(leg);
+        self
+    }
+}
+
+/// What a strategy order costs and locks up before it executes. Amounts are in the order's quote
+/// asset unless keyed by asset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrategyQuote {
+    pub net_premium: Amount, // paid for bought legs with fees, less written legs at their ask after fees, negative for a credit
+    pub spot_cost: Amount, // paid for spot bought less received for spot sold, negative for net proceeds
+    pub collateral: HashMap<Asset, Amount>, // escrowed in full by written legs, or their margin requirement when written on margin
+    pub max_loss: Option<Amount>, // most the legs can lose at expiry with the net premium and spot cost, None when unbounded
+    pub defined_risk: bool,       // written legs are covered by bought ones, see `max_expiry_loss`
+}
+
+/// A strategy order that went through
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrategyFill {
+    pub strategy_id: u32,
+    pub owner: Address,
+    pub listing_ids: Vec<u32>, // listings written by the order, in leg order
+    pub quote: StrategyQuote,
+    pub time: DateTime<Utc>,
+}
+
+/// Listings a margin account wrote and bought in one defined-risk strategy order. Its written
+/// listings need the most the legs can lose at expiry instead of their own requirements, as far
+/// as the owner still holds the bought contracts. Those can't be resold or exercised while the
+/// account couldn't margin the written listings without them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefinedRiskGroup {
+    pub strategy_id: u32,
+    pub written: Vec<u32>,       // listing ids
+    pub bought: Vec<(u32, u32)>, // listing ids with the contracts bought
+}
+
+/// Most the legs pay out on net at expiry, in quote, with written legs negative. None when the
+/// loss has no bound, see `lowest_expiry_value`.
+pub fn max_expiry_loss(legs: &[PortfolioLeg]) -> Result<Option<Amount>, AmountError> {
+    match lowest_expiry_value(legs, Amount::ZERO)? {
+        Some(lowest) => Ok(Some(Amount::ZERO.try_sub(lowest)?.max(Amount::ZERO))),
+        None => Ok(None),
+    }
+}
+
+/// Lowest the legs plus `base_held` units of their underlying are worth at expiry, in quote. The
+/// value only bends at the strikes, so it's lowest at one of them or at a zero spot, unless it
+/// keeps falling past the highest strike because more calls and underlying are short than long.
+/// None in that case, or when the legs aren't all on the same underlying and quote asset.
+pub fn lowest_expiry_value(
+    legs: &[PortfolioLeg],
+    base_held: Amount,
+) -> Result<Option<Amount>, AmountError> {
+    if let Some(first) = legs.first()
+        && legs.iter().any(|leg| {
+            leg.option.base_asset != first.option.base_asset
+                || leg.option.quote_asset != first.option.quote_asset
+        })
+    {
+        return Ok(None);
+    }
+
+    // Underlying units the value moves by per unit of spot past the highest strike
+    let mut slope = base_held;
+    for leg in legs
+        .iter()
+        .filter(|leg| leg.option.listing_type == ListingType::CALL)
+    {
+        slope = slope.try_add(leg.option.exercise_amount.try_mul_int(leg.contracts)?)?;
+    }
+    if slope.is_negative() {
+        return Ok(None);
+    }
+
+    let mut lowest: Option<Amount> = None;
+    for spot in std::iter::once(Amount::ZERO).chain(legs.iter().map(|leg| leg.option.strike_price))
+    {
+        let value = expiry_payoff(legs, spot)?.try_add(base_held.try_mul(spot, Rounding::Down)?)?;
+        lowest = Some(lowest.map_or(value, |lowest| lowest.min(value)));
+    }
+    Ok(lowest)
+}
+
+/// Net intrinsic value of the legs at `spot`, in quote, rounded down contract by contract as
+/// exercising pays it
+pub fn expiry_payoff(legs: &[PortfolioLeg], spot: Price) -> Result<Amount, AmountError> {
+    let mut payoff = Amount::ZERO;
+    for leg in legs {
+        let value = leg
+            .option
+            .get_intrinsic_value(spot)?
+            .try_mul_int(leg.contracts)?;
+        payoff = payoff.try_add(value)?;
+    }
+    Ok(payoff)
+}
@@ -0,0 +1,83 @@
+// transaction.rs - Balance changes staged against exchange accounts and applied all or nothing
+
+use crate::address::Address;
+use crate::amount::Amount;
+use crate::asset::Asset;
+use crate::exchange_rate_provider::AssetPair;
+
+/// Where a balance change lands. `Escrow` is the exchange's option escrow (`Exchange::escrow_user`),
+/// `Margin` the collateral of a writer's margin account (`Exchange::margin_accounts`),
+/// `Treasury` the exchange's fee revenue (`Exchange::treasury`), `InsuranceFund` what covers
+/// liquidation shortfalls (`Exchange::insurance_fund`), `Pool` the reserves of a pair's liquidity
+/// pool (`Exchange::pools`). Every other account, including the spot desk at the escrow address,
+/// lives in `Exchange::users`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Account {
+    Escrow,
+    User(Address),
+    Margin(Address),
+    Treasury,
+    InsuranceFund,
+    Pool(AssetPair),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Debit,
+    Credit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceChange {
+    pub account: Account,
+    pub asset: Asset,
+    pub amount: Amount,
+    pub direction: Direction,
+}
+
+/// Debits and credits in the order they should happen. Nothing is touched until
+/// `Exchange::apply` has validated every change against the staged balances.
+#[derive(Debug, Clone, Default)]
+pub struct BalanceTransaction {
+    pub changes: Vec<BalanceChange>,
+}
+
+impl BalanceTransaction {
+    pub fn new() -> Self {
+        BalanceTransaction::default()
+    }
+
+    pub fn debit(&mut self, account: Account, asset: &Asset, amount: Amount) -> &mut Self {
+        self.changes.push(BalanceChange {
+            account,
+            asset: asset.clone(),
+            amount,
+            direction: Direction::Debit,
+        });
+        self
+    }
+
+    pub fn credit(&mut self, account: Account, asset: &Asset, amount: Amount) -> &mut Self {
+        self.changes.push(BalanceChange {
+            account,
+            asset: asset.clone(),
+            amount,
+            direction: Direction::Credit,
+        });
+        self
+    }
+
+    pub fn transfer(
+        &mut self,
+        from: Account,
+        to: Account,
+        asset: &Asset,
+        amount: Amount,
+    ) -> &mut Self {
+        self.debit(from, asset, amount).credit(to, asset, amount)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
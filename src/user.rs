@@ -2,6 +2,8 @@
 
 use strum::IntoEnumIterator;
 
+use crate::amount::Amount;
+use crate::error::ExchangeError;
 use crate::asset::Asset;
 use crate::address::Address;
 use std::collections::HashMap;
@@ -10,7 +12,7 @@ use std::collections::HashMap;
 #[derive(Clone)]
 pub struct User {
     pub address: Address,
-    pub balances: HashMap<Asset, f64>, // map from asset to asset balance
+    pub balances: HashMap<Asset, Amount>, // map from asset to asset balance
 }
 
 impl User {
@@ -25,31 +27,56 @@ impl User {
         // balances.insert(Asset::APPLE, 20.0);
 
         for asset in Asset::iter() {
-            balances.insert(asset, 0.0);
+            balances.insert(asset, Amount::ZERO);
         }
 
         User { address, balances }
     }
 
     /// Get the balance of a specific asset
-    pub fn get_balance(&self, asset: &Asset) -> f64 {
-        *self.balances.get(asset).unwrap_or(&0.0)
+    pub fn get_balance(&self, asset: &Asset) -> Amount {
+        self.balances.get(asset).copied().unwrap_or(Amount::ZERO)
     }
 
     /// Add assets to the user's portfolio
-    pub fn add_asset(&mut self, asset: &Asset, amount: f64) -> Result<(), String> {
-        let balance = self.balances.entry(asset.clone()).or_insert(0.0);
-        *balance += amount;
+    pub fn add_asset(&mut self, asset: &Asset, amount: Amount) -> Result<(), ExchangeError> {
+        check_amount(asset, amount)?;
+        let balance = self.balances.entry(asset.clone()).or_insert(Amount::ZERO);
+        *balance = balance.try_add(amount)?;
         Ok(())
     }
 
     /// Deduct assets from the user's portfoli
-    pub fn deduct_asset(&mut self, asset: &Asset, amount: f64) -> Result<(), String> {
-        let balance = self.balances.entry(asset.clone()).or_insert(0.0);
+    pub fn deduct_asset(&mut self, asset: &Asset, amount: Amount) -> Result<(), ExchangeError> {
+        check_amount(asset, amount)?;
+        let balance = self.balances.entry(asset.clone()).or_insert(Amount::ZERO);
         if *balance < amount {
-            return Err(format!("Insufficient {} balance", asset));
+            return Err(ExchangeError::InsufficientBalance {
+                asset: asset.clone(),
+                required: amount,
+                available: *balance,
+            });
         }
-        *balance -= amount;
+        *balance = balance.try_sub(amount)?;
         Ok(())
     }
 }
+
+// Balances only ever move by whole units of the asset, so they always reconcile exactly
+fn check_amount(asset: &Asset, amount: Amount) -> Result<(), ExchangeError> {
+    if amount.is_negative() {
+        return Err(ExchangeError::InvalidInput(format!(
+            "Invalid negative {} amount {}",
+            asset, amount
+        )));
+    }
+    if !amount.fits_asset(asset) {
+        return Err(ExchangeError::InvalidInput(format!(
+            "{} amount {} has more than {} decimals",
+            asset,
+            amount,
+            asset.decimals()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,261 @@
+// vol_surface.rs - Implied volatility surfaces built from the listed premiums of each underlying
+
+use crate::amount::Price;
+use crate::asset::Asset;
+use crate::error::ExchangeError;
+use crate::listing_option::{ListingOption, OptionSeries};
+use crate::pricing::{self, OptionValuation, PricingParams};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// Strike over spot at the center of each moneyness bucket
+pub const MONEYNESS_BUCKETS: [f64; 9] = [0.7, 0.8, 0.9, 0.95, 1.0, 1.05, 1.1, 1.2, 1.3];
+
+/// Days to expiry at the center of each expiry bucket
+pub const EXPIRY_BUCKET_DAYS: [f64; 7] = [7.0, 14.0, 30.0, 60.0, 90.0, 180.0, 365.0];
+
+/// Listings that fell into one bucket of the surface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfacePoint {
+    pub volatility: f64, // vega weighted mean of the listings' mid implied volatilities
+    pub vega: f64,       // summed over the listings
+    pub listing_count: usize,
+}
+
+/// Implied volatility of an underlying by moneyness and time to expiry. Buckets without
+/// listings are filled in by interpolating the ones that have some, see `get_volatility`.
+#[derive(Debug, Clone)]
+pub struct VolatilitySurface {
+    pub asset: Asset,
+    pub quote_asset: Asset,
+    pub spot: f64, // the moneyness of every point was taken against this rate
+    pub built_at: DateTime<Utc>,
+    pub points: Vec<Vec<Option<SurfacePoint>>>, // [expiry bucket][moneyness bucket]
+}
+
+impl VolatilitySurface {
+    /// Bucket the implied volatilities of the unexpired listings of `asset` against `quote_asset`
+    /// with contracts left to sell, taking moneyness against the rate provider's current spot.
+    /// Listings whose quotes no volatility explains are left out.
+    pub fn build<'a>(
+        asset: &Asset,
+        quote_asset: &Asset,
+        listings: impl IntoIterator<Item = &'a ListingOption>,
+        risk_free_rate: f64,
+        now: DateTime<Utc>,
+    ) -> Result<VolatilitySurface, ExchangeError> {
+        let spot = pricing::spot_rate(asset, quote_asset)?;
+
+        // weighted sum, weights and count per bucket
+        let mut totals =
+            vec![vec![(0.0, 0.0, 0); MONEYNESS_BUCKETS.len()]; EXPIRY_BUCKET_DAYS.len()];
+        for option in listings {
+            if option.base_asset != *asset
+                || option.quote_asset != *quote_asset
+                || !option.has_unsold_contracts()
+                || option.expiration_time <= now
+            {
+                continue;
+            }
+            let Ok(implied) = pricing::listing_implied_volatility(option, risk_free_rate, now)
+            else {
+                continue;
+            };
+            let Some(mid) = implied.mid() else {
+                continue;
+            };
+            if implied.vega <= 0.0 {
+                continue;
+            }
+
+            let moneyness = option.strike_price.to_f64() / spot;
+            let days = pricing::years_until(now, option.expiration_time) * 365.0;
+            let bucket = &mut totals[nearest(&EXPIRY_BUCKET_DAYS, days)]
+                [nearest(&MONEYNESS_BUCKETS, moneyness)];
+            bucket.0 += mid * implied.vega;
+            bucket.1 += implied.vega;
+            bucket.2 += 1;
+        }
+
+        let points = totals
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|(weighted_sum, vega, listing_count)| {
+                        (listing_count > 0).then(|| SurfacePoint {
+                            volatility: weighted_sum / vega,
+                            vega,
+                            listing_count,
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Ok(VolatilitySurface {
+            asset: asset.clone(),
+            quote_asset: quote_asset.clone(),
+            spot,
+            built_at: now,
+            points,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.iter().flatten().all(|point| point.is_none())
+    }
+
+    /// Volatility of an option struck at `strike_price` expiring at `expiration_time`, None on an
+    /// empty surface. Interpolates linearly across moneyness within each expiry and linearly in
+    /// total variance across expiries, holding the outermost buckets flat beyond the grid.
+    pub fn get_volatility(
+        &self,
+        strike_price: Price,
+        expiration_time: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Option<f64> {
+        let moneyness = strike_price.to_f64() / self.spot;
+        let days = pricing::years_until(now, expiration_time) * 365.0;
+        self.interpolate(moneyness, days)
+    }
+
+    /// Value one unit of a series at the surface's volatility for its strike and expiry, so that
+    /// series without a live listing can still be priced
+    pub fn value_series(
+        &self,
+        series: &OptionSeries,
+        risk_free_rate: f64,
+        now: DateTime<Utc>,
+    ) -> Result<OptionValuation, ExchangeError> {
+        let volatility = self
+            .get_volatility(series.strike_price, series.expiration_time, now)
+            .ok_or_else(|| {
+                ExchangeError::InvalidInput(format!(
+                    "No listings to build a {} volatility surface from",
+                    self.asset
+                ))
+            })?;
+        pricing::value_series(series, &PricingParams::new(volatility, risk_free_rate), now)
+    }
+
+    /// Interpolated volatility at the center of every bucket, rows by `EXPIRY_BUCKET_DAYS` and
+    /// columns by `MONEYNESS_BUCKETS`. Every cell is None on an empty surface.
+    pub fn grid(&self) -> Vec<Vec<Option<f64>>> {
+        EXPIRY_BUCKET_DAYS
+            .iter()
+            .map(|days| {
+                MONEYNESS_BUCKETS
+                    .iter()
+                    .map(|moneyness| self.interpolate(*moneyness, *days))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// `grid` as CSV, one row per expiry bucket in days and one column per moneyness bucket
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("days");
+        for moneyness in MONEYNESS_BUCKETS {
+            csv.push_str(&format!(",{:.2}", moneyness));
+        }
+        csv.push('\n');
+
+        for (days, row) in EXPIRY_BUCKET_DAYS.iter().zip(self.grid()) {
+            csv.push_str(&format!("{}", days));
+            for volatility in row {
+                match volatility {
+                    Some(volatility) => csv.push_str(&format!(",{:.4}", volatility)),
+                    None => csv.push(','),
+                }
+            }
+            csv.push('\n');
+        }
+        csv
+    }
+
+    fn interpolate(&self, moneyness: f64, days: f64) -> Option<f64> {
+        // the smile of every expiry that has listings, taken at `moneyness`
+        let smiles: Vec<(f64, f64)> = EXPIRY_BUCKET_DAYS
+            .iter()
+            .zip(&self.points)
+            .filter_map(|(row_days, row)| {
+                let smile: Vec<(f64, f64)> = MONEYNESS_BUCKETS
+                    .iter()
+                    .zip(row)
+                    .filter_map(|(m, point)| point.map(|point| (*m, point.volatility)))
+                    .collect();
+                interpolate_linear(&smile, moneyness).map(|volatility| (*row_days, volatility))
+            })
+            .collect();
+
+        let (first, last) = (smiles.first()?, smiles.last()?);
+        if days <= first.0 {
+            return Some(first.1);
+        }
+        if days >= last.0 {
+            return Some(last.1);
+        }
+        let upper = smiles.iter().position(|(row_days, _)| *row_days >= days)?;
+        let ((days_0, vol_0), (days_1, vol_1)) = (smiles[upper - 1], smiles[upper]);
+        let variance_0 = vol_0 * vol_0 * days_0;
+        let variance_1 = vol_1 * vol_1 * days_1;
+        let variance = variance_0 + (variance_1 - variance_0) * (days - days_0) / (days_1 - days_0);
+        Some((variance / days).sqrt())
+    }
+}
+
+/// Build a surface for every underlying listed against `quote_asset` that has a spot rate,
+/// so a volatility can be looked up by (asset, strike, expiry)
+pub fn build_surfaces<'a>(
+    listings: impl IntoIterator<Item = &'a ListingOption> + Clone,
+    quote_asset: &Asset,
+    risk_free_rate: f64,
+    now: DateTime<Utc>,
+) -> HashMap<Asset, VolatilitySurface> {
+    let mut assets: Vec<Asset> = Vec::new();
+    for option in listings.clone() {
+        if option.quote_asset == *quote_asset && !assets.contains(&option.base_asset) {
+            assets.push(option.base_asset.clone());
+        }
+    }
+
+    assets
+        .into_iter()
+        .filter_map(|asset| {
+            let surface = VolatilitySurface::build(
+                &asset,
+                quote_asset,
+                listings.clone(),
+                risk_free_rate,
+                now,
+            )
+            .ok()?;
+            (!surface.is_empty()).then_some((asset, surface))
+        })
+        .collect()
+}
+
+// Index of the bucket center closest to `value`
+fn nearest(buckets: &[f64], value: f64) -> usize {
+    let mut best = 0;
+    for (index, center) in buckets.iter().enumerate() {
+        if (center - value).abs() < (buckets[best] - value).abs() {
+            best = index;
+        }
+    }
+    best
+}
+
+// Linear interpolation through (x, y) points sorted by x, flat beyond both ends
+fn interpolate_linear(points: &[(f64, f64)], x: f64) -> Option<f64> {
+    let (first, last) = (points.first()?, points.last()?);
+    if x <= first.0 {
+        return Some(first.1);
+    }
+    if x >= last.0 {
+        return Some(last.1);
+    }
+    let upper = points.iter().position(|(point_x, _)| *point_x >= x)?;
+    let ((x_0, y_0), (x_1, y_1)) = (points[upper - 1], points[upper]);
+    Some(y_0 + (y_1 - y_0) * (x - x_0) / (x_1 - x_0))
+}
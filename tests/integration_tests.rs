@@ -1,5 +1,5 @@
 use chrono::{Duration, Utc};
-use options_trading::{Address, Asset, Exchange, ListingOption, ListingType, User};
+use options_trading::{Address, Amount, Asset, Exchange, ListingOption, ListingType, OptionState, User, ExerciseStyle, SettlementType};
 
 #[cfg(test)]
 mod integration_tests {
@@ -22,16 +22,16 @@ mod integration_tests {
         let charlie_addr = create_test_address("3");
 
         let mut alice = User::new(alice_addr.clone());
-        alice.add_asset(&Asset::USDT, 10000000.0); // 10M USDT
-        alice.add_asset(&Asset::BTC, 5.0);
+        alice.add_asset(&Asset::USDT, Amount::from_f64(10000000.0)).unwrap(); // 10M USDT
+        alice.add_asset(&Asset::BTC, Amount::from_f64(5.0)).unwrap();
 
         let mut bob = User::new(bob_addr.clone());
-        bob.add_asset(&Asset::USDT, 200000.0); // 200k USDT
-        bob.add_asset(&Asset::ETH, 100.0);
+        bob.add_asset(&Asset::USDT, Amount::from_f64(200000.0)).unwrap(); // 200k USDT
+        bob.add_asset(&Asset::ETH, Amount::from_f64(100.0)).unwrap();
 
         let mut charlie = User::new(charlie_addr.clone());
-        charlie.add_asset(&Asset::USDT, 400000.0); // Increased to cover PUT option collateral (300k) + buffer
-        charlie.add_asset(&Asset::SOL, 500.0);
+        charlie.add_asset(&Asset::USDT, Amount::from_f64(400000.0)).unwrap(); // Increased to cover PUT option collateral (300k) + buffer
+        charlie.add_asset(&Asset::SOL, Amount::from_f64(500.0)).unwrap();
 
         market.users.insert(alice_addr.clone(), alice);
         market.users.insert(bob_addr.clone(), bob);
@@ -43,16 +43,16 @@ mod integration_tests {
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 500.0,
-            bid_price: 490.0,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
             expiration_time: Utc::now() + Duration::days(30),
             grantor_address: alice_addr.clone(),
-            beneficiary_address: None,
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
         };
 
         let alice_listing_id = market
@@ -62,28 +62,30 @@ mod integration_tests {
 
         // Check Alice's collateral was deducted (1 BTC for CALL option)
         let alice = market.users.get(&alice_addr).unwrap();
-        assert_eq!(alice.get_balance(&Asset::USDT), 10000000.0); // USDT unchanged
-        assert_eq!(alice.get_balance(&Asset::BTC), 4.0); // 5 - 1 BTC collateral
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(10000000.0)); // USDT unchanged
+        assert_eq!(alice.get_balance(&Asset::BTC), Amount::from_f64(4.0)); // 5 - 1 BTC collateral
 
         // Bob purchases Alice's option
         let result = market.purchase_option(alice_listing_id, bob_addr.clone());
         assert!(result.is_ok());
 
         // Verify the purchase
-        let listing = market.listings.get(&alice_listing_id).unwrap();
-        assert_eq!(listing.beneficiary_address, Some(bob_addr.clone()));
+        let position = market.get_position(alice_listing_id, &bob_addr).unwrap();
+        assert_eq!(position.contracts, 1);
 
         // Check balances after purchase
         let bob = market.users.get(&bob_addr).unwrap();
-        assert_eq!(bob.get_balance(&Asset::USDT), 149950.0); // 200k - 50k premium - 50 fee
+        assert_eq!(bob.get_balance(&Asset::USDT), Amount::from_f64(149950.0)); // 200k - 50k premium - 50 fee
 
         let alice_after = market.users.get(&alice_addr).unwrap();
-        assert_eq!(alice_after.get_balance(&Asset::USDT), 10049950.0); // 10M + 50k premium - 50 fee
-        assert_eq!(alice_after.get_balance(&Asset::BTC), 4.0); // Still 4 BTC (1 BTC in escrow)
+        assert_eq!(alice_after.get_balance(&Asset::USDT), Amount::from_f64(10049950.0)); // 10M + 50k premium - 50 fee
+        assert_eq!(alice_after.get_balance(&Asset::BTC), Amount::from_f64(4.0)); // Still 4 BTC (1 BTC in escrow)
 
-        // Escrow should hold BTC collateral + USDT fees
-        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), 1.0); // BTC collateral
-        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), 100.0); // 2 fees (50 each)
+        // Escrow should only hold BTC collateral, the fees are split between treasury and insurance fund
+        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), Amount::from_f64(1.0)); // BTC collateral
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+        assert_eq!(market.treasury.get_balance(&Asset::USDT), Amount::from_f64(90.0)); // 2 fees (50 each) less 10%
+        assert_eq!(market.insurance_fund.get_balance(&Asset::USDT), Amount::from_f64(10.0));
 
         // Alice tries to unlist but fails (option was purchased)
         let unlist_result = market.unlist_option(alice_listing_id, alice_addr.clone());
@@ -95,16 +97,16 @@ mod integration_tests {
             base_asset: Asset::ETH,
             quote_asset: Asset::USDT,
             listing_type: ListingType::PUT,
-            strike_price: 3000.0,
-            ask_price: 200.0,
-            bid_price: 190.0,
+            strike_price: Amount::from_f64(3000.0),
+            ask_price: Amount::from_f64(200.0),
+            bid_price: Amount::from_f64(190.0),
             expiration_time: Utc::now() + Duration::days(15),
             grantor_address: charlie_addr.clone(),
-            beneficiary_address: None,
-            exercise_amount: 100.0, // 100 ETH
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(100.0), // 100 ETH
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
         };
 
         let charlie_listing_id = market
@@ -121,7 +123,7 @@ mod integration_tests {
 
         // Charlie should get his collateral back
         let charlie_after = market.users.get(&charlie_addr).unwrap();
-        assert_eq!(charlie_after.get_balance(&Asset::USDT), 400000.0); // Back to original
+        assert_eq!(charlie_after.get_balance(&Asset::USDT), Amount::from_f64(400000.0)); // Back to original
 
         // Final state checks
         assert_eq!(market.listings.len(), 1); // Only Alice's option remains
@@ -137,11 +139,11 @@ mod integration_tests {
             .map(|i| create_test_address(&i.to_string()))
             .collect();
 
-        for (_i, addr) in addrs.iter().enumerate() {
+        for addr in addrs.iter() {
             let mut user = User::new(addr.clone());
-            user.add_asset(&Asset::USDT, 1000000.0); // 1M USDT each
-            user.add_asset(&Asset::BTC, 1.0);
-            user.add_asset(&Asset::ETH, 10.0);
+            user.add_asset(&Asset::USDT, Amount::from_f64(1000000.0)).unwrap(); // 1M USDT each
+            user.add_asset(&Asset::BTC, Amount::from_f64(1.0)).unwrap();
+            user.add_asset(&Asset::ETH, Amount::from_f64(10.0)).unwrap();
             market.users.insert(addr.clone(), user);
         }
 
@@ -158,16 +160,16 @@ mod integration_tests {
                 base_asset: assets[i].clone(),
                 quote_asset: Asset::USDT,
                 listing_type: ListingType::CALL,
-                strike_price: strikes[i],
-                ask_price: premiums[i],
-                bid_price: premiums[i] - 10.0,
+                strike_price: Amount::from_f64(strikes[i]),
+                ask_price: Amount::from_f64(premiums[i]),
+                bid_price: Amount::from_f64(premiums[i] - 10.0),
                 expiration_time: Utc::now() + Duration::days(30),
                 grantor_address: addr.clone(),
-                beneficiary_address: None,
-                exercise_amount: 1.0,
-                is_purchased: false,
-                is_unlisted: false,
-                is_exercised: false,
+                contract_count: 1,
+                exercise_amount: Amount::from_f64(1.0),
+                exercise_style: ExerciseStyle::American,
+                settlement_type: SettlementType::Physical,
+                state: OptionState::Listed,
             };
 
             let listing_id = market.list_option(addr.clone(), option).unwrap();
@@ -188,14 +190,13 @@ mod integration_tests {
             assert!(result.is_ok());
 
             // Verify beneficiary was set
-            let listing = market.listings.get(&listing_id).unwrap();
-            assert_eq!(listing.beneficiary_address, Some(buyer_addr.clone()));
+            let position = market.get_position(listing_id, buyer_addr).unwrap();
+            assert_eq!(position.contracts, 1);
         }
 
         // All options should now have beneficiaries
         for listing_id in &listing_ids {
-            let listing = market.listings.get(listing_id).unwrap();
-            assert!(listing.beneficiary_address.is_some());
+            assert_eq!(market.get_open_contracts(*listing_id), 1);
         }
     }
 
@@ -215,11 +216,11 @@ mod integration_tests {
         let buyer_addr = create_test_address("2");
 
         let mut seller = User::new(seller_addr.clone());
-        seller.add_asset(&Asset::USDT, 10000000.0);
-        seller.add_asset(&Asset::BTC, 5.0); // Need BTC for CALL option collateral
+        seller.add_asset(&Asset::USDT, Amount::from_f64(10000000.0)).unwrap();
+        seller.add_asset(&Asset::BTC, Amount::from_f64(5.0)).unwrap(); // Need BTC for CALL option collateral
 
         let mut buyer = User::new(buyer_addr.clone());
-        buyer.add_asset(&Asset::USDT, 200000.0); // Increased to cover premium + fee
+        buyer.add_asset(&Asset::USDT, Amount::from_f64(200000.0)).unwrap(); // Increased to cover premium + fee
 
         market.users.insert(seller_addr.clone(), seller);
         market.users.insert(buyer_addr.clone(), buyer);
@@ -230,17 +231,17 @@ mod integration_tests {
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 1000.0, // Higher premium for clearer fee calculation
-            bid_price: 990.0,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(1000.0), // Higher premium for clearer fee calculation
+            bid_price: Amount::from_f64(990.0),
             expiration_time: Utc::now() + Duration::days(30),
             grantor_address: seller_addr.clone(),
-            beneficiary_address: None,
+            contract_count: 1,
             // Added missing fields:
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
         };
 
         let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
@@ -253,14 +254,16 @@ mod integration_tests {
         // Grantor fee = 100,000 * 0.0025 = 250
 
         let buyer = market.users.get(&buyer_addr).unwrap();
-        assert_eq!(buyer.get_balance(&Asset::USDT), 99500.0); // 200k - 100k premium - 500 fee
+        assert_eq!(buyer.get_balance(&Asset::USDT), Amount::from_f64(99500.0)); // 200k - 100k premium - 500 fee
 
         let seller = market.users.get(&seller_addr).unwrap();
-        assert_eq!(seller.get_balance(&Asset::USDT), 10099750.0); // 10M + 100k premium - 250 fee
-        assert_eq!(seller.get_balance(&Asset::BTC), 4.0); // 5 - 1 BTC collateral
-
-        // Escrow should have BTC collateral + USDT fees
-        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), 1.0); // BTC collateral
-        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), 750.0); // 500 + 250 fees
+        assert_eq!(seller.get_balance(&Asset::USDT), Amount::from_f64(10099750.0)); // 10M + 100k premium - 250 fee
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(4.0)); // 5 - 1 BTC collateral
+
+        // Escrow should only have BTC collateral, the USDT fees went to treasury and insurance fund
+        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), Amount::from_f64(1.0)); // BTC collateral
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+        assert_eq!(market.treasury.get_balance(&Asset::USDT), Amount::from_f64(675.0)); // 500 + 250 fees less 10%
+        assert_eq!(market.insurance_fund.get_balance(&Asset::USDT), Amount::from_f64(75.0));
     }
 }
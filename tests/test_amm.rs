@@ -0,0 +1,340 @@
+use options_trading::amm::MINIMUM_SHARES;
+use options_trading::exchange::SpotAction;
+use options_trading::{Address, Amount, Asset, AssetPair, Exchange, ExchangeError, Rounding, User};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn btc_usdt() -> AssetPair {
+        AssetPair::from(Asset::BTC, Asset::USDT)
+    }
+
+    // Returns (exchange, alice, bob), each holding 100 BTC and 2M USDT
+    fn setup_exchange() -> (Exchange, Address, Address) {
+        let mut exchange = Exchange::new();
+        let alice_addr = create_test_address("1");
+        let bob_addr = create_test_address("2");
+
+        for address in [&alice_addr, &bob_addr] {
+            let mut user = User::new(address.clone());
+            user.add_asset(&Asset::BTC, Amount::from_f64(100.0))
+                .unwrap();
+            user.add_asset(&Asset::USDT, Amount::from_f64(2000000.0))
+                .unwrap();
+            exchange.users.insert(address.clone(), user);
+        }
+
+        (exchange, alice_addr, bob_addr)
+    }
+
+    // Alice funds a pool of 10 BTC and 1M USDT, a price of 100k
+    fn setup_pool() -> (Exchange, Address, Address) {
+        let (mut exchange, alice_addr, bob_addr) = setup_exchange();
+        exchange
+            .add_liquidity(
+                btc_usdt(),
+                Amount::from_f64(10.0),
+                Amount::from_f64(1000000.0),
+                alice_addr.clone(),
+            )
+            .unwrap();
+        (exchange, alice_addr, bob_addr)
+    }
+
+    fn balance(exchange: &Exchange, address: &Address, asset: &Asset) -> Amount {
+        exchange.users.get(address).unwrap().get_balance(asset)
+    }
+
+    #[test]
+    fn test_first_deposit_sets_price() {
+        let (exchange, alice_addr, _) = setup_pool();
+
+        // sqrt(10 * 1M) shares, of which the minimum stays locked in the pool
+        let pool = exchange.get_pool_or_error(&btc_usdt()).unwrap();
+        assert_eq!(pool.total_shares, Amount::from_units(3162277660168379));
+        assert_eq!(
+            pool.get_shares(&alice_addr),
+            pool.total_shares.try_sub(MINIMUM_SHARES).unwrap()
+        );
+        assert_eq!(
+            exchange.get_pool_price(&btc_usdt()),
+            Some(Amount::from_f64(100000.0))
+        );
+
+        assert_eq!(
+            balance(&exchange, &alice_addr, &Asset::BTC),
+            Amount::from_f64(90.0)
+        );
+        assert_eq!(
+            pool.balances.get_balance(&Asset::USDT),
+            Amount::from_f64(1000000.0)
+        );
+        assert_eq!(exchange.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_later_deposit_takes_pool_ratio() {
+        let (mut exchange, alice_addr, bob_addr) = setup_pool();
+        let total_shares = exchange
+            .get_pool_or_error(&btc_usdt())
+            .unwrap()
+            .total_shares;
+
+        // USDT is the scarce side, so only 1 BTC of the 5 offered is taken
+        let minted = exchange
+            .add_liquidity(
+                btc_usdt(),
+                Amount::from_f64(5.0),
+                Amount::from_f64(100000.0),
+                bob_addr.clone(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            minted,
+            total_shares
+                .try_div(Amount::from_int(10), Rounding::Down)
+                .unwrap()
+        );
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::BTC),
+            Amount::from_f64(99.0)
+        );
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::USDT),
+            Amount::from_f64(1900000.0)
+        );
+        assert_eq!(
+            exchange.get_pool_price(&btc_usdt()),
+            Some(Amount::from_f64(100000.0))
+        );
+        let pool = exchange.get_pool_or_error(&btc_usdt()).unwrap();
+        assert_eq!(
+            pool.get_shares(&alice_addr),
+            total_shares.try_sub(MINIMUM_SHARES).unwrap()
+        );
+        assert_eq!(pool.base_reserve, Amount::from_f64(11.0));
+    }
+
+    #[test]
+    fn test_swap_follows_constant_product_after_fee() {
+        let (mut exchange, _, bob_addr) = setup_pool();
+
+        // 30 bps of the 10k stays in the pool, 10 * 9970 / 1009970 BTC comes out
+        let quoted = exchange
+            .get_swap_output(&btc_usdt(), SpotAction::BUY, Amount::from_f64(10000.0))
+            .unwrap();
+        let received = exchange
+            .swap(
+                btc_usdt(),
+                SpotAction::BUY,
+                Amount::from_f64(10000.0),
+                Amount::ZERO,
+                bob_addr.clone(),
+            )
+            .unwrap();
+        assert_eq!(received, Amount::from_f64(0.0987158));
+        assert_eq!(received, quoted);
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::BTC),
+            Amount::from_f64(100.0987158)
+        );
+
+        // The price moved up and the product of the reserves grew by the fee
+        let pool = exchange.get_pool_or_error(&btc_usdt()).unwrap();
+        assert_eq!(pool.quote_reserve, Amount::from_f64(1010000.0));
+        assert_eq!(pool.base_reserve, Amount::from_f64(9.9012842));
+        assert!(exchange.get_pool_price(&btc_usdt()).unwrap() > Amount::from_f64(102000.0));
+        assert!(pool.base_reserve.to_f64() * pool.quote_reserve.to_f64() > 10_000_000.0);
+
+        // Selling pays out quote
+        let received = exchange
+            .swap(
+                btc_usdt(),
+                SpotAction::SELL,
+                Amount::from_f64(0.05),
+                Amount::ZERO,
+                bob_addr,
+            )
+            .unwrap();
+        assert!(received > Amount::from_f64(5000.0));
+        assert!(received < Amount::from_f64(5100.0));
+    }
+
+    #[test]
+    fn test_swap_below_minimum_output_trades_nothing() {
+        let (mut exchange, _, bob_addr) = setup_pool();
+
+        let result = exchange.swap(
+            btc_usdt(),
+            SpotAction::SELL,
+            Amount::from_f64(1.0),
+            Amount::from_f64(95000.0),
+            bob_addr.clone(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::SwapOutputTooLow {
+                amount_out: Amount::from_f64(90661.089388),
+                min_amount_out: Amount::from_f64(95000.0),
+            }
+        );
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::BTC),
+            Amount::from_f64(100.0)
+        );
+        assert_eq!(
+            exchange.get_pool_price(&btc_usdt()),
+            Some(Amount::from_f64(100000.0))
+        );
+
+        // A pair nobody funded has no pool to swap through
+        let result = exchange.swap(
+            AssetPair::from(Asset::ETH, Asset::USDT),
+            SpotAction::BUY,
+            Amount::from_f64(100.0),
+            Amount::ZERO,
+            bob_addr,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::PoolNotFound(AssetPair::from(Asset::ETH, Asset::USDT))
+        );
+    }
+
+    #[test]
+    fn test_fees_grow_provider_withdrawal() {
+        let (mut exchange, alice_addr, bob_addr) = setup_pool();
+
+        // A round trip through the pool costs bob the fee on both legs
+        let received = exchange
+            .swap(
+                btc_usdt(),
+                SpotAction::BUY,
+                Amount::from_f64(100000.0),
+                Amount::ZERO,
+                bob_addr.clone(),
+            )
+            .unwrap();
+        exchange
+            .swap(
+                btc_usdt(),
+                SpotAction::SELL,
+                received,
+                Amount::ZERO,
+                bob_addr.clone(),
+            )
+            .unwrap();
+        assert!(balance(&exchange, &bob_addr, &Asset::USDT) < Amount::from_f64(2000000.0));
+
+        // which stays in the pool for the provider
+        let shares = exchange
+            .get_pool_or_error(&btc_usdt())
+            .unwrap()
+            .get_shares(&alice_addr);
+        let (base, quote) = exchange
+            .remove_liquidity(btc_usdt(), shares, alice_addr.clone())
+            .unwrap();
+        assert!(quote > Amount::from_f64(1000000.0));
+
+        // less the dust behind the locked shares, which keeps the pool priced
+        let pool = exchange.get_pool_or_error(&btc_usdt()).unwrap();
+        assert_eq!(pool.total_shares, MINIMUM_SHARES);
+        assert_eq!(
+            base.try_add(pool.base_reserve).unwrap(),
+            Amount::from_f64(10.0)
+        );
+        assert!(pool.base_reserve > Amount::ZERO);
+        assert!(exchange.get_pool_price(&btc_usdt()).is_some());
+    }
+
+    #[test]
+    fn test_first_deposit_must_exceed_locked_shares() {
+        let (mut exchange, alice_addr, _) = setup_exchange();
+
+        // sqrt(1e-8 BTC * 1e-4 USDT) is exactly the locked minimum, nothing would be left to mint
+        let result = exchange.add_liquidity(
+            btc_usdt(),
+            Amount::from_f64(0.00000001),
+            Amount::from_f64(0.0001),
+            alice_addr.clone(),
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+        assert!(exchange.get_pool_or_error(&btc_usdt()).is_err());
+        assert_eq!(
+            balance(&exchange, &alice_addr, &Asset::BTC),
+            Amount::from_f64(100.0)
+        );
+    }
+
+    #[test]
+    fn test_remove_liquidity_is_pro_rata() {
+        let (mut exchange, alice_addr, bob_addr) = setup_pool();
+        let shares = exchange
+            .get_pool_or_error(&btc_usdt())
+            .unwrap()
+            .get_shares(&alice_addr);
+        let quarter = shares.try_div(Amount::from_int(4), Rounding::Down).unwrap();
+
+        let result = exchange.remove_liquidity(btc_usdt(), quarter, bob_addr);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+
+        let (base, quote) = exchange
+            .remove_liquidity(btc_usdt(), quarter, alice_addr.clone())
+            .unwrap();
+        // a quarter of what alice holds, just short of a quarter of the pool given the locked shares
+        assert_eq!(base, Amount::from_f64(2.49999999));
+        assert_eq!(quote, Amount::from_f64(249999.99992));
+        assert_eq!(
+            exchange
+                .get_pool_or_error(&btc_usdt())
+                .unwrap()
+                .get_shares(&alice_addr),
+            shares.try_sub(quarter).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_set_pool_fee_bps() {
+        let (mut exchange, _, bob_addr) = setup_pool();
+
+        let result = exchange.set_pool_fee_bps(0, bob_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not the market admin".into())
+        );
+        let result = exchange.set_pool_fee_bps(10001, exchange.market_admin_address.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+
+        // Without a fee the output is exactly 10 * 10000 / 1010000
+        exchange
+            .set_pool_fee_bps(0, exchange.market_admin_address.clone())
+            .unwrap();
+        let received = exchange
+            .swap(
+                btc_usdt(),
+                SpotAction::BUY,
+                Amount::from_f64(10000.0),
+                Amount::ZERO,
+                bob_addr,
+            )
+            .unwrap();
+        assert_eq!(received, Amount::from_f64(0.0990099));
+    }
+}
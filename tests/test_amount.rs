@@ -0,0 +1,230 @@
+use chrono::{Duration, Utc};
+use options_trading::{
+    Address, Amount, AmountError, Asset, Exchange, ExchangeError, ExerciseStyle, ListingOption,
+    ListingType, OptionState, Rounding, SettlementType, User,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    #[test]
+    fn test_from_f64_is_exact_decimal() {
+        let sum = Amount::from_f64(0.1)
+            .try_add(Amount::from_f64(0.2))
+            .unwrap();
+        assert_eq!(sum, Amount::from_f64(0.3));
+        assert_eq!(
+            Amount::from_f64(67432.123456789).to_string(),
+            "67432.123456789"
+        );
+        assert_eq!(Amount::from_f64(-1.5).to_string(), "-1.5");
+        assert!(Amount::try_from_f64(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let amount: Amount = "1234.5678".parse().unwrap();
+        assert_eq!(amount, Amount::from_f64(1234.5678));
+        assert_eq!(format!("{:.2}", amount), "1234.57");
+        assert_eq!(format!("{}", Amount::from_int(42)), "42");
+
+        assert!("12a".parse::<Amount>().is_err());
+        assert!(".5".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        let amount = Amount::from_f64(1.234565);
+        assert_eq!(
+            amount.round_dp(5, Rounding::Down),
+            Amount::from_f64(1.23456)
+        );
+        assert_eq!(amount.round_dp(5, Rounding::Up), Amount::from_f64(1.23457));
+        assert_eq!(
+            amount.round_dp(5, Rounding::HalfEven),
+            Amount::from_f64(1.23456)
+        );
+        assert_eq!(
+            Amount::from_f64(1.234575).round_dp(5, Rounding::HalfEven),
+            Amount::from_f64(1.23458)
+        );
+
+        // Down and up are floor and ceiling, also for negative amounts
+        let negative = Amount::from_f64(-1.5);
+        assert_eq!(negative.round_dp(0, Rounding::Down), Amount::from_int(-2));
+        assert_eq!(negative.round_dp(0, Rounding::Up), Amount::from_int(-1));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let one_third = Amount::from_int(1)
+            .try_div(Amount::from_int(3), Rounding::Down)
+            .unwrap();
+        assert_eq!(one_third.to_string(), "0.333333333333");
+
+        let rounded_up = Amount::from_int(2)
+            .try_div(Amount::from_int(3), Rounding::Up)
+            .unwrap();
+        assert_eq!(rounded_up.to_string(), "0.666666666667");
+
+        assert_eq!(
+            Amount::from_int(1).try_div(Amount::ZERO, Rounding::Down),
+            Err(AmountError::DivisionByZero)
+        );
+        assert_eq!(
+            Amount::from_units(i128::MAX).try_add(Amount::from_units(1)),
+            Err(AmountError::Overflow)
+        );
+        assert_eq!(
+            Amount::from_int(1_000_000_000)
+                .try_mul(Amount::from_int(1_000_000_000_000), Rounding::Down),
+            Err(AmountError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_balances_respect_asset_precision() {
+        let mut user = User::new(create_test_address("1"));
+
+        user.add_asset(&Asset::BTC, Amount::from_f64(0.00000001))
+            .unwrap();
+        let result = user.add_asset(&Asset::BTC, Amount::from_f64(0.000000001));
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput("BTC amount 0.000000001 has more than 8 decimals".into())
+        );
+
+        let result = user.add_asset(&Asset::USDT, Amount::from_f64(-1.0));
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput("Invalid negative USDT amount -1".into())
+        );
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(0.00000001));
+    }
+
+    #[test]
+    fn test_fees_round_up_and_escrow_reconciles() {
+        let mut market = Exchange::new();
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::USDT, Amount::from_f64(10000.0))
+            .unwrap();
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDT, Amount::from_f64(10000.0))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        // Strike value 0.3 * 33.333333 = 9.9999999 USDT, collected as 10.000000
+        // Premium 0.0123457 * 100 = 1.23457 USDT, fee 0.00123457 rounds up to 0.001235
+        let option = ListingOption {
+            listing_id: 0,
+            base_asset: Asset::ETH,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::PUT,
+            strike_price: Amount::from_f64(33.333333),
+            ask_price: Amount::from_f64(0.0123457),
+            bid_price: Amount::from_f64(0.01),
+            expiration_time: Utc::now() + Duration::days(30),
+            grantor_address: seller_addr.clone(),
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(0.3),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        };
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::USDT),
+            Amount::from_f64(10.0)
+        );
+
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+
+        let seller = market.users.get(&seller_addr).unwrap();
+        let buyer = market.users.get(&buyer_addr).unwrap();
+        assert_eq!(
+            buyer.get_balance(&Asset::USDT),
+            Amount::from_f64(9998.764195)
+        );
+        assert_eq!(
+            seller.get_balance(&Asset::USDT),
+            Amount::from_f64(9991.233335)
+        );
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::USDT),
+            Amount::from_f64(10.0)
+        );
+        // 10% of each fee cut to 0.000123 for the insurance fund, the rest to the treasury
+        assert_eq!(
+            market.treasury.get_balance(&Asset::USDT),
+            Amount::from_f64(0.002224)
+        );
+        assert_eq!(
+            market.insurance_fund.get_balance(&Asset::USDT),
+            Amount::from_f64(0.000246)
+        );
+
+        // Nothing was created or lost along the way
+        let total = buyer
+            .get_balance(&Asset::USDT)
+            .try_add(seller.get_balance(&Asset::USDT))
+            .unwrap()
+            .try_add(market.escrow_user.get_balance(&Asset::USDT))
+            .unwrap()
+            .try_add(market.treasury.get_balance(&Asset::USDT))
+            .unwrap()
+            .try_add(market.insurance_fund.get_balance(&Asset::USDT))
+            .unwrap();
+        assert_eq!(total, Amount::from_f64(20000.0));
+    }
+
+    #[test]
+    fn test_listing_rejects_exercise_amount_finer_than_asset() {
+        let mut market = Exchange::new();
+        let seller_addr = create_test_address("1");
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::APPLE, Amount::from_f64(10.0))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+
+        let option = ListingOption {
+            listing_id: 0,
+            base_asset: Asset::APPLE,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::CALL,
+            strike_price: Amount::from_f64(150.0),
+            ask_price: Amount::from_f64(1.0),
+            bid_price: Amount::from_f64(0.9),
+            expiration_time: Utc::now() + Duration::days(30),
+            grantor_address: seller_addr.clone(),
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(0.00001),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        };
+
+        let result = market.list_option(seller_addr, option);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(
+                "Exercise amount has more than 4 decimals for APPLE".into()
+            )
+        );
+    }
+}
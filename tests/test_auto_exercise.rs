@@ -0,0 +1,442 @@
+use chrono::{DateTime, Duration, Utc};
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ListingOption, ListingType, OptionState,
+    SettlementType, SimulatedClock, User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    // A pair no other test reads from the shared rate provider, always set to 100
+    fn set_eth_usdc_rate() {
+        get_rate_provider()
+            .set_rate(
+                Asset::ETH,
+                Asset::USDC,
+                Amount::from_int(100),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+    }
+
+    // 2 physically settled contracts of 1 unit each
+    fn create_test_option(
+        grantor_address: Address,
+        base_asset: Asset,
+        listing_type: ListingType,
+        strike_price: i64,
+        expiration_time: DateTime<Utc>,
+    ) -> ListingOption {
+        ListingOption::new(
+            0,
+            base_asset,
+            Asset::USDC,
+            listing_type,
+            Amount::from_int(strike_price),
+            Amount::from_f64(0.05),
+            Amount::from_f64(0.04),
+            expiration_time,
+            grantor_address,
+            2,
+            Amount::from_int(1),
+            OptionState::Listed,
+        )
+    }
+
+    // Returns (market, clock, seller, alice, bob), the buyers hold ETH to deliver on puts
+    // and only enough USDC for premiums
+    fn setup_market() -> (Exchange, Arc<SimulatedClock>, Address, Address, Address) {
+        set_eth_usdc_rate();
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+        let seller_addr = create_test_address("1");
+        let alice_addr = create_test_address("2");
+        let bob_addr = create_test_address("3");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller.add_asset(&Asset::ETH, Amount::from_int(10)).unwrap();
+        seller.add_asset(&Asset::SOL, Amount::from_int(10)).unwrap();
+        seller
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        for address in [&alice_addr, &bob_addr] {
+            let mut buyer = User::new(address.clone());
+            buyer.add_asset(&Asset::ETH, Amount::from_int(10)).unwrap();
+            buyer
+                .add_asset(&Asset::USDC, Amount::from_int(100))
+                .unwrap();
+            market.users.insert(address.clone(), buyer);
+        }
+
+        (market, clock, seller_addr, alice_addr, bob_addr)
+    }
+
+    // Lists the option expiring in 30 days and sells `contracts` to each buyer
+    fn list_and_buy(
+        market: &mut Exchange,
+        seller_addr: &Address,
+        buyers: &[(&Address, u32)],
+        option: ListingOption,
+    ) -> u32 {
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        for (buyer_addr, contracts) in buyers {
+            market
+                .purchase_contracts(listing_id, *contracts, (*buyer_addr).clone())
+                .unwrap();
+        }
+        listing_id
+    }
+
+    fn balance(market: &Exchange, address: &Address, asset: &Asset) -> Amount {
+        market.users[address].get_balance(asset)
+    }
+
+    #[test]
+    fn test_in_the_money_put_exercised_at_expiry() {
+        let (mut market, clock, seller_addr, alice_addr, _) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            120,
+            expiration_time,
+        );
+        let listing_id = list_and_buy(&mut market, &seller_addr, &[(&alice_addr, 2)], option);
+        let alice_usdc = balance(&market, &alice_addr, &Asset::USDC);
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        // Alice delivers 2 ETH at the 120 strike instead of letting the put lapse
+        assert_eq!(report.auto_exercised.len(), 1);
+        let auto_exercise = &report.auto_exercised[0];
+        assert_eq!(auto_exercise.listing_id, listing_id);
+        assert_eq!(auto_exercise.holder_address, alice_addr);
+        assert_eq!(auto_exercise.contracts, 2);
+        assert_eq!(auto_exercise.spot, Amount::from_int(100));
+        assert_eq!(market.auto_exercises, report.auto_exercised);
+        assert_eq!(
+            balance(&market, &alice_addr, &Asset::USDC),
+            alice_usdc.try_add(Amount::from_int(240)).unwrap()
+        );
+        assert_eq!(
+            balance(&market, &alice_addr, &Asset::ETH),
+            Amount::from_int(8)
+        );
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::ETH),
+            Amount::from_int(12)
+        );
+
+        assert!(report.settled.is_empty());
+        assert_eq!(market.listings[&listing_id].state, OptionState::Settled);
+        let position = market.get_position(listing_id, &alice_addr).unwrap();
+        assert_eq!((position.contracts, position.exercised_contracts), (0, 2));
+    }
+
+    #[test]
+    fn test_cash_settled_call_exercised_at_expiry() {
+        let (mut market, clock, seller_addr, alice_addr, _) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::CALL,
+            80,
+            expiration_time,
+        )
+        .with_settlement_type(SettlementType::Cash);
+        let listing_id = list_and_buy(&mut market, &seller_addr, &[(&alice_addr, 2)], option);
+        let alice_usdc = balance(&market, &alice_addr, &Asset::USDC);
+        // The spot desk buys the ETH collateral for the payout
+        let desk_addr = market.escrow_user.address.clone();
+        market
+            .users
+            .get_mut(&desk_addr)
+            .unwrap()
+            .add_asset(&Asset::USDC, Amount::from_int(100))
+            .unwrap();
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        assert_eq!(report.auto_exercised.len(), 1);
+        assert_eq!(
+            balance(&market, &alice_addr, &Asset::USDC),
+            alice_usdc.try_add(Amount::from_int(40)).unwrap()
+        );
+        assert_eq!(
+            balance(&market, &alice_addr, &Asset::ETH),
+            Amount::from_int(10)
+        );
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::ETH),
+            Amount::from_f64(9.6)
+        );
+        assert_eq!(
+            balance(&market, &desk_addr, &Asset::ETH),
+            Amount::from_f64(0.4)
+        );
+        assert_eq!(market.escrow_user.get_balance(&Asset::ETH), Amount::ZERO);
+        assert_eq!(market.listings[&listing_id].state, OptionState::Settled);
+    }
+
+    #[test]
+    fn test_out_of_the_money_lapses() {
+        let (mut market, clock, seller_addr, alice_addr, _) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            90,
+            expiration_time,
+        );
+        let listing_id = list_and_buy(&mut market, &seller_addr, &[(&alice_addr, 2)], option);
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        assert!(report.auto_exercised.is_empty());
+        assert!(market.auto_exercises.is_empty());
+        assert_eq!(report.settled.len(), 1);
+        assert_eq!(report.settled[0].released_amount, Amount::from_int(180));
+        assert_eq!(market.listings[&listing_id].state, OptionState::Expired);
+        assert_eq!(
+            balance(&market, &alice_addr, &Asset::ETH),
+            Amount::from_int(10)
+        );
+    }
+
+    #[test]
+    fn test_auto_exercise_threshold() {
+        let (mut market, clock, seller_addr, alice_addr, _) = setup_market();
+        let admin = market.market_admin_address.clone();
+        let result = market.set_auto_exercise_threshold_bps(Some(100), alice_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not the market admin".into())
+        );
+        let result = market.set_auto_exercise_threshold_bps(Some(10_001), admin.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+
+        // 20 in the money on a 120 strike is short of a 25% threshold
+        let expiration_time = market.now() + Duration::days(30);
+        let put = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            120,
+            expiration_time,
+        );
+        let first_id = list_and_buy(&mut market, &seller_addr, &[(&alice_addr, 1)], put.clone());
+        market
+            .set_auto_exercise_threshold_bps(Some(2_500), admin.clone())
+            .unwrap();
+        clock.advance(Duration::days(31));
+        assert!(market.settle_expired().unwrap().auto_exercised.is_empty());
+        assert_eq!(market.listings[&first_id].state, OptionState::Expired);
+
+        // and clears 10%
+        let put = ListingOption {
+            expiration_time: market.now() + Duration::days(30),
+            ..put
+        };
+        let second_id = list_and_buy(&mut market, &seller_addr, &[(&alice_addr, 1)], put.clone());
+        market
+            .set_auto_exercise_threshold_bps(Some(1_000), admin.clone())
+            .unwrap();
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+        assert_eq!(report.auto_exercised[0].listing_id, second_id);
+
+        // Disabled altogether
+        let put = ListingOption {
+            expiration_time: market.now() + Duration::days(30),
+            ..put
+        };
+        let third_id = list_and_buy(&mut market, &seller_addr, &[(&alice_addr, 1)], put);
+        market.set_auto_exercise_threshold_bps(None, admin).unwrap();
+        clock.advance(Duration::days(31));
+        assert!(market.settle_expired().unwrap().auto_exercised.is_empty());
+        assert_eq!(market.listings[&third_id].state, OptionState::Expired);
+    }
+
+    #[test]
+    fn test_holder_can_opt_out() {
+        let (mut market, clock, seller_addr, alice_addr, bob_addr) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            120,
+            expiration_time,
+        );
+        let listing_id = list_and_buy(
+            &mut market,
+            &seller_addr,
+            &[(&alice_addr, 1), (&bob_addr, 1)],
+            option,
+        );
+
+        let result = market.set_auto_exercise(listing_id, false, seller_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not beneficiary of option".into())
+        );
+        market
+            .set_auto_exercise(listing_id, false, bob_addr.clone())
+            .unwrap();
+        assert!(
+            !market
+                .get_position(listing_id, &bob_addr)
+                .unwrap()
+                .auto_exercise
+        );
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        // Only Alice is exercised, Bob's contract lapses and its collateral goes back
+        assert_eq!(report.auto_exercised.len(), 1);
+        assert_eq!(report.auto_exercised[0].holder_address, alice_addr);
+        assert_eq!(report.settled[0].released_amount, Amount::from_int(120));
+        assert_eq!(market.listings[&listing_id].state, OptionState::Expired);
+        assert_eq!(
+            balance(&market, &bob_addr, &Asset::ETH),
+            Amount::from_int(10)
+        );
+    }
+
+    #[test]
+    fn test_unpayable_or_unpriced_options_lapse() {
+        let (mut market, clock, seller_addr, alice_addr, bob_addr) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+
+        // Alice can't pay the 160 USDC strike on 2 ETH calls
+        let call = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::CALL,
+            80,
+            expiration_time,
+        );
+        let call_id = list_and_buy(&mut market, &seller_addr, &[(&alice_addr, 2)], call);
+        // Nobody quotes SOL/USDC
+        let sol_put = create_test_option(
+            seller_addr.clone(),
+            Asset::SOL,
+            ListingType::PUT,
+            120,
+            expiration_time,
+        );
+        let sol_id = list_and_buy(&mut market, &seller_addr, &[(&bob_addr, 2)], sol_put);
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        assert!(report.auto_exercised.is_empty());
+        assert_eq!(report.settled.len(), 2);
+        for listing_id in [call_id, sol_id] {
+            assert_eq!(market.listings[&listing_id].state, OptionState::Expired);
+        }
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::ETH),
+            Amount::from_int(10)
+        );
+    }
+
+    #[test]
+    fn test_listing_that_cant_be_exercised_lapses_at_expiry() {
+        let (mut market, clock, seller_addr, alice_addr, bob_addr) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+        let put = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            120,
+            expiration_time,
+        );
+        let put_id = list_and_buy(&mut market, &seller_addr, &[(&bob_addr, 2)], put);
+
+        // BTC is only read here. The call is written on 100 USDC of margin and the rate doubles,
+        // leaving 240 USDC to pay with nothing in the insurance fund to make up the difference.
+        get_rate_provider()
+            .set_rate(
+                Asset::BTC,
+                Asset::USDC,
+                Amount::from_int(100),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+        market
+            .set_insurance_fee_share_bps(0, market.market_admin_address.clone())
+            .unwrap();
+        market.open_margin_account(seller_addr.clone()).unwrap();
+        market
+            .deposit_margin(&Asset::USDC, Amount::from_int(100), seller_addr.clone())
+            .unwrap();
+        let call = create_test_option(
+            seller_addr.clone(),
+            Asset::BTC,
+            ListingType::CALL,
+            80,
+            expiration_time,
+        )
+        .with_settlement_type(SettlementType::Cash);
+        let call_id = list_and_buy(&mut market, &seller_addr, &[(&alice_addr, 2)], call);
+        get_rate_provider()
+            .set_rate(
+                Asset::BTC,
+                Asset::USDC,
+                Amount::from_int(200),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+        let alice_usdc = balance(&market, &alice_addr, &Asset::USDC);
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        // The put settles in the same pass, the call can't be exercised and lapses instead
+        assert_eq!(report.auto_exercised.len(), 1);
+        assert_eq!(report.auto_exercised[0].listing_id, put_id);
+        assert_eq!(market.listings[&put_id].state, OptionState::Settled);
+        assert_eq!(report.unsettled.len(), 1);
+        assert_eq!(report.unsettled[0].listing_id, call_id);
+        assert!(matches!(
+            report.unsettled[0].error,
+            ExchangeError::WriterShortfall { .. }
+        ));
+        assert_eq!(report.settled.len(), 1);
+        assert_eq!(report.settled[0].listing_id, call_id);
+        assert_eq!(market.listings[&call_id].state, OptionState::Expired);
+        assert_eq!(balance(&market, &alice_addr, &Asset::USDC), alice_usdc);
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert_eq!(health.initial_requirement, Amount::ZERO);
+        assert_eq!(health.collateral, Amount::from_int(100));
+
+        // Nothing is left for a later pass
+        let report = market.settle_expired().unwrap();
+        assert!(report.auto_exercised.is_empty());
+        assert!(report.unsettled.is_empty());
+        assert!(report.settled.is_empty());
+    }
+}
@@ -0,0 +1,350 @@
+use chrono::{DateTime, Duration, Utc};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ExerciseStyle, ListingOption, ListingType,
+    OptionSeries, OptionState, SettlementType, SimulatedClock, User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    // 5 contracts of 1 BTC each, every listing in these tests shares the same expiry
+    fn create_test_option(
+        grantor_address: Address,
+        expiration_time: DateTime<Utc>,
+        ask_price: f64,
+    ) -> ListingOption {
+        ListingOption {
+            listing_id: 0,
+            base_asset: Asset::BTC,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::CALL,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(ask_price),
+            bid_price: Amount::from_f64(ask_price * 0.95),
+            expiration_time,
+            grantor_address,
+            contract_count: 5,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        }
+    }
+
+    // Returns (market, clock, series, seller, alice, bob)
+    fn setup_market() -> (
+        Exchange,
+        Arc<SimulatedClock>,
+        OptionSeries,
+        Address,
+        Address,
+        Address,
+    ) {
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+        let seller_addr = create_test_address("1");
+        let alice_addr = create_test_address("2");
+        let bob_addr = create_test_address("3");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::BTC, Amount::from_f64(20.0))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        for address in [&alice_addr, &bob_addr] {
+            let mut buyer = User::new(address.clone());
+            buyer
+                .add_asset(&Asset::USDT, Amount::from_f64(300000.0))
+                .unwrap();
+            market.users.insert(address.clone(), buyer);
+        }
+
+        let series =
+            create_test_option(seller_addr.clone(), market.now() + Duration::days(30), 5.0)
+                .series();
+
+        (market, clock, series, seller_addr, alice_addr, bob_addr)
+    }
+
+    #[test]
+    fn test_bid_locks_premium_and_fee() {
+        let (mut market, _, series, _, alice_addr, _) = setup_market();
+
+        // 3 contracts at 500 USDT premium plus 0.5 USDT fee each
+        let bid_id = market
+            .place_bid(series, Amount::from_f64(5.0), 3, alice_addr.clone())
+            .unwrap();
+
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(298498.5));
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::USDT),
+            Amount::from_f64(1501.5)
+        );
+        assert_eq!(market.bids.get(&bid_id).unwrap().contract_count, 3);
+    }
+
+    #[test]
+    fn test_bid_without_funds_fails() {
+        let (mut market, _, series, _, alice_addr, _) = setup_market();
+
+        let result = market.place_bid(series, Amount::from_f64(5.0), 600, alice_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::USDT,
+                ..
+            }
+        ));
+        assert!(market.bids.is_empty());
+    }
+
+    #[test]
+    fn test_crossing_listing_fills_bid_at_bid_price() {
+        let (mut market, _, series, seller_addr, alice_addr, _) = setup_market();
+        let bid_id = market
+            .place_bid(series.clone(), Amount::from_f64(5.0), 3, alice_addr.clone())
+            .unwrap();
+
+        let option = create_test_option(seller_addr.clone(), series.expiration_time, 4.0);
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+
+        let listing = market.listings.get(&listing_id).unwrap();
+        assert_eq!(listing.state, OptionState::Purchased);
+        assert_eq!(listing.contract_count, 2);
+        assert_eq!(
+            market
+                .get_position(listing_id, &alice_addr)
+                .unwrap()
+                .contracts,
+            3
+        );
+        assert!(!market.bids.contains_key(&bid_id));
+
+        // Premium of 1500 at the bid price, less the 1.5 grantor fee
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::USDT), Amount::from_f64(1498.5));
+        // Both fees leave escrow, 10% of them for the insurance fund
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+        assert_eq!(
+            market.treasury.get_balance(&Asset::USDT),
+            Amount::from_f64(2.7)
+        );
+        assert_eq!(
+            market.insurance_fund.get_balance(&Asset::USDT),
+            Amount::from_f64(0.3)
+        );
+    }
+
+    #[test]
+    fn test_best_bid_fills_first() {
+        let (mut market, _, series, seller_addr, alice_addr, bob_addr) = setup_market();
+        let alice_bid = market
+            .place_bid(series.clone(), Amount::from_f64(5.0), 2, alice_addr.clone())
+            .unwrap();
+        let bob_bid = market
+            .place_bid(series.clone(), Amount::from_f64(6.0), 2, bob_addr.clone())
+            .unwrap();
+
+        let mut option = create_test_option(seller_addr.clone(), series.expiration_time, 4.0);
+        option.contract_count = 3;
+        let listing_id = market.list_option(seller_addr, option).unwrap();
+
+        assert_eq!(
+            market
+                .get_position(listing_id, &bob_addr)
+                .unwrap()
+                .contracts,
+            2
+        );
+        assert_eq!(
+            market
+                .get_position(listing_id, &alice_addr)
+                .unwrap()
+                .contracts,
+            1
+        );
+        assert!(!market.bids.contains_key(&bob_bid));
+        assert_eq!(market.bids.get(&alice_bid).unwrap().contract_count, 1);
+        assert_eq!(market.listings.get(&listing_id).unwrap().contract_count, 0);
+    }
+
+    #[test]
+    fn test_listing_above_bid_does_not_match() {
+        let (mut market, _, series, seller_addr, alice_addr, _) = setup_market();
+        let bid_id = market
+            .place_bid(series.clone(), Amount::from_f64(4.0), 2, alice_addr.clone())
+            .unwrap();
+
+        let option = create_test_option(seller_addr.clone(), series.expiration_time, 5.0);
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+
+        // A crossing ask on another strike belongs to a different series
+        let mut other_strike = create_test_option(seller_addr.clone(), series.expiration_time, 3.0);
+        other_strike.strike_price = Amount::from_f64(60000.0);
+        let other_id = market.list_option(seller_addr, other_strike).unwrap();
+
+        for id in [listing_id, other_id] {
+            assert_eq!(market.listings.get(&id).unwrap().state, OptionState::Listed);
+        }
+        assert_eq!(market.bids.get(&bid_id).unwrap().contract_count, 2);
+    }
+
+    #[test]
+    fn test_bid_buys_cheaper_listings_right_away() {
+        let (mut market, _, series, seller_addr, alice_addr, _) = setup_market();
+        let option = create_test_option(seller_addr.clone(), series.expiration_time, 5.0);
+        let listing_id = market.list_option(seller_addr, option).unwrap();
+
+        let bid_id = market
+            .place_bid(series, Amount::from_f64(6.0), 7, alice_addr.clone())
+            .unwrap();
+
+        // 5 contracts bought at the 500 ask plus 2.5 fee, 2 left resting at 600.6 each
+        assert_eq!(
+            market
+                .get_position(listing_id, &alice_addr)
+                .unwrap()
+                .contracts,
+            5
+        );
+        assert_eq!(market.bids.get(&bid_id).unwrap().contract_count, 2);
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(296296.3));
+    }
+
+    #[test]
+    fn test_grantor_does_not_fill_own_bid() {
+        let (mut market, _, series, seller_addr, _, _) = setup_market();
+        market
+            .users
+            .get_mut(&seller_addr)
+            .unwrap()
+            .add_asset(&Asset::USDT, Amount::from_f64(10000.0))
+            .unwrap();
+        let bid_id = market
+            .place_bid(
+                series.clone(),
+                Amount::from_f64(5.0),
+                1,
+                seller_addr.clone(),
+            )
+            .unwrap();
+
+        let option = create_test_option(seller_addr.clone(), series.expiration_time, 4.0);
+        let listing_id = market.list_option(seller_addr, option).unwrap();
+
+        assert_eq!(
+            market.listings.get(&listing_id).unwrap().state,
+            OptionState::Listed
+        );
+        assert!(market.bids.contains_key(&bid_id));
+    }
+
+    #[test]
+    fn test_listing_for_another_grantor_fails() {
+        let (mut market, _, series, seller_addr, alice_addr, bob_addr) = setup_market();
+        let bid_id = market
+            .place_bid(series.clone(), Amount::from_f64(5.0), 1, alice_addr)
+            .unwrap();
+
+        // Bob can't list the seller's option, and the bid stays untouched
+        let option = create_test_option(seller_addr.clone(), series.expiration_time, 4.0);
+        let result = market.list_option(bob_addr, option);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized(_)
+        ));
+
+        assert!(market.listings.is_empty());
+        assert_eq!(market.bids.get(&bid_id).unwrap().contract_count, 1);
+        assert_eq!(
+            market
+                .users
+                .get(&seller_addr)
+                .unwrap()
+                .get_balance(&Asset::BTC),
+            Amount::from_f64(20.0)
+        );
+    }
+
+    #[test]
+    fn test_listing_already_expired_fails() {
+        let (mut market, clock, series, seller_addr, alice_addr, _) = setup_market();
+        let bid_id = market
+            .place_bid(series.clone(), Amount::from_f64(5.0), 1, alice_addr)
+            .unwrap();
+
+        clock.advance(Duration::days(31));
+        let option = create_test_option(seller_addr.clone(), series.expiration_time, 4.0);
+        let result = market.list_option(seller_addr.clone(), option);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+
+        assert!(market.listings.is_empty());
+        assert!(market.bids.contains_key(&bid_id));
+        assert_eq!(
+            market
+                .users
+                .get(&seller_addr)
+                .unwrap()
+                .get_balance(&Asset::BTC),
+            Amount::from_f64(20.0)
+        );
+    }
+
+    #[test]
+    fn test_cancel_bid() {
+        let (mut market, _, series, _, alice_addr, bob_addr) = setup_market();
+        let bid_id = market
+            .place_bid(series, Amount::from_f64(5.0), 3, alice_addr.clone())
+            .unwrap();
+
+        let result = market.cancel_bid(bid_id, bob_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("only the bidder can cancel this bid".into())
+        );
+
+        market.cancel_bid(bid_id, alice_addr.clone()).unwrap();
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(300000.0));
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+
+        let result = market.cancel_bid(bid_id, alice_addr);
+        assert_eq!(result.unwrap_err(), ExchangeError::BidNotFound(bid_id));
+    }
+
+    #[test]
+    fn test_expired_bid_refunded_on_settlement() {
+        let (mut market, clock, series, _, alice_addr, _) = setup_market();
+        let bid_id = market
+            .place_bid(series.clone(), Amount::from_f64(5.0), 3, alice_addr.clone())
+            .unwrap();
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        assert_eq!(report.expired_bids, vec![bid_id]);
+        assert!(market.bids.is_empty());
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(300000.0));
+
+        // No new bids on a series that is past its expiry
+        let result = market.place_bid(series, Amount::from_f64(5.0), 1, alice_addr);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+use chrono::{Duration, Utc};
+use options_trading::binomial::{
+    DEFAULT_BINOMIAL_STEPS, binomial_american, value_listing_american,
+};
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::pricing::black_scholes;
+use options_trading::{
+    Address, Amount, Asset, ListingOption, ListingType, OptionState, PricingParams,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn textbook_params() -> PricingParams {
+        PricingParams::new(0.2, 0.05)
+    }
+
+    // A pair no other test reads from the shared rate provider, always set to 100
+    fn set_eth_usdc_rate() {
+        get_rate_provider()
+            .set_rate(
+                Asset::ETH,
+                Asset::USDC,
+                Amount::from_int(100),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+    }
+
+    fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_call_is_never_exercised_early() {
+        // Without dividends an American call is worth the European one
+        let american = binomial_american(
+            &ListingType::CALL,
+            100.0,
+            100.0,
+            1.0,
+            &textbook_params(),
+            500,
+        );
+        assert_near(american.valuation.price, 10.4506, 0.01);
+        assert!(!american.exercise_now);
+
+        let boundary = &american.exercise_boundary;
+        assert_eq!(boundary.len(), 501);
+        assert!(boundary[..500].iter().all(|spot| spot.is_none()));
+        assert!(boundary[500].unwrap() > 100.0);
+    }
+
+    #[test]
+    fn test_put_is_worth_more_than_european() {
+        let american = binomial_american(
+            &ListingType::PUT,
+            100.0,
+            100.0,
+            1.0,
+            &textbook_params(),
+            500,
+        );
+        let european = black_scholes(&ListingType::PUT, 100.0, 100.0, 1.0, &textbook_params());
+        assert_near(american.valuation.price, 6.0903, 0.01);
+        assert!(american.valuation.price > european.price + 0.5);
+        assert!(!american.exercise_now);
+    }
+
+    #[test]
+    fn test_put_exercise_boundary_rises_to_strike() {
+        let american = binomial_american(
+            &ListingType::PUT,
+            100.0,
+            100.0,
+            1.0,
+            &textbook_params(),
+            DEFAULT_BINOMIAL_STEPS,
+        );
+
+        // Exercising pays off below a spot that climbs towards the strike as expiry nears
+        let points = american.boundary_points();
+        assert!(points.len() > DEFAULT_BINOMIAL_STEPS / 2);
+        let (first_years, first_spot) = points[0];
+        let (last_years, last_spot) = *points.last().unwrap();
+        assert!(first_years < last_years);
+        assert!((1.0 - last_years).abs() < 1e-9);
+        assert!(first_spot < 90.0);
+        assert!(last_spot < 100.0 && last_spot > first_spot);
+
+        // Deep in the money holding on only costs interest on the strike
+        let deep = binomial_american(
+            &ListingType::PUT,
+            50.0,
+            100.0,
+            1.0,
+            &textbook_params(),
+            DEFAULT_BINOMIAL_STEPS,
+        );
+        assert!(deep.exercise_now);
+        assert_near(deep.valuation.price, 50.0, 1e-9);
+        assert_near(deep.valuation.delta, -1.0, 1e-9);
+    }
+
+    #[test]
+    fn test_tree_greeks_match_black_scholes_for_calls() {
+        let american = binomial_american(
+            &ListingType::CALL,
+            100.0,
+            100.0,
+            1.0,
+            &textbook_params(),
+            500,
+        )
+        .valuation;
+        let european = black_scholes(&ListingType::CALL, 100.0, 100.0, 1.0, &textbook_params());
+
+        assert_near(american.delta, european.delta, 0.005);
+        assert_near(american.gamma, european.gamma, 0.001);
+        assert_near(american.vega, european.vega, 0.005);
+        assert_near(american.theta, european.theta, 0.001);
+        assert_near(american.rho, european.rho, 0.005);
+    }
+
+    #[test]
+    fn test_no_time_left_is_intrinsic_value() {
+        let put = binomial_american(
+            &ListingType::PUT,
+            80.0,
+            100.0,
+            0.0,
+            &textbook_params(),
+            DEFAULT_BINOMIAL_STEPS,
+        );
+        assert_eq!(put.valuation.price, 20.0);
+        assert_eq!(put.valuation.delta, -1.0);
+        assert!(put.exercise_now);
+        assert!(put.exercise_boundary.is_empty());
+
+        let call = binomial_american(
+            &ListingType::CALL,
+            80.0,
+            100.0,
+            0.5,
+            &PricingParams::new(0.0, 0.05),
+            DEFAULT_BINOMIAL_STEPS,
+        );
+        assert_eq!(call.valuation.price, 0.0);
+        assert!(!call.exercise_now);
+    }
+
+    #[test]
+    fn test_listing_valued_per_contract() {
+        set_eth_usdc_rate();
+        let now = Utc::now();
+        let option = ListingOption::new(
+            1,
+            Asset::ETH,
+            Asset::USDC,
+            ListingType::PUT,
+            Amount::from_int(200),
+            Amount::from_int(1),
+            Amount::from_int(1),
+            now + Duration::days(365),
+            create_test_address("1"),
+            1,
+            Amount::from_int(2),
+            OptionState::Purchased,
+        );
+
+        // A contract covers 2 ETH, struck so deep that exercising today is best
+        let valuation =
+            value_listing_american(&option, &textbook_params(), DEFAULT_BINOMIAL_STEPS, now)
+                .unwrap();
+        assert!(valuation.exercise_now);
+        assert_near(valuation.valuation.price, 200.0, 1e-6);
+        assert_near(valuation.valuation.delta, -2.0, 1e-6);
+        assert_near(valuation.exercise_boundary[0].unwrap(), 100.0, 1e-9);
+    }
+}
@@ -0,0 +1,380 @@
+use chrono::{DateTime, Duration, Utc};
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ListingOption, ListingType, OptionState,
+    SettlementType, SimulatedClock, User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    // A pair no other test reads from the shared rate provider, always set to 100
+    fn set_eth_usdc_rate() {
+        get_rate_provider()
+            .set_rate(
+                Asset::ETH,
+                Asset::USDC,
+                Amount::from_int(100),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+    }
+
+    // 2 cash-settled contracts of 1 unit each, expiring in 30 days
+    fn create_test_option(
+        grantor_address: Address,
+        base_asset: Asset,
+        listing_type: ListingType,
+        strike_price: i64,
+        expiration_time: DateTime<Utc>,
+    ) -> ListingOption {
+        ListingOption::new(
+            0,
+            base_asset,
+            Asset::USDC,
+            listing_type,
+            Amount::from_int(strike_price),
+            Amount::from_f64(0.05),
+            Amount::from_f64(0.04),
+            expiration_time,
+            grantor_address,
+            2,
+            Amount::from_int(1),
+            OptionState::Listed,
+        )
+        .with_settlement_type(SettlementType::Cash)
+    }
+
+    // Returns (market, clock, seller, buyer), the buyer only holds enough USDC for premiums and
+    // the spot desk holds 1000 USDC
+    fn setup_market() -> (Exchange, Arc<SimulatedClock>, Address, Address) {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_unseeded_market();
+
+        // The spot desk buys the base collateral of cash-settled calls for their payout
+        let desk_addr = market.escrow_user.address.clone();
+        market
+            .users
+            .get_mut(&desk_addr)
+            .unwrap()
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+
+        (market, clock, seller_addr, buyer_addr)
+    }
+
+    // As `setup_market`, with the spot desk as empty as on a new exchange
+    fn setup_unseeded_market() -> (Exchange, Arc<SimulatedClock>, Address, Address) {
+        set_eth_usdc_rate();
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller.add_asset(&Asset::ETH, Amount::from_int(10)).unwrap();
+        seller.add_asset(&Asset::SOL, Amount::from_int(10)).unwrap();
+        seller
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDC, Amount::from_int(100))
+            .unwrap();
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        (market, clock, seller_addr, buyer_addr)
+    }
+
+    // Lists the option and sells both contracts to the buyer
+    fn list_and_buy(
+        market: &mut Exchange,
+        seller_addr: &Address,
+        buyer_addr: &Address,
+        option: ListingOption,
+    ) -> u32 {
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market
+            .purchase_contracts(listing_id, 2, buyer_addr.clone())
+            .unwrap();
+        listing_id
+    }
+
+    fn balance(market: &Exchange, address: &Address, asset: &Asset) -> Amount {
+        market.users[address].get_balance(asset)
+    }
+
+    #[test]
+    fn test_cash_settled_put_pays_intrinsic_value() {
+        let (mut market, _, seller_addr, buyer_addr) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            120,
+            expiration_time,
+        );
+        let listing_id = list_and_buy(&mut market, &seller_addr, &buyer_addr, option);
+        let buyer_usdc = balance(&market, &buyer_addr, &Asset::USDC);
+        let seller_usdc = balance(&market, &seller_addr, &Asset::USDC);
+
+        // Struck at 120 with ETH at 100, each contract pays 20 of its 120 USDC collateral
+        market
+            .exercise_contracts(listing_id, 1, buyer_addr.clone())
+            .unwrap();
+        assert_eq!(
+            balance(&market, &buyer_addr, &Asset::USDC),
+            buyer_usdc.try_add(Amount::from_int(20)).unwrap()
+        );
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::USDC),
+            seller_usdc.try_add(Amount::from_int(100)).unwrap()
+        );
+        // Nothing is delivered either way
+        assert_eq!(balance(&market, &buyer_addr, &Asset::ETH), Amount::ZERO);
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::ETH),
+            Amount::from_int(10)
+        );
+        assert_eq!(
+            market
+                .get_position(listing_id, &buyer_addr)
+                .unwrap()
+                .contracts,
+            1
+        );
+    }
+
+    #[test]
+    fn test_cash_settled_call_pays_in_quote() {
+        let (mut market, _, seller_addr, buyer_addr) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::CALL,
+            80,
+            expiration_time,
+        );
+        let listing_id = list_and_buy(&mut market, &seller_addr, &buyer_addr, option);
+        let buyer_usdc = balance(&market, &buyer_addr, &Asset::USDC);
+        let desk_addr = market.escrow_user.address.clone();
+
+        // 20 USDC of intrinsic value per contract, paid for by selling 0.2 ETH of the
+        // collateral to the desk. The buyer pays no strike.
+        market
+            .exercise_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        assert_eq!(
+            balance(&market, &buyer_addr, &Asset::USDC),
+            buyer_usdc.try_add(Amount::from_int(40)).unwrap()
+        );
+        assert_eq!(balance(&market, &buyer_addr, &Asset::ETH), Amount::ZERO);
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::ETH),
+            Amount::from_f64(9.6)
+        );
+        assert_eq!(
+            balance(&market, &desk_addr, &Asset::ETH),
+            Amount::from_f64(0.4)
+        );
+        assert_eq!(
+            balance(&market, &desk_addr, &Asset::USDC),
+            Amount::from_int(960)
+        );
+        assert_eq!(market.escrow_user.get_balance(&Asset::ETH), Amount::ZERO);
+        assert_eq!(market.listings[&listing_id].state, OptionState::Exercised);
+    }
+
+    #[test]
+    fn test_cash_settled_call_without_a_funded_desk() {
+        let (mut market, _, seller_addr, buyer_addr) = setup_unseeded_market();
+        let expiration_time = market.now() + Duration::days(30);
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::CALL,
+            80,
+            expiration_time,
+        );
+        let listing_id = list_and_buy(&mut market, &seller_addr, &buyer_addr, option);
+        let buyer_usdc = balance(&market, &buyer_addr, &Asset::USDC);
+
+        // Nobody can buy the collateral, so the buyer takes the 0.4 ETH worth the 40 USDC
+        market
+            .exercise_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        assert_eq!(balance(&market, &buyer_addr, &Asset::USDC), buyer_usdc);
+        assert_eq!(
+            balance(&market, &buyer_addr, &Asset::ETH),
+            Amount::from_f64(0.4)
+        );
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::ETH),
+            Amount::from_f64(9.6)
+        );
+        assert_eq!(market.escrow_user.get_balance(&Asset::ETH), Amount::ZERO);
+        assert_eq!(market.listings[&listing_id].state, OptionState::Exercised);
+    }
+
+    #[test]
+    fn test_out_of_the_money_returns_all_collateral() {
+        let (mut market, _, seller_addr, buyer_addr) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            90,
+            expiration_time,
+        );
+        let listing_id = list_and_buy(&mut market, &seller_addr, &buyer_addr, option);
+        let buyer_usdc = balance(&market, &buyer_addr, &Asset::USDC);
+        let seller_usdc = balance(&market, &seller_addr, &Asset::USDC);
+
+        market
+            .exercise_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        assert_eq!(balance(&market, &buyer_addr, &Asset::USDC), buyer_usdc);
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::USDC),
+            seller_usdc.try_add(Amount::from_int(180)).unwrap()
+        );
+        assert_eq!(market.listings[&listing_id].state, OptionState::Exercised);
+    }
+
+    #[test]
+    fn test_cash_settlement_needs_a_fresh_rate() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+
+        // Nobody quotes SOL/USDC
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::SOL,
+            ListingType::CALL,
+            80,
+            expiration_time,
+        );
+        let listing_id = list_and_buy(&mut market, &seller_addr, &buyer_addr, option);
+        let result = market.exercise_option(listing_id, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::RateMissing {
+                base: Asset::SOL,
+                quote: Asset::USDC,
+            }
+        );
+
+        let option = create_test_option(
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            120,
+            expiration_time,
+        );
+        let listing_id = list_and_buy(&mut market, &seller_addr, &buyer_addr, option);
+        market
+            .set_max_rate_age(
+                Some(Duration::hours(1)),
+                market.market_admin_address.clone(),
+            )
+            .unwrap();
+        clock.advance(Duration::days(1));
+        let result = market.exercise_option(listing_id, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::RateStale {
+                base: Asset::ETH,
+                quote: Asset::USDC,
+            }
+        );
+        assert_eq!(
+            market
+                .get_position(listing_id, &buyer_addr)
+                .unwrap()
+                .contracts,
+            2
+        );
+    }
+
+    #[test]
+    fn test_settlement_type_splits_series() {
+        let expiration_time = Utc::now() + Duration::days(30);
+        let cash = create_test_option(
+            create_test_address("1"),
+            Asset::ETH,
+            ListingType::PUT,
+            120,
+            expiration_time,
+        );
+        let physical = cash.clone().with_settlement_type(SettlementType::Physical);
+
+        assert_ne!(cash.series(), physical.series());
+        assert!(cash.series().to_string().ends_with("American cash"));
+        let default = ListingOption::new(
+            0,
+            Asset::ETH,
+            Asset::USDC,
+            ListingType::PUT,
+            Amount::from_int(120),
+            Amount::from_f64(0.05),
+            Amount::from_f64(0.04),
+            expiration_time,
+            create_test_address("1"),
+            1,
+            Amount::from_int(1),
+            OptionState::Listed,
+        );
+        assert_eq!(default.settlement_type, SettlementType::Physical);
+    }
+
+    #[test]
+    fn test_cash_settlement_amount_rounding() {
+        let option = create_test_option(
+            create_test_address("1"),
+            Asset::BTC,
+            ListingType::CALL,
+            100,
+            Utc::now() + Duration::days(30),
+        );
+
+        // A call sells two thirds of a BTC for its 200 USDC, rounded up to BTC's 8 decimals
+        assert_eq!(
+            option
+                .get_cash_settlement_amount(Amount::from_int(300))
+                .unwrap(),
+            Amount::from_f64(0.66666667)
+        );
+        assert_eq!(
+            option
+                .get_cash_settlement_amount(Amount::from_int(100))
+                .unwrap(),
+            Amount::ZERO
+        );
+
+        // A put pays out of its quote collateral, rounded down like the payout. Half a unit
+        // struck at 100.
+        let put = ListingOption {
+            listing_type: ListingType::PUT,
+            exercise_amount: Amount::from_f64(0.5),
+            ..option
+        };
+        assert_eq!(
+            put.get_cash_settlement_amount(Amount::from_f64(33.3333333))
+                .unwrap(),
+            Amount::from_f64(33.333333)
+        );
+    }
+}
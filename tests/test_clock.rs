@@ -0,0 +1,160 @@
+use chrono::{Duration, Utc};
+use options_trading::exchange::SpotAction;
+use options_trading::exchange_rate_provider::get_readonly_rate_provider;
+use options_trading::{
+    Address, Amount, Asset, Clock, Exchange, ExchangeError, ExerciseStyle, ListingOption,
+    ListingType, OptionState, SettlementType, SimulatedClock, SystemClock, User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn setup_market_with_clock() -> (Exchange, Arc<SimulatedClock>, Address, Address) {
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::BTC, Amount::from_f64(10.0))
+            .unwrap();
+
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDT, Amount::from_f64(200000.0))
+            .unwrap();
+
+        market.users.insert(seller_addr.clone(), seller);
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        (market, clock, seller_addr, buyer_addr)
+    }
+
+    #[test]
+    fn test_simulated_clock_only_moves_when_advanced() {
+        let start = Utc::now();
+        let clock = SimulatedClock::new(start);
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::hours(30));
+        assert_eq!(clock.now(), start + Duration::hours(30));
+
+        clock.set_time(start);
+        assert_eq!(clock.now(), start);
+    }
+
+    #[test]
+    fn test_system_clock_follows_wall_clock() {
+        let before = Utc::now();
+        let now = SystemClock.now();
+        assert!(now >= before);
+        assert!(now <= Utc::now());
+    }
+
+    #[test]
+    fn test_exercise_follows_exchange_clock() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market_with_clock();
+
+        let option = ListingOption {
+            listing_id: 0,
+            base_asset: Asset::BTC,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::CALL,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
+            expiration_time: clock.now() + Duration::days(30),
+            grantor_address: seller_addr.clone(),
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        };
+        let listing_id = market.list_option(seller_addr, option).unwrap();
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+
+        // The wall clock is still far from expiry, the exchange clock is not
+        clock.advance(Duration::days(31));
+        let result = market.exercise_option(listing_id, buyer_addr);
+        assert_eq!(result.unwrap_err(), ExchangeError::Expired(listing_id));
+    }
+
+    #[test]
+    fn test_stale_rate_rejected() {
+        let (mut market, clock, _, buyer_addr) = setup_market_with_clock();
+        let admin_addr = market.market_admin_address.clone();
+        let escrow_addr = market.escrow_user.address.clone();
+        market
+            .users
+            .get_mut(&escrow_addr)
+            .unwrap()
+            .add_asset(&Asset::BTC, Amount::from_f64(10.0))
+            .unwrap();
+
+        market
+            .set_max_rate_age(Some(Duration::hours(1)), admin_addr)
+            .unwrap();
+
+        // Fresh rate goes through
+        let result = market.spot_trade_current_price(
+            &Asset::BTC,
+            &Asset::USDT,
+            Amount::from_f64(0.1),
+            &SpotAction::BUY,
+            buyer_addr.clone(),
+        );
+        assert!(result.is_ok());
+
+        // Nobody updated the rate for two simulated hours
+        clock.advance(Duration::hours(2));
+        let result = market.spot_trade_current_price(
+            &Asset::BTC,
+            &Asset::USDT,
+            Amount::from_f64(0.1),
+            &SpotAction::BUY,
+            buyer_addr,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::RateStale {
+                base: Asset::BTC,
+                quote: Asset::USDT
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_max_rate_age_unauthorized() {
+        let (mut market, _, seller_addr, _) = setup_market_with_clock();
+
+        let result = market.set_max_rate_age(Some(Duration::hours(1)), seller_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not the market admin".into())
+        );
+        assert!(market.max_rate_age.is_none());
+    }
+
+    #[test]
+    fn test_rates_are_timestamped() {
+        let provider = get_readonly_rate_provider();
+        let updated_at = provider.get_rate_updated_at(&Asset::BTC, &Asset::USDT);
+
+        assert!(updated_at.is_some());
+        assert!(updated_at.unwrap() <= Utc::now());
+        assert_eq!(provider.get_rate_updated_at(&Asset::BTC, &Asset::BTC), None);
+    }
+}
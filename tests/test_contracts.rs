@@ -0,0 +1,394 @@
+use chrono::{Duration, Utc};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ExerciseStyle, ListingOption, ListingType,
+    OptionState, SettlementType, SimulatedClock, User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    // 5 contracts of 1 BTC each, premium 500 USDT per contract
+    fn create_test_option(grantor_address: Address) -> ListingOption {
+        ListingOption {
+            listing_id: 0,
+            base_asset: Asset::BTC,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::CALL,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(5.0),
+            bid_price: Amount::from_f64(4.9),
+            expiration_time: Utc::now() + Duration::days(30),
+            grantor_address,
+            contract_count: 5,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        }
+    }
+
+    // Returns (market, clock, seller, first buyer, second buyer, listing id)
+    fn setup_listed_contracts() -> (
+        Exchange,
+        Arc<SimulatedClock>,
+        Address,
+        Address,
+        Address,
+        u32,
+    ) {
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+        let seller_addr = create_test_address("1");
+        let alice_addr = create_test_address("2");
+        let bob_addr = create_test_address("3");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::BTC, Amount::from_f64(10.0))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        for address in [&alice_addr, &bob_addr] {
+            let mut buyer = User::new(address.clone());
+            buyer
+                .add_asset(&Asset::USDT, Amount::from_f64(300000.0))
+                .unwrap();
+            market.users.insert(address.clone(), buyer);
+        }
+
+        let option = create_test_option(seller_addr.clone());
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+
+        (market, clock, seller_addr, alice_addr, bob_addr, listing_id)
+    }
+
+    #[test]
+    fn test_listing_escrows_collateral_for_every_contract() {
+        let (market, _, seller_addr, _, _, _) = setup_listed_contracts();
+
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(5.0));
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::BTC),
+            Amount::from_f64(5.0)
+        );
+    }
+
+    #[test]
+    fn test_partial_purchases_track_positions() {
+        let (mut market, _, seller_addr, alice_addr, bob_addr, listing_id) =
+            setup_listed_contracts();
+
+        market
+            .purchase_contracts(listing_id, 2, alice_addr.clone())
+            .unwrap();
+
+        // Premium 2 * 500, fee 1 (1000 * 0.001) on each side
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(298999.0));
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::USDT), Amount::from_f64(999.0));
+
+        // The rest stays on sale
+        let listing = market.listings.get(&listing_id).unwrap();
+        assert_eq!(listing.contract_count, 3);
+        assert_eq!(listing.state, OptionState::Purchased);
+
+        market
+            .purchase_contracts(listing_id, 3, bob_addr.clone())
+            .unwrap();
+        assert_eq!(
+            market
+                .get_position(listing_id, &alice_addr)
+                .unwrap()
+                .contracts,
+            2
+        );
+        assert_eq!(
+            market
+                .get_position(listing_id, &bob_addr)
+                .unwrap()
+                .contracts,
+            3
+        );
+        assert_eq!(market.get_open_contracts(listing_id), 5);
+
+        // Sold out
+        let result = market.purchase_option(listing_id, alice_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidState {
+                listing_id,
+                state: OptionState::Purchased
+            }
+        );
+    }
+
+    #[test]
+    fn test_purchase_more_than_available_fails() {
+        let (mut market, _, _, alice_addr, _, listing_id) = setup_listed_contracts();
+
+        let result = market.purchase_contracts(listing_id, 6, alice_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(format!(
+                "Only 5 contracts of listing #{} are left",
+                listing_id
+            ))
+        );
+
+        let result = market.purchase_contracts(listing_id, 0, alice_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput("Contract count must be at least 1".into())
+        );
+
+        // Nothing moved
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(300000.0));
+        assert!(market.get_position(listing_id, &alice_addr).is_none());
+    }
+
+    #[test]
+    fn test_unlist_refunds_only_unsold_contracts() {
+        let (mut market, _, seller_addr, alice_addr, _, listing_id) = setup_listed_contracts();
+
+        market
+            .purchase_contracts(listing_id, 2, alice_addr.clone())
+            .unwrap();
+        market
+            .unlist_option(listing_id, seller_addr.clone())
+            .unwrap();
+
+        // 3 of the 5 BTC come back, the sold contracts stay covered
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(8.0));
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::BTC),
+            Amount::from_f64(2.0)
+        );
+
+        let listing = market.listings.get(&listing_id).unwrap();
+        assert_eq!(listing.contract_count, 0);
+        assert_eq!(listing.state, OptionState::Purchased);
+        assert_eq!(
+            market
+                .get_position(listing_id, &alice_addr)
+                .unwrap()
+                .contracts,
+            2
+        );
+
+        // Nothing left to unlist
+        let result = market.unlist_option(listing_id, seller_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidTransition {
+                listing_id,
+                from: OptionState::Purchased,
+                to: OptionState::Unlisted,
+            }
+        );
+    }
+
+    #[test]
+    fn test_each_holder_exercises_own_position() {
+        let (mut market, _, seller_addr, alice_addr, bob_addr, listing_id) =
+            setup_listed_contracts();
+
+        market
+            .purchase_contracts(listing_id, 2, alice_addr.clone())
+            .unwrap();
+        market
+            .purchase_contracts(listing_id, 3, bob_addr.clone())
+            .unwrap();
+
+        market
+            .exercise_option(listing_id, alice_addr.clone())
+            .unwrap();
+
+        // Alice pays 2 * 50000 for 2 BTC, Bob's contracts are still open
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::BTC), Amount::from_f64(2.0));
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(198999.0));
+        assert_eq!(
+            market.listings.get(&listing_id).unwrap().state,
+            OptionState::Purchased
+        );
+
+        market
+            .exercise_option(listing_id, bob_addr.clone())
+            .unwrap();
+
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::USDT), Amount::from_f64(252497.5)); // 2497.5 premiums after fees + 250k strike
+        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), Amount::ZERO);
+        assert_eq!(
+            market.listings.get(&listing_id).unwrap().state,
+            OptionState::Exercised
+        );
+    }
+
+    #[test]
+    fn test_settlement_releases_unsold_and_unexercised_contracts() {
+        let (mut market, clock, seller_addr, alice_addr, _, listing_id) = setup_listed_contracts();
+
+        market
+            .purchase_contracts(listing_id, 2, alice_addr.clone())
+            .unwrap();
+        // BTC trades above the strike, keep the exchange from exercising Alice's contracts
+        market
+            .set_auto_exercise(listing_id, false, alice_addr.clone())
+            .unwrap();
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        assert_eq!(report.settled.len(), 1);
+        assert_eq!(report.settled[0].released_amount, Amount::from_f64(5.0));
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(10.0));
+        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_partial_exercise_is_pro_rata() {
+        let (mut market, _, seller_addr, alice_addr, _, listing_id) = setup_listed_contracts();
+
+        market
+            .purchase_contracts(listing_id, 5, alice_addr.clone())
+            .unwrap();
+        market
+            .exercise_contracts(listing_id, 2, alice_addr.clone())
+            .unwrap();
+
+        // 2 BTC out of escrow for 2 * 50000 USDT to the grantor
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::BTC), Amount::from_f64(2.0));
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(197497.5));
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::USDT), Amount::from_f64(102497.5));
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::BTC),
+            Amount::from_f64(3.0)
+        );
+
+        let position = market.get_position(listing_id, &alice_addr).unwrap();
+        assert_eq!(position.contracts, 3);
+        assert_eq!(position.exercised_contracts, 2);
+        assert_eq!(
+            market.listings.get(&listing_id).unwrap().state,
+            OptionState::Purchased
+        );
+
+        // The remainder is still exercisable
+        market
+            .exercise_contracts(listing_id, 3, alice_addr.clone())
+            .unwrap();
+        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), Amount::ZERO);
+        assert_eq!(
+            market.listings.get(&listing_id).unwrap().state,
+            OptionState::Exercised
+        );
+    }
+
+    #[test]
+    fn test_exercise_more_than_held_fails() {
+        let (mut market, _, _, alice_addr, _, listing_id) = setup_listed_contracts();
+
+        market
+            .purchase_contracts(listing_id, 2, alice_addr.clone())
+            .unwrap();
+
+        let result = market.exercise_contracts(listing_id, 3, alice_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(format!(
+                "Only 2 open contracts left to exercise on listing #{}",
+                listing_id
+            ))
+        );
+        assert_eq!(
+            market
+                .get_position(listing_id, &alice_addr)
+                .unwrap()
+                .contracts,
+            2
+        );
+    }
+
+    #[test]
+    fn test_partial_exercise_when_short_of_quote() {
+        let (mut market, _, _, alice_addr, _, listing_id) = setup_listed_contracts();
+
+        market
+            .purchase_contracts(listing_id, 5, alice_addr.clone())
+            .unwrap();
+
+        market
+            .users
+            .get_mut(&alice_addr)
+            .unwrap()
+            .deduct_asset(&Asset::USDT, Amount::from_f64(100000.0))
+            .unwrap();
+
+        // 197497.5 USDT left can't pay 5 * 50000, nothing moves
+        let result = market.exercise_option(listing_id, alice_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::USDT,
+                ..
+            }
+        ));
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::BTC),
+            Amount::from_f64(5.0)
+        );
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::BTC), Amount::ZERO);
+
+        market
+            .exercise_contracts(listing_id, 3, alice_addr.clone())
+            .unwrap();
+        assert_eq!(
+            market
+                .get_position(listing_id, &alice_addr)
+                .unwrap()
+                .contracts,
+            2
+        );
+    }
+
+    #[test]
+    fn test_unexercised_remainder_expires() {
+        let (mut market, clock, seller_addr, alice_addr, _, listing_id) = setup_listed_contracts();
+
+        market
+            .purchase_contracts(listing_id, 5, alice_addr.clone())
+            .unwrap();
+        market
+            .exercise_contracts(listing_id, 2, alice_addr.clone())
+            .unwrap();
+        market
+            .set_auto_exercise(listing_id, false, alice_addr.clone())
+            .unwrap();
+
+        clock.advance(Duration::days(31));
+        let result = market.exercise_option(listing_id, alice_addr.clone());
+        assert_eq!(result.unwrap_err(), ExchangeError::Expired(listing_id));
+
+        // Only the collateral of the 3 open contracts goes back
+        let report = market.settle_expired().unwrap();
+        assert_eq!(report.settled[0].released_amount, Amount::from_f64(3.0));
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(8.0));
+    }
+}
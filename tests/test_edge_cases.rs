@@ -1,5 +1,6 @@
-use options_trading::{Exchange, User, ListingOption, Asset, ListingType, Address};
+use options_trading::{Exchange, User, ListingOption, Asset, ListingType, OptionState, Address, Amount, ExchangeError, ExerciseStyle, SettlementType, SimulatedClock};
 use chrono::{Utc, Duration};
+use std::sync::Arc;
 
 #[cfg(test)]
 mod tests {
@@ -18,12 +19,12 @@ mod tests {
         let buyer = create_test_address("2");
 
         let mut s = User::new(seller.clone());
-        s.add_asset(&Asset::BTC, 2.0).unwrap();
-        s.add_asset(&Asset::USDT, 0.0).unwrap();
+        s.add_asset(&Asset::BTC, Amount::from_f64(2.0)).unwrap();
+        s.add_asset(&Asset::USDT, Amount::from_f64(0.0)).unwrap();
 
         let mut b = User::new(buyer.clone());
         // Buyer has just enough to purchase but not enough to exercise
-        b.add_asset(&Asset::USDT, 100000.0).unwrap();
+        b.add_asset(&Asset::USDT, Amount::from_f64(100000.0)).unwrap();
         market.users.insert(seller.clone(), s);
         market.users.insert(buyer.clone(), b);
 
@@ -32,16 +33,16 @@ mod tests {
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 500.0,
-            bid_price: 490.0,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
             expiration_time: Utc::now() + Duration::days(30),
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
             grantor_address: seller.clone(),
-            beneficiary_address: None,
+            contract_count: 1,
         };
 
         let lid = market.list_option(seller.clone(), option).unwrap();
@@ -50,7 +51,13 @@ mod tests {
         // After purchase buyer will have ~49,950 USDT, which is less than strike 50,000 => exercise fails
         let res = market.exercise_option(lid, buyer.clone());
         assert!(res.is_err());
-        assert_eq!(res.unwrap_err(), "Insufficient USDT balance");
+        assert!(matches!(
+            res.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::USDT,
+                ..
+            }
+        ));
     }
 
     #[test]
@@ -61,12 +68,12 @@ mod tests {
 
         let mut s = User::new(seller.clone());
         // Seller must escrow quote (USDT)
-        s.add_asset(&Asset::USDT, 5000.0).unwrap();
+        s.add_asset(&Asset::USDT, Amount::from_f64(5000.0)).unwrap();
 
         let mut b = User::new(buyer.clone());
         // Buyer has no ETH to deliver when exercising
-        b.add_asset(&Asset::USDT, 100000.0).unwrap();
-        b.add_asset(&Asset::ETH, 0.0).unwrap();
+        b.add_asset(&Asset::USDT, Amount::from_f64(100000.0)).unwrap();
+        b.add_asset(&Asset::ETH, Amount::from_f64(0.0)).unwrap();
 
         market.users.insert(seller.clone(), s);
         market.users.insert(buyer.clone(), b);
@@ -76,16 +83,16 @@ mod tests {
             base_asset: Asset::ETH,
             quote_asset: Asset::USDT,
             listing_type: ListingType::PUT,
-            strike_price: 3000.0,
-            ask_price: 20.0,
-            bid_price: 18.0,
+            strike_price: Amount::from_f64(3000.0),
+            ask_price: Amount::from_f64(20.0),
+            bid_price: Amount::from_f64(18.0),
             expiration_time: Utc::now() + Duration::days(30),
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
             grantor_address: seller.clone(),
-            beneficiary_address: None,
+            contract_count: 1,
         };
 
         let lid = market.list_option(seller.clone(), option).unwrap();
@@ -93,49 +100,57 @@ mod tests {
 
         let res = market.exercise_option(lid, buyer.clone());
         assert!(res.is_err());
-        assert_eq!(res.unwrap_err(), "Insufficient ETH balance");
+        assert!(matches!(
+            res.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::ETH,
+                ..
+            }
+        ));
     }
 
     #[test]
     fn test_exercise_after_expiration_fails() {
-        let mut market = Exchange::new();
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
         let seller = create_test_address("5");
         let buyer = create_test_address("6");
 
         let mut s = User::new(seller.clone());
-        s.add_asset(&Asset::BTC, 2.0).unwrap();
-        s.add_asset(&Asset::USDT, 0.0).unwrap();
+        s.add_asset(&Asset::BTC, Amount::from_f64(2.0)).unwrap();
+        s.add_asset(&Asset::USDT, Amount::from_f64(0.0)).unwrap();
 
         let mut b = User::new(buyer.clone());
-        b.add_asset(&Asset::USDT, 200000.0).unwrap();
+        b.add_asset(&Asset::USDT, Amount::from_f64(200000.0)).unwrap();
 
         market.users.insert(seller.clone(), s);
         market.users.insert(buyer.clone(), b);
 
-        // Create option already expired
+        // Create option expiring tomorrow
         let option = ListingOption {
             listing_id: 0,
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 500.0,
-            bid_price: 490.0,
-            expiration_time: Utc::now() - Duration::days(1),
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
+            expiration_time: market.now() + Duration::days(1),
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
             grantor_address: seller.clone(),
-            beneficiary_address: None,
+            contract_count: 1,
         };
 
         let lid = market.list_option(seller.clone(), option).unwrap();
-        // Purchase still allowed; exercise should fail due to expiration
         market.purchase_option(lid, buyer.clone()).unwrap();
+        // Exercise should fail once the option has expired
+        clock.advance(Duration::days(2));
         let res = market.exercise_option(lid, buyer.clone());
         assert!(res.is_err());
-        assert_eq!(res.unwrap_err(), "Option has expired!");
+        assert_eq!(res.unwrap_err(), ExchangeError::Expired(lid));
     }
 
     #[test]
@@ -145,11 +160,11 @@ mod tests {
         let buyer = create_test_address("8");
 
         let mut s = User::new(seller.clone());
-        s.add_asset(&Asset::BTC, 2.0).unwrap();
-        s.add_asset(&Asset::USDT, 0.0).unwrap();
+        s.add_asset(&Asset::BTC, Amount::from_f64(2.0)).unwrap();
+        s.add_asset(&Asset::USDT, Amount::from_f64(0.0)).unwrap();
 
         let mut b = User::new(buyer.clone());
-        b.add_asset(&Asset::USDT, 200000.0).unwrap();
+        b.add_asset(&Asset::USDT, Amount::from_f64(200000.0)).unwrap();
 
         market.users.insert(seller.clone(), s);
         market.users.insert(buyer.clone(), b);
@@ -159,16 +174,16 @@ mod tests {
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 500.0,
-            bid_price: 490.0,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
             expiration_time: Utc::now() + Duration::days(30),
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
             grantor_address: seller.clone(),
-            beneficiary_address: None,
+            contract_count: 1,
         };
 
         let lid = market.list_option(seller.clone(), option).unwrap();
@@ -177,6 +192,6 @@ mod tests {
         // Seller attempting to exercise should fail
         let res = market.exercise_option(lid, seller.clone());
         assert!(res.is_err());
-        assert_eq!(res.unwrap_err(), "Caller is not beneficiary of option!");
+        assert_eq!(res.unwrap_err(), ExchangeError::Unauthorized("caller is not beneficiary of option".into()));
     }
 }
@@ -0,0 +1,106 @@
+use options_trading::exchange_rate_provider::get_rate_provider;
+use options_trading::rbac::UnauthorizedError;
+use options_trading::{Address, AddressError, Amount, AmountError, Asset, Exchange, ExchangeError};
+use std::error::Error;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    #[test]
+    fn test_display_messages() {
+        let error = ExchangeError::InsufficientBalance {
+            asset: Asset::USDT,
+            required: Amount::from_f64(50050.0),
+            available: Amount::from_f64(1000.0),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Insufficient USDT balance: 50050 required, 1000 available"
+        );
+
+        assert_eq!(
+            ExchangeError::ListingNotFound(7).to_string(),
+            "Listing #7 not found"
+        );
+        assert_eq!(
+            ExchangeError::Expired(3).to_string(),
+            "Option #3 has expired"
+        );
+        assert_eq!(
+            ExchangeError::RateMissing {
+                base: Asset::SOL,
+                quote: Asset::VNDT
+            }
+            .to_string(),
+            "Exchange rate for pair SOL/VNDT not found"
+        );
+        assert_eq!(
+            ExchangeError::WriterShortfall {
+                listing_id: 4,
+                writer: create_test_address("1"),
+                asset: Asset::USDC,
+                shortfall: Amount::from_int(140),
+                covered: Amount::from_int(20),
+            }
+            .to_string(),
+            format!(
+                "Writer {} of option #4 is 140 USDC short, the insurance fund covers 20",
+                create_test_address("1")
+            )
+        );
+    }
+
+    #[test]
+    fn test_wrapped_errors_convert_and_expose_source() {
+        let error: ExchangeError = UnauthorizedError::AddressNotAuthorized.into();
+        assert_eq!(
+            error,
+            ExchangeError::Role(UnauthorizedError::AddressNotAuthorized)
+        );
+        assert!(error.source().is_some());
+
+        let error: ExchangeError = AddressError::InvalidLength.into();
+        assert_eq!(
+            error.to_string(),
+            "Invalid address: address must be 42 characters long"
+        );
+        assert!(error.source().is_some());
+
+        let error: ExchangeError = AmountError::Overflow.into();
+        assert_eq!(error, ExchangeError::Amount(AmountError::Overflow));
+        assert!(ExchangeError::ListingNotFound(1).source().is_none());
+    }
+
+    #[test]
+    fn test_auth_failure_distinguishable_from_missing_funds() {
+        let mut market = Exchange::new();
+        let outsider = create_test_address("9");
+
+        let result = market.set_beneficiary_fee_bps(20, outsider.clone());
+        assert!(matches!(result, Err(ExchangeError::Unauthorized(_))));
+
+        let result = market.purchase_option(1, outsider);
+        assert_eq!(result.unwrap_err(), ExchangeError::ListingNotFound(1));
+    }
+
+    #[test]
+    fn test_set_rate_returns_role_error() {
+        let result = get_rate_provider().set_rate(
+            Asset::BTC,
+            Asset::USDT,
+            Amount::from_int(1),
+            create_test_address("9"),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Role(UnauthorizedError::AddressNotAuthorized)
+        );
+    }
+}
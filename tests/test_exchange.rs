@@ -1,5 +1,5 @@
 use chrono::{Duration, Utc};
-use options_trading::{Address, Asset, Exchange, ListingOption, ListingType, User};
+use options_trading::{Address, Amount, Asset, Exchange, ExchangeError, ListingOption, ListingType, OptionState, User, ExerciseStyle, SettlementType};
 
 #[cfg(test)]
 mod tests {
@@ -17,16 +17,16 @@ mod tests {
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 500.0,
-            bid_price: 490.0,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
             expiration_time: Utc::now() + Duration::days(30),
             grantor_address,
-            beneficiary_address: None,
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
         }
     }
 
@@ -37,12 +37,12 @@ mod tests {
         let buyer_addr = create_test_address("2");
 
         let mut seller = User::new(seller_addr.clone());
-        seller.add_asset(&Asset::USDT, 20000000.0).unwrap(); // 20M USDT for collateral (enough for multiple options)
-        seller.add_asset(&Asset::BTC, 10.0).unwrap();
+        seller.add_asset(&Asset::USDT, Amount::from_f64(20000000.0)).unwrap(); // 20M USDT for collateral (enough for multiple options)
+        seller.add_asset(&Asset::BTC, Amount::from_f64(10.0)).unwrap();
 
         let mut buyer = User::new(buyer_addr.clone());
-        buyer.add_asset(&Asset::USDT, 100000.0).unwrap(); // 100k USDT for purchasing
-        buyer.add_asset(&Asset::ETH, 100.0).unwrap();
+        buyer.add_asset(&Asset::USDT, Amount::from_f64(100000.0)).unwrap(); // 100k USDT for purchasing
+        buyer.add_asset(&Asset::ETH, Amount::from_f64(100.0)).unwrap();
 
         market.users.insert(seller_addr.clone(), seller);
         market.users.insert(buyer_addr.clone(), buyer);
@@ -65,11 +65,11 @@ mod tests {
     #[test]
     fn test_get_fees() {
         let market = Exchange::new();
-        let premium = 1000.0;
+        let premium = Amount::from_f64(1000.0);
 
         // Default fees are 10 bps = 0.1%
-        assert_eq!(market.get_beneficiary_fee(premium), 1.0); // 1000 * 0.001
-        assert_eq!(market.get_grantor_fee(premium), 1.0);
+        assert_eq!(market.get_beneficiary_fee(premium), Ok(Amount::from_f64(1.0))); // 1000 * 0.001
+        assert_eq!(market.get_grantor_fee(premium), Ok(Amount::from_f64(1.0)));
     }
 
     #[test]
@@ -89,7 +89,7 @@ mod tests {
 
         let result = market.set_beneficiary_fee_bps(50, unauthorized_addr);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Only market admin only");
+        assert_eq!(result.unwrap_err(), ExchangeError::Unauthorized("caller is not the market admin".into()));
         assert_eq!(market.beneficiary_fee_bps, 10); // Should remain unchanged
     }
 
@@ -100,10 +100,7 @@ mod tests {
 
         let result = market.set_beneficiary_fee_bps(10001, admin_addr);
         assert!(result.is_err());
-        assert_eq!(
-            result.unwrap_err(),
-            "Invalid bps, must be between 0 - 10.000"
-        );
+        assert_eq!(result.unwrap_err(), ExchangeError::InvalidInput("Invalid bps, must be between 0 - 10.000".into()));
         assert_eq!(market.beneficiary_fee_bps, 10); // Should remain unchanged
     }
 
@@ -122,10 +119,10 @@ mod tests {
 
         // Check that collateral was deducted (1 BTC for CALL option)
         let seller = market.users.get(&seller_addr).unwrap();
-        assert_eq!(seller.get_balance(&Asset::BTC), 9.0); // 10 - 1 BTC collateral
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(9.0)); // 10 - 1 BTC collateral
 
         // Check escrow received collateral
-        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), 1.0);
+        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), Amount::from_f64(1.0));
     }
 
     #[test]
@@ -134,18 +131,21 @@ mod tests {
         let seller_addr = create_test_address("1");
 
         let mut seller = User::new(seller_addr.clone());
-        seller.add_asset(&Asset::BTC, 0.5).unwrap(); // Not enough BTC for CALL option collateral
-        seller.add_asset(&Asset::USDT, 1000000.0).unwrap(); // Plenty of USDT but not needed for CALL
+        seller.add_asset(&Asset::BTC, Amount::from_f64(0.5)).unwrap(); // Not enough BTC for CALL option collateral
+        seller.add_asset(&Asset::USDT, Amount::from_f64(1000000.0)).unwrap(); // Plenty of USDT but not needed for CALL
         market.users.insert(seller_addr.clone(), seller);
 
         let option = create_test_option(seller_addr.clone());
         let result = market.list_option(seller_addr, option);
 
         assert!(result.is_err());
-        assert_eq!(
+        assert!(matches!(
             result.unwrap_err(),
-            "Insufficient BTC asset balance to cover CALL option"
-        );
+            ExchangeError::InsufficientBalance {
+                asset: Asset::BTC,
+                ..
+            }
+        ));
     }
 
     #[test]
@@ -156,7 +156,10 @@ mod tests {
 
         let result = market.list_option(seller_addr, option);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "User not found");
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::UserNotFound(_)
+        ));
     }
 
     #[test]
@@ -174,8 +177,8 @@ mod tests {
 
         // Collateral should be returned (1 BTC for CALL option)
         let seller = market.users.get(&seller_addr).unwrap();
-        assert_eq!(seller.get_balance(&Asset::BTC), 10.0); // Back to original
-        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), 0.0);
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(10.0)); // Back to original
+        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), Amount::from_f64(0.0));
     }
 
     #[test]
@@ -187,10 +190,7 @@ mod tests {
 
         let result = market.unlist_option(listing_id, buyer_addr);
         assert!(result.is_err());
-        assert_eq!(
-            result.unwrap_err(),
-            "Only the seller can unlist this option"
-        );
+        assert_eq!(result.unwrap_err(), ExchangeError::Unauthorized("only the seller can unlist this option".into()));
     }
 
     #[test]
@@ -204,20 +204,22 @@ mod tests {
         assert!(result.is_ok());
 
         // Check option has beneficiary
-        let listing = market.listings.get(&listing_id).unwrap();
-        assert_eq!(listing.beneficiary_address, Some(buyer_addr.clone()));
+        let position = market.get_position(listing_id, &buyer_addr).unwrap();
+        assert_eq!(position.contracts, 1);
 
         // Check payment flows - premium is 50000 (500 * 100), fee is 50 (50000 * 0.001)
         let buyer = market.users.get(&buyer_addr).unwrap();
-        assert_eq!(buyer.get_balance(&Asset::USDT), 49950.0); // 100k - premium - fee
+        assert_eq!(buyer.get_balance(&Asset::USDT), Amount::from_f64(49950.0)); // 100k - premium - fee
 
         let seller = market.users.get(&seller_addr).unwrap();
-        assert_eq!(seller.get_balance(&Asset::USDT), 20049950.0); // 20M + premium - fee
-        assert_eq!(seller.get_balance(&Asset::BTC), 9.0); // 10 - 1 BTC collateral still in escrow
-
-        // Escrow should have BTC collateral and USDT fees
-        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), 1.0); // BTC collateral
-        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), 100.0); // 2 fees (50 each)
+        assert_eq!(seller.get_balance(&Asset::USDT), Amount::from_f64(20049950.0)); // 20M + premium - fee
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(9.0)); // 10 - 1 BTC collateral still in escrow
+
+        // Escrow should have BTC collateral, the USDT fees go to treasury and insurance fund
+        assert_eq!(market.escrow_user.get_balance(&Asset::BTC), Amount::from_f64(1.0)); // BTC collateral
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+        assert_eq!(market.treasury.get_balance(&Asset::USDT), Amount::from_f64(90.0)); // 2 fees (50 each) less 10%
+        assert_eq!(market.insurance_fund.get_balance(&Asset::USDT), Amount::from_f64(10.0));
     }
 
     #[test]
@@ -226,17 +228,20 @@ mod tests {
 
         // Reduce buyer's balance
         let buyer = market.users.get_mut(&buyer_addr).unwrap();
-        buyer.deduct_asset(&Asset::USDT, 99000.0).unwrap(); // Leave only 1000 USDT
+        buyer.deduct_asset(&Asset::USDT, Amount::from_f64(99000.0)).unwrap(); // Leave only 1000 USDT
 
         let option = create_test_option(seller_addr.clone());
         let listing_id = market.list_option(seller_addr, option).unwrap();
 
         let result = market.purchase_option(listing_id, buyer_addr);
         assert!(result.is_err());
-        assert_eq!(
+        assert!(matches!(
             result.unwrap_err(),
-            "Buyer doesn't have enough USDT balance to purchase option"
-        );
+            ExchangeError::InsufficientBalance {
+                asset: Asset::USDT,
+                ..
+            }
+        ));
     }
 
     #[test]
@@ -245,7 +250,10 @@ mod tests {
 
         let result = market.purchase_option(999, buyer_addr);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Listing not found");
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::ListingNotFound(_)
+        ));
     }
 
     #[test]
@@ -260,7 +268,11 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err(),
-            "Option has been acquired, cannot unlist"
+            ExchangeError::InvalidTransition {
+                listing_id,
+                from: OptionState::Purchased,
+                to: OptionState::Unlisted,
+            }
         );
     }
 
@@ -270,7 +282,7 @@ mod tests {
 
         let option1 = create_test_option(seller_addr.clone());
         let mut option2 = create_test_option(seller_addr.clone());
-        option2.strike_price = 60000.0;
+        option2.strike_price = Amount::from_f64(60000.0);
 
         let listing_id1 = market.list_option(seller_addr.clone(), option1).unwrap();
         let listing_id2 = market.list_option(seller_addr.clone(), option2).unwrap();
@@ -282,11 +294,11 @@ mod tests {
         // Different strike prices
         assert_eq!(
             market.listings.get(&listing_id1).unwrap().strike_price,
-            50000.0
+            Amount::from_f64(50000.0)
         );
         assert_eq!(
             market.listings.get(&listing_id2).unwrap().strike_price,
-            60000.0
+            Amount::from_f64(60000.0)
         );
     }
 }
@@ -1,4 +1,5 @@
-use options_trading::{Address, Asset};
+use options_trading::rbac::UnauthorizedError;
+use options_trading::{Address, Amount, Asset, ExchangeError};
 use options_trading::exchange_rate_provider::{get_rate_provider, get_readonly_rate_provider, default_exchange_rate_provider_admin_address};
 
 #[cfg(test)]
@@ -21,7 +22,7 @@ mod tests {
         
         // Should have some rate set for BTC/USDT
         assert!(rate.is_some());
-        assert!(rate.unwrap() > 0.0);
+        assert!(rate.unwrap() > Amount::ZERO);
     }
 
     #[test]
@@ -39,14 +40,14 @@ mod tests {
         
         {
             let mut provider = get_rate_provider();
-            let result = provider.set_rate(Asset::ETH, Asset::USDT, 3000.0, admin_addr);
+            let result = provider.set_rate(Asset::ETH, Asset::USDT, Amount::from_f64(3000.0), admin_addr);
             assert!(result.is_ok());
         }
 
         // Verify the rate was set
         let provider = get_readonly_rate_provider();
         let rate = provider.get_rate(&Asset::ETH, &Asset::USDT);
-        assert_eq!(rate, Some(3000.0));
+        assert_eq!(rate, Some(Amount::from_f64(3000.0)));
     }
 
     #[test]
@@ -54,10 +55,10 @@ mod tests {
         let unauthorized_addr = create_test_address("999");
         
         let mut provider = get_rate_provider();
-        let result = provider.set_rate(Asset::ETH, Asset::USDT, 3000.0, unauthorized_addr);
+        let result = provider.set_rate(Asset::ETH, Asset::USDT, Amount::from_f64(3000.0), unauthorized_addr);
         
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "caller not authorized to update exchange rate");
+        assert_eq!(result.unwrap_err(), ExchangeError::Role(UnauthorizedError::AddressNotAuthorized));
     }
 
     #[test]
@@ -68,16 +69,16 @@ mod tests {
             let mut provider = get_rate_provider();
             
             // Set multiple rates
-            provider.set_rate(Asset::ETH, Asset::USDT, 3000.0, admin_addr.clone()).unwrap();
-            provider.set_rate(Asset::SOL, Asset::USDT, 100.0, admin_addr.clone()).unwrap();
-            provider.set_rate(Asset::APPLE, Asset::USDT, 150.0, admin_addr).unwrap();
+            provider.set_rate(Asset::ETH, Asset::USDT, Amount::from_f64(3000.0), admin_addr.clone()).unwrap();
+            provider.set_rate(Asset::SOL, Asset::USDT, Amount::from_f64(100.0), admin_addr.clone()).unwrap();
+            provider.set_rate(Asset::APPLE, Asset::USDT, Amount::from_f64(150.0), admin_addr).unwrap();
         }
 
         // Verify all rates
         let provider = get_readonly_rate_provider();
-        assert_eq!(provider.get_rate(&Asset::ETH, &Asset::USDT), Some(3000.0));
-        assert_eq!(provider.get_rate(&Asset::SOL, &Asset::USDT), Some(100.0));
-        assert_eq!(provider.get_rate(&Asset::APPLE, &Asset::USDT), Some(150.0));
+        assert_eq!(provider.get_rate(&Asset::ETH, &Asset::USDT), Some(Amount::from_f64(3000.0)));
+        assert_eq!(provider.get_rate(&Asset::SOL, &Asset::USDT), Some(Amount::from_f64(100.0)));
+        assert_eq!(provider.get_rate(&Asset::APPLE, &Asset::USDT), Some(Amount::from_f64(150.0)));
     }
 
     #[test]
@@ -88,15 +89,15 @@ mod tests {
             let mut provider = get_rate_provider();
             
             // Set initial rate for a unique pair
-            provider.set_rate(Asset::SOL, Asset::ETH, 0.03, admin_addr.clone()).unwrap();
+            provider.set_rate(Asset::SOL, Asset::ETH, Amount::from_f64(0.03), admin_addr.clone()).unwrap();
             
             // Update the rate
-            provider.set_rate(Asset::SOL, Asset::ETH, 0.035, admin_addr).unwrap();
+            provider.set_rate(Asset::SOL, Asset::ETH, Amount::from_f64(0.035), admin_addr).unwrap();
         }
 
         // Verify the rate was updated
         let provider = get_readonly_rate_provider();
-        assert_eq!(provider.get_rate(&Asset::SOL, &Asset::ETH), Some(0.035));
+        assert_eq!(provider.get_rate(&Asset::SOL, &Asset::ETH), Some(Amount::from_f64(0.035)));
     }
 
     #[test]
@@ -107,15 +108,15 @@ mod tests {
             let mut provider = get_rate_provider();
             
             // Set BTC/ETH rate (how many ETH for 1 BTC)
-            provider.set_rate(Asset::BTC, Asset::ETH, 25.0, admin_addr.clone()).unwrap();
+            provider.set_rate(Asset::BTC, Asset::ETH, Amount::from_f64(25.0), admin_addr.clone()).unwrap();
             
             // Set ETH/BTC rate (how many BTC for 1 ETH) 
-            provider.set_rate(Asset::ETH, Asset::BTC, 0.04, admin_addr).unwrap();
+            provider.set_rate(Asset::ETH, Asset::BTC, Amount::from_f64(0.04), admin_addr).unwrap();
         }
 
         let provider = get_readonly_rate_provider();
-        assert_eq!(provider.get_rate(&Asset::BTC, &Asset::ETH), Some(25.0));
-        assert_eq!(provider.get_rate(&Asset::ETH, &Asset::BTC), Some(0.04));
+        assert_eq!(provider.get_rate(&Asset::BTC, &Asset::ETH), Some(Amount::from_f64(25.0)));
+        assert_eq!(provider.get_rate(&Asset::ETH, &Asset::BTC), Some(Amount::from_f64(0.04)));
     }
 
     #[test]
@@ -126,11 +127,11 @@ mod tests {
             let mut provider = get_rate_provider();
             
             // Set precise rate
-            provider.set_rate(Asset::BTC, Asset::USDT, 67432.123456789, admin_addr).unwrap();
+            provider.set_rate(Asset::BTC, Asset::USDT, Amount::from_f64(67432.123456789), admin_addr).unwrap();
         }
 
         let provider = get_readonly_rate_provider();
-        assert_eq!(provider.get_rate(&Asset::BTC, &Asset::USDT), Some(67432.123456789));
+        assert_eq!(provider.get_rate(&Asset::BTC, &Asset::USDT), Some(Amount::from_f64(67432.123456789)));
     }
 
     #[test]
@@ -141,11 +142,11 @@ mod tests {
             let mut provider = get_rate_provider();
             
             // Set zero rate (could represent temporarily unavailable pair)
-            provider.set_rate(Asset::ETH, Asset::USDT, 0.0, admin_addr).unwrap();
+            provider.set_rate(Asset::ETH, Asset::USDT, Amount::from_f64(0.0), admin_addr).unwrap();
         }
 
         let provider = get_readonly_rate_provider();
-        assert_eq!(provider.get_rate(&Asset::ETH, &Asset::USDT), Some(0.0));
+        assert_eq!(provider.get_rate(&Asset::ETH, &Asset::USDT), Some(Amount::from_f64(0.0)));
     }
 
     #[test]
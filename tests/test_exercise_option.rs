@@ -1,4 +1,4 @@
-use options_trading::{Exchange, User, ListingOption, Asset, ListingType, Address};
+use options_trading::{Exchange, User, ListingOption, Asset, ListingType, OptionState, Address, Amount, ExchangeError, ExerciseStyle, SettlementType};
 use chrono::{Utc, Duration};
 
 #[cfg(test)]
@@ -17,11 +17,11 @@ mod tests {
         let buyer_addr = create_test_address("2");
 
         let mut seller = User::new(seller_addr.clone());
-    seller.add_asset(&Asset::BTC, 10.0).unwrap();
-    seller.add_asset(&Asset::USDT, 1000000.0).unwrap();
+    seller.add_asset(&Asset::BTC, Amount::from_f64(10.0)).unwrap();
+    seller.add_asset(&Asset::USDT, Amount::from_f64(1000000.0)).unwrap();
 
     let mut buyer = User::new(buyer_addr.clone());
-    buyer.add_asset(&Asset::USDT, 200000.0).unwrap();
+    buyer.add_asset(&Asset::USDT, Amount::from_f64(200000.0)).unwrap();
 
         market.users.insert(seller_addr.clone(), seller);
         market.users.insert(buyer_addr.clone(), buyer);
@@ -39,16 +39,16 @@ mod tests {
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 500.0,
-            bid_price: 490.0,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
             expiration_time: Utc::now() + Duration::days(30),
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
             grantor_address: seller_addr.clone(),
-            beneficiary_address: None,
+            contract_count: 1,
         };
 
         let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
@@ -60,15 +60,15 @@ mod tests {
 
         // After exercise: buyer receives 1 BTC from escrow, pays strike (1 * 50000 USDT)
         let buyer = market.users.get(&buyer_addr).unwrap();
-        assert_eq!(buyer.get_balance(&Asset::BTC), 1.0);
+        assert_eq!(buyer.get_balance(&Asset::BTC), Amount::from_f64(1.0));
 
         let seller = market.users.get(&seller_addr).unwrap();
         // Seller should have received strike price in USDT (minus fees taken earlier in purchase)
-        assert!(seller.get_balance(&Asset::USDT) > 1000000.0);
+        assert!(seller.get_balance(&Asset::USDT) > Amount::from_f64(1000000.0));
 
         // Listing marked exercised
         let listing = market.listings.get(&listing_id).unwrap();
-        assert!(listing.is_exercised);
+        assert_eq!(listing.state, OptionState::Exercised);
     }
 
     #[test]
@@ -81,16 +81,16 @@ mod tests {
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 500.0,
-            bid_price: 490.0,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
             expiration_time: Utc::now() + Duration::days(30),
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
             grantor_address: seller_addr.clone(),
-            beneficiary_address: None,
+            contract_count: 1,
         };
 
         let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
@@ -98,7 +98,7 @@ mod tests {
 
         let result = market.exercise_option(listing_id, other_addr);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Caller is not beneficiary of option!");
+        assert_eq!(result.unwrap_err(), ExchangeError::Unauthorized("caller is not beneficiary of option".into()));
     }
 
     #[test]
@@ -110,16 +110,16 @@ mod tests {
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 500.0,
-            bid_price: 490.0,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
             expiration_time: Utc::now() + Duration::days(30),
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
             grantor_address: seller_addr.clone(),
-            beneficiary_address: None,
+            contract_count: 1,
         };
 
         let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
@@ -129,7 +129,14 @@ mod tests {
         // Attempt to exercise again
         let result = market.exercise_option(listing_id, buyer_addr.clone());
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Option has already been exercised!");
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidTransition {
+                listing_id,
+                from: OptionState::Exercised,
+                to: OptionState::Exercised,
+            }
+        );
     }
 
     #[test]
@@ -140,11 +147,11 @@ mod tests {
         let buyer_addr = create_test_address("5");
 
         let mut seller = User::new(seller_addr.clone());
-    seller.add_asset(&Asset::USDT, 10000.0).unwrap(); // seller will need quote collateral
+    seller.add_asset(&Asset::USDT, Amount::from_f64(10000.0)).unwrap(); // seller will need quote collateral
 
     let mut buyer = User::new(buyer_addr.clone());
-    buyer.add_asset(&Asset::USDT, 100000.0).unwrap(); // to purchase premium
-    buyer.add_asset(&Asset::ETH, 5.0).unwrap(); // buyer must have base asset to transfer on exercise
+    buyer.add_asset(&Asset::USDT, Amount::from_f64(100000.0)).unwrap(); // to purchase premium
+    buyer.add_asset(&Asset::ETH, Amount::from_f64(5.0)).unwrap(); // buyer must have base asset to transfer on exercise
 
         market.users.insert(seller_addr.clone(), seller);
         market.users.insert(buyer_addr.clone(), buyer);
@@ -155,16 +162,16 @@ mod tests {
             base_asset: Asset::ETH,
             quote_asset: Asset::USDT,
             listing_type: ListingType::PUT,
-            strike_price: 3000.0,
-            ask_price: 20.0,
-            bid_price: 18.0,
+            strike_price: Amount::from_f64(3000.0),
+            ask_price: Amount::from_f64(20.0),
+            bid_price: Amount::from_f64(18.0),
             expiration_time: Utc::now() + Duration::days(30),
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
             grantor_address: seller_addr.clone(),
-            beneficiary_address: None,
+            contract_count: 1,
         };
 
         let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
@@ -176,13 +183,45 @@ mod tests {
         market.exercise_option(listing_id, buyer_addr.clone()).unwrap();
 
         let buyer = market.users.get(&buyer_addr).unwrap();
-        assert_eq!(buyer.get_balance(&Asset::ETH), 4.0); // 5 - 1 ETH
-        assert!(buyer.get_balance(&Asset::USDT) > 100000.0); // Received strike in USDT (minus fees earlier)
+        assert_eq!(buyer.get_balance(&Asset::ETH), Amount::from_f64(4.0)); // 5 - 1 ETH
+        assert!(buyer.get_balance(&Asset::USDT) > Amount::from_f64(100000.0)); // Received strike in USDT (minus fees earlier)
 
         let seller = market.users.get(&seller_addr).unwrap();
-        assert_eq!(seller.get_balance(&Asset::ETH), 1.0); // seller received 1 ETH from buyer
+        assert_eq!(seller.get_balance(&Asset::ETH), Amount::from_f64(1.0)); // seller received 1 ETH from buyer
 
         let listing = market.listings.get(&listing_id).unwrap();
-        assert!(listing.is_exercised);
+        assert_eq!(listing.state, OptionState::Exercised);
+    }
+
+    #[test]
+    fn test_exercise_unpurchased_fails() {
+        let (mut market, seller_addr, buyer_addr) = setup_market_and_users();
+
+        let option = ListingOption {
+            listing_id: 0,
+            base_asset: Asset::BTC,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::CALL,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
+            expiration_time: Utc::now() + Duration::days(30),
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+            grantor_address: seller_addr.clone(),
+            contract_count: 1,
+        };
+
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+
+        // Nobody holds the rights yet, so nobody can exercise them
+        let result = market.exercise_option(listing_id, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not beneficiary of option".into())
+        );
+        assert_eq!(market.listings.get(&listing_id).unwrap().state, OptionState::Listed);
     }
 }
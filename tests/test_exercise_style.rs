@@ -0,0 +1,312 @@
+use chrono::{DateTime, Duration, Utc};
+use options_trading::binomial::{DEFAULT_BINOMIAL_STEPS, value_series_by_style};
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::pricing::black_scholes;
+use options_trading::{
+    Address, Amount, Asset, Clock, Exchange, ExchangeError, ExerciseStyle, ListingOption,
+    ListingType, OptionState, PricingParams, SettlementType, SimulatedClock, User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    // 2 contracts of 1 BTC each, expiring in 30 days
+    fn create_test_option(
+        grantor_address: Address,
+        expiration_time: DateTime<Utc>,
+        exercise_style: ExerciseStyle,
+    ) -> ListingOption {
+        ListingOption {
+            listing_id: 0,
+            base_asset: Asset::BTC,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::CALL,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(5.0),
+            bid_price: Amount::from_f64(4.75),
+            expiration_time,
+            grantor_address,
+            contract_count: 2,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        }
+    }
+
+    // Returns (market, clock, seller, buyer)
+    fn setup_market() -> (Exchange, Arc<SimulatedClock>, Address, Address) {
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::BTC, Amount::from_f64(10.0))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDT, Amount::from_f64(300000.0))
+            .unwrap();
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        (market, clock, seller_addr, buyer_addr)
+    }
+
+    // Lists the option and sells both contracts to the buyer
+    fn list_and_buy(
+        market: &mut Exchange,
+        seller_addr: &Address,
+        buyer_addr: &Address,
+        exercise_style: ExerciseStyle,
+    ) -> u32 {
+        let expiration_time = market.now() + Duration::days(30);
+        let option = create_test_option(seller_addr.clone(), expiration_time, exercise_style);
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market
+            .purchase_contracts(listing_id, 2, buyer_addr.clone())
+            .unwrap();
+        listing_id
+    }
+
+    #[test]
+    fn test_european_exercises_only_at_expiry() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market();
+        let listing_id = list_and_buy(
+            &mut market,
+            &seller_addr,
+            &buyer_addr,
+            ExerciseStyle::European,
+        );
+        let expiration_time = market.listings[&listing_id].expiration_time;
+
+        let result = market.exercise_contracts(listing_id, 1, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::OutsideExerciseWindow {
+                listing_id,
+                opens_at: expiration_time - Duration::hours(24),
+            }
+        );
+        assert!(!market.can_exercise(&market.listings[&listing_id]));
+
+        // The window opens a day before expiry
+        clock.advance(Duration::days(29) + Duration::hours(12));
+        assert!(market.can_exercise(&market.listings[&listing_id]));
+        market
+            .exercise_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        assert_eq!(
+            market.users[&buyer_addr].get_balance(&Asset::BTC),
+            Amount::from_f64(2.0)
+        );
+        assert_eq!(market.listings[&listing_id].state, OptionState::Exercised);
+    }
+
+    #[test]
+    fn test_bermudan_exercises_on_listed_dates() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market();
+        let start = clock.now();
+        let (first_date, second_date) = (start + Duration::days(10), start + Duration::days(20));
+        // Dates are kept sorted and deduplicated
+        let listing_id = list_and_buy(
+            &mut market,
+            &seller_addr,
+            &buyer_addr,
+            ExerciseStyle::Bermudan(vec![second_date, first_date, second_date]),
+        );
+        assert_eq!(
+            market.listings[&listing_id].exercise_style,
+            ExerciseStyle::Bermudan(vec![first_date, second_date])
+        );
+
+        clock.advance(Duration::days(5));
+        let result = market.exercise_contracts(listing_id, 1, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::OutsideExerciseWindow {
+                listing_id,
+                opens_at: first_date - Duration::hours(24),
+            }
+        );
+
+        clock.advance(Duration::days(5) - Duration::hours(1));
+        market
+            .exercise_contracts(listing_id, 1, buyer_addr.clone())
+            .unwrap();
+
+        // Past the first date the next window is the second one
+        clock.advance(Duration::days(2));
+        let result = market.exercise_contracts(listing_id, 1, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::OutsideExerciseWindow {
+                listing_id,
+                opens_at: second_date - Duration::hours(24),
+            }
+        );
+        assert_eq!(
+            market
+                .get_position(listing_id, &buyer_addr)
+                .unwrap()
+                .contracts,
+            1
+        );
+    }
+
+    #[test]
+    fn test_bermudan_dates_are_validated() {
+        let (mut market, _, seller_addr, _) = setup_market();
+        let expiration_time = market.now() + Duration::days(30);
+
+        for dates in [vec![], vec![expiration_time + Duration::days(1)]] {
+            let option = create_test_option(
+                seller_addr.clone(),
+                expiration_time,
+                ExerciseStyle::Bermudan(dates),
+            );
+            let result = market.list_option(seller_addr.clone(), option);
+            assert!(matches!(
+                result.unwrap_err(),
+                ExchangeError::InvalidInput(_)
+            ));
+        }
+        assert!(market.listings.is_empty());
+        assert_eq!(
+            market.users[&seller_addr].get_balance(&Asset::BTC),
+            Amount::from_f64(10.0)
+        );
+    }
+
+    #[test]
+    fn test_set_exercise_window() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market();
+        let listing_id = list_and_buy(
+            &mut market,
+            &seller_addr,
+            &buyer_addr,
+            ExerciseStyle::European,
+        );
+
+        let result = market.set_exercise_window(Duration::days(7), buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not the market admin".into())
+        );
+        let result =
+            market.set_exercise_window(Duration::zero(), market.market_admin_address.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+
+        // A ten day window is already open three weeks in
+        clock.advance(Duration::days(21));
+        assert!(!market.can_exercise(&market.listings[&listing_id]));
+        market
+            .set_exercise_window(Duration::days(10), market.market_admin_address.clone())
+            .unwrap();
+        market.exercise_option(listing_id, buyer_addr).unwrap();
+    }
+
+    #[test]
+    fn test_exercise_style_windows() {
+        let now = Utc::now();
+        let expiration_time = now + Duration::days(30);
+        let window = Duration::hours(24);
+        let option = create_test_option(
+            create_test_address("1"),
+            expiration_time,
+            ExerciseStyle::American,
+        );
+
+        assert!(option.can_exercise_at(now, window));
+        assert_eq!(option.next_exercise_time(now, window), Some(now));
+        let after_expiry = expiration_time + Duration::seconds(1);
+        assert!(!option.can_exercise_at(after_expiry, window));
+        assert_eq!(option.next_exercise_time(after_expiry, window), None);
+
+        // Listings of another style don't share a series
+        let european = option.clone().with_exercise_style(ExerciseStyle::European);
+        assert_ne!(option.series(), european.series());
+        assert!(!european.can_exercise_at(now, window));
+        assert!(european.can_exercise_at(expiration_time, window));
+        assert_eq!(
+            european.next_exercise_time(now, window),
+            Some(expiration_time - window)
+        );
+        assert_eq!(european.exercise_style.to_string(), "European");
+        assert_eq!(
+            ExerciseStyle::Bermudan(vec![now, now + Duration::days(1)]).to_string(),
+            "Bermudan (2 dates)"
+        );
+    }
+
+    #[test]
+    fn test_pricing_per_exercise_style() {
+        // A pair no other test reads from the shared rate provider, always set to 100
+        get_rate_provider()
+            .set_rate(
+                Asset::ETH,
+                Asset::USDC,
+                Amount::from_int(100),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+        let now = Utc::now();
+        let expiration_time = now + Duration::days(365);
+        let params = PricingParams::new(0.2, 0.05);
+        let put = ListingOption::new(
+            1,
+            Asset::ETH,
+            Asset::USDC,
+            ListingType::PUT,
+            Amount::from_int(100),
+            Amount::from_int(1),
+            Amount::from_int(1),
+            expiration_time,
+            create_test_address("1"),
+            1,
+            Amount::from_int(1),
+            OptionState::Listed,
+        );
+        let price = |exercise_style: ExerciseStyle| {
+            let series = put.clone().with_exercise_style(exercise_style).series();
+            value_series_by_style(
+                &series,
+                Duration::hours(24),
+                &params,
+                DEFAULT_BINOMIAL_STEPS,
+                now,
+            )
+            .unwrap()
+            .valuation
+            .price
+        };
+
+        let european = price(ExerciseStyle::European);
+        let quarterly = (1..4)
+            .map(|quarter| now + Duration::days(91 * quarter))
+            .collect();
+        let bermudan = price(ExerciseStyle::Bermudan(quarterly));
+        let american = price(ExerciseStyle::American);
+
+        // The European tree converges on Black-Scholes, every extra exercise date adds value
+        let black_scholes = black_scholes(&ListingType::PUT, 100.0, 100.0, 1.0, &params).price;
+        assert!((european - black_scholes).abs() < 0.02);
+        assert!(european < bermudan && bermudan < american);
+        assert!((american - 6.0903).abs() < 0.02);
+    }
+}
@@ -0,0 +1,390 @@
+use chrono::{Duration, Utc};
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ListingOption, ListingType, MarginParams,
+    OptionState, SettlementType, SimulatedClock, User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    // Tests run in parallel against the shared rate provider, so every test that moves a rate
+    // writes on its own base asset. ETH/USDC stays at 100.
+    fn set_rate(base_asset: Asset, rate: i64) {
+        get_rate_provider()
+            .set_rate(
+                base_asset,
+                Asset::USDC,
+                Amount::from_int(rate),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+    }
+
+    // Cash-settled puts on 1 unit each struck at 90, expiring in 30 days
+    fn create_test_put(
+        market: &Exchange,
+        grantor_address: Address,
+        base_asset: Asset,
+    ) -> ListingOption {
+        ListingOption::new(
+            0,
+            base_asset,
+            Asset::USDC,
+            ListingType::PUT,
+            Amount::from_int(90),
+            Amount::from_f64(0.05),
+            Amount::from_f64(0.04),
+            market.now() + Duration::days(30),
+            grantor_address,
+            5,
+            Amount::from_int(1),
+            OptionState::Listed,
+        )
+        .with_settlement_type(SettlementType::Cash)
+    }
+
+    // Returns (market, seller, buyer). The seller has a margin account with 100 USDC in it.
+    fn setup_market() -> (Exchange, Address, Address) {
+        set_rate(Asset::ETH, 100);
+        let mut market = Exchange::with_clock(Arc::new(SimulatedClock::new(Utc::now())));
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller.add_asset(&Asset::ETH, Amount::from_int(10)).unwrap();
+        seller
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        // Keep purchase fees out of the insurance fund so it only holds what the tests put in
+        market
+            .set_insurance_fee_share_bps(0, market.market_admin_address.clone())
+            .unwrap();
+        market.open_margin_account(seller_addr.clone()).unwrap();
+        market
+            .deposit_margin(&Asset::USDC, Amount::from_int(100), seller_addr.clone())
+            .unwrap();
+
+        (market, seller_addr, buyer_addr)
+    }
+
+    // Writes a put on margin and sells `contracts` of its 5 contracts to the buyer
+    fn write_and_sell(
+        market: &mut Exchange,
+        seller_addr: &Address,
+        buyer_addr: &Address,
+        base_asset: Asset,
+        contracts: u32,
+    ) -> u32 {
+        let option = create_test_put(market, seller_addr.clone(), base_asset);
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        if contracts > 0 {
+            market
+                .purchase_contracts(listing_id, contracts, buyer_addr.clone())
+                .unwrap();
+        }
+        listing_id
+    }
+
+    fn collateral(market: &Exchange, owner: &Address, asset: &Asset) -> Amount {
+        market.margin_accounts[owner].collateral.get_balance(asset)
+    }
+
+    #[test]
+    fn test_healthy_accounts_are_not_liquidated() {
+        let (mut market, seller_addr, buyer_addr) = setup_market();
+        write_and_sell(&mut market, &seller_addr, &buyer_addr, Asset::ETH, 2);
+
+        let result = market.liquidate_margin_account(&seller_addr, &Asset::USDC);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+        let result = market.liquidate_margin_account(&buyer_addr, &Asset::USDC);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::MarginAccountNotFound(buyer_addr.clone())
+        );
+        assert!(market.liquidate_unhealthy().unwrap().is_empty());
+        assert!(market.liquidations.is_empty());
+
+        let result = market.fund_insurance(&Asset::USDC, Amount::ZERO, buyer_addr);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+    }
+
+    #[test]
+    fn test_liquidation_buys_back_at_intrinsic_value() {
+        set_rate(Asset::SOL, 100);
+        let (mut market, seller_addr, buyer_addr) = setup_market();
+        let listing_id = write_and_sell(&mut market, &seller_addr, &buyer_addr, Asset::SOL, 3);
+        let buyer_usdc = market.users[&buyer_addr].get_balance(&Asset::USDC);
+
+        // 20 in the money plus 10.5 maintenance on each of the 5 contracts is 152.5
+        set_rate(Asset::SOL, 70);
+        let liquidations = market.liquidate_unhealthy().unwrap();
+        assert_eq!(liquidations.len(), 1);
+        let liquidation = &liquidations[0];
+        assert_eq!(liquidation.listing_ids, vec![listing_id]);
+        assert_eq!(liquidation.contracts, 3);
+        assert_eq!(liquidation.buyback_cost, Amount::from_int(60));
+        assert_eq!(liquidation.penalty, Amount::from_f64(7.625));
+        assert_eq!(liquidation.shortfall, Amount::ZERO);
+        assert_eq!(market.liquidations, liquidations);
+
+        assert_eq!(
+            market.users[&buyer_addr].get_balance(&Asset::USDC),
+            buyer_usdc.try_add(Amount::from_int(60)).unwrap()
+        );
+        assert_eq!(
+            collateral(&market, &seller_addr, &Asset::USDC),
+            Amount::from_f64(32.375)
+        );
+        assert_eq!(
+            market.insurance_fund.get_balance(&Asset::USDC),
+            Amount::from_f64(7.625)
+        );
+        let option = &market.listings[&listing_id];
+        assert_eq!(option.state, OptionState::Settled);
+        assert_eq!(option.contract_count, 0);
+        assert_eq!(
+            market
+                .get_position(listing_id, &buyer_addr)
+                .unwrap()
+                .contracts,
+            0
+        );
+        assert!(
+            market
+                .margin_health(&seller_addr, &Asset::USDC)
+                .unwrap()
+                .is_healthy()
+        );
+    }
+
+    #[test]
+    fn test_insurance_fund_covers_the_shortfall() {
+        set_rate(Asset::APPLE, 100);
+        let (mut market, seller_addr, buyer_addr) = setup_market();
+        let listing_id = write_and_sell(&mut market, &seller_addr, &buyer_addr, Asset::APPLE, 5);
+        let buyer_usdc = market.users[&buyer_addr].get_balance(&Asset::USDC);
+
+        // 40 in the money on 5 contracts is twice what the account holds
+        set_rate(Asset::APPLE, 50);
+        let result = market.liquidate_margin_account(&seller_addr, &Asset::USDC);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::USDC,
+                required: Amount::from_int(100),
+                available: Amount::ZERO,
+            }
+        );
+        // an empty fund leaves the account for a later pass
+        assert!(market.liquidate_unhealthy().unwrap().is_empty());
+        assert_eq!(market.listings[&listing_id].state, OptionState::Purchased);
+
+        market
+            .fund_insurance(&Asset::USDC, Amount::from_int(150), buyer_addr.clone())
+            .unwrap();
+        let liquidation = market
+            .liquidate_margin_account(&seller_addr, &Asset::USDC)
+            .unwrap();
+        assert_eq!(liquidation.buyback_cost, Amount::from_int(200));
+        assert_eq!(liquidation.shortfall, Amount::from_int(100));
+        // nothing is left to take a penalty from
+        assert_eq!(liquidation.penalty, Amount::ZERO);
+
+        assert_eq!(
+            market.users[&buyer_addr].get_balance(&Asset::USDC),
+            buyer_usdc
+                .try_sub(Amount::from_int(150))
+                .unwrap()
+                .try_add(Amount::from_int(200))
+                .unwrap()
+        );
+        assert_eq!(
+            market.insurance_fund.get_balance(&Asset::USDC),
+            Amount::from_int(50)
+        );
+        assert_eq!(
+            collateral(&market, &seller_addr, &Asset::USDC),
+            Amount::ZERO
+        );
+    }
+
+    #[test]
+    fn test_liquidation_sells_other_collateral() {
+        set_rate(Asset::BTC, 100);
+        let (mut market, seller_addr, buyer_addr) = setup_market();
+        market
+            .deposit_margin(&Asset::ETH, Amount::from_int(1), seller_addr.clone())
+            .unwrap();
+        let desk_addr = market.escrow_user.address.clone();
+        market
+            .users
+            .get_mut(&desk_addr)
+            .unwrap()
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        write_and_sell(&mut market, &seller_addr, &buyer_addr, Asset::BTC, 5);
+
+        // Only USDC counts towards the USDC requirement, the ETH doesn't help until it's sold.
+        // The 100 USDC in the account pay for the buyback, only the penalty needs ETH sold.
+        set_rate(Asset::BTC, 70);
+        let liquidation = market
+            .liquidate_margin_account(&seller_addr, &Asset::USDC)
+            .unwrap();
+        assert_eq!(liquidation.buyback_cost, Amount::from_int(100));
+        assert_eq!(liquidation.penalty, Amount::from_f64(7.625));
+        assert_eq!(
+            liquidation.collateral_sold,
+            vec![(Asset::ETH, Amount::from_f64(0.07625))]
+        );
+        assert_eq!(liquidation.sale_proceeds, Amount::from_f64(7.625));
+        assert_eq!(liquidation.shortfall, Amount::ZERO);
+
+        assert_eq!(
+            collateral(&market, &seller_addr, &Asset::ETH),
+            Amount::from_f64(0.92375)
+        );
+        assert_eq!(
+            collateral(&market, &seller_addr, &Asset::USDC),
+            Amount::ZERO
+        );
+        assert_eq!(
+            market.users[&desk_addr].get_balance(&Asset::ETH),
+            Amount::from_f64(0.07625)
+        );
+        assert_eq!(
+            market.users[&desk_addr].get_balance(&Asset::USDC),
+            Amount::from_f64(992.375)
+        );
+    }
+
+    #[test]
+    fn test_liquidation_keeps_collateral_other_assets_need() {
+        let gold = Asset::OTHER("GOLD".into());
+        set_rate(gold.clone(), 100);
+        set_rate(Asset::USDT, 1);
+        get_rate_provider()
+            .set_rate(
+                Asset::ETH,
+                Asset::USDT,
+                Amount::from_int(100),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+        let (mut market, seller_addr, buyer_addr) = setup_market();
+        let desk_addr = market.escrow_user.address.clone();
+        market
+            .users
+            .get_mut(&desk_addr)
+            .unwrap()
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        market
+            .users
+            .get_mut(&seller_addr)
+            .unwrap()
+            .add_asset(&Asset::USDT, Amount::from_int(100))
+            .unwrap();
+        market
+            .deposit_margin(&Asset::USDT, Amount::from_int(100), seller_addr.clone())
+            .unwrap();
+        market
+            .fund_insurance(&Asset::USDC, Amount::from_int(150), buyer_addr.clone())
+            .unwrap();
+
+        // The USDT margins puts quoted in USDT, selling what the liquidation needs of it would
+        // leave them under-margined, so the insurance fund covers the shortfall instead
+        let mut option = create_test_put(&market, seller_addr.clone(), Asset::ETH);
+        option.quote_asset = Asset::USDT;
+        market.list_option(seller_addr.clone(), option).unwrap();
+        write_and_sell(&mut market, &seller_addr, &buyer_addr, gold.clone(), 5);
+
+        set_rate(gold, 50);
+        let liquidation = market
+            .liquidate_margin_account(&seller_addr, &Asset::USDC)
+            .unwrap();
+        assert!(liquidation.collateral_sold.is_empty());
+        assert_eq!(liquidation.buyback_cost, Amount::from_int(200));
+        assert_eq!(liquidation.shortfall, Amount::from_int(100));
+        assert_eq!(
+            collateral(&market, &seller_addr, &Asset::USDT),
+            Amount::from_int(100)
+        );
+        assert!(
+            market
+                .margin_health(&seller_addr, &Asset::USDT)
+                .unwrap()
+                .is_healthy()
+        );
+    }
+
+    #[test]
+    fn test_unsold_listings_are_unlisted() {
+        let (mut market, seller_addr, buyer_addr) = setup_market();
+        let admin = market.market_admin_address.clone();
+        let listing_id = write_and_sell(&mut market, &seller_addr, &buyer_addr, Asset::ETH, 0);
+
+        let result = market.set_liquidation_penalty_bps(1_000, seller_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not the market admin".into())
+        );
+        let result = market.set_liquidation_penalty_bps(10_001, admin.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+        market
+            .set_liquidation_penalty_bps(1_000, admin.clone())
+            .unwrap();
+
+        // Stricter rates need the whole strike for every contract, 450 against 100
+        let strict = MarginParams {
+            initial_margin_bps: 10_000,
+            maintenance_margin_bps: 10_000,
+            minimum_margin_bps: 10_000,
+        };
+        market.set_margin_params(strict, admin).unwrap();
+        let liquidation = market
+            .liquidate_margin_account(&seller_addr, &Asset::USDC)
+            .unwrap();
+        assert_eq!(liquidation.contracts, 0);
+        assert_eq!(liquidation.buyback_cost, Amount::ZERO);
+        assert_eq!(liquidation.penalty, Amount::from_int(45));
+
+        let option = &market.listings[&listing_id];
+        assert_eq!(option.state, OptionState::Unlisted);
+        assert_eq!(option.contract_count, 0);
+        assert_eq!(
+            collateral(&market, &seller_addr, &Asset::USDC),
+            Amount::from_int(55)
+        );
+        // nothing is left open, so all of it can be withdrawn
+        market
+            .withdraw_margin(&Asset::USDC, Amount::from_int(55), seller_addr)
+            .unwrap();
+    }
+}
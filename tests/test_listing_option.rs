@@ -1,4 +1,4 @@
-use options_trading::{ListingOption, Asset, ListingType, Address};
+use options_trading::{ListingOption, Asset, ListingType, OptionState, Address, Amount, ExchangeError, ExerciseStyle, SettlementType};
 use chrono::{Utc, Duration};
 
 #[cfg(test)]
@@ -17,16 +17,16 @@ mod tests {
             base_asset: Asset::BTC,
             quote_asset: Asset::USDT,
             listing_type: ListingType::CALL,
-            strike_price: 50000.0,
-            ask_price: 500.0,
-            bid_price: 490.0,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
             expiration_time: Utc::now() + Duration::days(30),
             grantor_address: create_test_address("1"),
-            beneficiary_address: None,
-            exercise_amount: 1.0,
-            is_purchased: false,
-            is_unlisted: false,
-            is_exercised: false,
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
         }
     }
 
@@ -38,10 +38,10 @@ mod tests {
         assert_eq!(option.base_asset, Asset::BTC);
         assert_eq!(option.quote_asset, Asset::USDT);
         assert_eq!(option.listing_type, ListingType::CALL);
-        assert_eq!(option.strike_price, 50000.0);
-        assert_eq!(option.ask_price, 500.0);
-        assert_eq!(option.bid_price, 490.0);
-        assert!(option.beneficiary_address.is_none());
+        assert_eq!(option.strike_price, Amount::from_f64(50000.0));
+        assert_eq!(option.ask_price, Amount::from_f64(500.0));
+        assert_eq!(option.bid_price, Amount::from_f64(490.0));
+        assert_eq!(option.contract_count, 1);
     }
 
     #[test]
@@ -49,7 +49,7 @@ mod tests {
         let option = create_test_option();
         
         // Premium price should be ask_price * 100
-        assert_eq!(option.get_premium_price(), 50000.0); // 500.0 * 100
+        assert_eq!(option.get_premium_price().unwrap(), Amount::from_f64(50000.0)); // 500.0 * 100
     }
 
     #[test]
@@ -57,7 +57,7 @@ mod tests {
         let option = create_test_option();
         
     // Collateral price should be strike_price * 100 (grantor viewpoint)
-    assert_eq!(option.get_buy_amount(true), 5000000.0); // 50000.0 * 100
+    assert_eq!(option.get_buy_amount(true).unwrap(), Amount::from_f64(5000000.0)); // 50000.0 * 100
     }
 
     #[test]
@@ -67,11 +67,11 @@ mod tests {
         // Test BTC/ETH pair
         option.base_asset = Asset::BTC;
         option.quote_asset = Asset::ETH;
-        option.strike_price = 15.0; // 1 BTC = 15 ETH
-        option.ask_price = 1.5;
+        option.strike_price = Amount::from_f64(15.0); // 1 BTC = 15 ETH
+        option.ask_price = Amount::from_f64(1.5);
         
-    assert_eq!(option.get_premium_price(), 150.0); // 1.5 * 100
-    assert_eq!(option.get_buy_amount(true), 1500.0); // 15.0 * 100
+    assert_eq!(option.get_premium_price().unwrap(), Amount::from_f64(150.0)); // 1.5 * 100
+    assert_eq!(option.get_buy_amount(true).unwrap(), Amount::from_f64(1500.0)); // 15.0 * 100
     }
 
     #[test]
@@ -81,18 +81,20 @@ mod tests {
         
         assert_eq!(option.listing_type, ListingType::PUT);
         // Premium and collateral calculations should remain the same
-    assert_eq!(option.get_premium_price(), 50000.0);
-    assert_eq!(option.get_buy_amount(true), 5000000.0);
+    assert_eq!(option.get_premium_price().unwrap(), Amount::from_f64(50000.0));
+    assert_eq!(option.get_buy_amount(true).unwrap(), Amount::from_f64(5000000.0));
     }
 
     #[test]
-    fn test_option_with_beneficiary() {
+    fn test_amounts_scale_with_contracts() {
         let mut option = create_test_option();
-        let beneficiary = create_test_address("2");
-        option.beneficiary_address = Some(beneficiary.clone());
-        
-        assert!(option.beneficiary_address.is_some());
-        assert_eq!(option.beneficiary_address.unwrap(), beneficiary);
+        option.contract_count = 3;
+
+        // Getters are per contract, the exchange scales them to the traded quantity
+        let premium = ListingOption::for_contracts(option.get_premium_price().unwrap(), 3);
+        assert_eq!(premium.unwrap(), Amount::from_f64(150000.0));
+        let collateral = ListingOption::for_contracts(option.get_sell_amount(true).unwrap(), 3);
+        assert_eq!(collateral.unwrap(), Amount::from_f64(3.0));
     }
 
     #[test]
@@ -112,21 +114,21 @@ mod tests {
     #[test]
     fn test_zero_prices() {
         let mut option = create_test_option();
-        option.ask_price = 0.0;
-        option.strike_price = 0.0;
+        option.ask_price = Amount::from_f64(0.0);
+        option.strike_price = Amount::from_f64(0.0);
         
-    assert_eq!(option.get_premium_price(), 0.0);
-    assert_eq!(option.get_buy_amount(true), 0.0);
+    assert_eq!(option.get_premium_price().unwrap(), Amount::from_f64(0.0));
+    assert_eq!(option.get_buy_amount(true).unwrap(), Amount::from_f64(0.0));
     }
 
     #[test]
     fn test_decimal_prices() {
         let mut option = create_test_option();
-        option.ask_price = 1.23;
-        option.strike_price = 45678.90;
+        option.ask_price = Amount::from_f64(1.23);
+        option.strike_price = Amount::from_f64(45678.90);
         
-    assert_eq!(option.get_premium_price(), 123.0); // 1.23 * 100
-    assert_eq!(option.get_buy_amount(true), 4567890.0); // 45678.90 * 100
+    assert_eq!(option.get_premium_price().unwrap(), Amount::from_f64(123.0)); // 1.23 * 100
+    assert_eq!(option.get_buy_amount(true).unwrap(), Amount::from_f64(4567890.0)); // 45678.90 * 100
     }
 
     #[test]
@@ -148,4 +150,39 @@ mod tests {
         assert!(debug_string.contains("USDT"));
         assert!(debug_string.contains("CALL"));
     }
+
+    #[test]
+    fn test_state_transitions() {
+        let mut option = create_test_option();
+        assert!(option.check_transition(OptionState::Exercised).is_err());
+
+        option.transition_to(OptionState::Purchased).unwrap();
+        assert_eq!(option.state, OptionState::Purchased);
+
+        // Bought rights can't be withdrawn by the grantor
+        assert_eq!(
+            option.transition_to(OptionState::Unlisted).unwrap_err(),
+            ExchangeError::InvalidTransition {
+                listing_id: 1,
+                from: OptionState::Purchased,
+                to: OptionState::Unlisted,
+            }
+        );
+        assert_eq!(option.state, OptionState::Purchased);
+
+        option.transition_to(OptionState::Exercised).unwrap();
+        assert!(option.state.is_final());
+        assert!(option.check_transition(OptionState::Expired).is_err());
+    }
+
+    #[test]
+    fn test_expired_option_reports_expiry() {
+        let mut option = create_test_option();
+        option.transition_to(OptionState::Expired).unwrap();
+
+        assert_eq!(
+            option.check_transition(OptionState::Purchased).unwrap_err(),
+            ExchangeError::Expired(1)
+        );
+    }
 }
@@ -0,0 +1,468 @@
+use chrono::{Duration, Utc};
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ListingOption, ListingType, MarginParams,
+    OptionState, SettlementType, SimulatedClock, User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn set_rate(base_asset: Asset, rate: i64) {
+        get_rate_provider()
+            .set_rate(
+                base_asset,
+                Asset::USDC,
+                Amount::from_int(rate),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+    }
+
+    // Cash-settled contracts of 1 unit each, expiring in 30 days
+    fn create_test_option(
+        market: &Exchange,
+        grantor_address: Address,
+        base_asset: Asset,
+        listing_type: ListingType,
+        strike_price: i64,
+        contract_count: u32,
+    ) -> ListingOption {
+        ListingOption::new(
+            0,
+            base_asset,
+            Asset::USDC,
+            listing_type,
+            Amount::from_int(strike_price),
+            Amount::from_f64(0.05),
+            Amount::from_f64(0.04),
+            market.now() + Duration::days(30),
+            grantor_address,
+            contract_count,
+            Amount::from_int(1),
+            OptionState::Listed,
+        )
+        .with_settlement_type(SettlementType::Cash)
+    }
+
+    // Returns (market, clock, seller, buyer). ETH/USDC is always 100 in this file, the seller
+    // has a margin account with 100 USDC in it.
+    fn setup_market() -> (Exchange, Arc<SimulatedClock>, Address, Address) {
+        set_rate(Asset::ETH, 100);
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller.add_asset(&Asset::ETH, Amount::from_int(10)).unwrap();
+        seller
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDC, Amount::from_int(100))
+            .unwrap();
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        market.open_margin_account(seller_addr.clone()).unwrap();
+        market
+            .deposit_margin(&Asset::USDC, Amount::from_int(100), seller_addr.clone())
+            .unwrap();
+
+        (market, clock, seller_addr, buyer_addr)
+    }
+
+    #[test]
+    fn test_margin_requirements() {
+        let market = Exchange::new();
+        let params = MarginParams::default();
+        let spot = Amount::from_int(100);
+        let requirements = |listing_type: ListingType, strike_price: i64, spot: Amount| {
+            let option = create_test_option(
+                &market,
+                create_test_address("1"),
+                Asset::ETH,
+                listing_type,
+                strike_price,
+                1,
+            );
+            (
+                params.initial_requirement(&option, spot).unwrap(),
+                params.maintenance_requirement(&option, spot).unwrap(),
+            )
+        };
+
+        // 20% of 100 less 10 out of the money, the maintenance 15% less 10 is under the 9 floor
+        assert_eq!(
+            requirements(ListingType::PUT, 90, spot),
+            (Amount::from_int(10), Amount::from_int(9))
+        );
+        // 20 in the money on top of 20% or 15% of 100
+        assert_eq!(
+            requirements(ListingType::CALL, 80, spot),
+            (Amount::from_int(40), Amount::from_int(35))
+        );
+        // Far out of the money calls fall back to 10% of the underlying
+        assert_eq!(
+            requirements(ListingType::CALL, 200, spot),
+            (Amount::from_int(10), Amount::from_int(10))
+        );
+        // A put never needs more than its strike
+        assert_eq!(
+            requirements(ListingType::PUT, 120, Amount::from_int(10)),
+            (Amount::from_int(120), Amount::from_int(120))
+        );
+    }
+
+    #[test]
+    fn test_write_on_margin() {
+        let (mut market, _, seller_addr, _) = setup_market();
+        let escrow_usdc = market.escrow_user.get_balance(&Asset::USDC);
+
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            90,
+            2,
+        );
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+
+        // Nothing is escrowed for the listing, the account covers it
+        assert!(market.is_margined(&market.listings[&listing_id]));
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDC), escrow_usdc);
+        assert_eq!(
+            market.users[&seller_addr].get_balance(&Asset::USDC),
+            Amount::from_int(900)
+        );
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert_eq!(health.collateral, Amount::from_int(100));
+        assert_eq!(health.initial_requirement, Amount::from_int(20));
+        assert_eq!(health.maintenance_requirement, Amount::from_int(18));
+        assert!(health.is_healthy());
+        assert_eq!(health.free_collateral().unwrap(), Amount::from_int(80));
+
+        // 9 more contracts would need 110
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            90,
+            9,
+        );
+        let result = market.list_option(seller_addr.clone(), option.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin {
+                owner: seller_addr.clone(),
+                asset: Asset::USDC,
+                required: Amount::from_int(110),
+                available: Amount::from_int(100),
+            }
+        );
+
+        let physical = option.with_settlement_type(SettlementType::Physical);
+        let result = market.list_option(seller_addr, physical);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+        assert_eq!(market.listings.len(), 1);
+    }
+
+    #[test]
+    fn test_exercise_pays_out_of_margin() {
+        let (mut market, _, seller_addr, buyer_addr) = setup_market();
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::CALL,
+            80,
+            2,
+        );
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market
+            .purchase_contracts(listing_id, 2, buyer_addr.clone())
+            .unwrap();
+        let buyer_usdc = market.users[&buyer_addr].get_balance(&Asset::USDC);
+
+        // 20 USDC of intrinsic value per contract, paid in quote out of the writer's margin
+        market
+            .exercise_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        assert_eq!(
+            market.users[&buyer_addr].get_balance(&Asset::USDC),
+            buyer_usdc.try_add(Amount::from_int(40)).unwrap()
+        );
+        assert_eq!(
+            market.users[&seller_addr].get_balance(&Asset::ETH),
+            Amount::from_int(10)
+        );
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert_eq!(health.collateral, Amount::from_int(60));
+        assert_eq!(health.initial_requirement, Amount::ZERO);
+        assert_eq!(market.listings[&listing_id].state, OptionState::Exercised);
+    }
+
+    // Lists 2 calls struck at 80 with `base_asset` at 100, sells both and moves spot to 200,
+    // 240 USDC of intrinsic value against the 100 in the writer's margin account
+    fn write_underwater_calls(
+        market: &mut Exchange,
+        seller_addr: &Address,
+        buyer_addr: &Address,
+        base_asset: Asset,
+    ) -> u32 {
+        set_rate(base_asset.clone(), 100);
+        let option = create_test_option(
+            market,
+            seller_addr.clone(),
+            base_asset.clone(),
+            ListingType::CALL,
+            80,
+            2,
+        );
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market
+            .purchase_contracts(listing_id, 2, buyer_addr.clone())
+            .unwrap();
+        set_rate(base_asset, 200);
+        listing_id
+    }
+
+    #[test]
+    fn test_exercise_against_an_underwater_writer() {
+        let (mut market, _, seller_addr, buyer_addr) = setup_market();
+        let listing_id = write_underwater_calls(&mut market, &seller_addr, &buyer_addr, Asset::BTC);
+        let buyer_usdc = market.users[&buyer_addr].get_balance(&Asset::USDC);
+        let fund_usdc = market.insurance_fund.get_balance(&Asset::USDC);
+
+        // The writer is short, not the holder
+        let result = market.exercise_option(listing_id, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::WriterShortfall {
+                listing_id,
+                writer: seller_addr.clone(),
+                asset: Asset::USDC,
+                shortfall: Amount::from_int(140),
+                covered: fund_usdc,
+            }
+        );
+        assert_eq!(
+            market
+                .get_position(listing_id, &buyer_addr)
+                .unwrap()
+                .contracts,
+            2
+        );
+
+        // The margin account pays what it holds, the insurance fund the rest
+        market
+            .fund_insurance(&Asset::USDC, Amount::from_int(140), seller_addr.clone())
+            .unwrap();
+        market
+            .exercise_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        assert_eq!(
+            market.users[&buyer_addr].get_balance(&Asset::USDC),
+            buyer_usdc.try_add(Amount::from_int(240)).unwrap()
+        );
+        assert_eq!(market.insurance_fund.get_balance(&Asset::USDC), fund_usdc);
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert_eq!(health.collateral, Amount::ZERO);
+        assert_eq!(market.listings[&listing_id].state, OptionState::Exercised);
+    }
+
+    #[test]
+    fn test_expiry_against_an_underwater_writer() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market();
+        let listing_id =
+            write_underwater_calls(&mut market, &seller_addr, &buyer_addr, Asset::APPLE);
+        let buyer_usdc = market.users[&buyer_addr].get_balance(&Asset::USDC);
+        let fund_usdc = market.insurance_fund.get_balance(&Asset::USDC);
+
+        // The insurance fund makes up what the margin account is short of at expiry too,
+        // without it the listing would lapse unexercised
+        market
+            .fund_insurance(&Asset::USDC, Amount::from_int(140), seller_addr.clone())
+            .unwrap();
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+        assert_eq!(report.auto_exercised.len(), 1);
+        assert!(report.unsettled.is_empty());
+        assert_eq!(
+            market.users[&buyer_addr].get_balance(&Asset::USDC),
+            buyer_usdc.try_add(Amount::from_int(240)).unwrap()
+        );
+        assert_eq!(market.listings[&listing_id].state, OptionState::Settled);
+        assert_eq!(market.insurance_fund.get_balance(&Asset::USDC), fund_usdc);
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_margin() {
+        let (mut market, _, seller_addr, buyer_addr) = setup_market();
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            90,
+            2,
+        );
+        market.list_option(seller_addr.clone(), option).unwrap();
+
+        // 20 of the 100 stay behind for the initial requirement
+        let result =
+            market.withdraw_margin(&Asset::USDC, Amount::from_int(90), seller_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin {
+                owner: seller_addr.clone(),
+                asset: Asset::USDC,
+                required: Amount::from_int(110),
+                available: Amount::from_int(100),
+            }
+        );
+        market
+            .withdraw_margin(&Asset::USDC, Amount::from_int(80), seller_addr.clone())
+            .unwrap();
+        assert_eq!(
+            market.users[&seller_addr].get_balance(&Asset::USDC),
+            Amount::from_int(980)
+        );
+        assert_eq!(
+            market
+                .margin_health(&seller_addr, &Asset::USDC)
+                .unwrap()
+                .collateral,
+            Amount::from_int(20)
+        );
+
+        let result = market.deposit_margin(&Asset::USDC, Amount::from_int(10), buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::MarginAccountNotFound(buyer_addr)
+        );
+        let result = market.deposit_margin(&Asset::USDC, Amount::ZERO, seller_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+        let result = market.open_margin_account(seller_addr);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+    }
+
+    #[test]
+    fn test_margin_health_follows_the_rate() {
+        // The only test moving SOL/USDC
+        set_rate(Asset::SOL, 100);
+        let (mut market, _, seller_addr, _) = setup_market();
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::SOL,
+            ListingType::PUT,
+            90,
+            10,
+        );
+        market.list_option(seller_addr.clone(), option).unwrap();
+        let healths = market.margin_healths().unwrap();
+        assert_eq!(healths.len(), 1);
+        assert!(healths[0].is_healthy());
+
+        // 20 in the money plus 15% of 70 puts 305 against the 100 posted
+        set_rate(Asset::SOL, 70);
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert_eq!(health.maintenance_requirement, Amount::from_int(305));
+        assert!(!health.is_healthy());
+        assert!(health.free_collateral().unwrap().is_negative());
+
+        let admin = market.market_admin_address.clone();
+        let lenient = MarginParams {
+            initial_margin_bps: 1_000,
+            maintenance_margin_bps: 500,
+            minimum_margin_bps: 500,
+        };
+        let result = market.set_margin_params(lenient, seller_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not the market admin".into())
+        );
+        let inverted = MarginParams {
+            maintenance_margin_bps: 1_500,
+            ..lenient
+        };
+        let result = market.set_margin_params(inverted, admin.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+        market.set_margin_params(lenient, admin).unwrap();
+        assert_eq!(
+            market
+                .margin_health(&seller_addr, &Asset::USDC)
+                .unwrap()
+                .maintenance_requirement,
+            Amount::from_int(245)
+        );
+    }
+
+    #[test]
+    fn test_unlisting_and_expiry_free_margin() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market();
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            90,
+            2,
+        );
+        let unlisted_id = market
+            .list_option(seller_addr.clone(), option.clone())
+            .unwrap();
+        let sold_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market
+            .purchase_contracts(sold_id, 2, buyer_addr.clone())
+            .unwrap();
+        let seller_usdc = market.users[&seller_addr].get_balance(&Asset::USDC);
+
+        // No refund on unlisting, the requirement drops instead
+        market
+            .unlist_option(unlisted_id, seller_addr.clone())
+            .unwrap();
+        assert_eq!(
+            market.users[&seller_addr].get_balance(&Asset::USDC),
+            seller_usdc
+        );
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert_eq!(health.initial_requirement, Amount::from_int(20));
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+        assert_eq!(report.settled.len(), 1);
+        assert_eq!(report.settled[0].released_amount, Amount::ZERO);
+        assert_eq!(market.listings[&sold_id].state, OptionState::Expired);
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert_eq!(health.initial_requirement, Amount::ZERO);
+        assert_eq!(health.collateral, Amount::from_int(100));
+    }
+}
@@ -0,0 +1,332 @@
+use chrono::{DateTime, Duration, Utc};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ExerciseStyle, ListingOption, ListingType,
+    OptionSeries, OptionState, OrderSide, OrderStatus, OrderType, SettlementType, SimulatedClock,
+    User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn create_test_option(
+        grantor_address: Address,
+        expiration_time: DateTime<Utc>,
+        ask_price: f64,
+        contract_count: u32,
+    ) -> ListingOption {
+        ListingOption {
+            listing_id: 0,
+            base_asset: Asset::BTC,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::CALL,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(ask_price),
+            bid_price: Amount::from_f64(ask_price * 0.95),
+            expiration_time,
+            grantor_address,
+            contract_count,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        }
+    }
+
+    // Returns (market, clock, series, seller, alice, bob)
+    fn setup_market() -> (
+        Exchange,
+        Arc<SimulatedClock>,
+        OptionSeries,
+        Address,
+        Address,
+        Address,
+    ) {
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+        let seller_addr = create_test_address("1");
+        let alice_addr = create_test_address("2");
+        let bob_addr = create_test_address("3");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::BTC, Amount::from_f64(20.0))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        for address in [&alice_addr, &bob_addr] {
+            let mut buyer = User::new(address.clone());
+            buyer
+                .add_asset(&Asset::USDT, Amount::from_f64(300000.0))
+                .unwrap();
+            market.users.insert(address.clone(), buyer);
+        }
+
+        let series = create_test_option(
+            seller_addr.clone(),
+            market.now() + Duration::days(30),
+            5.0,
+            1,
+        )
+        .series();
+
+        (market, clock, series, seller_addr, alice_addr, bob_addr)
+    }
+
+    fn list(
+        market: &mut Exchange,
+        seller_addr: &Address,
+        series: &OptionSeries,
+        ask: f64,
+        contracts: u32,
+    ) -> u32 {
+        let option =
+            create_test_option(seller_addr.clone(), series.expiration_time, ask, contracts);
+        market.list_option(seller_addr.clone(), option).unwrap()
+    }
+
+    #[test]
+    fn test_book_ranks_by_price_then_time() {
+        let (mut market, _, series, seller_addr, alice_addr, bob_addr) = setup_market();
+        let expensive = list(&mut market, &seller_addr, &series, 5.0, 1);
+        let cheap_first = list(&mut market, &seller_addr, &series, 4.0, 1);
+        let cheap_second = list(&mut market, &seller_addr, &series, 4.0, 1);
+
+        let low_bid = market
+            .place_bid(series.clone(), Amount::from_f64(2.0), 1, alice_addr)
+            .unwrap();
+        let high_bid = market
+            .place_bid(series.clone(), Amount::from_f64(3.0), 1, bob_addr)
+            .unwrap();
+
+        let book = market.order_book(&series);
+        let ask_ids: Vec<u32> = book.asks.iter().map(|ask| ask.id).collect();
+        assert_eq!(ask_ids, vec![cheap_first, cheap_second, expensive]);
+        let bid_ids: Vec<u32> = book.bids.iter().map(|bid| bid.id).collect();
+        assert_eq!(bid_ids, vec![high_bid, low_bid]);
+        assert_eq!(book.best_ask().unwrap().price, Amount::from_f64(4.0));
+        assert_eq!(book.best_bid().unwrap().price, Amount::from_f64(3.0));
+    }
+
+    #[test]
+    fn test_market_buy_sweeps_best_asks() {
+        let (mut market, _, series, seller_addr, alice_addr, _) = setup_market();
+        let expensive = list(&mut market, &seller_addr, &series, 5.0, 3);
+        let cheap = list(&mut market, &seller_addr, &series, 4.0, 2);
+
+        let order_id = market
+            .submit_buy_order(series, OrderType::Market, 4, alice_addr.clone())
+            .unwrap();
+
+        let fills = market.get_order_fills(order_id).unwrap();
+        assert_eq!(fills.len(), 2);
+        assert_eq!((fills[0].listing_id, fills[0].contracts), (cheap, 2));
+        assert_eq!(fills[0].price, Amount::from_f64(4.0));
+        assert_eq!((fills[1].listing_id, fills[1].contracts), (expensive, 2));
+        assert_eq!(fills[1].price, Amount::from_f64(5.0));
+        assert_eq!(
+            market.get_order_status(order_id).unwrap(),
+            OrderStatus::Filled
+        );
+
+        // 800 + 0.8 fee on the cheap listing, 1000 + 1 on the other
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(298198.2));
+    }
+
+    #[test]
+    fn test_market_buy_drops_unfilled_remainder() {
+        let (mut market, _, series, seller_addr, alice_addr, _) = setup_market();
+        list(&mut market, &seller_addr, &series, 4.0, 2);
+
+        let order_id = market
+            .submit_buy_order(series, OrderType::Market, 5, alice_addr)
+            .unwrap();
+
+        assert_eq!(market.get_filled_contracts(order_id).unwrap(), 2);
+        assert_eq!(
+            market.get_order_status(order_id).unwrap(),
+            OrderStatus::Cancelled
+        );
+        assert!(market.bids.is_empty());
+    }
+
+    #[test]
+    fn test_market_buy_without_funds_buys_nothing() {
+        let (mut market, _, series, seller_addr, alice_addr, _) = setup_market();
+        let cheap = list(&mut market, &seller_addr, &series, 4.0, 2);
+        list(&mut market, &seller_addr, &series, 2000.0, 2);
+
+        let result =
+            market.submit_buy_order(series.clone(), OrderType::Market, 4, alice_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::USDT,
+                ..
+            }
+        ));
+        assert!(market.get_position(cheap, &alice_addr).is_none());
+        assert!(market.fills.is_empty());
+
+        // An empty side has nothing to take
+        let mut empty = series;
+        empty.strike_price = Amount::from_f64(60000.0);
+        let result = market.submit_buy_order(empty, OrderType::Market, 1, alice_addr);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+    }
+
+    #[test]
+    fn test_limit_buy_rests_until_filled() {
+        let (mut market, _, series, seller_addr, alice_addr, _) = setup_market();
+
+        let order_id = market
+            .submit_buy_order(
+                series.clone(),
+                OrderType::Limit(Amount::from_f64(5.0)),
+                3,
+                alice_addr,
+            )
+            .unwrap();
+        assert_eq!(
+            market.get_order_status(order_id).unwrap(),
+            OrderStatus::Open
+        );
+
+        list(&mut market, &seller_addr, &series, 4.0, 2);
+        assert_eq!(
+            market.get_order_status(order_id).unwrap(),
+            OrderStatus::PartiallyFilled
+        );
+        // The resting bid sets the price
+        let fills = market.get_order_fills(order_id).unwrap();
+        assert_eq!(fills[0].price, Amount::from_f64(5.0));
+
+        list(&mut market, &seller_addr, &series, 5.0, 2);
+        assert_eq!(
+            market.get_order_status(order_id).unwrap(),
+            OrderStatus::Filled
+        );
+    }
+
+    #[test]
+    fn test_limit_sell_lists_at_limit_price() {
+        let (mut market, _, series, seller_addr, alice_addr, _) = setup_market();
+        let option = create_test_option(seller_addr.clone(), series.expiration_time, 9.0, 3);
+
+        let order_id = market
+            .submit_sell_order(option, OrderType::Limit(Amount::from_f64(6.0)), seller_addr)
+            .unwrap();
+        let order = market.get_order_or_error(order_id).unwrap();
+        assert_eq!(order.side, OrderSide::Sell);
+        let listing_id = order.book_id;
+        assert_eq!(
+            market.listings.get(&listing_id).unwrap().ask_price,
+            Amount::from_f64(6.0)
+        );
+
+        market
+            .purchase_contracts(listing_id, 3, alice_addr)
+            .unwrap();
+        assert_eq!(
+            market.get_order_status(order_id).unwrap(),
+            OrderStatus::Filled
+        );
+    }
+
+    #[test]
+    fn test_market_sell_hits_bids_and_unlists_rest() {
+        let (mut market, _, series, seller_addr, alice_addr, bob_addr) = setup_market();
+        market
+            .place_bid(series.clone(), Amount::from_f64(5.0), 2, alice_addr.clone())
+            .unwrap();
+        market
+            .place_bid(series.clone(), Amount::from_f64(6.0), 1, bob_addr.clone())
+            .unwrap();
+        let option = create_test_option(seller_addr.clone(), series.expiration_time, 9.0, 5);
+
+        let order_id = market
+            .submit_sell_order(option, OrderType::Market, seller_addr.clone())
+            .unwrap();
+
+        let fills = market.get_order_fills(order_id).unwrap();
+        assert_eq!(fills[0].buyer_address, bob_addr);
+        assert_eq!(fills[0].price, Amount::from_f64(6.0));
+        assert_eq!(fills[1].buyer_address, alice_addr);
+        assert_eq!(market.get_filled_contracts(order_id).unwrap(), 3);
+        assert_eq!(
+            market.get_order_status(order_id).unwrap(),
+            OrderStatus::Cancelled
+        );
+
+        // Collateral of the 2 unsold contracts came back
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(17.0));
+        assert!(market.order_book(&series).is_empty());
+    }
+
+    #[test]
+    fn test_cancel_order() {
+        let (mut market, _, series, _, alice_addr, bob_addr) = setup_market();
+        let order_id = market
+            .submit_buy_order(
+                series,
+                OrderType::Limit(Amount::from_f64(5.0)),
+                3,
+                alice_addr.clone(),
+            )
+            .unwrap();
+
+        let result = market.cancel_order(order_id, bob_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("only the owner can cancel this order".into())
+        );
+
+        market.cancel_order(order_id, alice_addr.clone()).unwrap();
+        assert_eq!(
+            market.get_order_status(order_id).unwrap(),
+            OrderStatus::Cancelled
+        );
+        let alice = market.users.get(&alice_addr).unwrap();
+        assert_eq!(alice.get_balance(&Asset::USDT), Amount::from_f64(300000.0));
+
+        let result = market.cancel_order(order_id, alice_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(format!("Order #{} is already cancelled", order_id))
+        );
+
+        let result = market.cancel_order(99, alice_addr);
+        assert_eq!(result.unwrap_err(), ExchangeError::OrderNotFound(99));
+    }
+
+    #[test]
+    fn test_order_expires_with_its_series() {
+        let (mut market, clock, series, _, alice_addr, _) = setup_market();
+        let order_id = market
+            .submit_buy_order(
+                series.clone(),
+                OrderType::Limit(Amount::from_f64(5.0)),
+                1,
+                alice_addr,
+            )
+            .unwrap();
+
+        clock.advance(Duration::days(31));
+        assert!(market.order_book(&series).is_empty());
+        assert_eq!(
+            market.get_order_status(order_id).unwrap(),
+            OrderStatus::Expired
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use chrono::{Duration, Utc};
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ListingOption, ListingType, OptionState,
+    Portfolio, PortfolioLeg, PortfolioMarginParams, PricingParams, SettlementType, SimulatedClock,
+    User,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn set_rate(base_asset: Asset, rate: i64) {
+        get_rate_provider()
+            .set_rate(
+                base_asset,
+                Asset::USDC,
+                Amount::from_int(rate),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+    }
+
+    // Cash-settled contracts of 1 unit each, expiring in 30 days
+    fn create_test_option(
+        market: &Exchange,
+        grantor_address: Address,
+        base_asset: Asset,
+        listing_type: ListingType,
+        strike_price: i64,
+        contract_count: u32,
+    ) -> ListingOption {
+        ListingOption::new(
+            0,
+            base_asset,
+            Asset::USDC,
+            listing_type,
+            Amount::from_int(strike_price),
+            Amount::from_f64(0.05),
+            Amount::from_f64(0.04),
+            market.now() + Duration::days(30),
+            grantor_address,
+            contract_count,
+            Amount::from_int(1),
+            OptionState::Listed,
+        )
+        .with_settlement_type(SettlementType::Cash)
+    }
+
+    // Returns (market, seller, buyer). ETH/USDC is always 100 in this file. The seller has a
+    // margin account on portfolio margin with 100 USDC in it, the buyer has none.
+    fn setup_market() -> (Exchange, Address, Address) {
+        set_rate(Asset::ETH, 100);
+        let mut market = Exchange::with_clock(Arc::new(SimulatedClock::new(Utc::now())));
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller.add_asset(&Asset::ETH, Amount::from_int(10)).unwrap();
+        seller.add_asset(&Asset::SOL, Amount::from_int(10)).unwrap();
+        seller
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer.add_asset(&Asset::ETH, Amount::from_int(10)).unwrap();
+        buyer
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        market.open_margin_account(seller_addr.clone()).unwrap();
+        market
+            .deposit_margin(&Asset::USDC, Amount::from_int(100), seller_addr.clone())
+            .unwrap();
+        market
+            .set_portfolio_margin(true, seller_addr.clone())
+            .unwrap();
+
+        (market, seller_addr, buyer_addr)
+    }
+
+    #[test]
+    fn test_shock_grid() {
+        let params = PortfolioMarginParams::default();
+        let grid = params.shock_grid(2_000);
+
+        // 3 steps either side of no move, each under every volatility shock
+        assert_eq!(grid.len(), 21);
+        assert!(grid.contains(&(0.0, 0.0)));
+        let spot_shocks: Vec<f64> = grid.iter().step_by(3).map(|(spot, _)| *spot).collect();
+        let expected = [
+            -0.2,
+            -0.2 * 2.0 / 3.0,
+            -0.2 / 3.0,
+            0.0,
+            0.2 / 3.0,
+            0.2 * 2.0 / 3.0,
+            0.2,
+        ];
+        for (shock, expected) in spot_shocks.iter().zip(expected) {
+            assert!((shock - expected).abs() < 1e-12);
+        }
+
+        let flat = PortfolioMarginParams {
+            spot_steps: 0,
+            ..params
+        };
+        assert_eq!(
+            flat.shock_grid(2_000),
+            vec![(0.0, -0.3), (0.0, 0.0), (0.0, 0.3)]
+        );
+    }
+
+    #[test]
+    fn test_spreads_offset_and_naked_shorts_do_not() {
+        let market = Exchange::new();
+        let params = PortfolioMarginParams::default();
+        let spots = HashMap::from([(Asset::ETH, Amount::from_int(100))]);
+        let leg = |listing_type: ListingType, strike_price: i64, contracts: i64| PortfolioLeg {
+            option: create_test_option(
+                &market,
+                create_test_address("1"),
+                Asset::ETH,
+                listing_type,
+                strike_price,
+                1,
+            ),
+            contracts,
+        };
+        let requirement = |legs: Vec<PortfolioLeg>| {
+            let mut portfolio = Portfolio::new(Asset::USDC);
+            portfolio.legs = legs;
+            portfolio
+                .requirement(&params, 2_000, &spots, market.now())
+                .unwrap()
+        };
+
+        // A short call loses the 20% move up less the time value it's already worth
+        let naked = requirement(vec![leg(ListingType::CALL, 100, -1)]);
+        assert!(naked > Amount::from_int(10) && naked < Amount::from_int(20));
+        // Owning the 110 call caps the loss at the 10 between the strikes
+        let spread = requirement(vec![
+            leg(ListingType::CALL, 100, -1),
+            leg(ListingType::CALL, 110, 1),
+        ]);
+        assert!(spread > Amount::ZERO && spread <= Amount::from_int(10));
+        // Long options alone can't lose more than they're worth
+        assert_eq!(
+            requirement(vec![leg(ListingType::PUT, 90, 2)]),
+            Amount::ZERO
+        );
+
+        // A short put 20% down loses more than the 10 it's in the money by then
+        let put = requirement(vec![leg(ListingType::PUT, 90, -1)]);
+        assert!(put > Amount::from_int(10) && put < Amount::from_int(20));
+
+        // Every underlying held needs a spot to be revalued at
+        let mut portfolio = Portfolio::new(Asset::USDC);
+        portfolio.legs = vec![leg(ListingType::CALL, 100, -1)];
+        portfolio
+            .spot_balances
+            .insert(Asset::SOL, Amount::from_int(1));
+        let result = portfolio.requirement(&params, 2_000, &spots, market.now());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::RateMissing {
+                base: Asset::SOL,
+                quote: Asset::USDC,
+            }
+        );
+    }
+
+    #[test]
+    fn test_covered_call_needs_less_quote_collateral() {
+        let (mut market, seller_addr, buyer_addr) = setup_market();
+
+        // 10 naked calls lose more than the 100 deposited once ETH is up 20%
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::CALL,
+            100,
+            10,
+        );
+        let result = market.list_option(seller_addr.clone(), option.clone());
+        let Err(ExchangeError::InsufficientMargin {
+            required: naked, ..
+        }) = result
+        else {
+            panic!("naked calls should need more margin");
+        };
+
+        // Covered by ETH in the account they only lose on the way down, with the ETH less the
+        // premium the calls shed
+        market
+            .deposit_margin(&Asset::ETH, Amount::from_int(10), seller_addr.clone())
+            .unwrap();
+        market
+            .deposit_margin(&Asset::USDC, Amount::from_int(50), seller_addr.clone())
+            .unwrap();
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market
+            .purchase_contracts(listing_id, 4, buyer_addr.clone())
+            .unwrap();
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert!(health.initial_requirement > Amount::ZERO);
+        assert!(health.initial_requirement < naked);
+        assert!(health.maintenance_requirement <= health.initial_requirement);
+        assert_eq!(health.collateral, Amount::from_int(150));
+
+        // The ETH is the hedge, it can't leave the account while the calls are open
+        let result = market.withdraw_margin(&Asset::ETH, Amount::from_int(10), seller_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin {
+                asset: Asset::USDC,
+                ..
+            }
+        ));
+        // Per listing the same calls would need 20 each
+        let result = market.set_portfolio_margin(false, seller_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin {
+                owner: seller_addr.clone(),
+                asset: Asset::USDC,
+                required: Amount::from_int(200),
+                available: Amount::from_int(150),
+            }
+        );
+        assert!(market.margin_accounts[&seller_addr].portfolio_margin);
+    }
+
+    #[test]
+    fn test_long_options_offset_written_ones() {
+        let (mut market, seller_addr, buyer_addr) = setup_market();
+
+        // The buyer writes 110 calls the usual way and the seller buys them
+        let hedge = create_test_option(
+            &market,
+            buyer_addr.clone(),
+            Asset::ETH,
+            ListingType::CALL,
+            110,
+            10,
+        );
+        let hedge_id = market.list_option(buyer_addr.clone(), hedge).unwrap();
+
+        // 10 naked 100 calls lose well over the 100 in the account
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::CALL,
+            100,
+            10,
+        );
+        let result = market.list_option(seller_addr.clone(), option.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin { .. }
+        ));
+
+        // As a call spread they can't lose more than 10 each. The hedge goes up for resale
+        // before anything leans on it.
+        market
+            .purchase_contracts(hedge_id, 10, seller_addr.clone())
+            .unwrap();
+        market
+            .relist_purchased_option(hedge_id, Amount::from_f64(0.05), seller_addr.clone())
+            .unwrap();
+        market.list_option(seller_addr.clone(), option).unwrap();
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert!(health.initial_requirement <= Amount::from_int(100));
+        assert!(health.maintenance_requirement <= health.initial_requirement);
+        assert!(health.is_healthy());
+
+        // Now the hedge can't be sold or exercised away from the calls it covers
+        let result = market.purchase_resale(hedge_id, buyer_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin { .. }
+        ));
+        let result =
+            market.relist_purchased_option(hedge_id, Amount::from_f64(0.06), seller_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin { .. }
+        ));
+        let result = market.exercise_contracts(hedge_id, 10, seller_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin { .. }
+        ));
+        assert_eq!(
+            market
+                .get_position(hedge_id, &seller_addr)
+                .unwrap()
+                .contracts,
+            10
+        );
+
+        // Accounts that aren't open can't switch
+        let result = market.set_portfolio_margin(true, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::MarginAccountNotFound(buyer_addr)
+        );
+    }
+
+    #[test]
+    fn test_covered_call_in_the_money_is_liquidated() {
+        set_rate(Asset::SOL, 100);
+        let (mut market, seller_addr, buyer_addr) = setup_market();
+        market
+            .deposit_margin(&Asset::SOL, Amount::from_int(2), seller_addr.clone())
+            .unwrap();
+        let desk_addr = market.escrow_user.address.clone();
+        market
+            .users
+            .get_mut(&desk_addr)
+            .unwrap()
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::SOL,
+            ListingType::CALL,
+            100,
+            2,
+        );
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market
+            .purchase_contracts(listing_id, 2, buyer_addr.clone())
+            .unwrap();
+
+        // The SOL covers any move, but what the calls owe settles in USDC
+        set_rate(Asset::SOL, 160);
+        let health = market.margin_health(&seller_addr, &Asset::USDC).unwrap();
+        assert_eq!(health.maintenance_requirement, Amount::from_int(120));
+        assert!(!health.is_healthy());
+
+        let liquidation = market
+            .liquidate_margin_account(&seller_addr, &Asset::USDC)
+            .unwrap();
+        // Only the 26 the USDC in the account falls short by is sold
+        assert_eq!(
+            liquidation.collateral_sold,
+            vec![(Asset::SOL, Amount::from_f64(0.1625))]
+        );
+        assert_eq!(liquidation.sale_proceeds, Amount::from_int(26));
+        assert_eq!(liquidation.buyback_cost, Amount::from_int(120));
+        assert_eq!(liquidation.penalty, Amount::from_int(6));
+        let collateral = &market.margin_accounts[&seller_addr].collateral;
+        assert_eq!(collateral.get_balance(&Asset::USDC), Amount::ZERO);
+        assert_eq!(
+            collateral.get_balance(&Asset::SOL),
+            Amount::from_f64(1.8375)
+        );
+    }
+
+    #[test]
+    fn test_portfolio_margin_params_are_set_by_the_admin() {
+        let (mut market, seller_addr, _) = setup_market();
+        let admin = market.market_admin_address.clone();
+        let option = create_test_option(
+            &market,
+            seller_addr.clone(),
+            Asset::ETH,
+            ListingType::PUT,
+            90,
+            1,
+        );
+        market.list_option(seller_addr.clone(), option).unwrap();
+        let default_requirement = market
+            .margin_health(&seller_addr, &Asset::USDC)
+            .unwrap()
+            .initial_requirement;
+
+        let calm = PortfolioMarginParams {
+            pricing_params: PricingParams::new(0.2, 0.04),
+            spot_steps: 3,
+            vol_shocks: vec![0.0],
+        };
+        let result = market.set_portfolio_margin_params(calm.clone(), seller_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not the market admin".into())
+        );
+        for invalid in [
+            PortfolioMarginParams {
+                pricing_params: PricingParams::new(0.0, 0.04),
+                ..calm.clone()
+            },
+            PortfolioMarginParams {
+                vol_shocks: vec![],
+                ..calm.clone()
+            },
+            PortfolioMarginParams {
+                vol_shocks: vec![-1.0],
+                ..calm.clone()
+            },
+        ] {
+            let result = market.set_portfolio_margin_params(invalid, admin.clone());
+            assert!(matches!(
+                result.unwrap_err(),
+                ExchangeError::InvalidInput(_)
+            ));
+        }
+
+        // At 20% volatility the put is worth next to nothing today, and at 80 just over the 10
+        // it's in the money by
+        market.set_portfolio_margin_params(calm, admin).unwrap();
+        let requirement = market
+            .margin_health(&seller_addr, &Asset::USDC)
+            .unwrap()
+            .initial_requirement;
+        assert!(default_requirement > Amount::from_int(10));
+        assert!(requirement > Amount::from_int(9) && requirement < Amount::from_int(10));
+    }
+}
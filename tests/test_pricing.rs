@@ -0,0 +1,301 @@
+use chrono::{Duration, Utc};
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::pricing::{
+    black_scholes, fair_ask_price, implied_volatility, implied_volatility_by_asset,
+    listing_implied_volatility, value_listing, value_series, years_until,
+};
+use options_trading::{
+    Address, Amount, Asset, ExchangeError, ListingOption, ListingType, OptionState, PricingParams,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn textbook_params() -> PricingParams {
+        PricingParams::new(0.2, 0.05)
+    }
+
+    // A pair no other test reads from the shared rate provider, always set to 100
+    fn set_eth_usdc_rate() {
+        get_rate_provider()
+            .set_rate(
+                Asset::ETH,
+                Asset::USDC,
+                Amount::from_int(100),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_textbook_call_and_put() {
+        let call = black_scholes(&ListingType::CALL, 100.0, 100.0, 1.0, &textbook_params());
+        assert_close(call.price, 10.4506);
+        assert_close(call.delta, 0.6368);
+        assert_close(call.gamma, 0.018762);
+        assert_close(call.vega, 0.375240);
+        assert_close(call.theta, -6.414028 / 365.0);
+        assert_close(call.rho, 0.532325);
+
+        let put = black_scholes(&ListingType::PUT, 100.0, 100.0, 1.0, &textbook_params());
+        assert_close(put.price, 5.5735);
+        assert_close(put.delta, -0.3632);
+        assert_close(put.gamma, call.gamma);
+        assert_close(put.vega, call.vega);
+        assert_close(put.rho, -0.418905);
+    }
+
+    #[test]
+    fn test_put_call_parity() {
+        let params = PricingParams::new(0.65, 0.03);
+        for (spot, strike, years) in [(45000.0, 50000.0, 0.1), (3000.0, 2500.0, 0.5)] {
+            let call = black_scholes(&ListingType::CALL, spot, strike, years, &params);
+            let put = black_scholes(&ListingType::PUT, spot, strike, years, &params);
+
+            // C - P = S - K * e^(-rT)
+            let forward_gap = spot - strike * (-params.risk_free_rate * years).exp();
+            assert!((call.price - put.price - forward_gap).abs() < 1e-6);
+            assert!((call.delta - put.delta - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_no_time_left_is_intrinsic_value() {
+        let params = textbook_params();
+        let call = black_scholes(&ListingType::CALL, 120.0, 100.0, 0.0, &params);
+        assert_eq!(call.price, 20.0);
+        assert_eq!(call.delta, 1.0);
+        assert_eq!(call.gamma, 0.0);
+
+        let put = black_scholes(&ListingType::PUT, 120.0, 100.0, 0.0, &params);
+        assert_eq!(put.price, 0.0);
+        assert_eq!(put.delta, 0.0);
+
+        // Expiry in the past counts as no time left
+        let now = Utc::now();
+        assert_eq!(years_until(now, now - Duration::days(1)), 0.0);
+        assert_eq!(years_until(now, now + Duration::days(365)), 1.0);
+    }
+
+    #[test]
+    fn test_deeper_in_the_money_is_worth_more() {
+        let params = PricingParams::new(0.8, 0.0);
+        let mut last_price = 0.0;
+        for spot in [80.0, 90.0, 100.0, 110.0, 120.0] {
+            let call = black_scholes(&ListingType::CALL, spot, 100.0, 30.0 / 365.0, &params);
+            assert!(call.price > last_price);
+            assert!(call.delta > 0.0 && call.delta < 1.0);
+            assert!(call.theta < 0.0);
+            last_price = call.price;
+        }
+    }
+
+    #[test]
+    fn test_listing_priced_at_provider_spot() {
+        set_eth_usdc_rate();
+
+        let now = Utc::now();
+        let option = ListingOption::new(
+            1,
+            Asset::ETH,
+            Asset::USDC,
+            ListingType::CALL,
+            Amount::from_int(100),
+            Amount::from_int(20),
+            Amount::from_int(19),
+            now + Duration::days(365),
+            create_test_address("1"),
+            1,
+            Amount::from_int(2),
+            OptionState::Listed,
+        );
+
+        // A contract covers 2 ETH
+        let valuation = value_listing(&option, &textbook_params(), now).unwrap();
+        assert_close(valuation.price, 2.0 * 10.4506);
+        assert_close(valuation.delta, 2.0 * 0.6368);
+        let per_unit = value_series(&option.series(), &textbook_params(), now).unwrap();
+        assert_close(per_unit.price, 10.4506);
+
+        // Its premium is ask_price * 100
+        let fair_ask = fair_ask_price(&option, &textbook_params(), now).unwrap();
+        assert_close(fair_ask.to_f64(), 0.209012);
+    }
+
+    #[test]
+    fn test_missing_spot_rate() {
+        let now = Utc::now();
+        let option = ListingOption::new(
+            1,
+            Asset::SOL,
+            Asset::USDC,
+            ListingType::PUT,
+            Amount::from_int(100),
+            Amount::from_int(20),
+            Amount::from_int(19),
+            now + Duration::days(30),
+            create_test_address("1"),
+            1,
+            Amount::from_int(1),
+            OptionState::Listed,
+        );
+
+        let result = value_listing(&option, &textbook_params(), now);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::RateMissing {
+                base: Asset::SOL,
+                quote: Asset::USDC,
+            }
+        );
+    }
+
+    #[test]
+    fn test_implied_volatility_recovers_model_volatility() {
+        for (listing_type, strike) in [
+            (ListingType::CALL, 60.0),
+            (ListingType::CALL, 100.0),
+            (ListingType::CALL, 160.0),
+            (ListingType::PUT, 70.0),
+            (ListingType::PUT, 130.0),
+        ] {
+            let params = PricingParams::new(0.65, 0.03);
+            let price = black_scholes(&listing_type, 100.0, strike, 0.25, &params).price;
+
+            let implied = implied_volatility(&listing_type, price, 100.0, strike, 0.25, 0.03);
+            assert!(
+                (implied.unwrap() - 0.65).abs() < 1e-6,
+                "{:?} struck at {} implied {:?}",
+                listing_type,
+                strike,
+                implied
+            );
+        }
+    }
+
+    #[test]
+    fn test_implied_volatility_rejects_arbitrage_prices() {
+        // Below intrinsic value
+        assert_eq!(
+            implied_volatility(&ListingType::CALL, 15.0, 120.0, 100.0, 0.5, 0.0),
+            None
+        );
+        // A call can't be worth more than the underlying
+        assert_eq!(
+            implied_volatility(&ListingType::CALL, 130.0, 120.0, 100.0, 0.5, 0.0),
+            None
+        );
+        // Nothing left to solve at expiry
+        assert_eq!(
+            implied_volatility(&ListingType::PUT, 5.0, 100.0, 100.0, 0.0, 0.0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_listing_implied_volatility_per_side() {
+        set_eth_usdc_rate();
+        let now = Utc::now();
+        let params = PricingParams::new(0.5, 0.0);
+        // Premiums of 2 ETH contracts at 60% and 40% volatility, quoted as price * 100
+        let quote = |volatility: f64| {
+            let value = black_scholes(
+                &ListingType::PUT,
+                100.0,
+                90.0,
+                0.5,
+                &PricingParams::new(volatility, 0.0),
+            );
+            Amount::from_f64(value.price * 2.0 / 100.0)
+        };
+        let option = ListingOption::new(
+            7,
+            Asset::ETH,
+            Asset::USDC,
+            ListingType::PUT,
+            Amount::from_int(90),
+            quote(0.6),
+            quote(0.4),
+            now + Duration::days(365) / 2,
+            create_test_address("1"),
+            1,
+            Amount::from_int(2),
+            OptionState::Listed,
+        );
+
+        let implied = listing_implied_volatility(&option, params.risk_free_rate, now).unwrap();
+        assert_eq!(implied.listing_id, 7);
+        assert!((implied.ask.unwrap() - 0.6).abs() < 1e-6);
+        assert!((implied.bid.unwrap() - 0.4).abs() < 1e-6);
+        assert!((implied.mid().unwrap() - 0.5).abs() < 1e-6);
+        assert_close(
+            implied.vega,
+            black_scholes(&ListingType::PUT, 100.0, 90.0, 0.5, &params).vega * 2.0,
+        );
+    }
+
+    #[test]
+    fn test_implied_volatility_aggregated_per_asset() {
+        set_eth_usdc_rate();
+        let now = Utc::now();
+        let listing = |listing_id: u32, volatility: f64, state: OptionState| {
+            let value = black_scholes(
+                &ListingType::CALL,
+                100.0,
+                100.0,
+                1.0,
+                &PricingParams::new(volatility, 0.0),
+            );
+            let price = Amount::from_f64(value.price / 100.0);
+            ListingOption::new(
+                listing_id,
+                Asset::ETH,
+                Asset::USDC,
+                ListingType::CALL,
+                Amount::from_int(100),
+                price,
+                price,
+                now + Duration::days(365),
+                create_test_address("1"),
+                1,
+                Amount::from_int(1),
+                state,
+            )
+        };
+        // Partly sold listings still quote their ask, sold out ones don't
+        let mut sold_out = listing(5, 2.0, OptionState::Purchased);
+        sold_out.contract_count = 0;
+        let listings = vec![
+            listing(1, 0.4, OptionState::Listed),
+            listing(2, 0.6, OptionState::Listed),
+            listing(3, 0.5, OptionState::Purchased),
+            listing(4, 2.0, OptionState::Unlisted),
+            sold_out,
+        ];
+
+        let by_asset = implied_volatility_by_asset(&listings, 0.0, now);
+        let eth = by_asset.get(&Asset::ETH).unwrap();
+        assert_eq!(eth.listing_count, 3);
+        // At the money vega barely moves with volatility, so the weighted mean sits near 50%
+        assert!((eth.volatility - 0.5).abs() < 0.01);
+        assert!(!by_asset.contains_key(&Asset::BTC));
+    }
+}
@@ -126,7 +126,7 @@ mod tests {
 
         // Old manager should no longer be authorized
         let roles_manager_role = NamedRole("RolesManager".to_string());
-        let result = authorizer.only_authorized_role(&[roles_manager_role.clone()], old_manager_addr);
+        let result = authorizer.only_authorized_role(std::slice::from_ref(&roles_manager_role), old_manager_addr);
         assert!(result.is_err());
 
         // New manager should be authorized
@@ -171,7 +171,7 @@ mod tests {
         authorizer.assign_role(admin_role.clone(), user_addr.clone(), manager_addr).unwrap();
 
         // User should be authorized for admin role
-        let result = authorizer.only_authorized_role(&[admin_role.clone()], user_addr.clone());
+        let result = authorizer.only_authorized_role(std::slice::from_ref(&admin_role), user_addr.clone());
         assert!(result.is_ok());
 
         // User should be authorized when checking for either admin OR moderator
@@ -197,14 +197,14 @@ mod tests {
         authorizer.assign_role(admin_role.clone(), first_admin_addr.clone(), manager_addr.clone()).unwrap();
 
         // Verify first admin is authorized
-        let result = authorizer.only_authorized_role(&[admin_role.clone()], first_admin_addr.clone());
+        let result = authorizer.only_authorized_role(std::slice::from_ref(&admin_role), first_admin_addr.clone());
         assert!(result.is_ok());
 
         // Reassign role to second admin
         authorizer.assign_role(admin_role.clone(), second_admin_addr.clone(), manager_addr).unwrap();
 
         // First admin should no longer be authorized (role was reassigned, not duplicated)
-        let result = authorizer.only_authorized_role(&[admin_role.clone()], first_admin_addr);
+        let result = authorizer.only_authorized_role(std::slice::from_ref(&admin_role), first_admin_addr);
         assert!(result.is_err());
 
         // Second admin should be authorized
@@ -0,0 +1,307 @@
+use chrono::{Duration, Utc};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ExerciseStyle, ListingOption, ListingType,
+    OptionState, SettlementType, User,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn create_test_option(grantor_address: Address) -> ListingOption {
+        ListingOption {
+            listing_id: 0,
+            base_asset: Asset::BTC,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::CALL,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
+            expiration_time: Utc::now() + Duration::days(30),
+            grantor_address,
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        }
+    }
+
+    // Seller lists, holder purchases; returns (market, seller, holder, new buyer, listing id)
+    fn setup_purchased_option() -> (Exchange, Address, Address, Address, u32) {
+        let mut market = Exchange::new();
+        let seller_addr = create_test_address("1");
+        let holder_addr = create_test_address("2");
+        let buyer_addr = create_test_address("3");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::BTC, Amount::from_f64(10.0))
+            .unwrap();
+
+        let mut holder = User::new(holder_addr.clone());
+        holder
+            .add_asset(&Asset::USDT, Amount::from_f64(100000.0))
+            .unwrap();
+
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDT, Amount::from_f64(100000.0))
+            .unwrap();
+
+        market.users.insert(seller_addr.clone(), seller);
+        market.users.insert(holder_addr.clone(), holder);
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        let option = create_test_option(seller_addr.clone());
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market
+            .purchase_option(listing_id, holder_addr.clone())
+            .unwrap();
+
+        (market, seller_addr, holder_addr, buyer_addr, listing_id)
+    }
+
+    #[test]
+    fn test_resale_success() {
+        let (mut market, seller_addr, holder_addr, buyer_addr, listing_id) =
+            setup_purchased_option();
+
+        market
+            .relist_purchased_option(listing_id, Amount::from_f64(600.0), holder_addr.clone())
+            .unwrap();
+        assert!(market.resale_listings.contains_key(&listing_id));
+
+        market
+            .purchase_resale(listing_id, buyer_addr.clone())
+            .unwrap();
+
+        // Rights moved over and the ask is consumed
+        assert_eq!(
+            market
+                .get_position(listing_id, &buyer_addr)
+                .unwrap()
+                .contracts,
+            1
+        );
+        assert!(market.get_position(listing_id, &holder_addr).is_none());
+        assert!(!market.resale_listings.contains_key(&listing_id));
+
+        // Resale premium is 60000 (600 * 100), fee is 60 (60000 * 0.001) on each side
+        let buyer = market.users.get(&buyer_addr).unwrap();
+        assert_eq!(buyer.get_balance(&Asset::USDT), Amount::from_f64(39940.0)); // 100k - 60k - 60
+
+        let holder = market.users.get(&holder_addr).unwrap();
+        assert_eq!(holder.get_balance(&Asset::USDT), Amount::from_f64(109890.0)); // 49950 + 60k - 60
+
+        // Grantor is untouched and the collateral stays in escrow
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(9.0));
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::BTC),
+            Amount::from_f64(1.0)
+        );
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+        // 100 + 120 fees, 10% of them for the insurance fund
+        assert_eq!(
+            market.treasury.get_balance(&Asset::USDT),
+            Amount::from_f64(198.0)
+        );
+        assert_eq!(
+            market.insurance_fund.get_balance(&Asset::USDT),
+            Amount::from_f64(22.0)
+        );
+    }
+
+    #[test]
+    fn test_new_beneficiary_can_exercise_after_resale() {
+        let (mut market, _, holder_addr, buyer_addr, listing_id) = setup_purchased_option();
+        market
+            .users
+            .get_mut(&buyer_addr)
+            .unwrap()
+            .add_asset(&Asset::USDT, Amount::from_f64(100000.0))
+            .unwrap();
+
+        market
+            .relist_purchased_option(listing_id, Amount::from_f64(600.0), holder_addr.clone())
+            .unwrap();
+        market
+            .purchase_resale(listing_id, buyer_addr.clone())
+            .unwrap();
+
+        // Previous holder lost the rights
+        let result = market.exercise_option(listing_id, holder_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not beneficiary of option".into())
+        );
+
+        market
+            .exercise_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        let buyer = market.users.get(&buyer_addr).unwrap();
+        assert_eq!(buyer.get_balance(&Asset::BTC), Amount::from_f64(1.0));
+    }
+
+    #[test]
+    fn test_relist_by_non_beneficiary_fails() {
+        let (mut market, seller_addr, _, buyer_addr, listing_id) = setup_purchased_option();
+
+        let result =
+            market.relist_purchased_option(listing_id, Amount::from_f64(600.0), buyer_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not beneficiary of option".into())
+        );
+
+        let result =
+            market.relist_purchased_option(listing_id, Amount::from_f64(600.0), seller_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not beneficiary of option".into())
+        );
+    }
+
+    #[test]
+    fn test_relist_unpurchased_option_fails() {
+        let mut market = Exchange::new();
+        let seller_addr = create_test_address("1");
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::BTC, Amount::from_f64(10.0))
+            .unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+
+        let option = create_test_option(seller_addr.clone());
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+
+        let result =
+            market.relist_purchased_option(listing_id, Amount::from_f64(600.0), seller_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidState {
+                listing_id,
+                state: OptionState::Listed
+            }
+        );
+    }
+
+    #[test]
+    fn test_purchase_resale_insufficient_funds() {
+        let (mut market, _, holder_addr, buyer_addr, listing_id) = setup_purchased_option();
+
+        market
+            .relist_purchased_option(listing_id, Amount::from_f64(2000.0), holder_addr.clone())
+            .unwrap();
+
+        let result = market.purchase_resale(listing_id, buyer_addr);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::USDT,
+                ..
+            }
+        ));
+
+        // Nothing moved
+        assert_eq!(
+            market
+                .get_position(listing_id, &holder_addr)
+                .unwrap()
+                .contracts,
+            1
+        );
+        assert!(market.resale_listings.contains_key(&listing_id));
+    }
+
+    #[test]
+    fn test_negative_resale_ask_is_rejected() {
+        let (mut market, _, holder_addr, _, listing_id) = setup_purchased_option();
+
+        let result =
+            market.relist_purchased_option(listing_id, Amount::from_f64(-1.0), holder_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput("Ask price must not be negative".into())
+        );
+        assert!(!market.resale_listings.contains_key(&listing_id));
+    }
+
+    #[test]
+    fn test_cancel_resale() {
+        let (mut market, _, holder_addr, buyer_addr, listing_id) = setup_purchased_option();
+
+        market
+            .relist_purchased_option(listing_id, Amount::from_f64(600.0), holder_addr.clone())
+            .unwrap();
+
+        let result = market.cancel_resale(listing_id, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("only the reseller can cancel this resale".into())
+        );
+
+        market.cancel_resale(listing_id, holder_addr).unwrap();
+
+        let result = market.purchase_resale(listing_id, buyer_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::ResaleNotFound(listing_id)
+        );
+    }
+
+    #[test]
+    fn test_exercise_removes_resale() {
+        let (mut market, _, holder_addr, _, listing_id) = setup_purchased_option();
+        market
+            .users
+            .get_mut(&holder_addr)
+            .unwrap()
+            .add_asset(&Asset::USDT, Amount::from_f64(100000.0))
+            .unwrap();
+
+        market
+            .relist_purchased_option(listing_id, Amount::from_f64(600.0), holder_addr.clone())
+            .unwrap();
+        market.exercise_option(listing_id, holder_addr).unwrap();
+
+        assert!(!market.resale_listings.contains_key(&listing_id));
+    }
+
+    #[test]
+    fn test_one_open_resale_per_listing() {
+        let (mut market, seller_addr, holder_addr, buyer_addr, _) = setup_purchased_option();
+
+        // A second listing split between two holders
+        let mut option = create_test_option(seller_addr.clone());
+        option.contract_count = 2;
+        option.ask_price = Amount::from_f64(1.0);
+        let listing_id = market.list_option(seller_addr, option).unwrap();
+        market
+            .purchase_option(listing_id, holder_addr.clone())
+            .unwrap();
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+
+        market
+            .relist_purchased_option(listing_id, Amount::from_f64(600.0), holder_addr)
+            .unwrap();
+        let result =
+            market.relist_purchased_option(listing_id, Amount::from_f64(550.0), buyer_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(format!(
+                "Listing #{} already has an open resale",
+                listing_id
+            ))
+        );
+    }
+}
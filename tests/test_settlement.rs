@@ -0,0 +1,188 @@
+use chrono::{Duration, Utc};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ExerciseStyle, ListingOption, ListingType,
+    OptionState, SettlementType, SimulatedClock, User,
+};
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn create_test_option(grantor_address: Address, listing_type: ListingType) -> ListingOption {
+        ListingOption {
+            listing_id: 0,
+            base_asset: Asset::BTC,
+            quote_asset: Asset::USDT,
+            listing_type,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
+            expiration_time: Utc::now() + Duration::days(30),
+            grantor_address,
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        }
+    }
+
+    fn setup_market_with_users() -> (Exchange, Arc<SimulatedClock>, Address, Address) {
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::BTC, Amount::from_f64(10.0))
+            .unwrap();
+        seller
+            .add_asset(&Asset::USDT, Amount::from_f64(100000.0))
+            .unwrap();
+
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDT, Amount::from_f64(200000.0))
+            .unwrap();
+
+        market.users.insert(seller_addr.clone(), seller);
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        (market, clock, seller_addr, buyer_addr)
+    }
+
+    #[test]
+    fn test_settle_unsold_expired_listing() {
+        let (mut market, clock, seller_addr, _) = setup_market_with_users();
+        let option = create_test_option(seller_addr.clone(), ListingType::CALL);
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        assert_eq!(report.settled.len(), 1);
+        assert_eq!(report.settled[0].listing_id, listing_id);
+        assert_eq!(report.settled[0].released_asset, Asset::BTC);
+        assert_eq!(report.settled[0].released_amount, Amount::from_f64(1.0));
+        assert!(!report.settled[0].was_purchased);
+        assert_eq!(
+            report.total_released.get(&Asset::BTC),
+            Some(&Amount::from_f64(1.0))
+        );
+
+        // Collateral is back with the grantor
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(10.0));
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::BTC),
+            Amount::from_f64(0.0)
+        );
+        assert_eq!(
+            market.listings.get(&listing_id).unwrap().state,
+            OptionState::Expired
+        );
+    }
+
+    #[test]
+    fn test_settle_purchased_unexercised_put() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market_with_users();
+        let mut option = create_test_option(seller_addr.clone(), ListingType::PUT);
+        option.strike_price = Amount::from_f64(3000.0);
+        option.ask_price = Amount::from_f64(20.0);
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+
+        assert_eq!(report.settled.len(), 1);
+        assert_eq!(report.settled[0].released_asset, Asset::USDT);
+        assert_eq!(report.settled[0].released_amount, Amount::from_f64(3000.0));
+        assert!(report.settled[0].was_purchased);
+
+        // 100k - 3000 collateral + 2000 premium - 2 fee + 3000 refund
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::USDT), Amount::from_f64(101998.0));
+        // Nothing remains in escrow, the fees went to the treasury and insurance fund
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+        assert_eq!(
+            market.treasury.get_balance(&Asset::USDT),
+            Amount::from_f64(3.6)
+        );
+    }
+
+    #[test]
+    fn test_settle_skips_live_and_exercised_listings() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market_with_users();
+
+        let live_option = create_test_option(seller_addr.clone(), ListingType::CALL);
+        market
+            .list_option(seller_addr.clone(), live_option)
+            .unwrap();
+
+        let exercised_option = create_test_option(seller_addr.clone(), ListingType::CALL);
+        let exercised_id = market
+            .list_option(seller_addr.clone(), exercised_option)
+            .unwrap();
+        market
+            .purchase_option(exercised_id, buyer_addr.clone())
+            .unwrap();
+        market
+            .exercise_option(exercised_id, buyer_addr.clone())
+            .unwrap();
+
+        // Nothing has expired yet
+        let report = market.settle_expired().unwrap();
+        assert!(report.settled.is_empty());
+
+        // The live listing expires, the exercised one has no collateral left
+        clock.advance(Duration::days(31));
+        let report = market.settle_expired().unwrap();
+        assert_eq!(report.settled.len(), 1);
+        assert_ne!(report.settled[0].listing_id, exercised_id);
+    }
+
+    #[test]
+    fn test_settle_is_idempotent() {
+        let (mut market, clock, seller_addr, _) = setup_market_with_users();
+        let option = create_test_option(seller_addr.clone(), ListingType::CALL);
+        market.list_option(seller_addr.clone(), option).unwrap();
+
+        clock.advance(Duration::days(31));
+        assert_eq!(market.settle_expired().unwrap().settled.len(), 1);
+        assert!(market.settle_expired().unwrap().settled.is_empty());
+
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(10.0));
+    }
+
+    #[test]
+    fn test_expired_listing_cannot_be_purchased_or_unlisted() {
+        let (mut market, clock, seller_addr, buyer_addr) = setup_market_with_users();
+        let option = create_test_option(seller_addr.clone(), ListingType::CALL);
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+
+        clock.advance(Duration::days(31));
+        market.settle_expired().unwrap();
+
+        let result = market.purchase_option(listing_id, buyer_addr);
+        assert_eq!(result.unwrap_err(), ExchangeError::Expired(listing_id));
+
+        let result = market.unlist_option(listing_id, seller_addr.clone());
+        assert_eq!(result.unwrap_err(), ExchangeError::Expired(listing_id));
+
+        // Collateral was only returned once
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(10.0));
+    }
+}
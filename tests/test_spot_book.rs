@@ -0,0 +1,325 @@
+use options_trading::exchange::SpotAction;
+use options_trading::exchange_rate_provider::get_readonly_rate_provider;
+use options_trading::{Address, Amount, Asset, AssetPair, Exchange, ExchangeError, User};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn btc_usdt() -> AssetPair {
+        AssetPair::from(Asset::BTC, Asset::USDT)
+    }
+
+    // Returns (exchange, alice, bob, carol), each holding 10 BTC and 10k USDT
+    fn setup_exchange() -> (Exchange, Address, Address, Address) {
+        let mut exchange = Exchange::new();
+        let alice_addr = create_test_address("1");
+        let bob_addr = create_test_address("2");
+        let carol_addr = create_test_address("3");
+
+        for address in [&alice_addr, &bob_addr, &carol_addr] {
+            let mut user = User::new(address.clone());
+            user.add_asset(&Asset::BTC, Amount::from_f64(10.0)).unwrap();
+            user.add_asset(&Asset::USDT, Amount::from_f64(10000.0))
+                .unwrap();
+            exchange.users.insert(address.clone(), user);
+        }
+
+        (exchange, alice_addr, bob_addr, carol_addr)
+    }
+
+    fn balance(exchange: &Exchange, address: &Address, asset: &Asset) -> Amount {
+        exchange.users.get(address).unwrap().get_balance(asset)
+    }
+
+    fn ask(exchange: &mut Exchange, seller: &Address, price: f64, amount: f64) -> u32 {
+        exchange
+            .place_spot_limit_order(
+                btc_usdt(),
+                SpotAction::SELL,
+                Amount::from_f64(price),
+                Amount::from_f64(amount),
+                seller.clone(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_limit_orders_rest_with_funds_locked() {
+        let (mut exchange, alice_addr, bob_addr, _) = setup_exchange();
+
+        ask(&mut exchange, &alice_addr, 100.0, 1.0);
+        exchange
+            .place_spot_limit_order(
+                btc_usdt(),
+                SpotAction::BUY,
+                Amount::from_f64(90.0),
+                Amount::from_f64(2.0),
+                bob_addr.clone(),
+            )
+            .unwrap();
+
+        // Nothing crosses, both sides rest and their funds sit in escrow
+        let book = exchange.spot_books.get(&btc_usdt()).unwrap();
+        assert_eq!(book.best_ask().unwrap().price, Amount::from_f64(100.0));
+        assert_eq!(book.best_bid().unwrap().price, Amount::from_f64(90.0));
+        assert!(book.trades.is_empty());
+        assert_eq!(
+            balance(&exchange, &alice_addr, &Asset::BTC),
+            Amount::from_f64(9.0)
+        );
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::USDT),
+            Amount::from_f64(9820.0)
+        );
+        assert_eq!(
+            exchange.escrow_user.get_balance(&Asset::USDT),
+            Amount::from_f64(180.0)
+        );
+    }
+
+    #[test]
+    fn test_crossing_limit_order_trades_at_resting_price() {
+        let (mut exchange, alice_addr, bob_addr, _) = setup_exchange();
+        ask(&mut exchange, &alice_addr, 100.0, 1.0);
+
+        let order_id = exchange
+            .place_spot_limit_order(
+                btc_usdt(),
+                SpotAction::BUY,
+                Amount::from_f64(110.0),
+                Amount::from_f64(1.5),
+                bob_addr.clone(),
+            )
+            .unwrap();
+
+        // 1 BTC bought at 100, the other 0.5 rests at 110 with 55 USDT locked
+        let book = exchange.spot_books.get(&btc_usdt()).unwrap();
+        assert_eq!(book.last_price(), Some(Amount::from_f64(100.0)));
+        assert!(book.asks.is_empty());
+        let resting = book.best_bid().unwrap();
+        assert_eq!(resting.order_id, order_id);
+        assert_eq!(resting.base_amount, Amount::from_f64(0.5));
+        assert_eq!(resting.locked_amount, Amount::from_f64(55.0));
+
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::BTC),
+            Amount::from_f64(11.0)
+        );
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::USDT),
+            Amount::from_f64(9845.0)
+        );
+        assert_eq!(
+            balance(&exchange, &alice_addr, &Asset::USDT),
+            Amount::from_f64(10100.0)
+        );
+    }
+
+    #[test]
+    fn test_price_time_priority_and_no_self_trade() {
+        let (mut exchange, alice_addr, bob_addr, carol_addr) = setup_exchange();
+        ask(&mut exchange, &alice_addr, 100.0, 1.0);
+        ask(&mut exchange, &carol_addr, 100.0, 1.0);
+        ask(&mut exchange, &carol_addr, 99.0, 1.0);
+        ask(&mut exchange, &bob_addr, 98.0, 1.0);
+
+        exchange
+            .place_spot_limit_order(
+                btc_usdt(),
+                SpotAction::BUY,
+                Amount::from_f64(100.0),
+                Amount::from_f64(1.5),
+                bob_addr.clone(),
+            )
+            .unwrap();
+
+        // Bob's own ask is skipped, then the best price, then the oldest order at 100
+        let trades = &exchange.spot_books.get(&btc_usdt()).unwrap().trades;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].seller_address, carol_addr);
+        assert_eq!(trades[0].price, Amount::from_f64(99.0));
+        assert_eq!(trades[1].seller_address, alice_addr);
+        assert_eq!(trades[1].base_amount, Amount::from_f64(0.5));
+    }
+
+    #[test]
+    fn test_market_order_walks_book_within_slippage() {
+        let (mut exchange, alice_addr, bob_addr, _) = setup_exchange();
+        ask(&mut exchange, &alice_addr, 100.0, 1.0);
+        ask(&mut exchange, &alice_addr, 101.0, 1.0);
+        ask(&mut exchange, &alice_addr, 105.0, 1.0);
+
+        // 2% above the best ask allows up to 102
+        exchange
+            .place_spot_market_order(
+                btc_usdt(),
+                SpotAction::BUY,
+                Amount::from_f64(2.0),
+                200,
+                bob_addr.clone(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::USDT),
+            Amount::from_f64(9799.0)
+        );
+        let book = exchange.spot_books.get(&btc_usdt()).unwrap();
+        assert_eq!(book.asks.len(), 1);
+        assert_eq!(book.last_price(), Some(Amount::from_f64(101.0)));
+    }
+
+    #[test]
+    fn test_market_order_beyond_slippage_trades_nothing() {
+        let (mut exchange, alice_addr, bob_addr, _) = setup_exchange();
+        ask(&mut exchange, &alice_addr, 100.0, 1.0);
+        ask(&mut exchange, &alice_addr, 101.0, 1.0);
+        ask(&mut exchange, &alice_addr, 105.0, 1.0);
+
+        let result = exchange.place_spot_market_order(
+            btc_usdt(),
+            SpotAction::BUY,
+            Amount::from_f64(3.0),
+            200,
+            bob_addr.clone(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::SlippageExceeded {
+                limit_price: Amount::from_f64(102.0),
+                unfilled: Amount::from_f64(1.0),
+            }
+        );
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::USDT),
+            Amount::from_f64(10000.0)
+        );
+        assert_eq!(exchange.spot_books.get(&btc_usdt()).unwrap().asks.len(), 3);
+
+        // Nothing on the other side at all
+        let result = exchange.place_spot_market_order(
+            btc_usdt(),
+            SpotAction::SELL,
+            Amount::from_f64(1.0),
+            200,
+            bob_addr,
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+    }
+
+    #[test]
+    fn test_market_sell_fills_resting_bid() {
+        let (mut exchange, alice_addr, bob_addr, _) = setup_exchange();
+        exchange
+            .place_spot_limit_order(
+                btc_usdt(),
+                SpotAction::BUY,
+                Amount::from_f64(95.0),
+                Amount::from_f64(2.0),
+                bob_addr.clone(),
+            )
+            .unwrap();
+
+        exchange
+            .place_spot_market_order(
+                btc_usdt(),
+                SpotAction::SELL,
+                Amount::from_f64(2.0),
+                100,
+                alice_addr.clone(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            balance(&exchange, &alice_addr, &Asset::USDT),
+            Amount::from_f64(10190.0)
+        );
+        assert_eq!(
+            balance(&exchange, &bob_addr, &Asset::BTC),
+            Amount::from_f64(12.0)
+        );
+        assert_eq!(exchange.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+        assert!(
+            exchange
+                .spot_books
+                .get(&btc_usdt())
+                .unwrap()
+                .bids
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_cancel_spot_order() {
+        let (mut exchange, alice_addr, bob_addr, _) = setup_exchange();
+        let order_id = ask(&mut exchange, &alice_addr, 100.0, 1.0);
+
+        let result = exchange.cancel_spot_order(order_id, bob_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("only the owner can cancel this spot order".into())
+        );
+
+        exchange
+            .cancel_spot_order(order_id, alice_addr.clone())
+            .unwrap();
+        assert_eq!(
+            balance(&exchange, &alice_addr, &Asset::BTC),
+            Amount::from_f64(10.0)
+        );
+
+        let result = exchange.cancel_spot_order(order_id, alice_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::SpotOrderNotFound(order_id)
+        );
+    }
+
+    #[test]
+    fn test_last_price_feeds_rate_provider() {
+        let (mut exchange, alice_addr, bob_addr, _) = setup_exchange();
+        // A pair no other test reads from the shared rate provider
+        let pair = AssetPair::from(Asset::APPLE, Asset::BTC);
+        exchange
+            .users
+            .get_mut(&alice_addr)
+            .unwrap()
+            .add_asset(&Asset::APPLE, Amount::from_f64(10.0))
+            .unwrap();
+
+        let result = exchange.set_spot_rate_feed(true, bob_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not the market admin".into())
+        );
+        exchange
+            .set_spot_rate_feed(true, exchange.market_admin_address.clone())
+            .unwrap();
+
+        exchange
+            .place_spot_limit_order(
+                pair.clone(),
+                SpotAction::SELL,
+                Amount::from_f64(0.0025),
+                Amount::from_f64(4.0),
+                alice_addr,
+            )
+            .unwrap();
+        exchange
+            .place_spot_market_order(pair, SpotAction::BUY, Amount::from_f64(1.0), 50, bob_addr)
+            .unwrap();
+
+        let rate = get_readonly_rate_provider().get_rate(&Asset::APPLE, &Asset::BTC);
+        assert_eq!(rate, Some(Amount::from_f64(0.0025)));
+    }
+}
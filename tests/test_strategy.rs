@@ -0,0 +1,467 @@
+use chrono::{Duration, Utc};
+use options_trading::exchange::SpotAction;
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::strategy::{expiry_payoff, max_expiry_loss};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ListingOption, ListingType, OptionState,
+    PortfolioLeg, SettlementType, SimulatedClock, StrategyLeg, StrategyOrder, User,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    // ETH/USDC contracts of 1 ETH each, expiring in 30 days. The ask of 0.05 makes the premium
+    // 5 USDC a contract.
+    fn create_test_option(
+        market: &Exchange,
+        grantor_address: Address,
+        listing_type: ListingType,
+        strike_price: i64,
+        contract_count: u32,
+    ) -> ListingOption {
+        ListingOption::new(
+            0,
+            Asset::ETH,
+            Asset::USDC,
+            listing_type,
+            Amount::from_int(strike_price),
+            Amount::from_f64(0.05),
+            Amount::from_f64(0.04),
+            market.now() + Duration::days(30),
+            grantor_address,
+            contract_count,
+            Amount::from_int(1),
+            OptionState::Listed,
+        )
+    }
+
+    // Returns (market, dealer, trader). ETH/USDC is always 100 in this file. The dealer lists
+    // the options the trader buys, and the spot desk has ETH to sell.
+    fn setup_market() -> (Exchange, Address, Address) {
+        get_rate_provider()
+            .set_rate(
+                Asset::ETH,
+                Asset::USDC,
+                Amount::from_int(100),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+        let mut market = Exchange::with_clock(Arc::new(SimulatedClock::new(Utc::now())));
+        let dealer_addr = create_test_address("1");
+        let trader_addr = create_test_address("2");
+
+        let mut dealer = User::new(dealer_addr.clone());
+        dealer.add_asset(&Asset::ETH, Amount::from_int(10)).unwrap();
+        dealer
+            .add_asset(&Asset::USDC, Amount::from_int(10000))
+            .unwrap();
+        market.users.insert(dealer_addr.clone(), dealer);
+        let mut trader = User::new(trader_addr.clone());
+        trader.add_asset(&Asset::ETH, Amount::from_int(2)).unwrap();
+        trader
+            .add_asset(&Asset::USDC, Amount::from_int(1000))
+            .unwrap();
+        market.users.insert(trader_addr.clone(), trader);
+
+        let desk_addr = market.escrow_user.address.clone();
+        market
+            .users
+            .get_mut(&desk_addr)
+            .unwrap()
+            .add_asset(&Asset::ETH, Amount::from_int(10))
+            .unwrap();
+
+        (market, dealer_addr, trader_addr)
+    }
+
+    fn list(
+        market: &mut Exchange,
+        dealer_addr: &Address,
+        listing_type: ListingType,
+        strike_price: i64,
+        contract_count: u32,
+    ) -> u32 {
+        let option = create_test_option(
+            market,
+            dealer_addr.clone(),
+            listing_type,
+            strike_price,
+            contract_count,
+        );
+        market.list_option(dealer_addr.clone(), option).unwrap()
+    }
+
+    fn balance(market: &Exchange, address: &Address, asset: &Asset) -> Amount {
+        market.users[address].get_balance(asset)
+    }
+
+    #[test]
+    fn test_max_expiry_loss() {
+        let market = Exchange::new();
+        let leg = |listing_type: ListingType, strike_price: i64, contracts: i64| PortfolioLeg {
+            option: create_test_option(
+                &market,
+                create_test_address("1"),
+                listing_type,
+                strike_price,
+                1,
+            ),
+            contracts,
+        };
+        let loss = |legs: Vec<PortfolioLeg>| max_expiry_loss(&legs).unwrap();
+
+        // Call and put credit spreads lose at most the width between the strikes
+        let call_spread = vec![
+            leg(ListingType::CALL, 100, -1),
+            leg(ListingType::CALL, 110, 1),
+        ];
+        assert_eq!(loss(call_spread.clone()), Some(Amount::from_int(10)));
+        assert_eq!(
+            expiry_payoff(&call_spread, Amount::from_int(105)).unwrap(),
+            Amount::from_int(-5)
+        );
+        assert_eq!(
+            loss(vec![
+                leg(ListingType::PUT, 100, -2),
+                leg(ListingType::PUT, 90, 2)
+            ]),
+            Some(Amount::from_int(20))
+        );
+        // An iron condor only loses on one side at a time
+        assert_eq!(
+            loss(vec![
+                leg(ListingType::PUT, 80, 1),
+                leg(ListingType::PUT, 90, -1),
+                leg(ListingType::CALL, 110, -1),
+                leg(ListingType::CALL, 120, 1),
+            ]),
+            Some(Amount::from_int(10))
+        );
+
+        // A naked call loses without bound, a naked put down to a zero spot
+        assert_eq!(loss(vec![leg(ListingType::CALL, 100, -1)]), None);
+        assert_eq!(
+            loss(vec![leg(ListingType::PUT, 100, -1)]),
+            Some(Amount::from_int(100))
+        );
+        // A long straddle can't pay out less than nothing
+        assert_eq!(
+            loss(vec![
+                leg(ListingType::CALL, 100, 1),
+                leg(ListingType::PUT, 100, 1)
+            ]),
+            Some(Amount::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_vertical_spread_executes_every_leg() {
+        let (mut market, dealer_addr, trader_addr) = setup_market();
+        let long_id = list(&mut market, &dealer_addr, ListingType::CALL, 110, 2);
+        let short_option =
+            create_test_option(&market, trader_addr.clone(), ListingType::CALL, 100, 2);
+        let order = StrategyOrder::new(Asset::ETH, Asset::USDC)
+            .with_leg(StrategyLeg::Buy {
+                listing_id: long_id,
+                contracts: 2,
+            })
+            .with_leg(StrategyLeg::Write(short_option));
+
+        // 10.01 for the long calls with the buyer fee, 9.99 for the short ones after the
+        // grantor fee. Off margin the short calls escrow their 2 ETH, even though the spread
+        // can't lose more than 20.02.
+        let quote = market.quote_strategy(&order, &trader_addr).unwrap();
+        assert_eq!(quote.net_premium, Amount::from_f64(0.02));
+        assert_eq!(quote.spot_cost, Amount::ZERO);
+        assert_eq!(
+            quote.collateral,
+            HashMap::from([(Asset::ETH, Amount::from_int(2))])
+        );
+        assert!(quote.defined_risk);
+        assert_eq!(quote.max_loss, Some(Amount::from_f64(20.02)));
+
+        let fill = market.execute_strategy(order, trader_addr.clone()).unwrap();
+        assert_eq!(fill.quote, quote);
+        assert_eq!(fill.strategy_id, 1);
+        assert_eq!(fill.listing_ids.len(), 1);
+        assert_eq!(market.strategy_fills, vec![fill.clone()]);
+
+        let written = &market.listings[&fill.listing_ids[0]];
+        assert_eq!(written.grantor_address, trader_addr);
+        assert_eq!(written.contract_count, 2);
+        assert_eq!(
+            market
+                .get_position(long_id, &trader_addr)
+                .unwrap()
+                .contracts,
+            2
+        );
+        assert_eq!(balance(&market, &trader_addr, &Asset::ETH), Amount::ZERO);
+        // The written listing hasn't sold yet, so only the long calls are paid for
+        assert_eq!(
+            balance(&market, &trader_addr, &Asset::USDC),
+            Amount::from_f64(989.99)
+        );
+    }
+
+    #[test]
+    fn test_failing_leg_rolls_back_the_whole_order() {
+        let (mut market, dealer_addr, trader_addr) = setup_market();
+        let long_id = list(&mut market, &dealer_addr, ListingType::CALL, 110, 2);
+        let short_option =
+            create_test_option(&market, trader_addr.clone(), ListingType::CALL, 100, 2);
+        let next_listing_id = market.next_listing_id;
+        let listing_count = market.listings.len();
+
+        // The written calls escrow fine, but only 2 long calls are listed
+        let order = StrategyOrder::new(Asset::ETH, Asset::USDC)
+            .with_leg(StrategyLeg::Write(short_option.clone()))
+            .with_leg(StrategyLeg::Buy {
+                listing_id: long_id,
+                contracts: 3,
+            });
+        assert!(market.execute_strategy(order, trader_addr.clone()).is_err());
+
+        // Each leg is affordable alone, but the spot leg leaves too little USDC for the calls
+        let order = StrategyOrder::new(Asset::ETH, Asset::USDC)
+            .with_leg(StrategyLeg::Write(short_option))
+            .with_leg(StrategyLeg::Spot {
+                action: SpotAction::BUY,
+                base_amount: Amount::from_int(10),
+            })
+            .with_leg(StrategyLeg::Buy {
+                listing_id: long_id,
+                contracts: 2,
+            });
+        assert!(matches!(
+            market.execute_strategy(order, trader_addr.clone()),
+            Err(ExchangeError::InsufficientBalance { .. })
+        ));
+
+        assert_eq!(
+            balance(&market, &trader_addr, &Asset::ETH),
+            Amount::from_int(2)
+        );
+        assert_eq!(
+            balance(&market, &trader_addr, &Asset::USDC),
+            Amount::from_int(1000)
+        );
+        assert_eq!(market.next_listing_id, next_listing_id);
+        assert_eq!(market.listings.len(), listing_count);
+        assert!(market.get_position(long_id, &trader_addr).is_none());
+        assert!(market.strategy_fills.is_empty());
+        assert_eq!(market.next_strategy_id, 1);
+    }
+
+    #[test]
+    fn test_defined_risk_spread_on_margin_needs_its_max_loss() {
+        let (mut market, dealer_addr, trader_addr) = setup_market();
+        let long_id = list(&mut market, &dealer_addr, ListingType::PUT, 90, 10);
+        market.open_margin_account(trader_addr.clone()).unwrap();
+        market
+            .deposit_margin(&Asset::USDC, Amount::from_int(100), trader_addr.clone())
+            .unwrap();
+        let short_option =
+            create_test_option(&market, trader_addr.clone(), ListingType::PUT, 100, 10)
+                .with_settlement_type(SettlementType::Cash);
+
+        // Written alone, 10 puts need more than the 100 deposited
+        let result = market.list_option(trader_addr.clone(), short_option.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin { .. }
+        ));
+
+        // Against the 90 puts they can't lose more than 10 a contract
+        let order = StrategyOrder::new(Asset::ETH, Asset::USDC)
+            .with_leg(StrategyLeg::Buy {
+                listing_id: long_id,
+                contracts: 10,
+            })
+            .with_leg(StrategyLeg::Write(short_option));
+        let fill = market.execute_strategy(order, trader_addr.clone()).unwrap();
+        assert!(fill.quote.defined_risk);
+        assert_eq!(
+            fill.quote.collateral,
+            HashMap::from([(Asset::USDC, Amount::from_int(100))])
+        );
+
+        let account = &market.margin_accounts[&trader_addr];
+        assert_eq!(account.defined_risk.len(), 1);
+        assert_eq!(account.defined_risk[0].written, fill.listing_ids);
+        assert_eq!(account.defined_risk[0].bought, vec![(long_id, 10)]);
+        let health = market.margin_health(&trader_addr, &Asset::USDC).unwrap();
+        assert_eq!(health.initial_requirement, Amount::from_int(100));
+        assert_eq!(health.maintenance_requirement, Amount::from_int(100));
+        assert!(health.is_healthy());
+
+        // The long puts can't leave the strategy while the written ones lean on them
+        let result =
+            market.relist_purchased_option(long_id, Amount::from_f64(0.05), trader_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin { .. }
+        ));
+        let result = market.exercise_contracts(long_id, 10, trader_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin { .. }
+        ));
+
+        // Enough margin for the written puts on their own frees them, until it's withdrawn again
+        market
+            .users
+            .get_mut(&trader_addr)
+            .unwrap()
+            .add_asset(&Asset::USDC, Amount::from_int(900))
+            .unwrap();
+        market
+            .deposit_margin(&Asset::USDC, Amount::from_int(900), trader_addr.clone())
+            .unwrap();
+        market
+            .relist_purchased_option(long_id, Amount::from_f64(0.05), trader_addr.clone())
+            .unwrap();
+        market
+            .withdraw_margin(&Asset::USDC, Amount::from_int(900), trader_addr.clone())
+            .unwrap();
+        let result = market.purchase_resale(long_id, dealer_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientMargin { .. }
+        ));
+        assert_eq!(
+            market
+                .get_position(long_id, &trader_addr)
+                .unwrap()
+                .contracts,
+            10
+        );
+    }
+
+    #[test]
+    fn test_collar_buys_the_underlying_it_covers() {
+        let (mut market, dealer_addr, trader_addr) = setup_market();
+        let put_id = list(&mut market, &dealer_addr, ListingType::PUT, 90, 1);
+        let trader = market.users.get_mut(&trader_addr).unwrap();
+        trader
+            .deduct_asset(&Asset::ETH, Amount::from_int(2))
+            .unwrap();
+        let call_option =
+            create_test_option(&market, trader_addr.clone(), ListingType::CALL, 110, 1);
+
+        // The ETH bought on the first leg is escrowed by the call written on the last
+        let order = StrategyOrder::new(Asset::ETH, Asset::USDC)
+            .with_leg(StrategyLeg::Spot {
+                action: SpotAction::BUY,
+                base_amount: Amount::from_int(1),
+            })
+            .with_leg(StrategyLeg::Buy {
+                listing_id: put_id,
+                contracts: 1,
+            })
+            .with_leg(StrategyLeg::Write(call_option));
+        let fill = market.execute_strategy(order, trader_addr.clone()).unwrap();
+
+        assert_eq!(fill.quote.spot_cost, Amount::from_int(100));
+        assert_eq!(fill.quote.net_premium, Amount::from_f64(0.01));
+        assert_eq!(
+            fill.quote.collateral,
+            HashMap::from([(Asset::ETH, Amount::from_int(1))])
+        );
+        // Without the ETH the short call is naked, so there's no group to margin
+        assert!(!fill.quote.defined_risk);
+        // The put floors the ETH at 90, 10 under what it cost
+        assert_eq!(fill.quote.max_loss, Some(Amount::from_f64(10.01)));
+
+        assert_eq!(balance(&market, &trader_addr, &Asset::ETH), Amount::ZERO);
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::ETH),
+            Amount::from_int(1)
+        );
+        assert_eq!(
+            balance(&market, &trader_addr, &Asset::USDC),
+            Amount::from_f64(894.995)
+        );
+    }
+
+    #[test]
+    fn test_invalid_orders_are_rejected() {
+        let (mut market, dealer_addr, trader_addr) = setup_market();
+        let long_id = list(&mut market, &dealer_addr, ListingType::CALL, 110, 2);
+
+        let empty = StrategyOrder::new(Asset::ETH, Asset::USDC);
+        assert_eq!(
+            market.quote_strategy(&empty, &trader_addr).unwrap_err(),
+            ExchangeError::InvalidInput("Strategy orders need at least one leg".into())
+        );
+
+        let mut btc_option =
+            create_test_option(&market, trader_addr.clone(), ListingType::CALL, 100, 1);
+        btc_option.base_asset = Asset::BTC;
+        let mixed = StrategyOrder::new(Asset::ETH, Asset::USDC)
+            .with_leg(StrategyLeg::Buy {
+                listing_id: long_id,
+                contracts: 1,
+            })
+            .with_leg(StrategyLeg::Write(btc_option));
+        assert_eq!(
+            market.quote_strategy(&mixed, &trader_addr).unwrap_err(),
+            ExchangeError::InvalidInput("Strategy legs must all trade ETH/USDC".into())
+        );
+
+        let twice = StrategyOrder::new(Asset::ETH, Asset::USDC)
+            .with_leg(StrategyLeg::Buy {
+                listing_id: long_id,
+                contracts: 1,
+            })
+            .with_leg(StrategyLeg::Buy {
+                listing_id: long_id,
+                contracts: 1,
+            });
+        assert_eq!(
+            market
+                .execute_strategy(twice, trader_addr.clone())
+                .unwrap_err(),
+            ExchangeError::InvalidInput(format!(
+                "Listing #{} is bought by more than one leg",
+                long_id
+            ))
+        );
+
+        // A written leg's listing id is only a placeholder until it's listed
+        let mut short_option =
+            create_test_option(&market, trader_addr.clone(), ListingType::CALL, 100, 1);
+        short_option.listing_id = long_id;
+        let spread = StrategyOrder::new(Asset::ETH, Asset::USDC)
+            .with_leg(StrategyLeg::Write(short_option))
+            .with_leg(StrategyLeg::Buy {
+                listing_id: long_id,
+                contracts: 1,
+            });
+        assert!(market.quote_strategy(&spread, &trader_addr).is_ok());
+
+        // Quoting a valid order leaves everything as it was
+        let order = StrategyOrder::new(Asset::ETH, Asset::USDC).with_leg(StrategyLeg::Buy {
+            listing_id: long_id,
+            contracts: 1,
+        });
+        market.quote_strategy(&order, &trader_addr).unwrap();
+        assert!(market.get_position(long_id, &trader_addr).is_none());
+        assert_eq!(
+            balance(&market, &trader_addr, &Asset::USDC),
+            Amount::from_int(1000)
+        );
+        assert!(market.strategy_fills.is_empty());
+    }
+}
@@ -0,0 +1,226 @@
+use chrono::{Duration, Utc};
+use options_trading::exchange::SpotAction;
+use options_trading::{
+    Account, Address, Amount, Asset, BalanceTransaction, Exchange, ExchangeError, ExerciseStyle,
+    ListingOption, ListingType, OptionState, SettlementType, User,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    fn create_test_option(grantor_address: Address) -> ListingOption {
+        ListingOption {
+            listing_id: 0,
+            base_asset: Asset::BTC,
+            quote_asset: Asset::USDT,
+            listing_type: ListingType::CALL,
+            strike_price: Amount::from_f64(50000.0),
+            ask_price: Amount::from_f64(500.0),
+            bid_price: Amount::from_f64(490.0),
+            expiration_time: Utc::now() + Duration::days(30),
+            grantor_address,
+            contract_count: 1,
+            exercise_amount: Amount::from_f64(1.0),
+            exercise_style: ExerciseStyle::American,
+            settlement_type: SettlementType::Physical,
+            state: OptionState::Listed,
+        }
+    }
+
+    fn setup_market_with_users() -> (Exchange, Address, Address) {
+        let mut market = Exchange::new();
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller
+            .add_asset(&Asset::BTC, Amount::from_f64(10.0))
+            .unwrap();
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDT, Amount::from_f64(100000.0))
+            .unwrap();
+
+        market.users.insert(seller_addr.clone(), seller);
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        (market, seller_addr, buyer_addr)
+    }
+
+    #[test]
+    fn test_apply_is_all_or_nothing() {
+        let (mut market, seller_addr, buyer_addr) = setup_market_with_users();
+
+        let mut transaction = BalanceTransaction::new();
+        transaction
+            .transfer(
+                Account::User(buyer_addr.clone()),
+                Account::User(seller_addr.clone()),
+                &Asset::USDT,
+                Amount::from_f64(1000.0),
+            )
+            .debit(
+                Account::User(seller_addr.clone()),
+                &Asset::BTC,
+                Amount::from_f64(11.0),
+            );
+
+        let result = market.apply(transaction);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::BTC,
+                required: Amount::from_f64(11.0),
+                available: Amount::from_f64(10.0),
+            }
+        );
+
+        // The first transfer was rolled back with the failing debit
+        let buyer = market.users.get(&buyer_addr).unwrap();
+        assert_eq!(buyer.get_balance(&Asset::USDT), Amount::from_f64(100000.0));
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::USDT), Amount::ZERO);
+        assert_eq!(seller.get_balance(&Asset::BTC), Amount::from_f64(10.0));
+    }
+
+    #[test]
+    fn test_changes_apply_in_order() {
+        let (mut market, seller_addr, buyer_addr) = setup_market_with_users();
+
+        // The seller can only pass on USDT received earlier in the same transaction
+        let mut transaction = BalanceTransaction::new();
+        transaction
+            .transfer(
+                Account::User(buyer_addr.clone()),
+                Account::User(seller_addr.clone()),
+                &Asset::USDT,
+                Amount::from_f64(1000.0),
+            )
+            .transfer(
+                Account::User(seller_addr.clone()),
+                Account::Escrow,
+                &Asset::USDT,
+                Amount::from_f64(400.0),
+            );
+        market.apply(transaction).unwrap();
+
+        let seller = market.users.get(&seller_addr).unwrap();
+        assert_eq!(seller.get_balance(&Asset::USDT), Amount::from_f64(600.0));
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::USDT),
+            Amount::from_f64(400.0)
+        );
+    }
+
+    #[test]
+    fn test_unknown_account_fails_before_anything_moves() {
+        let (mut market, _, buyer_addr) = setup_market_with_users();
+
+        let mut transaction = BalanceTransaction::new();
+        transaction.transfer(
+            Account::User(buyer_addr.clone()),
+            Account::User(create_test_address("9")),
+            &Asset::USDT,
+            Amount::from_f64(1000.0),
+        );
+
+        let result = market.apply(transaction);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::UserNotFound(create_test_address("9"))
+        );
+        let buyer = market.users.get(&buyer_addr).unwrap();
+        assert_eq!(buyer.get_balance(&Asset::USDT), Amount::from_f64(100000.0));
+    }
+
+    #[test]
+    fn test_failed_exercise_leaves_escrow_untouched() {
+        let (mut market, seller_addr, buyer_addr) = setup_market_with_users();
+
+        let option = create_test_option(seller_addr.clone());
+        let listing_id = market.list_option(seller_addr, option).unwrap();
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+
+        // 49950 USDT left can't pay the 50000 strike
+        let result = market.exercise_option(listing_id, buyer_addr.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::USDT,
+                ..
+            }
+        ));
+
+        let buyer = market.users.get(&buyer_addr).unwrap();
+        assert_eq!(buyer.get_balance(&Asset::BTC), Amount::ZERO);
+        assert_eq!(buyer.get_balance(&Asset::USDT), Amount::from_f64(49950.0));
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::BTC),
+            Amount::from_f64(1.0)
+        );
+        assert_eq!(
+            market
+                .get_position(listing_id, &buyer_addr)
+                .unwrap()
+                .contracts,
+            1
+        );
+    }
+
+    #[test]
+    fn test_purchase_with_missing_grantor_charges_nothing() {
+        let (mut market, seller_addr, buyer_addr) = setup_market_with_users();
+
+        let option = create_test_option(seller_addr.clone());
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+        market.users.remove(&seller_addr);
+
+        let result = market.purchase_option(listing_id, buyer_addr.clone());
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::UserNotFound(seller_addr)
+        );
+
+        let buyer = market.users.get(&buyer_addr).unwrap();
+        assert_eq!(buyer.get_balance(&Asset::USDT), Amount::from_f64(100000.0));
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+        assert_eq!(
+            market.listings.get(&listing_id).unwrap().state,
+            OptionState::Listed
+        );
+    }
+
+    #[test]
+    fn test_spot_buy_without_desk_inventory_charges_nothing() {
+        let (mut market, _, buyer_addr) = setup_market_with_users();
+
+        // The spot desk holds no BTC, so the base leg fails after the quote leg was staged
+        let result = market.spot_trade_current_price(
+            &Asset::BTC,
+            &Asset::USDT,
+            Amount::from_f64(0.5),
+            &SpotAction::BUY,
+            buyer_addr.clone(),
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::BTC,
+                ..
+            }
+        ));
+
+        let buyer = market.users.get(&buyer_addr).unwrap();
+        assert_eq!(buyer.get_balance(&Asset::USDT), Amount::from_f64(100000.0));
+        assert_eq!(buyer.get_balance(&Asset::BTC), Amount::ZERO);
+    }
+}
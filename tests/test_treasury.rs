@@ -0,0 +1,288 @@
+use chrono::{Duration, Utc};
+use options_trading::exchange::default_exchange_admin_address;
+use options_trading::rbac::{NamedRole, UnauthorizedError};
+use options_trading::{
+    Address, Amount, Asset, Exchange, ExchangeError, ListingOption, ListingType, OptionState,
+    SimulatedClock, User,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    // Returns (market, clock, seller, buyer, listing id). The listing has 3 BTC calls whose
+    // premium is 50000 USDT a contract, so every purchase collects 50 USDT of fees on each side.
+    fn setup_market() -> (Exchange, Arc<SimulatedClock>, Address, Address, u32) {
+        let clock = Arc::new(SimulatedClock::new(Utc::now()));
+        let mut market = Exchange::with_clock(clock.clone());
+        let seller_addr = create_test_address("1");
+        let buyer_addr = create_test_address("2");
+
+        let mut seller = User::new(seller_addr.clone());
+        seller.add_asset(&Asset::BTC, Amount::from_int(10)).unwrap();
+        market.users.insert(seller_addr.clone(), seller);
+        let mut buyer = User::new(buyer_addr.clone());
+        buyer
+            .add_asset(&Asset::USDT, Amount::from_int(200000))
+            .unwrap();
+        market.users.insert(buyer_addr.clone(), buyer);
+
+        let option = ListingOption::new(
+            0,
+            Asset::BTC,
+            Asset::USDT,
+            ListingType::CALL,
+            Amount::from_int(50000),
+            Amount::from_int(500),
+            Amount::from_int(490),
+            market.now() + Duration::days(30),
+            seller_addr.clone(),
+            3,
+            Amount::from_int(1),
+            OptionState::Listed,
+        );
+        let listing_id = market.list_option(seller_addr.clone(), option).unwrap();
+
+        (market, clock, seller_addr, buyer_addr, listing_id)
+    }
+
+    fn balance(market: &Exchange, address: &Address, asset: &Asset) -> Amount {
+        market.users[address].get_balance(asset)
+    }
+
+    #[test]
+    fn test_fees_are_split_between_treasury_and_insurance_fund() {
+        let (mut market, _, seller_addr, buyer_addr, listing_id) = setup_market();
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+
+        assert_eq!(
+            balance(&market, &buyer_addr, &Asset::USDT),
+            Amount::from_int(149950)
+        );
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::USDT),
+            Amount::from_int(49950)
+        );
+        // Escrow keeps the collateral and none of the fees
+        assert_eq!(
+            market.escrow_user.get_balance(&Asset::BTC),
+            Amount::from_int(3)
+        );
+        assert_eq!(market.escrow_user.get_balance(&Asset::USDT), Amount::ZERO);
+        assert_eq!(
+            market.treasury.get_balance(&Asset::USDT),
+            Amount::from_int(90)
+        );
+        assert_eq!(
+            market.insurance_fund.get_balance(&Asset::USDT),
+            Amount::from_int(10)
+        );
+    }
+
+    #[test]
+    fn test_insurance_fee_share_is_configurable() {
+        let (mut market, _, seller_addr, buyer_addr, listing_id) = setup_market();
+        let admin = market.market_admin_address.clone();
+
+        let result = market.set_insurance_fee_share_bps(5_000, seller_addr);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Unauthorized("caller is not the market admin".into())
+        );
+        let result = market.set_insurance_fee_share_bps(10_001, admin.clone());
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+
+        market
+            .set_insurance_fee_share_bps(10_000, admin.clone())
+            .unwrap();
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        assert_eq!(market.treasury.get_balance(&Asset::USDT), Amount::ZERO);
+        assert_eq!(
+            market.insurance_fund.get_balance(&Asset::USDT),
+            Amount::from_int(100)
+        );
+
+        market.set_insurance_fee_share_bps(0, admin).unwrap();
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        assert_eq!(
+            market.treasury.get_balance(&Asset::USDT),
+            Amount::from_int(100)
+        );
+        assert_eq!(
+            market.insurance_fund.get_balance(&Asset::USDT),
+            Amount::from_int(100)
+        );
+    }
+
+    #[test]
+    fn test_treasurer_withdraws_treasury_funds() {
+        let (mut market, _, seller_addr, buyer_addr, listing_id) = setup_market();
+        let admin = default_exchange_admin_address();
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        let seller_usdt = balance(&market, &seller_addr, &Asset::USDT);
+
+        let result = market.withdraw_treasury(
+            &Asset::USDT,
+            Amount::from_int(50),
+            seller_addr.clone(),
+            seller_addr.clone(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Role(UnauthorizedError::AddressNotAuthorized)
+        );
+
+        market
+            .withdraw_treasury(
+                &Asset::USDT,
+                Amount::from_int(50),
+                seller_addr.clone(),
+                admin.clone(),
+            )
+            .unwrap();
+        assert_eq!(
+            balance(&market, &seller_addr, &Asset::USDT),
+            seller_usdt.try_add(Amount::from_int(50)).unwrap()
+        );
+        assert_eq!(
+            market.treasury.get_balance(&Asset::USDT),
+            Amount::from_int(40)
+        );
+
+        let result = market.withdraw_treasury(
+            &Asset::USDT,
+            Amount::from_int(41),
+            seller_addr.clone(),
+            admin.clone(),
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::InsufficientBalance {
+                asset: Asset::USDT,
+                required: Amount::from_int(41),
+                available: Amount::from_int(40),
+            }
+        );
+        let result = market.withdraw_treasury(&Asset::USDT, Amount::ZERO, seller_addr, admin);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+        // Withdrawals only touch the treasury
+        assert_eq!(
+            market.insurance_fund.get_balance(&Asset::USDT),
+            Amount::from_int(10)
+        );
+    }
+
+    #[test]
+    fn test_treasurer_role_can_be_handed_on() {
+        let (mut market, _, _, buyer_addr, listing_id) = setup_market();
+        let admin = default_exchange_admin_address();
+        let treasurer_addr = create_test_address("3");
+        market
+            .users
+            .insert(treasurer_addr.clone(), User::new(treasurer_addr.clone()));
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+
+        market
+            .role_authorizer
+            .assign_role(
+                NamedRole("Treasurer".to_string()),
+                treasurer_addr.clone(),
+                admin.clone(),
+            )
+            .unwrap();
+        let result =
+            market.withdraw_treasury(&Asset::USDT, Amount::from_int(10), admin.clone(), admin);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::Role(UnauthorizedError::AddressNotAuthorized)
+        );
+        market
+            .withdraw_treasury(
+                &Asset::USDT,
+                Amount::from_int(90),
+                treasurer_addr.clone(),
+                treasurer_addr.clone(),
+            )
+            .unwrap();
+        assert_eq!(
+            balance(&market, &treasurer_addr, &Asset::USDT),
+            Amount::from_int(90)
+        );
+        assert_eq!(market.treasury.get_balance(&Asset::USDT), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_fund_reports_over_time() {
+        let (mut market, clock, _, buyer_addr, listing_id) = setup_market();
+        let opened_at = market.now();
+
+        let report = market.record_fund_report();
+        assert!(report.treasury.is_empty());
+        assert!(report.insurance_fund.is_empty());
+
+        clock.advance(Duration::days(1));
+        market
+            .purchase_option(listing_id, buyer_addr.clone())
+            .unwrap();
+        market.record_fund_report();
+
+        clock.advance(Duration::days(1));
+        market
+            .fund_insurance(&Asset::USDT, Amount::from_int(5), buyer_addr)
+            .unwrap();
+        let report = market.record_fund_report();
+        assert_eq!(
+            report.treasury,
+            HashMap::from([(Asset::USDT, Amount::from_int(90))])
+        );
+        assert_eq!(
+            report.insurance_fund,
+            HashMap::from([(Asset::USDT, Amount::from_int(15))])
+        );
+
+        let times: Vec<_> = market
+            .fund_reports
+            .iter()
+            .map(|report| report.time)
+            .collect();
+        assert_eq!(
+            times,
+            vec![
+                opened_at,
+                opened_at + Duration::days(1),
+                opened_at + Duration::days(2)
+            ]
+        );
+        assert_eq!(
+            market.fund_reports[1].insurance_fund,
+            HashMap::from([(Asset::USDT, Amount::from_int(10))])
+        );
+        // Reading the current balances doesn't add to the history
+        assert_eq!(market.fund_report(), report);
+        assert_eq!(market.fund_reports.len(), 3);
+    }
+}
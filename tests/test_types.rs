@@ -101,7 +101,7 @@ mod tests {
         // Addresses should be comparable via their string value; test case-insensitivity by normalization
         let mixed = "0xABCDEFabcdef1234567890123456789012345678";
         // Ensure length is 42 for Address::from to succeed
-        let normalized = format!("{}", &mixed[0..42]);
+        let normalized = mixed[0..42].to_string();
         let a = Address::from(&normalized).unwrap();
         let b = a.to_normalized();
         assert_eq!(b.to_string(), a.to_string().to_lowercase());
@@ -1,4 +1,4 @@
-use options_trading::{Address, Asset, User};
+use options_trading::{Address, Amount, Asset, ExchangeError, User};
 
 #[cfg(test)]
 mod tests {
@@ -19,10 +19,10 @@ mod tests {
         assert!(!user.balances.is_empty());
 
         // Should have all assets initialized to 0
-        assert_eq!(user.get_balance(&Asset::BTC), 0.0);
-        assert_eq!(user.get_balance(&Asset::ETH), 0.0);
-        assert_eq!(user.get_balance(&Asset::SOL), 0.0);
-        assert_eq!(user.get_balance(&Asset::USDT), 0.0);
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(0.0));
+        assert_eq!(user.get_balance(&Asset::ETH), Amount::from_f64(0.0));
+        assert_eq!(user.get_balance(&Asset::SOL), Amount::from_f64(0.0));
+        assert_eq!(user.get_balance(&Asset::USDT), Amount::from_f64(0.0));
     }
 
     #[test]
@@ -30,17 +30,17 @@ mod tests {
         let address = create_test_address("1");
         let mut user = User::new(address);
 
-        user.add_asset(&Asset::BTC, 1.5).unwrap();
-        assert_eq!(user.get_balance(&Asset::BTC), 1.5);
+        user.add_asset(&Asset::BTC, Amount::from_f64(1.5)).unwrap();
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(1.5));
 
         // Add more to the same asset
-        user.add_asset(&Asset::BTC, 0.5).unwrap();
-        assert_eq!(user.get_balance(&Asset::BTC), 2.0);
+        user.add_asset(&Asset::BTC, Amount::from_f64(0.5)).unwrap();
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(2.0));
 
         // Add different asset
-        user.add_asset(&Asset::ETH, 10.0).unwrap();
-        assert_eq!(user.get_balance(&Asset::ETH), 10.0);
-        assert_eq!(user.get_balance(&Asset::BTC), 2.0); // Should not affect BTC
+        user.add_asset(&Asset::ETH, Amount::from_f64(10.0)).unwrap();
+        assert_eq!(user.get_balance(&Asset::ETH), Amount::from_f64(10.0));
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(2.0)); // Should not affect BTC
     }
 
     #[test]
@@ -48,11 +48,11 @@ mod tests {
         let address = create_test_address("2");
         let mut user = User::new(address);
 
-        user.add_asset(&Asset::BTC, 5.0).unwrap();
+        user.add_asset(&Asset::BTC, Amount::from_f64(5.0)).unwrap();
 
-        let result = user.deduct_asset(&Asset::BTC, 2.0);
+        let result = user.deduct_asset(&Asset::BTC, Amount::from_f64(2.0));
         assert!(result.is_ok());
-        assert_eq!(user.get_balance(&Asset::BTC), 3.0);
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(3.0));
     }
 
     #[test]
@@ -60,12 +60,16 @@ mod tests {
         let address = create_test_address("3");
         let mut user = User::new(address);
 
-        user.add_asset(&Asset::BTC, 1.0).unwrap();
+        user.add_asset(&Asset::BTC, Amount::from_f64(1.0)).unwrap();
 
-        let result = user.deduct_asset(&Asset::BTC, 2.0);
+        let result = user.deduct_asset(&Asset::BTC, Amount::from_f64(2.0));
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Insufficient BTC balance");
-        assert_eq!(user.get_balance(&Asset::BTC), 1.0); // Balance should remain unchanged
+        assert_eq!(result.unwrap_err(), ExchangeError::InsufficientBalance {
+            asset: Asset::BTC,
+            required: Amount::from_f64(2.0),
+            available: Amount::from_f64(1.0),
+        });
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(1.0)); // Balance should remain unchanged
     }
 
     #[test]
@@ -73,9 +77,13 @@ mod tests {
         let address = create_test_address("4");
         let mut user = User::new(address);
 
-        let result = user.deduct_asset(&Asset::BTC, 1.0);
+        let result = user.deduct_asset(&Asset::BTC, Amount::from_f64(1.0));
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "Insufficient BTC balance");
+        assert_eq!(result.unwrap_err(), ExchangeError::InsufficientBalance {
+            asset: Asset::BTC,
+            required: Amount::from_f64(1.0),
+            available: Amount::ZERO,
+        });
     }
 
     #[test]
@@ -83,11 +91,11 @@ mod tests {
         let address = create_test_address("5");
         let mut user = User::new(address);
 
-        user.add_asset(&Asset::ETH, 10.0).unwrap();
+        user.add_asset(&Asset::ETH, Amount::from_f64(10.0)).unwrap();
 
-        let result = user.deduct_asset(&Asset::ETH, 10.0);
+        let result = user.deduct_asset(&Asset::ETH, Amount::from_f64(10.0));
         assert!(result.is_ok());
-        assert_eq!(user.get_balance(&Asset::ETH), 0.0);
+        assert_eq!(user.get_balance(&Asset::ETH), Amount::from_f64(0.0));
     }
 
     #[test]
@@ -98,7 +106,7 @@ mod tests {
         // All assets should be initialized to 0.0
         assert_eq!(
             user.get_balance(&Asset::OTHER("NONEXISTENT".to_string())),
-            0.0
+            Amount::from_f64(0.0)
         );
     }
 
@@ -108,38 +116,38 @@ mod tests {
         let mut user = User::new(address);
 
         // Add multiple assets
-        user.add_asset(&Asset::BTC, 2.0).unwrap();
-        user.add_asset(&Asset::ETH, 50.0).unwrap();
-        user.add_asset(&Asset::USDT, 1000.0).unwrap();
+        user.add_asset(&Asset::BTC, Amount::from_f64(2.0)).unwrap();
+        user.add_asset(&Asset::ETH, Amount::from_f64(50.0)).unwrap();
+        user.add_asset(&Asset::USDT, Amount::from_f64(1000.0)).unwrap();
 
         // Verify balances
-        assert_eq!(user.get_balance(&Asset::BTC), 2.0);
-        assert_eq!(user.get_balance(&Asset::ETH), 50.0);
-        assert_eq!(user.get_balance(&Asset::USDT), 1000.0);
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(2.0));
+        assert_eq!(user.get_balance(&Asset::ETH), Amount::from_f64(50.0));
+        assert_eq!(user.get_balance(&Asset::USDT), Amount::from_f64(1000.0));
 
         // Deduct from multiple assets
-        user.deduct_asset(&Asset::BTC, 0.5).unwrap();
-        user.deduct_asset(&Asset::USDT, 500.0).unwrap();
+        user.deduct_asset(&Asset::BTC, Amount::from_f64(0.5)).unwrap();
+        user.deduct_asset(&Asset::USDT, Amount::from_f64(500.0)).unwrap();
 
         // Verify final balances
-        assert_eq!(user.get_balance(&Asset::BTC), 1.5);
-        assert_eq!(user.get_balance(&Asset::ETH), 50.0); // unchanged
-        assert_eq!(user.get_balance(&Asset::USDT), 500.0);
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(1.5));
+        assert_eq!(user.get_balance(&Asset::ETH), Amount::from_f64(50.0)); // unchanged
+        assert_eq!(user.get_balance(&Asset::USDT), Amount::from_f64(500.0));
     }
 
     #[test]
     fn test_user_clone() {
         let address = create_test_address("8");
         let mut user = User::new(address.clone());
-        user.add_asset(&Asset::BTC, 1.0).unwrap();
+        user.add_asset(&Asset::BTC, Amount::from_f64(1.0)).unwrap();
 
         let cloned_user = user.clone();
         assert_eq!(cloned_user.address, address);
-        assert_eq!(cloned_user.get_balance(&Asset::BTC), 1.0);
+        assert_eq!(cloned_user.get_balance(&Asset::BTC), Amount::from_f64(1.0));
 
         // Modifying original should not affect clone
-        user.add_asset(&Asset::BTC, 1.0).unwrap();
-        assert_eq!(user.get_balance(&Asset::BTC), 2.0);
-        assert_eq!(cloned_user.get_balance(&Asset::BTC), 1.0);
+        user.add_asset(&Asset::BTC, Amount::from_f64(1.0)).unwrap();
+        assert_eq!(user.get_balance(&Asset::BTC), Amount::from_f64(2.0));
+        assert_eq!(cloned_user.get_balance(&Asset::BTC), Amount::from_f64(1.0));
     }
 }
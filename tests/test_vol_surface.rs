@@ -0,0 +1,239 @@
+use chrono::{DateTime, Duration, Utc};
+use options_trading::exchange_rate_provider::{
+    default_exchange_rate_provider_admin_address, get_rate_provider,
+};
+use options_trading::pricing::black_scholes;
+use options_trading::vol_surface::{EXPIRY_BUCKET_DAYS, MONEYNESS_BUCKETS, build_surfaces};
+use options_trading::{
+    Address, Amount, Asset, ExchangeError, ListingOption, ListingType, OptionState, PricingParams,
+    VolatilitySurface,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_address(suffix: &str) -> Address {
+        let base = "0x123456789012345678901234567890123456789";
+        let full_address = format!("{}{}", base, suffix);
+        Address::from(&full_address).unwrap()
+    }
+
+    // A pair no other test reads from the shared rate provider, always set to 100
+    fn set_eth_usdc_rate() {
+        get_rate_provider()
+            .set_rate(
+                Asset::ETH,
+                Asset::USDC,
+                Amount::from_int(100),
+                default_exchange_rate_provider_admin_address(),
+            )
+            .unwrap();
+    }
+
+    // An ETH/USDC listing quoted at the Black-Scholes value of `volatility` on both sides
+    fn listing(
+        listing_id: u32,
+        listing_type: ListingType,
+        strike: f64,
+        days: i64,
+        volatility: f64,
+        now: DateTime<Utc>,
+    ) -> ListingOption {
+        let value = black_scholes(
+            &listing_type,
+            100.0,
+            strike,
+            days as f64 / 365.0,
+            &PricingParams::new(volatility, 0.0),
+        );
+        let price = Amount::from_f64(value.price / 100.0);
+        ListingOption::new(
+            listing_id,
+            Asset::ETH,
+            Asset::USDC,
+            listing_type,
+            Amount::from_f64(strike),
+            price,
+            price,
+            now + Duration::days(days),
+            create_test_address("1"),
+            1,
+            Amount::from_int(1),
+            OptionState::Listed,
+        )
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_listings_bucketed_by_moneyness_and_expiry() {
+        set_eth_usdc_rate();
+        let now = Utc::now();
+        let mut listings = vec![
+            listing(1, ListingType::CALL, 100.0, 30, 0.4, now),
+            listing(2, ListingType::PUT, 101.0, 32, 0.6, now),
+            listing(3, ListingType::PUT, 80.0, 90, 0.9, now),
+        ];
+        // Partly sold listings still quote their ask
+        listings[1].state = OptionState::Purchased;
+
+        let surface =
+            VolatilitySurface::build(&Asset::ETH, &Asset::USDC, &listings, 0.0, now).unwrap();
+        assert_eq!(surface.spot, 100.0);
+        assert_eq!(surface.points.len(), EXPIRY_BUCKET_DAYS.len());
+        assert_eq!(surface.points[0].len(), MONEYNESS_BUCKETS.len());
+
+        // The first two share the 30 day at the money bucket, weighted by their vega
+        let at_the_money = surface.points[2][4].unwrap();
+        assert_eq!(at_the_money.listing_count, 2);
+        assert!((at_the_money.volatility - 0.5).abs() < 0.01);
+
+        let wing = surface.points[4][1].unwrap();
+        assert_eq!(wing.listing_count, 1);
+        assert_close(wing.volatility, 0.9);
+        assert_eq!(surface.points.iter().flatten().flatten().count(), 2);
+    }
+
+    #[test]
+    fn test_interpolates_across_moneyness() {
+        set_eth_usdc_rate();
+        let now = Utc::now();
+        let listings = vec![
+            listing(1, ListingType::PUT, 90.0, 30, 0.7, now),
+            listing(2, ListingType::CALL, 110.0, 30, 0.5, now),
+        ];
+        let surface =
+            VolatilitySurface::build(&Asset::ETH, &Asset::USDC, &listings, 0.0, now).unwrap();
+        let expiry = now + Duration::days(30);
+
+        let volatility = |strike: f64| {
+            surface
+                .get_volatility(Amount::from_f64(strike), expiry, now)
+                .unwrap()
+        };
+        assert_close(volatility(100.0), 0.6);
+        assert_close(volatility(95.0), 0.65);
+        // Flat beyond the outermost strikes
+        assert_close(volatility(60.0), 0.7);
+        assert_close(volatility(150.0), 0.5);
+    }
+
+    #[test]
+    fn test_interpolates_total_variance_across_expiry() {
+        set_eth_usdc_rate();
+        let now = Utc::now();
+        let listings = vec![
+            listing(1, ListingType::CALL, 100.0, 30, 0.4, now),
+            listing(2, ListingType::CALL, 100.0, 90, 0.6, now),
+        ];
+        let surface =
+            VolatilitySurface::build(&Asset::ETH, &Asset::USDC, &listings, 0.0, now).unwrap();
+        let volatility = |days: i64| {
+            surface
+                .get_volatility(Amount::from_int(100), now + Duration::days(days), now)
+                .unwrap()
+        };
+
+        // 0.4^2 * 30 and 0.6^2 * 90 meet halfway at 18.6 over 60 days
+        assert_close(volatility(60), (18.6f64 / 60.0).sqrt());
+        assert_close(volatility(7), 0.4);
+        assert_close(volatility(365), 0.6);
+
+        // The exported grid is filled in everywhere from the two points
+        let grid = surface.grid();
+        assert_eq!(grid.len(), EXPIRY_BUCKET_DAYS.len());
+        assert!(grid.iter().flatten().all(|volatility| volatility.is_some()));
+        assert_close(grid[3][0].unwrap(), (18.6f64 / 60.0).sqrt());
+    }
+
+    #[test]
+    fn test_values_series_without_a_listing() {
+        set_eth_usdc_rate();
+        let now = Utc::now();
+        let listings = vec![
+            listing(1, ListingType::PUT, 90.0, 30, 0.7, now),
+            listing(2, ListingType::CALL, 110.0, 30, 0.5, now),
+        ];
+        let surface =
+            VolatilitySurface::build(&Asset::ETH, &Asset::USDC, &listings, 0.0, now).unwrap();
+
+        // Nothing is listed at 100, the surface puts it at 60%
+        let series = listing(3, ListingType::CALL, 100.0, 30, 0.0, now).series();
+        let valuation = surface.value_series(&series, 0.0, now).unwrap();
+        let expected = black_scholes(
+            &ListingType::CALL,
+            100.0,
+            100.0,
+            30.0 / 365.0,
+            &PricingParams::new(0.6, 0.0),
+        );
+        assert!((valuation.price - expected.price).abs() < 1e-3);
+        assert!((valuation.delta - expected.delta).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_empty_surface_and_missing_rate() {
+        set_eth_usdc_rate();
+        let now = Utc::now();
+        let mut unlisted = listing(1, ListingType::CALL, 100.0, 30, 0.5, now);
+        unlisted.state = OptionState::Unlisted;
+        let expired = listing(2, ListingType::CALL, 100.0, -1, 0.5, now);
+        let mut sold_out = listing(3, ListingType::CALL, 100.0, 30, 0.5, now);
+        sold_out.state = OptionState::Purchased;
+        sold_out.contract_count = 0;
+        let listings = vec![unlisted, expired, sold_out];
+
+        let surface =
+            VolatilitySurface::build(&Asset::ETH, &Asset::USDC, &listings, 0.0, now).unwrap();
+        assert!(surface.is_empty());
+        assert_eq!(
+            surface.get_volatility(Amount::from_int(100), now + Duration::days(30), now),
+            None
+        );
+        let result = surface.value_series(&listings[0].series(), 0.0, now);
+        assert!(matches!(
+            result.unwrap_err(),
+            ExchangeError::InvalidInput(_)
+        ));
+        assert!(build_surfaces(&listings, &Asset::USDC, 0.0, now).is_empty());
+
+        let result = VolatilitySurface::build(&Asset::SOL, &Asset::USDC, &listings, 0.0, now);
+        assert_eq!(
+            result.unwrap_err(),
+            ExchangeError::RateMissing {
+                base: Asset::SOL,
+                quote: Asset::USDC,
+            }
+        );
+    }
+
+    #[test]
+    fn test_surfaces_by_asset_export_as_csv() {
+        set_eth_usdc_rate();
+        let now = Utc::now();
+        let listings = vec![listing(1, ListingType::CALL, 100.0, 30, 0.5, now)];
+
+        let surfaces = build_surfaces(&listings, &Asset::USDC, 0.0, now);
+        assert_eq!(surfaces.len(), 1);
+        let csv = surfaces.get(&Asset::ETH).unwrap().to_csv();
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), EXPIRY_BUCKET_DAYS.len() + 1);
+        assert_eq!(
+            lines[0],
+            "days,0.70,0.80,0.90,0.95,1.00,1.05,1.10,1.20,1.30"
+        );
+        assert_eq!(
+            lines[1],
+            "7,0.5000,0.5000,0.5000,0.5000,0.5000,0.5000,0.5000,0.5000,0.5000"
+        );
+    }
+}